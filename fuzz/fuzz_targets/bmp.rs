@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::bmp::Bmp;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(bmp) = Bmp::try_from(data) {
+        let _ = bmp.to_image();
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::exif::Exif;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(exif) = Exif::try_from(data) {
+        let _ = exif.orientation();
+        let _ = exif.date_time();
+        let _ = exif.resolution();
+    }
+});
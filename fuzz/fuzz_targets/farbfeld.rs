@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::farbfeld::Farbfeld;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(farbfeld) = Farbfeld::try_from(data) {
+        let _ = farbfeld.to_image();
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::gif::Gif;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(gif) = Gif::try_from(data) {
+        let _ = gif.to_animation();
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::{ico::Ico, ConvertibleImage};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(ico) = Ico::try_from(data) {
+        for index in 0..ico.image_count() {
+            let _ = ico.to_image(index);
+        }
+    }
+});
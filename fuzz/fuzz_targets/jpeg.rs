@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::jpeg::Jpeg;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(jpeg) = Jpeg::try_from(data) {
+        let _ = jpeg.metadata();
+        let _ = jpeg.to_image();
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::netpbm::Netpbm;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(netpbm) = Netpbm::try_from(data) {
+        let _ = netpbm.to_image();
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::qoi::Qoi;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(qoi) = Qoi::try_from(data) {
+        let _ = qoi.to_image();
+    }
+});
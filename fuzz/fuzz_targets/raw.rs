@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::raw::{
+    image_data,
+    options::{ChannelOrder, RawOptions, SampleType},
+};
+
+// a dump has no header, so the first two bytes pick its layout and the rest are pixels
+fuzz_target!(|data: &[u8]| {
+    let [size, layout, ref pixels @ ..] = *data else {
+        return;
+    };
+    let options = RawOptions {
+        width: Some((size & 0x0f) as u32 + 1),
+        height: Some((size >> 4) as u32 + 1),
+        channels: [
+            ChannelOrder::Rgba,
+            ChannelOrder::Bgra,
+            ChannelOrder::Rgb,
+            ChannelOrder::Gray,
+        ][(layout & 3) as usize],
+        sample: [
+            SampleType::U8,
+            SampleType::U16Le,
+            SampleType::U16Be,
+            SampleType::F32Le,
+            SampleType::F32Be,
+        ][(layout >> 2) as usize % 5],
+    };
+    let _ = image_data::decode(&mut &pixels[..], &options);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::tga::Tga;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tga) = Tga::try_from(data) {
+        let _ = tga.to_image();
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::{
+    tiff::{self, Tiff},
+    ConvertibleImage,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tiff::CODEC.inspect(&mut &data[..]);
+    if let Ok(tiff) = Tiff::try_from(data) {
+        let _ = tiff.metadata();
+        for index in 0..tiff.image_count() {
+            let _ = tiff.to_image(index);
+        }
+    }
+});
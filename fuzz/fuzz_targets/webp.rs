@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use modular_image_converter::webp::Webp;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(webp) = Webp::try_from(data) {
+        let _ = webp.metadata();
+        let _ = webp.to_animation();
+        let _ = webp.to_image();
+    }
+});
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use crate::{
+    image::{Color, ColorModel, Image},
+    metadata::Metadata,
+    Error, Result,
+};
+
+/// One frame of an `Animation`: the whole canvas as it looks while the frame is shown
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub image: Image,
+    /// How long the frame stays up before the next one replaces it
+    pub delay: Duration,
+}
+
+/// A sequence of frames that all share one size and layout. Every frame is stored fully
+/// composited, so formats with different disposal and blending rules convert through it
+/// without having to agree on them.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    frames: Vec<Frame>,
+    /// Times to play the animation through, or 0 to loop forever
+    plays: u32,
+    metadata: Metadata,
+}
+
+impl Animation {
+    pub fn new(frames: Vec<Frame>, plays: u32) -> Result<Self> {
+        let first = match frames.first() {
+            Some(frame) => frame.image.layout(),
+            None => {
+                return Err(Error::invalid_argument(
+                    "animations need at least one frame",
+                ))
+            }
+        };
+        if frames.iter().any(|frame| frame.image.layout() != first) {
+            return Err(Error::invalid_argument(
+                "every frame of an animation has to have the same size, color model and bit depth",
+            ));
+        }
+        Ok(Animation {
+            frames,
+            plays,
+            metadata: Metadata::default(),
+        })
+    }
+
+    /// Builds an animation from 8-bit RGBA canvases, dropping the alpha channel if every
+    /// pixel of every frame is opaque
+    pub fn from_rgba8(
+        width: u32,
+        height: u32,
+        canvases: Vec<(Vec<u8>, Duration)>,
+        plays: u32,
+    ) -> Result<Self> {
+        let opaque = canvases
+            .iter()
+            .all(|(canvas, _)| canvas.chunks_exact(4).all(|pixel| pixel[3] == 255));
+        let frames = canvases
+            .into_iter()
+            .map(|(canvas, delay)| {
+                let image = match opaque {
+                    true => {
+                        let rgb = canvas
+                            .chunks_exact(4)
+                            .flat_map(|pixel| pixel[..3].to_vec())
+                            .collect();
+                        Image::new(width, height, ColorModel::Rgb, 8, rgb)?
+                    }
+                    false => Image::new(width, height, ColorModel::Rgba, 8, canvas)?,
+                };
+                Ok(Frame { image, delay })
+            })
+            .collect::<Result<Vec<Frame>>>()?;
+        Animation::new(frames, plays)
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frames[0].image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frames[0].image.height()
+    }
+
+    pub fn plays(&self) -> u32 {
+        self.plays
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = metadata;
+    }
+
+    /// Reduces every frame to 8 bits per sample, as `Image::reduce_depth` does
+    pub fn reduce_depth(&mut self) {
+        for frame in &mut self.frames {
+            frame.image.reduce_depth();
+        }
+    }
+
+    /// Flattens every frame onto an opaque matte, as `Image::flatten_alpha` does
+    pub fn flatten_alpha(&mut self, matte: Option<Color>) {
+        let matte = matte.or(self.metadata.background());
+        for frame in &mut self.frames {
+            frame.image.flatten_alpha(matte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_rgba8() {
+        let delay = Duration::from_millis(40);
+        let opaque = vec![(vec![1, 2, 3, 255], delay), (vec![4, 5, 6, 255], delay)];
+        let animation = Animation::from_rgba8(1, 1, opaque, 0).unwrap();
+        assert_eq!(animation.frames()[1].image.color_model(), ColorModel::Rgb);
+        assert_eq!(animation.frames()[1].image.data(), &[4, 5, 6]);
+
+        let mut translucent =
+            Animation::from_rgba8(1, 1, vec![(vec![0, 0, 0, 0], delay)], 2).unwrap();
+        assert_eq!(
+            translucent.frames()[0].image.color_model(),
+            ColorModel::Rgba
+        );
+        translucent.flatten_alpha(Some(Color::from_rgb8(9, 9, 9)));
+        assert_eq!(translucent.frames()[0].image.data(), &[9, 9, 9]);
+
+        let red = Image::new(1, 1, ColorModel::Rgb, 8, vec![255, 0, 0]).unwrap();
+        let gray = Image::new(1, 1, ColorModel::Gray, 8, vec![0]).unwrap();
+        let frames = [red, gray].map(|image| Frame { image, delay });
+        assert!(Animation::new(frames.to_vec(), 0).is_err());
+        assert!(Animation::new(Vec::new(), 0).is_err());
+    }
+}
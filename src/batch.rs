@@ -0,0 +1,515 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Condvar, Mutex},
+    thread,
+};
+
+use crate::{
+    convert::{convert_validated, Conversion, ConvertOptions, Input, Output},
+    format::{Codec, Registry},
+    Error, Result,
+};
+
+/// Memory that the images being converted at once may take up, unless set otherwise
+pub const DEFAULT_MEMORY_BUDGET: usize = 1 << 30;
+
+/// Settings for converting many files into one output directory
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    /// Where converted files go. Files found inside a directory or under a glob pattern keep
+    /// their place relative to it; files named directly go straight in.
+    pub output_dir: PathBuf,
+    /// Look inside the subdirectories of directory inputs too
+    pub recursive: bool,
+    /// Applied to every file. `to` names the format to write and has to be set.
+    pub convert: ConvertOptions,
+    /// Files converted at the same time; 0 runs one per CPU core
+    pub jobs: usize,
+    /// Bytes of decoded pixels allowed in memory across all jobs. A file is only started
+    /// once its image fits in what the running jobs leave over; one larger than the whole
+    /// budget waits to run on its own.
+    pub memory_budget: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            output_dir: PathBuf::new(),
+            recursive: false,
+            convert: ConvertOptions::default(),
+            jobs: 0,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+        }
+    }
+}
+
+/// One file of a batch
+#[derive(Debug)]
+pub struct BatchItem {
+    pub input: PathBuf,
+    /// Where the converted file goes, or why the input can't be converted
+    pub output: Result<PathBuf>,
+}
+
+/// How one file of a batch went
+#[derive(Debug)]
+pub struct FileReport {
+    pub input: PathBuf,
+    /// Where the file was (or would have been) written, if that got worked out
+    pub output: Option<PathBuf>,
+    pub result: Result<Conversion>,
+}
+
+/// How a whole batch went, one report per file in the order the files were found
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub files: Vec<FileReport>,
+}
+
+impl BatchReport {
+    pub fn succeeded(&self) -> usize {
+        self.files.iter().filter(|file| file.result.is_ok()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.files.len() - self.succeeded()
+    }
+
+    /// The error of the first file that failed, if any did
+    pub fn first_error(&self) -> Option<&Error> {
+        self.files
+            .iter()
+            .find_map(|file| file.result.as_ref().err())
+    }
+}
+
+/// Converts every file `inputs` names, carrying on past files that fail. Files are spread
+/// over `options.jobs` threads, but the report lists them in the order they were found.
+pub fn convert_batch<P: AsRef<Path>>(inputs: &[P], options: &BatchOptions) -> Result<BatchReport> {
+    let registry = Registry::default();
+    let items = plan(&registry, inputs, options)?;
+    let jobs = match options.jobs {
+        0 => thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+        jobs => jobs,
+    }
+    .min(items.len());
+    if jobs <= 1 {
+        return Ok(BatchReport {
+            files: items
+                .into_iter()
+                .map(|item| run(&registry, item, options))
+                .collect(),
+        });
+    }
+
+    let count = items.len();
+    let budget = MemoryBudget::new(options.memory_budget);
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel::<(usize, FileReport)>();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (registry, budget, queue) = (&registry, &budget, &queue);
+            scope.spawn(move || loop {
+                // the lock is released before converting, so other jobs can take the next file
+                let next = queue
+                    .lock()
+                    .expect("no job panics holding the queue")
+                    .next();
+                let Some((index, item)) = next else {
+                    return;
+                };
+                let cost = budget.reserve(estimate(registry, &item, options));
+                let report = run(registry, item, options);
+                budget.release(cost);
+                if sender.send((index, report)).is_err() {
+                    return;
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut files: Vec<Option<FileReport>> = (0..count).map(|_| None).collect();
+    for (index, report) in receiver {
+        files[index] = Some(report);
+    }
+    Ok(BatchReport {
+        files: files
+            .into_iter()
+            .map(|report| report.expect("every file is reported once"))
+            .collect(),
+    })
+}
+
+/// Works out which files `inputs` stand for and where each one's output goes. Inputs can be
+/// files, directories or glob patterns; problems with a single input, like a pattern that
+/// matches nothing, come back as items rather than stopping the batch.
+pub fn plan<P: AsRef<Path>>(
+    registry: &Registry,
+    inputs: &[P],
+    options: &BatchOptions,
+) -> Result<Vec<BatchItem>> {
+    options.convert.validate()?;
+    let target = match &options.convert.to {
+        Some(format) => registry.by_format(format)?,
+        None => {
+            return Err(Error::invalid_argument(
+                "batch conversions need the format to write",
+            ))
+        }
+    };
+    if !target.can_encode() {
+        return Err(Error::unsupported(target.name, "encoding"));
+    }
+
+    let mut items: Vec<BatchItem> = Vec::new();
+    let mut seen_inputs: HashSet<PathBuf> = HashSet::new();
+    let mut seen_outputs: HashSet<PathBuf> = HashSet::new();
+    for input in inputs {
+        let input = input.as_ref();
+        let found = match find_files(registry, input, options.recursive) {
+            Ok(found) => found,
+            Err(e) => {
+                items.push(BatchItem {
+                    input: input.to_path_buf(),
+                    output: Err(e),
+                });
+                continue;
+            }
+        };
+        for (file, relative) in found {
+            // overlapping inputs, like a directory and a pattern inside it, name files twice
+            if !seen_inputs.insert(file.clone()) {
+                continue;
+            }
+            let output = options
+                .output_dir
+                .join(relative)
+                .with_extension(target.extensions[0]);
+            let output = if output == file {
+                Err(Error::invalid_argument(format!(
+                    "converting {} would overwrite it",
+                    file.display()
+                )))
+            } else if !seen_outputs.insert(output.clone()) {
+                Err(Error::invalid_argument(format!(
+                    "{} is already the output for another input",
+                    output.display()
+                )))
+            } else {
+                Ok(output)
+            };
+            items.push(BatchItem {
+                input: file,
+                output,
+            });
+        }
+    }
+    Ok(items)
+}
+
+/// Converts one file `plan` worked out with the same `options`, creating the directories its
+/// output goes in. `plan` has already validated the options, so they aren't checked again.
+pub fn run(registry: &Registry, item: BatchItem, options: &BatchOptions) -> FileReport {
+    let output = match item.output {
+        Ok(output) => output,
+        Err(e) => {
+            return FileReport {
+                input: item.input,
+                output: None,
+                result: Err(e),
+            }
+        }
+    };
+    let result = output
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .map_err(Error::from)
+        .and_then(|_| {
+            convert_validated(
+                registry,
+                Input::Path(&item.input),
+                Output::Path(&output),
+                &options.convert,
+            )
+        });
+    FileReport {
+        input: item.input,
+        output: Some(output),
+        result,
+    }
+}
+
+/// Roughly how many bytes converting `item` will hold at once: the size of its decoded
+/// image where the headers give it away, otherwise the size of the file
+fn estimate(registry: &Registry, item: &BatchItem, options: &BatchOptions) -> usize {
+    let codec = match &options.convert.from {
+        Some(format) => registry.by_format(format).ok(),
+        None => registry.by_path(&item.input).ok(),
+    };
+    let probed = codec.filter(|codec| codec.can_probe()).and_then(|codec| {
+        let layout = codec.probe(&mut File::open(&item.input).ok()?).ok()?;
+        Some(layout.row_len().saturating_mul(layout.height as usize))
+    });
+    probed
+        .or_else(|| Some(fs::metadata(&item.input).ok()?.len() as usize))
+        .unwrap_or(0)
+}
+
+/// Bytes of memory shared out between the jobs of a batch
+struct MemoryBudget {
+    total: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    fn new(total: usize) -> Self {
+        MemoryBudget {
+            total,
+            available: Mutex::new(total),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until `bytes` are free and takes them, returning what was taken. Requests
+    /// larger than the whole budget take all of it, so they run alone rather than never.
+    fn reserve(&self, bytes: usize) -> usize {
+        let bytes = bytes.min(self.total);
+        let available = self
+            .available
+            .lock()
+            .expect("no job panics holding the budget");
+        let mut available = self
+            .released
+            .wait_while(available, |available| *available < bytes)
+            .expect("no job panics holding the budget");
+        *available -= bytes;
+        bytes
+    }
+
+    fn release(&self, bytes: usize) {
+        *self
+            .available
+            .lock()
+            .expect("no job panics holding the budget") += bytes;
+        self.released.notify_all();
+    }
+}
+
+/// Lists the files `input` stands for, each with the path its output takes under the
+/// output directory
+fn find_files(
+    registry: &Registry,
+    input: &Path,
+    recursive: bool,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let pattern = input.to_string_lossy();
+    // a file whose name happens to contain wildcard characters is still taken literally
+    if !input.exists() && pattern.contains(['*', '?', '[']) {
+        return glob_files(input);
+    }
+    if fs::metadata(input)?.is_dir() {
+        let mut files: Vec<PathBuf> = Vec::new();
+        list_dir(registry, input, recursive, &mut files)?;
+        return Ok(files
+            .into_iter()
+            .map(|file| {
+                let relative = file.strip_prefix(input).unwrap_or(&file).to_path_buf();
+                (file, relative)
+            })
+            .collect());
+    }
+    let name = input.file_name().ok_or_else(|| {
+        Error::invalid_argument(format!("{} doesn't name a file", input.display()))
+    })?;
+    Ok(vec![(input.to_path_buf(), PathBuf::from(name))])
+}
+
+/// Lists the files matching the glob `pattern`, placed relative to the directory the pattern
+/// starts from
+fn glob_files(pattern: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let text = pattern.to_string_lossy();
+    let paths = glob::glob(&text)
+        .map_err(|e| Error::invalid_argument(format!("bad pattern {text}: {e}")))?;
+    // the leading components without wildcards, which the matches all sit under
+    let base: PathBuf = pattern
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '['])
+        })
+        .collect();
+    let mut files: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for path in paths {
+        let path = path.map_err(|e| Error::Io(e.into()))?;
+        if !path.is_file() {
+            continue;
+        }
+        let relative = path.strip_prefix(&base).unwrap_or(&path);
+        // absolute or parent components would put the output outside the output directory
+        let relative: PathBuf = relative
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        files.push((path, relative));
+    }
+    if files.is_empty() {
+        return Err(Error::invalid_argument(format!("no files match {text}")));
+    }
+    Ok(files)
+}
+
+/// Adds the image files in `dir` to `files` in name order, going into subdirectories if
+/// `recursive` is set. Only files with an extension of a readable format count.
+fn list_dir(
+    registry: &Registry,
+    dir: &Path,
+    recursive: bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        // symlinked directories aren't followed, so links can't send the walk round in circles
+        if fs::symlink_metadata(&path)?.is_dir() {
+            if recursive {
+                list_dir(registry, &path, recursive, files)?;
+            }
+        } else if path.is_file() && is_readable_image(registry, &path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_readable_image(registry: &Registry, path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(|extension| registry.by_extension(extension))
+        .is_some_and(Codec::can_decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image::{ColorModel, Image},
+        png::Png,
+        ConvertibleImage,
+    };
+
+    fn write_png(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let image = Image::new(1, 1, ColorModel::Gray, 8, vec![128]).unwrap();
+        fs::write(path, Png::from_image(&image).unwrap().to_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_convert_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        write_png(&input.join("a.png"));
+        write_png(&input.join("nested/b.png"));
+        write_png(&input.join("nested/deeper/c.PNG"));
+        fs::write(input.join("notes.txt"), "not an image").unwrap();
+        fs::write(input.join("broken.png"), "not a png either").unwrap();
+
+        let options = BatchOptions {
+            output_dir: dir.path().join("out"),
+            recursive: true,
+            convert: ConvertOptions {
+                to: Some("bmp".to_string()),
+                ..ConvertOptions::default()
+            },
+            // spread over threads, with too little memory for more than one image at a time
+            jobs: 3,
+            memory_budget: 3,
+        };
+        let missing = dir.path().join("missing.png");
+        let report = convert_batch(&[input.clone(), missing.clone()], &options).unwrap();
+
+        let inputs: Vec<&Path> = report
+            .files
+            .iter()
+            .map(|file| file.input.as_path())
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                input.join("a.png").as_path(),
+                &input.join("broken.png"),
+                &input.join("nested/b.png"),
+                &input.join("nested/deeper/c.PNG"),
+                &missing,
+            ]
+        );
+        assert_eq!(report.succeeded(), 3);
+        assert_eq!(report.failed(), 2);
+        assert!(dir.path().join("out/nested/deeper/c.bmp").is_file());
+        assert!(!dir.path().join("out/notes.bmp").exists());
+
+        let flat = BatchOptions {
+            recursive: false,
+            jobs: 1,
+            ..options.clone()
+        };
+        let report = convert_batch(&[&input], &flat).unwrap();
+        assert_eq!(report.files.len(), 2);
+    }
+
+    #[test]
+    fn test_plan_globs_and_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        write_png(&dir.path().join("src/x/one.png"));
+        write_png(&dir.path().join("src/y/one.png"));
+        let options = BatchOptions {
+            output_dir: dir.path().join("out"),
+            convert: ConvertOptions {
+                to: Some("BMP".to_string()),
+                ..ConvertOptions::default()
+            },
+            ..BatchOptions::default()
+        };
+        let registry = Registry::default();
+
+        let pattern = dir.path().join("src/*/one.png");
+        let items = plan(&registry, &[&pattern], &options).unwrap();
+        let outputs: Vec<PathBuf> = items.into_iter().map(|item| item.output.unwrap()).collect();
+        assert_eq!(
+            outputs,
+            [
+                dir.path().join("out/x/one.bmp"),
+                dir.path().join("out/y/one.bmp")
+            ]
+        );
+
+        // files named directly go straight into the output directory, so these collide
+        let items = plan(
+            &registry,
+            &[
+                dir.path().join("src/x/one.png"),
+                dir.path().join("src/y/one.png"),
+                dir.path().join("src/*.gif"),
+            ],
+            &options,
+        )
+        .unwrap();
+        assert!(items[0].output.is_ok());
+        assert!(matches!(items[1].output, Err(Error::InvalidArgument(_))));
+        assert!(matches!(items[2].output, Err(Error::InvalidArgument(_))));
+
+        let no_format = BatchOptions {
+            convert: ConvertOptions::default(),
+            ..options
+        };
+        assert!(plan(&registry, &[&pattern], &no_format).is_err());
+    }
+}
@@ -1,14 +1,107 @@
-use std::{fs, str::from_utf8};
-
-use crate::{compression::CompressionType, ConvertibleImage, Error};
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::{
+    byte_reader::{ByteReader, StreamReader},
+    compression::CompressionType,
+    convert::ConvertOptions,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{BmpDetails, FormatDetails, ImageInfo, MetadataInfo},
+    metadata::Metadata,
+    png::Png,
+    scanline::RowSource,
+    ConvertibleImage, Error,
+};
 
 use self::{
     bmp_file_header::BmpFileHeader,
-    bmp_info_header::{BitmapInfoHeader, BmpInfoHeader},
+    bmp_info_header::{BitmapInfoHeader, BitmapV4Header, BmpInfoHeader},
+    options::BmpOptions,
 };
 
 pub mod bmp_file_header;
 pub mod bmp_info_header;
+pub mod image_data;
+pub mod options;
+
+/// Registry entry for BMP files
+pub const CODEC: Codec = Codec {
+    name: "BMP",
+    extensions: &["bmp", "dib"],
+    magic: &[b"BM"],
+    check_signature: None,
+    decoder: Some(decode_with::<Bmp>),
+    encoder: Some(write_bmp),
+    row_decoder: Some(read_bmp_rows),
+    row_encoder: Some(write_bmp_rows),
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a BMP from its headers for the registry, without reading the pixel data
+fn inspect(reader: &mut dyn Read) -> crate::Result<ImageInfo> {
+    let bmp = Bmp::read_headers(&mut StreamReader::new("BMP", BufReader::new(reader)))?;
+    let header = bmp.info_header();
+    let (color_model, bit_depth) = image_data::stored_format(&bmp)?;
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: header.px_width().unsigned_abs(),
+        height: header.px_height().unsigned_abs(),
+        color_model,
+        bit_depth,
+        compression: match header.compression_type() {
+            None => "none",
+            Some(CompressionType::BI_RLE8) => "RLE8",
+            Some(CompressionType::BI_RLE4) => "RLE4",
+            Some(CompressionType::BI_BITFIELDS) => "bit fields",
+            Some(CompressionType::BI_JPEG) => "JPEG",
+        },
+        interlaced: false,
+        palette_size: bmp.color_table().map(|table| table.len() / 4),
+        metadata: MetadataInfo::default(),
+        details: FormatDetails::Bmp(BmpDetails {
+            file_size: bmp.file_header().length(),
+            pixel_offset: bmp.file_header().img_offset(),
+            header: match header.length() {
+                40 => "BITMAPINFOHEADER",
+                108 => "BITMAPV4HEADER",
+                _ => "BITMAPV5HEADER",
+            },
+            header_length: header.length(),
+            bits_per_pixel: header.bits_per_pixel(),
+            top_down: header.px_height() < 0,
+            image_size: header.img_size(),
+            pixels_per_meter: [header.res_horiz(), header.res_vert()],
+            colors_used: header.num_colors(),
+            important_colors: header.num_important_colors(),
+            bit_masks: header.bit_masks(),
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// Reads a BMP file a row at a time for the registry
+fn read_bmp_rows<'a>(
+    reader: &'a mut dyn Read,
+    _options: &ConvertOptions,
+) -> crate::Result<Box<dyn RowSource + 'a>> {
+    image_data::decode_rows(reader)
+}
+
+/// Encodes `png` as a BMP for the registry
+fn write_bmp(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> crate::Result<()> {
+    Bmp::from_image_with(&png.to_image()?, &options.bmp)?.to_writer(writer)
+}
+
+/// Writes rows out as a BMP for the registry; BMP has nowhere to put metadata
+fn write_bmp_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> crate::Result<()> {
+    image_data::encode_rows(source, &options.bmp, writer)
+}
 
 pub struct Bmp {
     file_header: BmpFileHeader,
@@ -22,8 +115,8 @@ impl Bmp {
         &self.file_header
     }
 
-    pub fn info_header(&self) -> &Box<dyn BmpInfoHeader> {
-        &self.info_header
+    pub fn info_header(&self) -> &dyn BmpInfoHeader {
+        self.info_header.as_ref()
     }
 
     pub fn color_table(&self) -> Option<&Vec<u8>> {
@@ -33,108 +126,114 @@ impl Bmp {
     pub fn image_data(&self) -> &[u8] {
         &self.data
     }
+
+    /// Decodes the pixel data into an `Image`
+    pub fn to_image(&self) -> crate::Result<Image> {
+        image_data::decode(self)
+    }
+
+    /// Encodes `image` as a new `Bmp`
+    pub fn from_image(image: &Image) -> crate::Result<Bmp> {
+        image_data::encode(image, &BmpOptions::default())
+    }
+
+    /// Encodes `image` as a new `Bmp` laid out as `options` asks
+    pub fn from_image_with(image: &Image, options: &BmpOptions) -> crate::Result<Bmp> {
+        image_data::encode(image, options)
+    }
 }
 
 impl TryFrom<&[u8]> for Bmp {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let file_header = BmpFileHeader::try_from(&value[0..14])?;
-
-        let mut buf: [u8; 4] = [0; 4];
-        buf.copy_from_slice(&value[14..18]);
-        let info_header: Box<dyn BmpInfoHeader> = match u32::from_le_bytes(buf) {
-            40 => {
-                let boxed = BitmapInfoHeader::try_from(&value[14..54])?;
-                Box::new(boxed)
-            }
-            _ => return Err(format!("unknown header type").into()),
-        };
+        Bmp::from_reader(value)
+    }
+}
 
-        let color_table: Option<Vec<u8>>;
+impl Bmp {
+    /// Reads everything up to the pixel data, leaving `reader` at its start. The returned
+    /// `Bmp` has no pixel data yet.
+    fn read_headers<R: BufRead>(reader: &mut StreamReader<R>) -> crate::Result<Bmp> {
+        // the headers are small, so they're gathered up and parsed in memory
+        let mut headers = reader.read_bytes(BmpFileHeader::LENGTH)?;
+        let file_header = BmpFileHeader::read(&mut ByteReader::new("BMP", &headers))?;
+
+        let info_length = reader.read_u32_le()?;
+        if !matches!(info_length, 40 | 108 | 124) {
+            return Err(Error::unsupported(
+                "BMP",
+                format!("{info_length}-byte info headers"),
+            ));
+        }
+        headers.extend(info_length.to_le_bytes());
+        headers.extend(reader.read_bytes(info_length as usize - 4)?);
+        let mut header_reader = ByteReader::new("BMP", &headers).at(BmpFileHeader::LENGTH)?;
+        let info_header: Box<dyn BmpInfoHeader> = match info_length {
+            40 => Box::new(BitmapInfoHeader::read(&mut header_reader)?),
+            _ => Box::new(BitmapV4Header::read(&mut header_reader)?),
+        };
 
-        if info_header.bits_per_pixel() == 24 {
-            color_table = None;
-        } else {
-            let color_table_start = 14 + info_header.length() as usize;
-            let color_table_end = color_table_start + info_header.num_colors() as usize;
-            color_table = Some(value[color_table_start..color_table_end].to_vec());
+        // indexed images that don't give a color count use every color their depth allows
+        let num_colors = match (info_header.bits_per_pixel(), info_header.num_colors()) {
+            (bits_per_pixel @ (1 | 4 | 8), 0) => 1 << bits_per_pixel,
+            (_, num_colors) => num_colors as usize,
+        };
+        let color_table: Option<Vec<u8>> = match num_colors {
+            0 => None,
+            // each entry is blue, green, red and a reserved byte
+            _ => Some(reader.read_bytes(num_colors.saturating_mul(4))?),
         };
 
-        let data = value[file_header.img_offset() as usize..].to_vec();
+        // the stream can't be rewound, so the pixel data has to come after everything else
+        let img_offset = file_header.img_offset() as usize;
+        if img_offset < reader.position() {
+            return Err(Error::malformed_at(
+                "BMP",
+                10,
+                format!(
+                    "pixel data offset {img_offset} overlaps the headers, which end at {}",
+                    reader.position()
+                ),
+            ));
+        }
+        reader.skip(img_offset - reader.position())?;
 
         Ok(Bmp {
             file_header,
             info_header,
             color_table,
-            data,
+            data: Vec::new(),
         })
     }
 }
 
 impl ConvertibleImage for Bmp {
-    fn from_file<P: AsRef<std::path::Path>>(path: P) -> crate::Result<Box<Self>>
-    where
-        Self: Sized,
-    {
-        let file_bytes = fs::read(path)?;
-        match Bmp::try_from(&file_bytes[..]) {
-            Ok(bmp) => Ok(Box::new(bmp)),
-            Err(e) => Err(e),
-        }
+    fn from_reader<R: Read>(reader: R) -> crate::Result<Self> {
+        let mut reader = StreamReader::new("BMP", BufReader::new(reader));
+        let mut bmp = Bmp::read_headers(&mut reader)?;
+        bmp.data = reader.read_rest()?;
+        Ok(bmp)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        self.file_header
-            .as_bytes()
-            .iter()
-            .chain(self.info_header.as_bytes().iter())
-            .chain(self.color_table.iter().flatten())
-            .chain(self.data.iter())
-            .copied()
-            .collect()
+    fn to_writer(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        writer.write_all(&self.file_header.as_bytes())?;
+        writer.write_all(&self.info_header.as_bytes())?;
+        if let Some(color_table) = &self.color_table {
+            writer.write_all(color_table)?;
+        }
+        writer.write_all(&self.data)?;
+        Ok(())
     }
 
-    fn from_png(png: crate::png::Png) -> Self
+    fn from_png(png: Png) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        //generate infoheader
-        let png_header_bytes = png.header_chunk().unwrap().data();
-        let buf: [u8; 4] = [0; 4];
-        buf.copy_from_slice(&png_header_bytes[0..4]);
-        let px_width: i32 = u32::from_be_bytes(buf) as i32;
-        buf.copy_from_slice(&png_header_bytes[4..8]);
-        let px_height: i32 = u32::from_be_bytes(buf) as i32;
-
-        let color_type = png_header_bytes[10];
-
-        /*
-         * Some of these values should never occur under normal transfer circumstances
-         * 4 in particular doesn't match up neatly with how most BMP interpreters
-         * will actually work; as such, it will only ever occur when directly
-         * converting a PNG file to a BMP file
-         */
-        let bits_per_pixel: u16 = match color_type {
-            0 => png_header_bytes[9] as u16,
-            2 => png_header_bytes[9] as u16 * 3,
-            3 => 8,
-            4 => png_header_bytes[9] as u16 * 2,
-            6 => 32,
-        };
+        Bmp::from_image(&png.to_image()?)
+    }
 
-        let num_colors: u32 = (2 as u32).pow(bits_per_pixel as u32);
-
-        let info_header = BitmapInfoHeader::new(
-            px_width,
-            px_height,
-            bits_per_pixel,
-            None,
-            (px_width * px_height) as u32,
-            8,
-            8,
-            num_colors,
-            0,
-        );
+    fn to_png(&self) -> crate::Result<Png> {
+        Png::from_image(&self.to_image()?)
     }
 }
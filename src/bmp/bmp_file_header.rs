@@ -1,4 +1,4 @@
-use crate::Error;
+use crate::{byte_reader::ByteReader, Error};
 
 #[derive(Clone, Copy, Debug)]
 pub struct BmpFileHeader {
@@ -8,6 +8,9 @@ pub struct BmpFileHeader {
 }
 
 impl BmpFileHeader {
+    /// Size of the file header in bytes
+    pub const LENGTH: usize = 14;
+
     pub fn new(length: u32, reserved_vals: [u8; 4], img_offset: u32) -> BmpFileHeader {
         BmpFileHeader {
             length,
@@ -29,9 +32,9 @@ impl BmpFileHeader {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.length
-            .to_le_bytes()
+        b"BM"
             .iter()
+            .chain(self.length.to_le_bytes().iter())
             .chain(self.reserved_vals.iter())
             .chain(&self.img_offset.to_le_bytes())
             .copied()
@@ -43,17 +46,24 @@ impl TryFrom<&[u8]> for BmpFileHeader {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        match String::from_utf8(value[0..2].to_vec())?.as_str() {
-            "BM" => (),
-            _ => return Err(format!("Invalid bitmap signature").into()),
-        };
-        let mut buf: [u8; 4] = [0; 4];
-        buf.copy_from_slice(&value[2..6]);
-        let length = u32::from_le_bytes(buf);
-        let mut reserved_vals: [u8; 4] = [0; 4];
-        reserved_vals.copy_from_slice(&value[6..10]);
-        buf.copy_from_slice(&value[10..14]);
-        let img_offset = u32::from_le_bytes(buf);
+        BmpFileHeader::read(&mut ByteReader::new("BMP", value))
+    }
+}
+
+impl BmpFileHeader {
+    /// Reads the 14-byte file header from the start of a BMP file
+    pub fn read(reader: &mut ByteReader) -> Result<Self, Error> {
+        let start = reader.position();
+        if reader.read_bytes(2)? != b"BM" {
+            return Err(Error::malformed_at(
+                "BMP",
+                start,
+                "invalid bitmap signature",
+            ));
+        }
+        let length = reader.read_u32_le()?;
+        let reserved_vals: [u8; 4] = reader.read_array()?;
+        let img_offset = reader.read_u32_le()?;
         Ok(BmpFileHeader {
             length,
             reserved_vals,
@@ -96,5 +106,9 @@ mod tests {
         assert_eq!(header.length(), 25);
         assert_eq!(reserved_vals, [0; 4]);
         assert_eq!(header.img_offset(), 30);
+        assert_eq!(header.as_bytes(), header_data);
+
+        assert!(BmpFileHeader::try_from(&header_data[..13]).is_err());
+        assert!(BmpFileHeader::try_from(&header_data[..1]).is_err());
     }
 }
@@ -1,4 +1,6 @@
-use crate::{compression::CompressionType, Error};
+use crate::{byte_reader::ByteReader, compression::CompressionType, Error};
+
+use super::options::BmpHeaderVersion;
 
 /*
  * Most BMP files just use the BMPINFOHEADER type, so for now we'll only implement
@@ -11,12 +13,16 @@ pub trait BmpInfoHeader {
     fn px_width(&self) -> i32;
     fn px_height(&self) -> i32;
     fn bits_per_pixel(&self) -> u16;
-    fn compression_type<'a>(&'a self) -> Option<&'a CompressionType>;
+    fn compression_type(&self) -> Option<&CompressionType>;
     fn img_size(&self) -> u32;
     fn res_horiz(&self) -> i32;
     fn res_vert(&self) -> i32;
     fn num_colors(&self) -> u32;
     fn num_important_colors(&self) -> u32;
+    /// Red, green, blue and alpha masks for BI_BITFIELDS images, for headers that hold them
+    fn bit_masks(&self) -> Option<[u32; 4]> {
+        None
+    }
     fn as_bytes(&self) -> Vec<u8>;
 }
 
@@ -33,6 +39,7 @@ pub struct BitmapInfoHeader {
 }
 
 impl BitmapInfoHeader {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         px_width: i32,
         px_height: i32,
@@ -75,7 +82,7 @@ impl BmpInfoHeader for BitmapInfoHeader {
         self.bits_per_pixel
     }
 
-    fn compression_type<'a>(&'a self) -> Option<&'a CompressionType> {
+    fn compression_type(&self) -> Option<&CompressionType> {
         self.compression_type.as_ref()
     }
 
@@ -100,17 +107,26 @@ impl BmpInfoHeader for BitmapInfoHeader {
     }
 
     fn as_bytes(&self) -> Vec<u8> {
-        let comp_bytes: u32 = match self.compression_type() {
-            Some(CompressionType::BI_RLE8) => 1,
-            Some(CompressionType::BI_RLE4) => 2,
-            Some(_) => u32::MAX,
-            None => 0,
-        };
-        (40 as u32)
+        40_u32
+            .to_le_bytes()
+            .iter()
+            .chain(self.field_bytes().iter())
+            .copied()
+            .collect()
+    }
+}
+
+impl BitmapInfoHeader {
+    /// Every field after the header length, which later header versions share
+    fn field_bytes(&self) -> Vec<u8> {
+        let comp_bytes: u32 = self
+            .compression_type
+            .map_or(0, |compression| compression.code());
+        self.px_width
             .to_le_bytes()
             .iter()
-            .chain(self.px_width.to_le_bytes().iter())
             .chain(self.px_height.to_le_bytes().iter())
+            .chain(1_u16.to_le_bytes().iter())
             .chain(self.bits_per_pixel.to_le_bytes().iter())
             .chain(comp_bytes.to_le_bytes().iter())
             .chain(self.img_size.to_le_bytes().iter())
@@ -127,45 +143,57 @@ impl TryFrom<&[u8]> for BitmapInfoHeader {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let mut buf_4: [u8; 4] = [0; 4];
-        buf_4.copy_from_slice(&value[0..4]);
-        if u32::from_le_bytes(buf_4) != 40 {
-            return Err(format!("invalid length for header type").into());
-        };
-        let length = 40;
-
-        buf_4.copy_from_slice(&value[4..8]);
-        let px_width = i32::from_le_bytes(buf_4);
+        BitmapInfoHeader::read(&mut ByteReader::new("BMP", value))
+    }
+}
 
-        buf_4.copy_from_slice(&value[8..12]);
-        let px_height = i32::from_le_bytes(buf_4);
+impl BitmapInfoHeader {
+    /// Reads a 40-byte BITMAPINFOHEADER, starting with its length field
+    pub fn read(reader: &mut ByteReader) -> Result<Self, Error> {
+        let start = reader.position();
+        let header_len = reader.read_u32_le()?;
+        if header_len != 40 {
+            return Err(Error::malformed_at(
+                "BMP",
+                start,
+                format!("a BITMAPINFOHEADER is 40 bytes long, not {header_len}"),
+            ));
+        };
+        let header = BitmapInfoHeader::read_fields(reader)?;
+        if header.compression_type == Some(CompressionType::BI_BITFIELDS) {
+            return Err(Error::unsupported(
+                "BMP",
+                "BI_BITFIELDS masks after a BITMAPINFOHEADER",
+            ));
+        }
+        Ok(header)
+    }
 
-        let mut buf_2: [u8; 2] = [0; 2];
-        buf_2.copy_from_slice(&value[14..16]);
-        let bits_per_pixel = u16::from_le_bytes(buf_2);
+    /// Reads every field after the header length
+    fn read_fields(reader: &mut ByteReader) -> Result<Self, Error> {
+        let px_width = reader.read_i32_le()?;
+        let px_height = reader.read_i32_le()?;
+        // the number of color planes is always 1
+        reader.skip(2)?;
+        let bits_per_pixel = reader.read_u16_le()?;
 
-        buf_4.copy_from_slice(&value[16..20]);
-        let compression_type = match u32::from_le_bytes(buf_4) {
+        let compression_type = match reader.read_u32_le()? {
             0 => None,
             1 => Some(CompressionType::BI_RLE8),
             2 => Some(CompressionType::BI_RLE4),
-            _ => return Err(format!("unknown compression type").into()),
+            3 => Some(CompressionType::BI_BITFIELDS),
+            4 => return Err(Error::unsupported("BMP", "embedded JPEG data")),
+            5 => return Err(Error::unsupported("BMP", "embedded PNG data")),
+            other => {
+                return Err(reader.error(format!("unknown compression type {other}")));
+            }
         };
 
-        buf_4.copy_from_slice(&value[20..24]);
-        let img_size = u32::from_le_bytes(buf_4);
-
-        buf_4.copy_from_slice(&value[24..28]);
-        let res_horiz = i32::from_le_bytes(buf_4);
-
-        buf_4.copy_from_slice(&value[28..32]);
-        let res_vert = i32::from_le_bytes(buf_4);
-
-        buf_4.copy_from_slice(&value[32..36]);
-        let num_colors = u32::from_le_bytes(buf_4);
-
-        buf_4.copy_from_slice(&value[36..40]);
-        let num_important_colors = u32::from_le_bytes(buf_4);
+        let img_size = reader.read_u32_le()?;
+        let res_horiz = reader.read_i32_le()?;
+        let res_vert = reader.read_i32_le()?;
+        let num_colors = reader.read_u32_le()?;
+        let num_important_colors = reader.read_u32_le()?;
 
         Ok(BitmapInfoHeader {
             px_width,
@@ -180,3 +208,169 @@ impl TryFrom<&[u8]> for BitmapInfoHeader {
         })
     }
 }
+
+/// A BITMAPV4HEADER or BITMAPV5HEADER. Both add channel masks and color space details to
+/// the BITMAPINFOHEADER fields; everything past the color space type is kept as raw bytes.
+pub struct BitmapV4Header {
+    length: u32,
+    info: BitmapInfoHeader,
+    bit_masks: [u32; 4],
+    color_space_type: u32,
+    color_space: Vec<u8>,
+}
+
+impl BitmapV4Header {
+    /// The LCS_sRGB color space type
+    pub const SRGB: u32 = u32::from_be_bytes(*b"sRGB");
+
+    /// Wraps `info` in a V4 or V5 header in the sRGB color space. `version` must not be
+    /// `BmpHeaderVersion::Info`.
+    pub fn new(info: BitmapInfoHeader, version: BmpHeaderVersion, bit_masks: [u32; 4]) -> Self {
+        let length = version.length();
+        // endpoints and gamma, followed by the V5 fields
+        let mut color_space: Vec<u8> = vec![0; length as usize - 60];
+        if version == BmpHeaderVersion::V5 {
+            // LCS_GM_IMAGES, the rendering intent for photographs
+            color_space[48..52].copy_from_slice(&4_u32.to_le_bytes());
+        }
+        BitmapV4Header {
+            length,
+            info,
+            bit_masks,
+            color_space_type: BitmapV4Header::SRGB,
+            color_space,
+        }
+    }
+
+    pub fn color_space_type(&self) -> u32 {
+        self.color_space_type
+    }
+
+    /// Reads a 108-byte BITMAPV4HEADER or 124-byte BITMAPV5HEADER, starting with its length field
+    pub fn read(reader: &mut ByteReader) -> Result<Self, Error> {
+        let start = reader.position();
+        let length = reader.read_u32_le()?;
+        if length != 108 && length != 124 {
+            return Err(Error::malformed_at(
+                "BMP",
+                start,
+                format!("V4 and V5 headers are 108 or 124 bytes long, not {length}"),
+            ));
+        }
+        let info = BitmapInfoHeader::read_fields(reader)?;
+        let bit_masks = [
+            reader.read_u32_le()?,
+            reader.read_u32_le()?,
+            reader.read_u32_le()?,
+            reader.read_u32_le()?,
+        ];
+        let color_space_type = reader.read_u32_le()?;
+        let color_space = reader.read_bytes(length as usize - 60)?.to_vec();
+        Ok(BitmapV4Header {
+            length,
+            info,
+            bit_masks,
+            color_space_type,
+            color_space,
+        })
+    }
+}
+
+impl BmpInfoHeader for BitmapV4Header {
+    fn length(&self) -> u32 {
+        self.length
+    }
+
+    fn px_width(&self) -> i32 {
+        self.info.px_width
+    }
+
+    fn px_height(&self) -> i32 {
+        self.info.px_height
+    }
+
+    fn bits_per_pixel(&self) -> u16 {
+        self.info.bits_per_pixel
+    }
+
+    fn compression_type(&self) -> Option<&CompressionType> {
+        self.info.compression_type.as_ref()
+    }
+
+    fn img_size(&self) -> u32 {
+        self.info.img_size
+    }
+
+    fn res_horiz(&self) -> i32 {
+        self.info.res_horiz
+    }
+
+    fn res_vert(&self) -> i32 {
+        self.info.res_vert
+    }
+
+    fn num_colors(&self) -> u32 {
+        self.info.num_colors
+    }
+
+    fn num_important_colors(&self) -> u32 {
+        self.info.num_important_colors
+    }
+
+    fn bit_masks(&self) -> Option<[u32; 4]> {
+        Some(self.bit_masks)
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.length.to_le_bytes().to_vec();
+        bytes.extend(self.info.field_bytes());
+        for mask in self.bit_masks {
+            bytes.extend_from_slice(&mask.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.color_space_type.to_le_bytes());
+        bytes.extend_from_slice(&self.color_space);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = BitmapInfoHeader::new(
+            3,
+            -2,
+            8,
+            Some(CompressionType::BI_RLE8),
+            12,
+            2835,
+            2835,
+            4,
+            0,
+        );
+        let bytes = header.as_bytes();
+        assert_eq!(bytes.len(), 40);
+        assert_eq!(
+            BitmapInfoHeader::try_from(bytes.as_ref())
+                .unwrap()
+                .as_bytes(),
+            bytes
+        );
+
+        for version in [BmpHeaderVersion::V4, BmpHeaderVersion::V5] {
+            let header = BitmapV4Header::new(
+                BitmapInfoHeader::try_from(bytes.as_ref()).unwrap(),
+                version,
+                [0xff0000, 0xff00, 0xff, 0xff000000],
+            );
+            let v4_bytes = header.as_bytes();
+            assert_eq!(v4_bytes.len() as u32, version.length());
+            let read = BitmapV4Header::read(&mut ByteReader::new("BMP", &v4_bytes)).unwrap();
+            assert_eq!(read.as_bytes(), v4_bytes);
+            assert_eq!(read.px_height(), -2);
+            assert_eq!(read.color_space_type(), BitmapV4Header::SRGB);
+        }
+    }
+}
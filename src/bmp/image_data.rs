@@ -0,0 +1,1000 @@
+use std::io::{BufReader, Read, Write};
+
+use crate::{
+    byte_reader::{ByteReader, StreamReader},
+    compression::CompressionType,
+    image::{ColorModel, Image},
+    metadata::Metadata,
+    scanline::{read_image, ImageRows, RowLayout, RowSource, Spill, MAX_ROW_LEN, SPILL_THRESHOLD},
+    ConvertibleImage, Error, Result,
+};
+
+use super::{
+    bmp_file_header::BmpFileHeader,
+    bmp_info_header::{BitmapInfoHeader, BitmapV4Header, BmpInfoHeader},
+    options::{BmpHeaderVersion, BmpOptions},
+    Bmp,
+};
+
+/// Pixels per meter written to new files, which works out to 72 DPI
+const DEFAULT_RESOLUTION: i32 = 2835;
+
+/// Decodes the pixel data of `bmp` into an `Image`. Indexed images with an all-gray palette
+/// come out as grayscale, and the fourth byte of 32-bit pixels is read as alpha unless it
+/// is zero throughout, as it is in files that leave it reserved.
+pub fn decode(bmp: &Bmp) -> Result<Image> {
+    let header = bmp.info_header();
+    let (width, height, top_down) = dimensions(header)?;
+    let bits_per_pixel = header.bits_per_pixel();
+
+    if is_run_length_encoded(header) {
+        if top_down {
+            return Err(Error::malformed(
+                "BMP",
+                "run-length encoded images must be stored bottom-up",
+            ));
+        }
+        let indices = decode_rle(bmp.image_data(), width, height, bits_per_pixel == 4)?;
+        return apply_palette(&indices, bmp.color_table(), width, height);
+    }
+
+    let mut pixels = PixelFormat::new(header, bmp.color_table())?;
+    let rows = uncompressed_rows(bmp.image_data(), width, height, bits_per_pixel)?;
+    if rows.iter().any(|row| pixels.uses_alpha(row)) {
+        pixels.color_model = ColorModel::Rgba;
+    }
+    // the rows were checked against the data, and their length in `dimensions`
+    let row_len = width as usize * pixels.color_model.channels();
+    let mut data: Vec<u8> = vec![0; row_len * height as usize];
+    for (y, out) in data.chunks_exact_mut(row_len).enumerate() {
+        pixels.decode_row(rows[file_row(y as u32, height, top_down)], out)?;
+    }
+    Image::new(width, height, pixels.color_model, 8, data)
+}
+
+/// How a BMP's headers say its pixels are stored: a color model name as `ImageInfo` gives
+/// it, and the bits per sample or per palette index
+pub fn stored_format(bmp: &Bmp) -> Result<(&'static str, u8)> {
+    let header = bmp.info_header();
+    if is_run_length_encoded(header) {
+        return Ok(("indexed", header.bits_per_pixel() as u8));
+    }
+    let pixels = PixelFormat::new(header, bmp.color_table())?;
+    Ok(match pixels.masks() {
+        None => ("indexed", pixels.bits_per_pixel as u8),
+        Some(masks) => {
+            // 24-bit pixels have no room for the alpha byte the default masks allow for
+            let stored = masks.map(|mask| mask & (u32::MAX >> (32 - pixels.bits_per_pixel)));
+            let bits = stored
+                .iter()
+                .map(|mask| mask.count_ones())
+                .max()
+                .unwrap_or(0);
+            let color_model = if stored[3] != 0 { "rgba" } else { "rgb" };
+            (color_model, bits as u8)
+        }
+    })
+}
+
+/// Reads the size of a BMP from its headers. Whether a mask-based image uses its alpha
+/// channel isn't known until the pixels are seen, so the layout assumes it does.
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let mut reader = StreamReader::new("BMP", BufReader::new(reader));
+    let bmp = Bmp::read_headers(&mut reader)?;
+    let (width, height, _) = dimensions(bmp.info_header())?;
+    let color_model = match is_run_length_encoded(bmp.info_header()) {
+        true => ColorModel::Rgb,
+        false => {
+            let pixels = PixelFormat::new(bmp.info_header(), bmp.color_table())?;
+            match pixels.masks() {
+                Some(masks) if masks[3] != 0 => ColorModel::Rgba,
+                _ => pixels.color_model,
+            }
+        }
+    };
+    Ok(RowLayout {
+        width,
+        height,
+        color_model,
+        bit_depth: 8,
+    })
+}
+
+/// Reads a BMP from `reader` a row at a time. The pixel data is moved into a `Spill` first,
+/// since bottom-up files store the top row last and 32-bit files have to be checked for
+/// alpha before the first row can be handed out. Run-length encoded files are decoded whole.
+pub fn decode_rows(reader: &mut dyn Read) -> Result<Box<dyn RowSource + '_>> {
+    let mut reader = StreamReader::new("BMP", BufReader::new(reader));
+    let mut bmp = Bmp::read_headers(&mut reader)?;
+    if is_run_length_encoded(bmp.info_header()) {
+        bmp.data = reader.read_rest()?;
+        return Ok(Box::new(ImageRows::new(decode(&bmp)?)));
+    }
+
+    let (width, height, top_down) = dimensions(bmp.info_header())?;
+    let mut pixels = PixelFormat::new(bmp.info_header(), bmp.color_table())?;
+    let stride = row_stride(width, pixels.bits_per_pixel);
+    let mut spill = Spill::new(SPILL_THRESHOLD);
+    spill.append_from(&mut reader)?;
+    if (stride as u64).saturating_mul(height as u64) > spill.len() {
+        return Err(too_little_data(spill.len(), width, height));
+    }
+    let mut buffer: Vec<u8> = vec![0; stride];
+    if pixels.masks().is_some_and(|masks| masks[3] != 0) {
+        for y in 0..height as u64 {
+            spill.read_at(y * stride as u64, &mut buffer)?;
+            if pixels.uses_alpha(&buffer) {
+                pixels.color_model = ColorModel::Rgba;
+                break;
+            }
+        }
+    }
+    Ok(Box::new(BmpRows {
+        metadata: Metadata::default(),
+        pixels,
+        spill,
+        buffer,
+        height,
+        top_down,
+        y: 0,
+    }))
+}
+
+/// Encodes `image` as a new `Bmp` laid out as `options` asks. Without run-length encoding,
+/// grayscale images get an 8-bit gray palette, color images are stored as 24-bit pixels and
+/// anything with alpha as 32-bit pixels. BMP has no room for 16-bit samples, so those
+/// images are refused; `Image::reduce_depth` makes them fit.
+pub fn encode(image: &Image, options: &BmpOptions) -> Result<Bmp> {
+    options.validate()?;
+    image.layout().require_8_bit("BMP")?;
+    let (bits_per_pixel, compression_type, color_table, data) = match options.rle {
+        true => encode_rle_pixels(image)?,
+        false => encode_pixels(image, options.top_down),
+    };
+    let mut bmp = with_headers(
+        image.layout(),
+        bits_per_pixel,
+        compression_type,
+        color_table,
+        data.len(),
+        options,
+    )?;
+    bmp.data = data;
+    Ok(bmp)
+}
+
+/// Encodes the rows of `source` as a BMP laid out as `options` asks, writing it to `writer`
+/// as it goes. Top-down files are written straight through, while bottom-up files keep their
+/// rows in a `Spill` until the last one, which goes first, has arrived. Run-length encoding
+/// needs the whole image, so those rows are gathered up first.
+pub fn encode_rows(
+    source: &mut dyn RowSource,
+    options: &BmpOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    options.validate()?;
+    source.layout().require_8_bit("BMP")?;
+    if options.rle {
+        return encode(&read_image(source)?, options)?.to_writer(writer);
+    }
+
+    let layout = source.layout();
+    let (bits_per_pixel, color_table) = pixel_layout(layout.color_model);
+    let stride = row_stride(layout.width, bits_per_pixel);
+    let headers = with_headers(
+        layout,
+        bits_per_pixel,
+        None,
+        color_table,
+        stride.saturating_mul(layout.height as usize),
+        options,
+    )?;
+
+    let mut row: Vec<u8> = vec![0; layout.row_len()];
+    let mut encoded: Vec<u8> = Vec::with_capacity(stride);
+    let mut next_row = |source: &mut dyn RowSource, encoded: &mut Vec<u8>| -> Result<()> {
+        source.read_row(&mut row)?;
+        encoded.clear();
+        encode_row(&layout, &row, bits_per_pixel, encoded);
+        Ok(())
+    };
+    if options.top_down {
+        headers.to_writer(writer)?;
+        for _ in 0..layout.height {
+            next_row(source, &mut encoded)?;
+            writer.write_all(&encoded)?;
+        }
+        return Ok(());
+    }
+
+    let mut spill = Spill::new(SPILL_THRESHOLD);
+    for _ in 0..layout.height {
+        next_row(source, &mut encoded)?;
+        spill.append(&encoded)?;
+    }
+    headers.to_writer(writer)?;
+    for y in (0..layout.height as u64).rev() {
+        spill.read_at(y * stride as u64, &mut encoded)?;
+        writer.write_all(&encoded)?;
+    }
+    Ok(())
+}
+
+/// Builds a `Bmp` with headers describing pixel data laid out as given, but no pixel data
+fn with_headers(
+    layout: RowLayout,
+    bits_per_pixel: u16,
+    compression_type: Option<CompressionType>,
+    color_table: Option<Vec<u8>>,
+    data_len: usize,
+    options: &BmpOptions,
+) -> Result<Bmp> {
+    let too_large = || {
+        Error::invalid_argument(format!(
+            "a {}x{} image is too large for a BMP file",
+            layout.width, layout.height
+        ))
+    };
+    let px_width = i32::try_from(layout.width).map_err(|_| too_large())?;
+    let px_height = i32::try_from(layout.height).map_err(|_| too_large())?;
+    // a negative height is how BMP marks rows stored from the top
+    let px_height = if options.top_down {
+        -px_height
+    } else {
+        px_height
+    };
+    let data_len = u32::try_from(data_len).map_err(|_| too_large())?;
+    let table_len = color_table.as_ref().map_or(0, |table| table.len() as u32);
+
+    let mut info_header = BitmapInfoHeader::new(
+        px_width,
+        px_height,
+        bits_per_pixel,
+        compression_type,
+        data_len,
+        DEFAULT_RESOLUTION,
+        DEFAULT_RESOLUTION,
+        table_len / 4,
+        0,
+    );
+    let info_header: Box<dyn BmpInfoHeader> = match options.header_version {
+        BmpHeaderVersion::Info => Box::new(info_header),
+        version => {
+            // newer headers spell out where the alpha channel is instead of leaving it implied
+            let bit_masks = match bits_per_pixel {
+                32 => {
+                    info_header = BitmapInfoHeader::new(
+                        px_width,
+                        px_height,
+                        bits_per_pixel,
+                        Some(CompressionType::BI_BITFIELDS),
+                        data_len,
+                        DEFAULT_RESOLUTION,
+                        DEFAULT_RESOLUTION,
+                        0,
+                        0,
+                    );
+                    [0xff0000, 0xff00, 0xff, 0xff000000]
+                }
+                _ => [0; 4],
+            };
+            Box::new(BitmapV4Header::new(info_header, version, bit_masks))
+        }
+    };
+
+    let img_offset = BmpFileHeader::LENGTH as u32 + info_header.length() + table_len;
+    let length = img_offset.checked_add(data_len).ok_or_else(too_large)?;
+    Ok(Bmp {
+        file_header: BmpFileHeader::new(length, [0; 4], img_offset),
+        info_header,
+        color_table,
+        data: Vec::new(),
+    })
+}
+
+/// Bits per pixel, compression, color table and pixel data of an encoded image
+type EncodedPixels = (u16, Option<CompressionType>, Option<Vec<u8>>, Vec<u8>);
+
+/// Stores each pixel in full, apart from grayscale which goes through a palette
+fn encode_pixels(image: &Image, top_down: bool) -> EncodedPixels {
+    let layout = image.layout();
+    let (bits_per_pixel, color_table) = pixel_layout(layout.color_model);
+    let stride = row_stride(image.width(), bits_per_pixel);
+    let mut data: Vec<u8> = Vec::with_capacity(stride * image.height() as usize);
+    for idx in 0..image.height() {
+        let y = file_row(idx, image.height(), top_down) as u32;
+        encode_row(&layout, image.row(y), bits_per_pixel, &mut data);
+    }
+    (bits_per_pixel, None, color_table, data)
+}
+
+/// Bits per pixel and color table that uncompressed pixels of `color_model` are stored with
+fn pixel_layout(color_model: ColorModel) -> (u16, Option<Vec<u8>>) {
+    match color_model {
+        ColorModel::Gray => (
+            8,
+            Some(
+                (0..=255)
+                    .flat_map(|value| [value, value, value, 0])
+                    .collect(),
+            ),
+        ),
+        ColorModel::Rgb => (24, None),
+        ColorModel::GrayAlpha | ColorModel::Rgba => (32, None),
+    }
+}
+
+/// Appends `row` to `data` as uncompressed BMP pixels, padded out to the row stride
+fn encode_row(layout: &RowLayout, row: &[u8], bits_per_pixel: u16, data: &mut Vec<u8>) {
+    let row_start = data.len();
+    for x in 0..layout.width as usize {
+        let [red, green, blue, alpha] = layout.rgba8(row, x);
+        match bits_per_pixel {
+            8 => data.push(red),
+            24 => data.extend_from_slice(&[blue, green, red]),
+            _ => data.extend_from_slice(&[blue, green, red, alpha]),
+        }
+    }
+    data.resize(row_start + row_stride(layout.width, bits_per_pixel), 0);
+}
+
+/// Produces the rows of an uncompressed BMP from its pixel data, held in a `Spill`
+struct BmpRows {
+    metadata: Metadata,
+    pixels: PixelFormat,
+    spill: Spill,
+    buffer: Vec<u8>,
+    height: u32,
+    top_down: bool,
+    y: u32,
+}
+
+impl RowSource for BmpRows {
+    fn layout(&self) -> RowLayout {
+        RowLayout {
+            width: self.pixels.width,
+            height: self.height,
+            color_model: self.pixels.color_model,
+            bit_depth: 8,
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        if self.y >= self.height {
+            return Err(Error::invalid_argument(
+                "read past the last row of the image",
+            ));
+        }
+        let offset = file_row(self.y, self.height, self.top_down) as u64 * self.buffer.len() as u64;
+        self.spill.read_at(offset, &mut self.buffer)?;
+        self.pixels.decode_row(&self.buffer, row)?;
+        self.y += 1;
+        Ok(())
+    }
+}
+
+/// Where the pixels of an uncompressed BMP get their colors from
+enum PixelSource {
+    /// RGB colors from the color table, looked up by index
+    Palette(Vec<[u8; 3]>),
+    /// Red, green, blue and alpha masks over each little-endian pixel value
+    Masks([u32; 4]),
+}
+
+/// Turns rows of uncompressed BMP pixels into `Image` rows
+struct PixelFormat {
+    width: u32,
+    bits_per_pixel: u16,
+    source: PixelSource,
+    /// Starts out without alpha; switched to RGBA once some pixel is found to use it
+    color_model: ColorModel,
+}
+
+impl PixelFormat {
+    fn new(header: &dyn BmpInfoHeader, color_table: Option<&Vec<u8>>) -> Result<Self> {
+        let (width, _, _) = dimensions(header)?;
+        let bits_per_pixel = header.bits_per_pixel();
+        let compression = header.compression_type().copied();
+        let (source, color_model) = match (bits_per_pixel, compression) {
+            (1 | 4 | 8, None) => {
+                let palette = read_palette(color_table);
+                let color_model = match is_gray(&palette) {
+                    true => ColorModel::Gray,
+                    false => ColorModel::Rgb,
+                };
+                (PixelSource::Palette(palette), color_model)
+            }
+            (16 | 24 | 32, None) | (16 | 32, Some(CompressionType::BI_BITFIELDS)) => {
+                let bit_masks = match compression {
+                    Some(_) => header.bit_masks().ok_or_else(|| {
+                        Error::unsupported("BMP", "BI_BITFIELDS without masks in the header")
+                    })?,
+                    None if bits_per_pixel == 16 => [0x7c00, 0x03e0, 0x001f, 0],
+                    None => [0xff0000, 0xff00, 0xff, 0xff000000],
+                };
+                (PixelSource::Masks(bit_masks), ColorModel::Rgb)
+            }
+            (1 | 4 | 8 | 16 | 24 | 32, Some(compression)) => {
+                return Err(Error::unsupported(
+                    "BMP",
+                    format!("{compression:?} compression of {bits_per_pixel}-bit images"),
+                ))
+            }
+            (other, _) => {
+                return Err(Error::malformed(
+                    "BMP",
+                    format!("{other} bits per pixel is not a valid BMP depth"),
+                ))
+            }
+        };
+        Ok(PixelFormat {
+            width,
+            bits_per_pixel,
+            source,
+            color_model,
+        })
+    }
+
+    fn masks(&self) -> Option<[u32; 4]> {
+        match self.source {
+            PixelSource::Masks(masks) => Some(masks),
+            PixelSource::Palette(_) => None,
+        }
+    }
+
+    /// Whether any pixel of `row` sets bits of the alpha mask. Files that leave the alpha
+    /// byte reserved fill it with zeroes.
+    fn uses_alpha(&self, row: &[u8]) -> bool {
+        match self.masks() {
+            Some(masks) if masks[3] != 0 => {
+                (0..self.width as usize).any(|x| self.pixel(row, x) & masks[3] != 0)
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads pixel `x` of `row` as a little-endian value
+    fn pixel(&self, row: &[u8], x: usize) -> u32 {
+        let bytes_per_pixel = self.bits_per_pixel as usize / 8;
+        let mut value = [0; 4];
+        value[..bytes_per_pixel]
+            .copy_from_slice(&row[x * bytes_per_pixel..(x + 1) * bytes_per_pixel]);
+        u32::from_le_bytes(value)
+    }
+
+    /// Decodes one padded row from the file into `out`, which holds one row of 8-bit
+    /// samples in `color_model`
+    fn decode_row(&self, row: &[u8], out: &mut [u8]) -> Result<()> {
+        let channels = self.color_model.channels();
+        for (x, out) in out.chunks_exact_mut(channels).enumerate() {
+            match &self.source {
+                PixelSource::Palette(palette) => {
+                    let index = unpack(row, x, self.bits_per_pixel);
+                    let color = palette
+                        .get(index as usize)
+                        .ok_or_else(|| index_out_of_range(index, palette.len()))?;
+                    out.copy_from_slice(&color[..channels]);
+                }
+                PixelSource::Masks(masks) => {
+                    let pixel = self.pixel(row, x);
+                    for (sample, &mask) in out.iter_mut().zip(masks) {
+                        *sample = masked_channel(pixel, mask);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stores the image through a palette with BI_RLE4 or BI_RLE8 compression
+fn encode_rle_pixels(image: &Image) -> Result<EncodedPixels> {
+    let indexed = image.to_indexed(256).ok_or_else(|| {
+        Error::lossy("run-length encoded BMPs hold at most 256 colors, and the image has more")
+    })?;
+    if indexed.palette.iter().any(|color| color[3] != 255) {
+        return Err(Error::lossy(
+            "run-length encoded BMPs can't hold transparency",
+        ));
+    }
+    let four_bit = indexed.palette.len() <= 16;
+    let color_table: Vec<u8> = indexed
+        .palette
+        .iter()
+        .flat_map(|&[red, green, blue, _]| [blue, green, red, 0])
+        .collect();
+    let data = encode_rle(
+        &indexed.indices,
+        image.width() as usize,
+        image.height() as usize,
+        four_bit,
+    );
+    Ok(match four_bit {
+        true => (4, Some(CompressionType::BI_RLE4), Some(color_table), data),
+        false => (8, Some(CompressionType::BI_RLE8), Some(color_table), data),
+    })
+}
+
+/// Bytes taken up by one row of pixels, which BMP pads to a multiple of 4
+pub(crate) fn row_stride(width: u32, bits_per_pixel: u16) -> usize {
+    (width as usize * bits_per_pixel as usize).div_ceil(32) * 4
+}
+
+/// Width, height and row order of the image `header` describes
+fn dimensions(header: &dyn BmpInfoHeader) -> Result<(u32, u32, bool)> {
+    let (width, height) = (header.px_width(), header.px_height());
+    if width <= 0 || height == 0 || height == i32::MIN {
+        return Err(Error::malformed(
+            "BMP",
+            format!("{width}x{height} is not a valid image size"),
+        ));
+    }
+    // decoded rows take up to 4 bytes a pixel
+    if width as usize * 4 > MAX_ROW_LEN {
+        return Err(Error::malformed(
+            "BMP",
+            format!("rows {width} pixels wide are too long to read"),
+        ));
+    }
+    Ok((width as u32, height.unsigned_abs(), height < 0))
+}
+
+fn is_run_length_encoded(header: &dyn BmpInfoHeader) -> bool {
+    matches!(
+        (header.bits_per_pixel(), header.compression_type()),
+        (8, Some(CompressionType::BI_RLE8)) | (4, Some(CompressionType::BI_RLE4))
+    )
+}
+
+fn too_little_data(len: u64, width: u32, height: u32) -> Error {
+    Error::malformed(
+        "BMP",
+        format!("image data holds {len} bytes, which is too little for a {width}x{height} image"),
+    )
+}
+
+/// Index into the rows of the file for row `y` counted from the top of the image
+fn file_row(y: u32, height: u32, top_down: bool) -> usize {
+    match top_down {
+        true => y as usize,
+        false => (height - 1 - y) as usize,
+    }
+}
+
+/// Splits uncompressed pixel data into padded rows, in the order the file stores them
+fn uncompressed_rows(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bits_per_pixel: u16,
+) -> Result<Vec<&[u8]>> {
+    let stride = row_stride(width, bits_per_pixel);
+    let mut reader = ByteReader::new("BMP image data", data);
+    // checked before collecting the rows, so a forged size can't exhaust memory
+    match stride.checked_mul(height as usize) {
+        Some(len) if len <= data.len() => (0..height).map(|_| reader.read_bytes(stride)).collect(),
+        _ => Err(too_little_data(data.len() as u64, width, height)),
+    }
+}
+
+/// Reads the palette index of pixel `x` from a row of 1, 4 or 8-bit indices
+fn unpack(row: &[u8], x: usize, bits_per_pixel: u16) -> u8 {
+    let bits = bits_per_pixel as usize;
+    let byte = row[x * bits / 8];
+    let shift = 8 - bits - (x * bits % 8);
+    (byte >> shift) & (0xff >> (8 - bits))
+}
+
+/// Expands BI_RLE8 or BI_RLE4 data into one palette index per pixel, top row first.
+/// Pixels the data skips over are left at index 0, but data too short to fill the image
+/// with runs is rejected, so a forged size can't set aside more than the data could cover.
+fn decode_rle(data: &[u8], width: u32, height: u32, four_bit: bool) -> Result<Vec<u8>> {
+    // each 2-byte run covers at most 255 pixels
+    if width as u64 * height as u64 > (data.len() / 2) as u64 * 255 {
+        return Err(too_little_data(data.len() as u64, width, height));
+    }
+    let (width, height) = (width as usize, height as usize);
+    let mut indices: Vec<u8> = vec![0; width * height];
+    let mut put = |x: usize, y: usize, index: u8| {
+        // runs that spill past the edges are clipped rather than rejected
+        if x < width && y < height {
+            indices[(height - 1 - y) * width + x] = index;
+        }
+    };
+    let nibble = |byte: u8, i: usize| {
+        if i.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        }
+    };
+
+    let mut reader = ByteReader::new("BMP image data", data);
+    let (mut x, mut y) = (0, 0);
+    // a missing end-of-bitmap marker is common enough to tolerate
+    while !reader.is_empty() && y < height {
+        let count = reader.read_u8()? as usize;
+        let value = reader.read_u8()?;
+        if count > 0 {
+            for i in 0..count {
+                put(x, y, if four_bit { nibble(value, i) } else { value });
+                x += 1;
+            }
+            continue;
+        }
+        match value {
+            // end of line
+            0 => {
+                x = 0;
+                y += 1;
+            }
+            // end of bitmap
+            1 => break,
+            // move the cursor right and up
+            2 => {
+                x += reader.read_u8()? as usize;
+                y += reader.read_u8()? as usize;
+            }
+            // a literal run of `count` indices, padded to a 2-byte boundary
+            count => {
+                let count = count as usize;
+                let len = if four_bit { count.div_ceil(2) } else { count };
+                let bytes = reader.read_bytes(len)?;
+                for i in 0..count {
+                    put(
+                        x,
+                        y,
+                        if four_bit {
+                            nibble(bytes[i / 2], i)
+                        } else {
+                            bytes[i]
+                        },
+                    );
+                    x += 1;
+                }
+                if !len.is_multiple_of(2) {
+                    reader.skip(1)?;
+                }
+            }
+        }
+    }
+    Ok(indices)
+}
+
+/// Compresses palette indices, given top row first, as BI_RLE8 or BI_RLE4 data. Runs of
+/// three or more repeats are stored as runs and everything between them as literals.
+fn encode_rle(indices: &[u8], width: usize, height: usize, four_bit: bool) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    for y in (0..height).rev() {
+        let row = &indices[y * width..(y + 1) * width];
+        let run_len = |x: usize| {
+            row[x..]
+                .iter()
+                .take(255)
+                .take_while(|&&index| index == row[x])
+                .count()
+        };
+        let mut x = 0;
+        while x < width {
+            let run = run_len(x);
+            if run >= 3 {
+                let value = if four_bit {
+                    row[x] << 4 | row[x]
+                } else {
+                    row[x]
+                };
+                data.extend_from_slice(&[run as u8, value]);
+                x += run;
+                continue;
+            }
+            let mut end = x;
+            while end < width && end - x < 255 && run_len(end) < 3 {
+                end += 1;
+            }
+            let literal = &row[x..end];
+            if literal.len() < 3 {
+                // literal runs must hold at least 3 pixels, so short ones become runs of 1
+                for &index in literal {
+                    data.extend_from_slice(&[1, if four_bit { index << 4 } else { index }]);
+                }
+            } else {
+                data.extend_from_slice(&[0, literal.len() as u8]);
+                let start = data.len();
+                match four_bit {
+                    true => data.extend(
+                        literal
+                            .chunks(2)
+                            .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0)),
+                    ),
+                    false => data.extend_from_slice(literal),
+                }
+                if !(data.len() - start).is_multiple_of(2) {
+                    data.push(0);
+                }
+            }
+            x = end;
+        }
+        // end of line, or end of bitmap after the top row
+        data.extend_from_slice(&[0, if y == 0 { 1 } else { 0 }]);
+    }
+    if height == 0 {
+        data.extend_from_slice(&[0, 1]);
+    }
+    data
+}
+
+/// Scales the channel `mask` selects from `pixel` to 8 bits
+fn masked_channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = (mask >> mask.trailing_zeros()) as u64;
+    let value = ((pixel & mask) >> mask.trailing_zeros()) as u64;
+    ((value * 255 + max / 2) / max) as u8
+}
+
+/// Reads the BMP color table, which stores colors as BGR plus a reserved byte
+fn read_palette(color_table: Option<&Vec<u8>>) -> Vec<[u8; 3]> {
+    color_table
+        .map(|table| {
+            table
+                .chunks_exact(4)
+                .map(|entry| [entry[2], entry[1], entry[0]])
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn is_gray(palette: &[[u8; 3]]) -> bool {
+    palette
+        .iter()
+        .all(|[red, green, blue]| red == green && green == blue)
+}
+
+fn index_out_of_range(index: u8, palette_len: usize) -> Error {
+    Error::malformed(
+        "BMP",
+        format!("palette index {index} is past the end of the {palette_len}-color table"),
+    )
+}
+
+/// Looks up each palette index in the BMP color table
+fn apply_palette(
+    indices: &[u8],
+    color_table: Option<&Vec<u8>>,
+    width: u32,
+    height: u32,
+) -> Result<Image> {
+    let palette = read_palette(color_table);
+    if let Some(&index) = indices
+        .iter()
+        .find(|&&index| index as usize >= palette.len())
+    {
+        return Err(index_out_of_range(index, palette.len()));
+    }
+
+    let (color_model, data): (ColorModel, Vec<u8>) = if is_gray(&palette) {
+        (
+            ColorModel::Gray,
+            indices
+                .iter()
+                .map(|&index| palette[index as usize][0])
+                .collect(),
+        )
+    } else {
+        (
+            ColorModel::Rgb,
+            indices
+                .iter()
+                .flat_map(|&index| palette[index as usize])
+                .collect(),
+        )
+    };
+    Image::new(width, height, color_model, 8, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(image: &Image) -> Image {
+        round_trip_with(image, &BmpOptions::default())
+    }
+
+    fn round_trip_with(image: &Image, options: &BmpOptions) -> Image {
+        let bmp = encode(image, options).unwrap();
+        decode(&Bmp::try_from(bmp.to_bytes().as_ref()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // 3 pixels wide, so every row needs padding
+        let rgb = Image::new(3, 2, ColorModel::Rgb, 8, (0..18).collect()).unwrap();
+        let decoded = round_trip(&rgb);
+        assert_eq!(decoded.color_model(), ColorModel::Rgb);
+        assert_eq!(decoded.data(), rgb.data());
+
+        let gray = Image::new(3, 2, ColorModel::Gray, 8, vec![0, 50, 100, 150, 200, 250]).unwrap();
+        let decoded = round_trip(&gray);
+        assert_eq!(decoded.color_model(), ColorModel::Gray);
+        assert_eq!(decoded.data(), gray.data());
+
+        let rgba = Image::new(2, 1, ColorModel::Rgba, 8, vec![1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(round_trip(&rgba).data(), rgba.data());
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let options = BmpOptions {
+            rle: true,
+            ..BmpOptions::default()
+        };
+        // long runs, short runs and literals, in both 4 and 8-bit form
+        for colors in [5, 40] {
+            let data: Vec<u8> = (0..300_u32)
+                .map(|idx| {
+                    if idx < 100 {
+                        0
+                    } else {
+                        (idx % colors * 3) as u8
+                    }
+                })
+                .flat_map(|value| [value, 255 - value, 7])
+                .collect();
+            let image = Image::new(30, 10, ColorModel::Rgb, 8, data).unwrap();
+            let bmp = encode(&image, &options).unwrap();
+            assert!(bmp.image_data().len() < 300);
+            assert_eq!(round_trip_with(&image, &options).data(), image.data());
+        }
+
+        let transparent = Image::new(1, 1, ColorModel::Rgba, 8, vec![0, 0, 0, 0]).unwrap();
+        assert!(matches!(
+            encode(&transparent, &options),
+            Err(Error::LossyConversion(_))
+        ));
+    }
+
+    #[test]
+    fn test_newer_headers() {
+        let image = Image::new(2, 2, ColorModel::Rgba, 8, (0..16).collect()).unwrap();
+        for header_version in [BmpHeaderVersion::V4, BmpHeaderVersion::V5] {
+            let options = BmpOptions {
+                header_version,
+                ..BmpOptions::default()
+            };
+            let bmp = encode(&image, &options).unwrap();
+            assert_eq!(bmp.info_header().length(), header_version.length());
+            assert_eq!(
+                bmp.info_header().compression_type(),
+                Some(&CompressionType::BI_BITFIELDS)
+            );
+            assert_eq!(round_trip_with(&image, &options).data(), image.data());
+        }
+    }
+
+    #[test]
+    fn test_refuses_16_bit_samples() {
+        let mut image = Image::new(
+            1,
+            1,
+            ColorModel::Rgb,
+            16,
+            vec![0x12, 0x34, 0xab, 0xcd, 0xff, 0],
+        )
+        .unwrap();
+        assert!(matches!(
+            encode(&image, &BmpOptions::default()),
+            Err(Error::LossyConversion(_))
+        ));
+        let mut bytes: Vec<u8> = Vec::new();
+        let rows = encode_rows(
+            &mut ImageRows::new(image.clone()),
+            &BmpOptions::default(),
+            &mut bytes,
+        );
+        assert!(matches!(rows, Err(Error::LossyConversion(_))));
+        image.reduce_depth();
+        assert_eq!(round_trip(&image).data(), &[0x12, 0xab, 0xff]);
+    }
+
+    #[test]
+    fn test_decode_1_bit_top_down() {
+        let mut bmp = encode(
+            &Image::new(10, 2, ColorModel::Gray, 8, vec![0; 20]).unwrap(),
+            &BmpOptions::default(),
+        )
+        .unwrap();
+        bmp.info_header = Box::new(BitmapInfoHeader::new(10, -2, 1, None, 8, 0, 0, 2, 0));
+        bmp.color_table = Some(vec![0, 0, 0, 0, 0, 0, 255, 0]);
+        bmp.data = vec![0b1000_0000, 0b0100_0000, 0, 0, 0, 0, 0, 0];
+        let image = decode(&bmp).unwrap();
+        assert_eq!(image.color_model(), ColorModel::Rgb);
+        assert_eq!(&image.row(0)[..6], &[255, 0, 0, 0, 0, 0]);
+        assert_eq!(&image.row(0)[24..30], &[0, 0, 0, 255, 0, 0]);
+        assert_eq!(image.row(1), &[0; 30]);
+    }
+
+    #[test]
+    fn test_decode_rle8() {
+        let mut bmp = encode(
+            &Image::new(4, 2, ColorModel::Gray, 8, vec![0; 8]).unwrap(),
+            &BmpOptions::default(),
+        )
+        .unwrap();
+        bmp.info_header = Box::new(BitmapInfoHeader::new(
+            4,
+            2,
+            8,
+            Some(CompressionType::BI_RLE8),
+            0,
+            0,
+            0,
+            0,
+            0,
+        ));
+        #[rustfmt::skip]
+        let data = vec![
+            3, 7,           // three pixels of index 7
+            0, 0,           // end of line
+            0, 3, 1, 2, 3,  // literal run, padded
+            0,
+            0, 1,           // end of bitmap
+        ];
+        bmp.data = data;
+        let image = decode(&bmp).unwrap();
+        assert_eq!(image.data(), &[1, 2, 3, 0, 7, 7, 7, 0]);
+    }
+
+    #[test]
+    fn test_forged_rle_size() {
+        let mut bmp = encode(
+            &Image::new(4, 2, ColorModel::Gray, 8, vec![0; 8]).unwrap(),
+            &BmpOptions::default(),
+        )
+        .unwrap();
+        bmp.data = [[255, 0x12].repeat(60), vec![0, 1]].concat();
+        for (width, height) in [(0x7fffffff, 0x7fffffff), (1000, 1000)] {
+            bmp.info_header = Box::new(BitmapInfoHeader::new(
+                width,
+                height,
+                4,
+                Some(CompressionType::BI_RLE4),
+                0,
+                0,
+                0,
+                0,
+                0,
+            ));
+            assert!(matches!(decode(&bmp), Err(Error::Malformed { .. })));
+            let bytes = bmp.to_bytes();
+            assert!(matches!(
+                decode_rows(&mut bytes.as_slice()).map(|_| ()),
+                Err(Error::Malformed { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_data() {
+        let mut bmp = encode(
+            &Image::new(4, 4, ColorModel::Rgb, 8, vec![0; 48]).unwrap(),
+            &BmpOptions::default(),
+        )
+        .unwrap();
+        bmp.data.truncate(40);
+        assert!(decode(&bmp).is_err());
+
+        let mut bmp = encode(
+            &Image::new(1, 1, ColorModel::Gray, 8, vec![0]).unwrap(),
+            &BmpOptions::default(),
+        )
+        .unwrap();
+        bmp.color_table = Some(vec![0; 4]);
+        bmp.data = vec![1, 0, 0, 0];
+        assert!(decode(&bmp).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{Error, Result};
+
+/// Which info header new BMP files are written with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BmpHeaderVersion {
+    /// The 40-byte BITMAPINFOHEADER every reader understands
+    #[default]
+    Info,
+    /// The 108-byte BITMAPV4HEADER, which adds channel masks and a color space
+    V4,
+    /// The 124-byte BITMAPV5HEADER, which adds a rendering intent and room for an ICC profile
+    V5,
+}
+
+impl BmpHeaderVersion {
+    /// Length of the header, which is also how files tell header versions apart
+    pub fn length(&self) -> u32 {
+        match self {
+            BmpHeaderVersion::Info => 40,
+            BmpHeaderVersion::V4 => 108,
+            BmpHeaderVersion::V5 => 124,
+        }
+    }
+}
+
+/// How BMP output is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BmpOptions {
+    pub header_version: BmpHeaderVersion,
+    /// Run-length encode the pixels, which needs an opaque image with at most 256 colors.
+    /// Images with up to 16 colors use BI_RLE4 and the rest BI_RLE8.
+    pub rle: bool,
+    /// Store the top row first, marked by a negative height. Readers can then stream the
+    /// file, but some older software only understands the usual bottom-up order.
+    pub top_down: bool,
+}
+
+impl BmpOptions {
+    /// Checks for settings that contradict each other
+    pub fn validate(&self) -> Result<()> {
+        if self.rle && self.top_down {
+            return Err(Error::invalid_argument(
+                "run-length encoded BMPs have to be stored bottom-up",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::{self, BufRead, Read};
+
+use crate::{Error, Result};
+
+/// A cursor over untrusted bytes. Every read is bounds-checked, so malformed input turns
+/// into an error naming the format and the offset where parsing went wrong rather than a panic.
+#[derive(Clone, Debug)]
+pub struct ByteReader<'a> {
+    format: &'static str,
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(format: &'static str, bytes: &'a [u8]) -> Self {
+        ByteReader {
+            format,
+            bytes,
+            position: 0,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Builds an error for malformed data at the current position
+    pub fn error(&self, message: impl Into<String>) -> Error {
+        Error::malformed_at(self.format, self.position, message)
+    }
+
+    /// Moves the cursor to an absolute offset, which may be the very end of the data
+    pub fn seek(&mut self, position: usize) -> Result<()> {
+        if position > self.bytes.len() {
+            return Err(self.error(format!(
+                "offset {position} is past the end of the {} bytes available",
+                self.bytes.len()
+            )));
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    /// Returns a new cursor over the same data, starting at `position`
+    pub fn at(&self, position: usize) -> Result<ByteReader<'a>> {
+        let mut reader = self.clone();
+        reader.seek(position)?;
+        Ok(reader)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    pub fn peek_bytes(&self, len: usize) -> Result<&'a [u8]> {
+        match self.position.checked_add(len) {
+            Some(end) if end <= self.bytes.len() => Ok(&self.bytes[self.position..end]),
+            _ => Err(self.error(format!(
+                "needed {len} bytes but only {} remain",
+                self.remaining()
+            ))),
+        }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.peek_bytes(len)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Reads everything left in the data
+    pub fn read_rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.position..];
+        self.position = self.bytes.len();
+        rest
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf: [u8; N] = [0; N];
+        buf.copy_from_slice(self.read_bytes(N)?);
+        Ok(buf)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u16_le(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u16_be(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32_le(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32_be(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_i32_le(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+}
+
+/// The streaming counterpart to `ByteReader`, for data that arrives through `BufRead` rather
+/// than sitting in memory. It keeps count of how far it has read so errors carry the same
+/// offsets, and never allocates more than the data that has actually arrived.
+pub struct StreamReader<R> {
+    format: &'static str,
+    inner: R,
+    position: usize,
+}
+
+impl<R: BufRead> StreamReader<R> {
+    pub fn new(format: &'static str, inner: R) -> Self {
+        StreamReader {
+            format,
+            inner,
+            position: 0,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns true once the underlying reader has no more data
+    pub fn is_empty(&mut self) -> Result<bool> {
+        Ok(self.inner.fill_buf()?.is_empty())
+    }
+
+    /// Builds an error for malformed data at the current position
+    pub fn error(&self, message: impl Into<String>) -> Error {
+        Error::malformed_at(self.format, self.position, message)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.inner).take(len as u64), &mut io::sink())?;
+        self.advance(len, skipped as usize)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        // read through `take` so a bogus length can't reserve memory ahead of the data
+        let mut bytes: Vec<u8> = Vec::new();
+        let read = (&mut self.inner).take(len as u64).read_to_end(&mut bytes)?;
+        self.advance(len, read)?;
+        Ok(bytes)
+    }
+
+    /// Reads everything left in the data
+    pub fn read_rest(&mut self) -> Result<Vec<u8>> {
+        let mut bytes: Vec<u8> = Vec::new();
+        self.position += self.inner.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Returns the next byte without moving past it, or `None` at the end of the data
+    pub fn peek_u8(&mut self) -> Result<Option<u8>> {
+        Ok(self.inner.fill_buf()?.first().copied())
+    }
+
+    /// Fills all of `buf`, failing if the data runs out first
+    pub fn fill(&mut self, buf: &mut [u8]) -> Result<()> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(len) => read += len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.advance(buf.len(), read)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf: [u8; N] = [0; N];
+        self.fill(&mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u16_le(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u16_be(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32_le(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32_be(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    /// Moves past `read` bytes, failing if that's short of the `wanted` length
+    fn advance(&mut self, wanted: usize, read: usize) -> Result<()> {
+        if read < wanted {
+            return Err(self.error(format!("needed {wanted} bytes but only {read} remain")));
+        }
+        self.position += read;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_advance() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7];
+        let mut reader = ByteReader::new("test", &bytes);
+        assert_eq!(reader.read_u8().unwrap(), 1);
+        assert_eq!(reader.read_u16_be().unwrap(), 0x0203);
+        assert_eq!(reader.read_u32_le().unwrap(), 0x07060504);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_out_of_bounds_reports_offset() {
+        let bytes = [1, 2, 3];
+        let mut reader = ByteReader::new("test", &bytes);
+        reader.skip(2).unwrap();
+        assert!(matches!(
+            reader.read_u32_be(),
+            Err(Error::Malformed {
+                format: "test",
+                offset: Some(2),
+                ..
+            })
+        ));
+        assert_eq!(reader.position(), 2);
+
+        assert!(reader.read_bytes(usize::MAX).is_err());
+        assert!(reader.seek(4).is_err());
+        assert!(reader.at(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_stream_reader() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7];
+        let mut reader = StreamReader::new("test", &bytes[..]);
+        assert_eq!(reader.read_u8().unwrap(), 1);
+        assert_eq!(reader.read_u16_le().unwrap(), 0x0302);
+        reader.skip(1).unwrap();
+        assert_eq!(reader.position(), 4);
+        assert!(matches!(
+            reader.read_u32_be(),
+            Err(Error::Malformed {
+                format: "test",
+                offset: Some(4),
+                ..
+            })
+        ));
+        assert!(reader.is_empty().unwrap());
+
+        let mut reader = StreamReader::new("test", &bytes[..]);
+        assert!(reader.read_bytes(usize::MAX).is_err());
+        let mut reader = StreamReader::new("test", &bytes[..]);
+        assert_eq!(reader.read_bytes(2).unwrap(), [1, 2]);
+        assert_eq!(reader.read_rest().unwrap(), [3, 4, 5, 6, 7]);
+    }
+}
@@ -1,10 +1,385 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    error::ErrorKind,
+    CommandFactory, Parser, Subcommand, ValueEnum,
+};
+use modular_image_converter::{
+    bmp::options::{BmpHeaderVersion, BmpOptions},
+    gif::options::GifOptions,
+    ico::options::IcoOptions,
+    image::Color,
+    jpeg::options::{JpegOptions, JpegSubsampling},
+    netpbm::options::NetpbmOptions,
+    png::options::PngOptions,
+    qoi::{header::Colorspace, options::QoiOptions},
+    raw::options::{ChannelOrder, RawOptions, SampleType},
+    tga::options::TgaOptions,
+    tiff::options::{TiffCompression, TiffOptions},
+    BatchOptions, ConvertOptions,
+};
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    pub source: PathBuf,
-    pub target: PathBuf,
-    pub flags: Option<Vec<String>>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Image to convert and file to write, either of which can be - for stdin or stdout. With
+    /// --out-dir, any number of files, directories and glob patterns to convert instead.
+    #[arg(value_name = "PATHS", required = true)]
+    pub paths: Vec<PathBuf>,
+    /// Convert every input into this directory, keeping the layout of directories and patterns
+    #[arg(long, value_name = "DIR", requires = "to")]
+    pub out_dir: Option<PathBuf>,
+    /// Look inside subdirectories of directory inputs too
+    #[arg(short, long, requires = "out_dir")]
+    pub recursive: bool,
+    /// Files to convert at once with --out-dir; defaults to one per CPU core
+    #[arg(short, long, value_name = "N", requires = "out_dir", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+    /// Memory in MiB that images converted at once with --out-dir may take up between them
+    #[arg(long, value_name = "MIB", requires = "out_dir", default_value_t = 1024)]
+    pub memory_budget: usize,
+    /// Format of the source, such as png or bmp; detected from its contents if not given
+    #[arg(long, value_name = "FORMAT")]
+    pub from: Option<String>,
+    /// Format to write; taken from the target's extension if not given, and needed with - and --out-dir
+    #[arg(long, value_name = "FORMAT")]
+    pub to: Option<String>,
+    /// Image to take from files holding several, such as icon sizes, counting from 0
+    #[arg(long, value_name = "INDEX")]
+    pub image: Option<usize>,
+    /// Leave metadata (EXIF camera details, capture time and location, background color) out of the target
+    #[arg(long, alias = "strip-exif")]
+    pub strip_metadata: bool,
+    /// Background color as #rrggbb, recorded in the target and used by --flatten-alpha
+    #[arg(long, value_name = "COLOR")]
+    pub background: Option<Color>,
+    /// Composite transparent pixels onto the background color (or white) and drop alpha
+    #[arg(long)]
+    pub flatten_alpha: bool,
+    /// Reduce 16-bit samples to 8 bits, which BMP, TGA, QOI, WebP, GIF and BMP icons need
+    #[arg(long)]
+    pub reduce_depth: bool,
+    /// Record the conversion time in a tIME chunk whenever the image data is modified
+    #[arg(long)]
+    pub update_time: bool,
+
+    /// zlib compression level, from 0 (fastest) to 9 (smallest)
+    #[arg(long, value_name = "LEVEL", help_heading = "PNG output", value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub compression_level: Option<u8>,
+    /// Bits per sample; depths below 8 need --palette or a grayscale image
+    #[arg(
+        long,
+        value_name = "BITS",
+        help_heading = "PNG output",
+        value_parser = PossibleValuesParser::new(["1", "2", "4", "8", "16"]).map(|bits| bits.parse::<u8>().expect("only numbers are allowed"))
+    )]
+    pub bit_depth: Option<u8>,
+    /// Store pixels through a palette; fails for images with more than 256 colors
+    #[arg(long, help_heading = "PNG output")]
+    pub palette: bool,
+    /// Write an Adam7 interlaced image
+    #[arg(long, help_heading = "PNG output")]
+    pub interlace: bool,
+    /// Largest IDAT chunk to write; existing chunks are kept if unset
+    #[arg(long, value_name = "BYTES", help_heading = "PNG output")]
+    pub max_idat_size: Option<u32>,
+
+    /// Info header version to write
+    #[arg(long, value_enum, default_value_t = BmpHeader::Info, help_heading = "BMP output")]
+    pub bmp_header: BmpHeader,
+    /// Run-length encode the pixels; needs an opaque image with at most 256 colors
+    #[arg(long, help_heading = "BMP output", conflicts_with = "top_down")]
+    pub rle: bool,
+    /// Store the top row first, so the file can be written without buffering the image
+    #[arg(long, help_heading = "BMP output")]
+    pub top_down: bool,
+
+    /// Write the plain (text) form of PBM, PGM and PPM
+    #[arg(long, help_heading = "Netpbm output")]
+    pub plain: bool,
+
+    /// Run-length encode the pixels
+    #[arg(long, help_heading = "TGA output")]
+    pub tga_rle: bool,
+    /// Store the top row first instead of the bottom row
+    #[arg(long, help_heading = "TGA output")]
+    pub tga_top_down: bool,
+    /// Store pixels through a color map; fails for images with more than 256 colors
+    #[arg(long, help_heading = "TGA output")]
+    pub tga_color_map: bool,
+    /// Text for the image ID field, at most 255 bytes
+    #[arg(long, value_name = "TEXT", help_heading = "TGA output")]
+    pub tga_image_id: Option<String>,
+
+    /// Mark every channel as linear instead of sRGB; the samples are written unchanged
+    #[arg(long, help_heading = "QOI output")]
+    pub qoi_linear: bool,
+
+    /// Store rows interlaced, so a rough image shows while the file loads
+    #[arg(long, help_heading = "GIF output")]
+    pub gif_interlace: bool,
+
+    /// Square sizes to store, such as 16,32,48; the image keeps its own size otherwise
+    #[arg(
+        long,
+        value_name = "SIZES",
+        value_delimiter = ',',
+        help_heading = "ICO and CUR output"
+    )]
+    pub ico_sizes: Vec<u32>,
+    /// Store every size as PNG rather than only 256x256
+    #[arg(long, help_heading = "ICO and CUR output")]
+    pub ico_png: bool,
+    /// Pixel of the source image a cursor points with
+    #[arg(long, value_name = "X,Y", value_parser = parse_point, help_heading = "ICO and CUR output")]
+    pub cur_hotspot: Option<(u32, u32)>,
+
+    /// How to compress strips or tiles
+    #[arg(long, value_enum, value_name = "METHOD", default_value_t = TiffMethod::None, help_heading = "TIFF output")]
+    pub tiff_compression: TiffMethod,
+    /// Store each sample as the difference from its left neighbor; needs lzw or deflate
+    #[arg(long, help_heading = "TIFF output")]
+    pub tiff_predictor: bool,
+    /// Store square tiles of this many pixels, a multiple of 16, instead of strips
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        conflicts_with = "tiff_rows_per_strip",
+        help_heading = "TIFF output"
+    )]
+    pub tiff_tile_size: Option<u32>,
+    /// Rows in each strip; strips of about 8 KiB are written otherwise
+    #[arg(long, value_name = "ROWS", help_heading = "TIFF output")]
+    pub tiff_rows_per_strip: Option<u32>,
+    /// Write big-endian (Motorola) numbers instead of little-endian (Intel) ones
+    #[arg(long, help_heading = "TIFF output")]
+    pub tiff_big_endian: bool,
+    /// Store pixels through a color map; fails for images with more than 256 colors
+    #[arg(long, help_heading = "TIFF output")]
+    pub tiff_palette: bool,
+
+    /// Quality from 1 to 100; higher keeps more detail in a larger file
+    #[arg(long, value_name = "QUALITY", default_value_t = 75, help_heading = "JPEG output", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub jpeg_quality: u8,
+    /// How much chroma resolution to keep; ignored for gray images
+    #[arg(long, value_enum, value_name = "RATIO", default_value_t = JpegChroma::Yuv420, help_heading = "JPEG output")]
+    pub jpeg_subsampling: JpegChroma,
+    /// Build Huffman tables for the image instead of using the standard ones
+    #[arg(long, help_heading = "JPEG output")]
+    pub jpeg_optimize: bool,
+    /// Leave the source's EXIF block out
+    #[arg(long, help_heading = "JPEG output")]
+    pub jpeg_no_exif: bool,
+    /// Leave the source's ICC profile out
+    #[arg(long, help_heading = "JPEG output")]
+    pub jpeg_no_icc: bool,
+
+    /// Width of a raw source in pixels; needed to read one
+    #[arg(long, value_name = "PIXELS", help_heading = "Raw input and output", value_parser = clap::value_parser!(u32).range(1..))]
+    pub raw_width: Option<u32>,
+    /// Height of a raw source in pixels; needed to read one
+    #[arg(long, value_name = "PIXELS", help_heading = "Raw input and output", value_parser = clap::value_parser!(u32).range(1..))]
+    pub raw_height: Option<u32>,
+    /// Channels in each pixel, in the order they're stored
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = RawChannels::Rgba, help_heading = "Raw input and output")]
+    pub raw_channels: RawChannels,
+    /// How each sample is stored; floats run from 0.0 to 1.0
+    #[arg(long, value_enum, value_name = "TYPE", default_value_t = RawSample::U8, help_heading = "Raw input and output")]
+    pub raw_sample: RawSample,
+}
+
+/// Parses a pixel position written as X,Y
+fn parse_point(value: &str) -> Result<(u32, u32), String> {
+    let (x, y) = value
+        .split_once(',')
+        .ok_or_else(|| "expected two numbers separated by a comma".to_string())?;
+    let parse = |coordinate: &str| coordinate.trim().parse::<u32>().map_err(|e| e.to_string());
+    Ok((parse(x)?, parse(y)?))
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Describe an image file without converting it
+    Info {
+        /// Image to describe, or - to read it from stdin
+        path: PathBuf,
+        /// Format of the image; detected from its contents if not given
+        #[arg(long, value_name = "FORMAT")]
+        from: Option<String>,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum BmpHeader {
+    /// 40-byte BITMAPINFOHEADER, readable everywhere
+    Info,
+    /// 108-byte BITMAPV4HEADER
+    V4,
+    /// 124-byte BITMAPV5HEADER
+    V5,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TiffMethod {
+    None,
+    Packbits,
+    Lzw,
+    Deflate,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum JpegChroma {
+    #[value(name = "444")]
+    Yuv444,
+    #[value(name = "422")]
+    Yuv422,
+    #[value(name = "420")]
+    Yuv420,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RawChannels {
+    Rgba,
+    Bgra,
+    Rgb,
+    Gray,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RawSample {
+    U8,
+    U16le,
+    U16be,
+    F32le,
+    F32be,
+}
+
+impl Cli {
+    /// Parses the command line, also checking what clap can't: that a single conversion names
+    /// exactly a source and a target
+    pub fn parse_args() -> Self {
+        let cli = Cli::parse();
+        if cli.command.is_none() && cli.out_dir.is_none() && cli.paths.len() != 2 {
+            Cli::command()
+                .error(
+                    ErrorKind::WrongNumberOfValues,
+                    "give a source and a target, or use --out-dir to convert several files",
+                )
+                .exit();
+        }
+        cli
+    }
+
+    /// The batch settings, if --out-dir asked for a batch conversion
+    pub fn batch_options(&self) -> Option<BatchOptions> {
+        self.out_dir.as_ref().map(|output_dir| BatchOptions {
+            output_dir: output_dir.clone(),
+            recursive: self.recursive,
+            convert: self.convert_options(),
+            jobs: self.jobs.map_or(0, usize::from),
+            memory_budget: self.memory_budget.saturating_mul(1 << 20),
+        })
+    }
+
+    /// Whether `path` stands for stdin or stdout
+    pub fn is_stdio(path: &Path) -> bool {
+        path == Path::new("-")
+    }
+
+    pub fn convert_options(&self) -> ConvertOptions {
+        ConvertOptions {
+            png: PngOptions {
+                compression_level: self.compression_level,
+                bit_depth: self.bit_depth,
+                palette: self.palette,
+                interlace: self.interlace,
+                max_idat_size: self.max_idat_size,
+            },
+            bmp: BmpOptions {
+                header_version: match self.bmp_header {
+                    BmpHeader::Info => BmpHeaderVersion::Info,
+                    BmpHeader::V4 => BmpHeaderVersion::V4,
+                    BmpHeader::V5 => BmpHeaderVersion::V5,
+                },
+                rle: self.rle,
+                top_down: self.top_down,
+            },
+            netpbm: NetpbmOptions { plain: self.plain },
+            tga: TgaOptions {
+                rle: self.tga_rle,
+                top_down: self.tga_top_down,
+                color_map: self.tga_color_map,
+                image_id: self.tga_image_id.clone(),
+            },
+            qoi: QoiOptions {
+                colorspace: match self.qoi_linear {
+                    true => Colorspace::Linear,
+                    false => Colorspace::Srgb,
+                },
+            },
+            gif: GifOptions {
+                interlace: self.gif_interlace,
+            },
+            ico: IcoOptions {
+                sizes: self.ico_sizes.clone(),
+                png: self.ico_png,
+                hotspot: self.cur_hotspot.unwrap_or_default(),
+            },
+            tiff: TiffOptions {
+                compression: match self.tiff_compression {
+                    TiffMethod::None => TiffCompression::None,
+                    TiffMethod::Packbits => TiffCompression::PackBits,
+                    TiffMethod::Lzw => TiffCompression::Lzw,
+                    TiffMethod::Deflate => TiffCompression::Deflate,
+                },
+                predictor: self.tiff_predictor,
+                tile_size: self.tiff_tile_size,
+                rows_per_strip: self.tiff_rows_per_strip,
+                big_endian: self.tiff_big_endian,
+                palette: self.tiff_palette,
+            },
+            jpeg: JpegOptions {
+                quality: self.jpeg_quality,
+                subsampling: match self.jpeg_subsampling {
+                    JpegChroma::Yuv444 => JpegSubsampling::Chroma444,
+                    JpegChroma::Yuv422 => JpegSubsampling::Chroma422,
+                    JpegChroma::Yuv420 => JpegSubsampling::Chroma420,
+                },
+                optimize_huffman: self.jpeg_optimize,
+                exif: !self.jpeg_no_exif,
+                icc_profile: !self.jpeg_no_icc,
+            },
+            raw: RawOptions {
+                width: self.raw_width,
+                height: self.raw_height,
+                channels: match self.raw_channels {
+                    RawChannels::Rgba => ChannelOrder::Rgba,
+                    RawChannels::Bgra => ChannelOrder::Bgra,
+                    RawChannels::Rgb => ChannelOrder::Rgb,
+                    RawChannels::Gray => ChannelOrder::Gray,
+                },
+                sample: match self.raw_sample {
+                    RawSample::U8 => SampleType::U8,
+                    RawSample::U16le => SampleType::U16Le,
+                    RawSample::U16be => SampleType::U16Be,
+                    RawSample::F32le => SampleType::F32Le,
+                    RawSample::F32be => SampleType::F32Be,
+                },
+            },
+            strip_metadata: self.strip_metadata,
+            background: self.background,
+            flatten_alpha: self.flatten_alpha,
+            reduce_depth: self.reduce_depth,
+            update_time: self.update_time,
+            image: self.image,
+            from: self.from.clone(),
+            to: self.to.clone(),
+        }
+    }
 }
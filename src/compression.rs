@@ -1,6 +1,21 @@
 //may be factored out into different sections for different file types
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     BI_RLE8,
     BI_RLE4,
+    BI_BITFIELDS,
     BI_JPEG,
 }
+
+impl CompressionType {
+    /// The value BMP info headers store for this compression type
+    pub fn code(&self) -> u32 {
+        match self {
+            CompressionType::BI_RLE8 => 1,
+            CompressionType::BI_RLE4 => 2,
+            CompressionType::BI_BITFIELDS => 3,
+            CompressionType::BI_JPEG => 4,
+        }
+    }
+}
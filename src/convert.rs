@@ -0,0 +1,657 @@
+use std::{
+    fs::{self, File, Permissions},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use tempfile::NamedTempFile;
+
+use crate::{
+    bmp::options::BmpOptions,
+    format::{Codec, Registry},
+    gif::options::GifOptions,
+    ico::options::IcoOptions,
+    image::Color,
+    jpeg::options::JpegOptions,
+    metadata::Metadata,
+    netpbm::options::NetpbmOptions,
+    png::{options::PngOptions, Png},
+    qoi::options::QoiOptions,
+    raw::options::RawOptions,
+    scanline::{FlattenRows, ReduceDepthRows},
+    tga::options::TgaOptions,
+    tiff::options::TiffOptions,
+    Error, Result,
+};
+
+/// Settings for a conversion. The per-format settings only apply when writing that format,
+/// apart from `raw`, which also describes raw input.
+#[derive(Clone, Debug, Default)]
+pub struct ConvertOptions {
+    pub png: PngOptions,
+    pub bmp: BmpOptions,
+    pub netpbm: NetpbmOptions,
+    pub tga: TgaOptions,
+    pub qoi: QoiOptions,
+    pub gif: GifOptions,
+    /// Settings for both ICO and CUR output
+    pub ico: IcoOptions,
+    pub tiff: TiffOptions,
+    pub jpeg: JpegOptions,
+    /// Layout of raw pixel dumps, whether read or written
+    pub raw: RawOptions,
+    /// Leave all metadata, such as EXIF and the background color, out of the target
+    pub strip_metadata: bool,
+    /// Background color to record in the target in place of the source's, which is also
+    /// the matte used when flattening
+    pub background: Option<Color>,
+    /// Composite transparent pixels onto the background color (or white) and drop alpha
+    pub flatten_alpha: bool,
+    /// Reduce 16-bit samples to 8 bits, which formats that only hold 8 bits otherwise refuse
+    pub reduce_depth: bool,
+    /// Record the conversion time whenever the image data is modified
+    pub update_time: bool,
+    /// Image to convert from files holding several, such as the sizes in an icon, counting
+    /// from 0. Each format picks one itself otherwise.
+    pub image: Option<usize>,
+    /// Format to read the input as, by name or extension, instead of working it out
+    pub from: Option<String>,
+    /// Format to write, by name or extension, instead of going by the output's extension
+    pub to: Option<String>,
+}
+
+impl ConvertOptions {
+    /// Checks for settings that are out of range or contradict each other
+    pub fn validate(&self) -> Result<()> {
+        self.png.validate()?;
+        self.bmp.validate()?;
+        self.tga.validate()?;
+        self.ico.validate()?;
+        self.tiff.validate()?;
+        self.jpeg.validate()?;
+        self.raw.validate()
+    }
+}
+
+/// What a finished conversion did
+#[derive(Clone, Debug)]
+pub struct Conversion {
+    /// Name of the format the input was read as
+    pub source: &'static str,
+    /// Name of the format the output was written in
+    pub target: &'static str,
+    /// Problems that didn't stop the conversion, worded for showing to a user
+    pub warnings: Vec<String>,
+}
+
+/// Where a conversion reads its image from
+pub enum Input<'a> {
+    /// A file, whose extension is the fallback for telling its format
+    Path(&'a Path),
+    /// Any other stream, such as stdin. Its format has to be recognisable from its contents
+    /// unless `ConvertOptions::from` gives it.
+    Reader(&'a mut dyn Read),
+}
+
+/// Where a conversion writes its image to
+pub enum Output<'a> {
+    /// A file, whose extension names the format unless `ConvertOptions::to` does
+    Path(&'a Path),
+    /// Any other stream, such as stdout; `ConvertOptions::to` has to name the format
+    Writer(&'a mut dyn Write),
+}
+
+/// Converts the image at `input` into the format named by `output`'s extension. The
+/// input's format is worked out from its contents, falling back to its extension.
+pub fn convert<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    options: &ConvertOptions,
+) -> Result<Conversion> {
+    convert_io(
+        Input::Path(input.as_ref()),
+        Output::Path(output.as_ref()),
+        options,
+    )
+}
+
+/// Like `convert`, but either end can be a stream instead of a file
+pub fn convert_io(input: Input, output: Output, options: &ConvertOptions) -> Result<Conversion> {
+    convert_with(&Registry::default(), input, output, options)
+}
+
+/// Like `convert_io`, but only considers the formats in `registry`
+pub fn convert_with(
+    registry: &Registry,
+    input: Input,
+    output: Output,
+    options: &ConvertOptions,
+) -> Result<Conversion> {
+    options.validate()?;
+    convert_validated(registry, input, output, options)
+}
+
+/// `convert_with` for options that have already been validated, like those of a batch
+pub(crate) fn convert_validated(
+    registry: &Registry,
+    input: Input,
+    output: Output,
+    options: &ConvertOptions,
+) -> Result<Conversion> {
+    let target = match (&options.to, &output) {
+        (Some(format), _) => registry.by_format(format)?,
+        (None, Output::Path(path)) => registry.by_path(path)?,
+        (None, Output::Writer(_)) => {
+            return Err(Error::invalid_argument(
+                "the output format has to be given when writing to a stream",
+            ))
+        }
+    };
+    // checked up front so an unwritable format doesn't cost a decode
+    if !target.can_encode() {
+        return Err(Error::unsupported(target.name, "encoding"));
+    }
+
+    let mut file_reader: BufReader<File>;
+    let (reader, input_path): (&mut dyn Read, Option<&Path>) = match input {
+        Input::Path(path) => {
+            file_reader = BufReader::new(File::open(path)?);
+            (&mut file_reader, Some(path))
+        }
+        Input::Reader(reader) => (reader, None),
+    };
+    let mut warnings: Vec<String> = Vec::new();
+    let (source, signature) = identify(
+        registry,
+        reader,
+        input_path,
+        options.from.as_deref(),
+        &mut warnings,
+    )?;
+    let mut reader = signature.as_slice().chain(reader);
+
+    // a file is written beside the target and only renamed over it once the conversion has
+    // succeeded, so a failure leaves any existing file, possibly the input itself, untouched
+    let mut temp_file: Option<(NamedTempFile, &Path)> = None;
+    let writer: &mut dyn Write = match output {
+        Output::Path(path) => {
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let mut builder = tempfile::Builder::new();
+            builder.prefix(".convert-");
+            // temporary files are private to their owner, unlike the file this one becomes
+            if let Some(permissions) = output_permissions(path) {
+                builder.permissions(permissions);
+            }
+            let file = builder.tempfile_in(directory)?;
+            &mut temp_file.insert((file, path)).0
+        }
+        Output::Writer(writer) => writer,
+    };
+    let mut writer = BufWriter::new(writer);
+    convert_stream_validated(&mut reader, source, target, options, &mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    if let Some((file, path)) = temp_file {
+        file.persist(path).map_err(|e| e.error)?;
+    }
+    Ok(Conversion {
+        source: source.name,
+        target: target.name,
+        warnings,
+    })
+}
+
+/// The permissions a new file at `path` should get: those of the file it replaces, or the
+/// ones `File::create` would give
+fn output_permissions(path: &Path) -> Option<Permissions> {
+    match fs::metadata(path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        #[cfg(unix)]
+        Err(_) => Some(std::os::unix::fs::PermissionsExt::from_mode(0o666)),
+        #[cfg(not(unix))]
+        Err(_) => None,
+    }
+}
+
+/// Works out the format of the data in `reader`: the one `from` names if given, otherwise
+/// from the data's signature, falling back to `path`'s extension. Returns the bytes read to
+/// find it, which belong back in front of the rest of the stream.
+pub(crate) fn identify<'a>(
+    registry: &'a Registry,
+    reader: &mut dyn Read,
+    path: Option<&Path>,
+    from: Option<&str>,
+    warnings: &mut Vec<String>,
+) -> Result<(&'a Codec, Vec<u8>)> {
+    // only the start of the input is needed to recognise it
+    let mut signature: Vec<u8> = Vec::new();
+    Read::take(&mut *reader, registry.signature_len() as u64).read_to_end(&mut signature)?;
+    let codec = match (from, path) {
+        (Some(format), _) => registry.by_format(format)?,
+        (None, Some(path)) => {
+            let detection = registry.detect(path, &signature)?;
+            if let Some(by_extension) = detection.extension_mismatch {
+                warnings.push(format!(
+                    "{} is named like a {} file but contains {} data; reading it as {}",
+                    path.display(),
+                    by_extension.name,
+                    detection.codec.name,
+                    detection.codec.name
+                ));
+            }
+            detection.codec
+        }
+        (None, None) => registry.sniff(&signature).ok_or_else(|| {
+            Error::unsupported("image", "input that isn't in a recognised format")
+        })?,
+    };
+    Ok((codec, signature))
+}
+
+/// Converts the data in `reader` from `source`'s format into `target`'s, writing the result
+/// to `writer` as it's encoded
+pub fn convert_stream(
+    reader: &mut dyn Read,
+    source: &Codec,
+    target: &Codec,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    options.validate()?;
+    convert_stream_validated(reader, source, target, options, writer)
+}
+
+/// `convert_stream` for options that have already been validated
+fn convert_stream_validated(
+    reader: &mut dyn Read,
+    source: &Codec,
+    target: &Codec,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    // PNG to PNG is left to the whole-file path, which can copy the chunks across as they are
+    if source.can_decode_rows()
+        && target.can_encode_rows()
+        && source.name != target.name
+        && options.image.is_none()
+    {
+        return convert_rows(reader, source, target, options, writer);
+    }
+    let input = source.decode(reader, options)?;
+
+    let mut metadata = match options.strip_metadata {
+        true => Metadata::default(),
+        false => input.metadata()?,
+    };
+    if options.background.is_some() {
+        metadata.set_background(options.background);
+    }
+    let mut png = match options.image {
+        Some(index) => input.image_to_png(index)?,
+        None => input.to_png()?,
+    };
+    // nothing needs the source any more, so it isn't kept around while the output is written
+    drop(input);
+    if options.flatten_alpha || options.reduce_depth {
+        png = match png.to_animation()? {
+            Some(mut animation) => {
+                if options.flatten_alpha {
+                    animation.flatten_alpha(options.background);
+                }
+                if options.reduce_depth {
+                    animation.reduce_depth();
+                }
+                Png::from_animation(&animation, &PngOptions::default())?
+            }
+            None => {
+                let mut image = png.to_image()?;
+                if options.flatten_alpha {
+                    image.flatten_alpha(options.background);
+                }
+                if options.reduce_depth {
+                    image.reduce_depth();
+                }
+                Png::from_image(&image)?
+            }
+        };
+        if options.update_time {
+            png.touch()?;
+        }
+    }
+    png.set_metadata(&metadata)?;
+
+    target.encode(png, options, writer)
+}
+
+/// Converts between two formats a row at a time, so the decoded image is never held whole
+/// unless one of the formats needs it to be
+fn convert_rows(
+    reader: &mut dyn Read,
+    source: &Codec,
+    target: &Codec,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let mut rows = source.decode_rows(reader, options)?;
+    let mut metadata = match options.strip_metadata {
+        true => Metadata::default(),
+        false => rows.metadata().clone(),
+    };
+    if options.background.is_some() {
+        metadata.set_background(options.background);
+    }
+    if options.flatten_alpha {
+        rows = Box::new(FlattenRows::new(rows, options.background));
+    }
+    if options.reduce_depth {
+        rows = Box::new(ReduceDepthRows::new(rows));
+    }
+    target.encode_rows(rows.as_mut(), &metadata, options, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bmp,
+        image::{ColorModel, Image},
+        png,
+        raw::options::ChannelOrder,
+        ConvertibleImage,
+    };
+
+    #[test]
+    fn test_convert_stream() {
+        let image = Image::new(
+            2,
+            1,
+            ColorModel::Rgba,
+            8,
+            vec![255, 0, 0, 255, 0, 0, 255, 0],
+        )
+        .unwrap();
+        let png_bytes = Png::from_image(&image).unwrap().to_bytes();
+        let options = ConvertOptions {
+            flatten_alpha: true,
+            ..ConvertOptions::default()
+        };
+
+        let mut bmp_bytes: Vec<u8> = Vec::new();
+        convert_stream(
+            &mut &png_bytes[..],
+            &png::CODEC,
+            &bmp::CODEC,
+            &options,
+            &mut bmp_bytes,
+        )
+        .unwrap();
+        let converted = bmp::Bmp::try_from(bmp_bytes.as_ref())
+            .unwrap()
+            .to_image()
+            .unwrap();
+        assert_eq!(converted.color_model(), ColorModel::Rgb);
+        assert_eq!(converted.data(), &[255, 0, 0, 255, 255, 255]);
+    }
+
+    /// Converts through the row pipeline and through whole images, which should agree
+    fn convert_both_ways(
+        bytes: &[u8],
+        source: &Codec,
+        target: &Codec,
+        options: &ConvertOptions,
+    ) -> Vec<u8> {
+        let whole = |codec: &Codec| Codec {
+            row_decoder: None,
+            row_encoder: None,
+            ..*codec
+        };
+        let mut streamed: Vec<u8> = Vec::new();
+        convert_stream(&mut &bytes[..], source, target, options, &mut streamed).unwrap();
+        let mut buffered: Vec<u8> = Vec::new();
+        convert_stream(
+            &mut &bytes[..],
+            &whole(source),
+            &whole(target),
+            options,
+            &mut buffered,
+        )
+        .unwrap();
+        assert_eq!(streamed, buffered);
+        streamed
+    }
+
+    #[test]
+    fn test_row_pipeline_matches_whole_images() {
+        let rgba =
+            Image::new(3, 2, ColorModel::Rgba, 8, (0..24).map(|v| v * 10).collect()).unwrap();
+        let gray16 = Image::new(
+            2,
+            3,
+            ColorModel::Gray,
+            16,
+            (0..12).map(|v| v * 20).collect(),
+        )
+        .unwrap();
+        let top_down = ConvertOptions {
+            bmp: BmpOptions {
+                top_down: true,
+                ..BmpOptions::default()
+            },
+            ..ConvertOptions::default()
+        };
+        let flatten = ConvertOptions {
+            flatten_alpha: true,
+            background: Some(Color::from_rgb8(0, 128, 255)),
+            ..ConvertOptions::default()
+        };
+        let interlace = ConvertOptions {
+            png: PngOptions {
+                interlace: true,
+                ..PngOptions::default()
+            },
+            ..ConvertOptions::default()
+        };
+
+        for image in [&rgba, &gray16] {
+            let png_bytes = Png::from_image(image).unwrap().to_bytes();
+            for options in [&ConvertOptions::default(), &top_down, &flatten] {
+                // BMP only holds 8-bit samples, so 16-bit ones are refused unless reduced
+                let options = ConvertOptions {
+                    reduce_depth: image.bit_depth() == 16,
+                    ..options.clone()
+                };
+                let bmp_bytes = convert_both_ways(&png_bytes, &png::CODEC, &bmp::CODEC, &options);
+                for options in [&ConvertOptions::default(), &interlace] {
+                    convert_both_ways(&bmp_bytes, &bmp::CODEC, &png::CODEC, options);
+                }
+            }
+        }
+
+        let interlaced = Png::from_image_with(
+            &rgba,
+            &PngOptions {
+                interlace: true,
+                ..PngOptions::default()
+            },
+        )
+        .unwrap()
+        .to_bytes();
+        convert_both_ways(&interlaced, &png::CODEC, &bmp::CODEC, &top_down);
+
+        let png_bytes = Png::from_image(&gray16).unwrap().to_bytes();
+        for target in [
+            bmp::CODEC,
+            Codec {
+                row_encoder: None,
+                ..bmp::CODEC
+            },
+        ] {
+            let mut output: Vec<u8> = Vec::new();
+            assert!(matches!(
+                convert_stream(
+                    &mut &png_bytes[..],
+                    &png::CODEC,
+                    &target,
+                    &ConvertOptions::default(),
+                    &mut output
+                ),
+                Err(Error::LossyConversion(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_row_pipeline_rejects_truncated_input() {
+        let image = Image::new(4, 4, ColorModel::Rgb, 8, vec![7; 48]).unwrap();
+        let png_bytes = Png::from_image(&image).unwrap().to_bytes();
+        let bmp_bytes = bmp::Bmp::from_image(&image).unwrap().to_bytes();
+        let mut output: Vec<u8> = Vec::new();
+        let options = ConvertOptions::default();
+        for (bytes, source, target) in [
+            (&png_bytes, &png::CODEC, &bmp::CODEC),
+            (&bmp_bytes, &bmp::CODEC, &png::CODEC),
+        ] {
+            let truncated = &bytes[..bytes.len() - 20];
+            assert!(matches!(
+                convert_stream(&mut &truncated[..], source, target, &options, &mut output),
+                Err(Error::Malformed { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_convert_io_streams() {
+        let image = Image::new(2, 2, ColorModel::Gray, 8, vec![0, 85, 170, 255]).unwrap();
+        let png_bytes = Png::from_image(&image).unwrap().to_bytes();
+        let to_bmp = ConvertOptions {
+            to: Some("bmp".to_string()),
+            ..ConvertOptions::default()
+        };
+
+        let mut bmp_bytes: Vec<u8> = Vec::new();
+        let conversion = convert_io(
+            Input::Reader(&mut &png_bytes[..]),
+            Output::Writer(&mut bmp_bytes),
+            &to_bmp,
+        )
+        .unwrap();
+        assert_eq!((conversion.source, conversion.target), ("PNG", "BMP"));
+        let converted = bmp::Bmp::try_from(bmp_bytes.as_ref()).unwrap();
+        assert_eq!(converted.to_image().unwrap().data(), image.data());
+
+        // a format given explicitly is trusted over the contents
+        let from_bmp = ConvertOptions {
+            from: Some("BMP".to_string()),
+            ..to_bmp.clone()
+        };
+        assert!(matches!(
+            convert_io(
+                Input::Reader(&mut &png_bytes[..]),
+                Output::Writer(&mut Vec::new()),
+                &from_bmp,
+            ),
+            Err(Error::Malformed { format: "BMP", .. })
+        ));
+        assert!(matches!(
+            convert_io(
+                Input::Reader(&mut &png_bytes[..]),
+                Output::Writer(&mut Vec::new()),
+                &ConvertOptions::default(),
+            ),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            convert_io(
+                Input::Reader(&mut &b"not an image"[..]),
+                Output::Writer(&mut Vec::new()),
+                &to_bmp,
+            ),
+            Err(Error::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_raw_through_convert_io() {
+        let bgra: Vec<u8> = (0..24).map(|v| v * 10).collect();
+        let raw = RawOptions {
+            width: Some(3),
+            height: Some(2),
+            channels: ChannelOrder::Bgra,
+            ..RawOptions::default()
+        };
+        let to_farbfeld = ConvertOptions {
+            from: Some("raw".to_string()),
+            to: Some("ff".to_string()),
+            raw: raw.clone(),
+            ..ConvertOptions::default()
+        };
+        let mut farbfeld: Vec<u8> = Vec::new();
+        let conversion = convert_io(
+            Input::Reader(&mut &bgra[..]),
+            Output::Writer(&mut farbfeld),
+            &to_farbfeld,
+        )
+        .unwrap();
+        assert_eq!((conversion.source, conversion.target), ("raw", "farbfeld"));
+        // the first pixel, stored as B G R A, comes out as R G B A
+        assert_eq!(farbfeld[16..24], [20, 20, 10, 10, 0, 0, 30, 30]);
+
+        let to_raw = ConvertOptions {
+            to: Some("raw".to_string()),
+            raw,
+            ..ConvertOptions::default()
+        };
+        let mut back: Vec<u8> = Vec::new();
+        convert_io(
+            Input::Reader(&mut &farbfeld[..]),
+            Output::Writer(&mut back),
+            &to_raw,
+        )
+        .unwrap();
+        assert_eq!(back, bgra);
+
+        assert!(matches!(
+            convert_io(
+                Input::Reader(&mut &bgra[..]),
+                Output::Writer(&mut Vec::new()),
+                &ConvertOptions {
+                    raw: RawOptions::default(),
+                    ..to_farbfeld
+                },
+            ),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_output_replaced_only_on_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let image = Image::new(2, 1, ColorModel::Rgb, 8, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        std::fs::write(&path, Png::from_image(&image).unwrap().to_bytes()).unwrap();
+        let original = std::fs::read(&path).unwrap();
+
+        // reading and writing the same file would truncate it before it was read
+        let recompress = ConvertOptions {
+            png: PngOptions {
+                compression_level: Some(0),
+                ..PngOptions::default()
+            },
+            ..ConvertOptions::default()
+        };
+        convert(&path, &path, &recompress).unwrap();
+        let converted = std::fs::read(&path).unwrap();
+        assert_ne!(converted, original);
+        let decoded = Png::try_from(converted.as_slice()).unwrap();
+        assert_eq!(decoded.to_image().unwrap().data(), image.data());
+
+        let broken = dir.path().join("broken.bmp");
+        std::fs::write(&broken, b"BM not really").unwrap();
+        assert!(convert(&broken, &path, &ConvertOptions::default()).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), converted);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
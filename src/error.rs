@@ -0,0 +1,180 @@
+use std::{fmt, io};
+
+/// Everything that can go wrong while reading, converting or writing an image. Each
+/// variant maps to its own process exit code so scripts can tell failures apart.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file or stream failed
+    Io(io::Error),
+    /// The input isn't valid data for the format it claims to be
+    Malformed {
+        format: &'static str,
+        /// Byte offset into the data being parsed, when the failure has a position
+        offset: Option<usize>,
+        message: String,
+    },
+    /// The input is valid, but uses a feature this crate can't handle
+    Unsupported {
+        format: &'static str,
+        feature: String,
+    },
+    /// The conversion would throw information away and wasn't explicitly allowed to
+    LossyConversion(String),
+    /// A value passed in by the caller is out of range
+    InvalidArgument(String),
+}
+
+impl Error {
+    pub fn malformed(format: &'static str, message: impl Into<String>) -> Self {
+        Error::Malformed {
+            format,
+            offset: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn malformed_at(format: &'static str, offset: usize, message: impl Into<String>) -> Self {
+        Error::Malformed {
+            format,
+            offset: Some(offset),
+            message: message.into(),
+        }
+    }
+
+    pub fn unsupported(format: &'static str, feature: impl Into<String>) -> Self {
+        Error::Unsupported {
+            format,
+            feature: feature.into(),
+        }
+    }
+
+    pub fn lossy(message: impl Into<String>) -> Self {
+        Error::LossyConversion(message.into())
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Error::InvalidArgument(message.into())
+    }
+
+    /// Rejects a request for image `index` of a file that only holds `count`
+    pub(crate) fn no_such_image(index: usize, count: usize) -> Self {
+        Error::invalid_argument(format!(
+            "there is no image {index}; the file holds {count}, numbered from 0"
+        ))
+    }
+
+    /// Reclassifies a rejected constructor argument as malformed data, for constructors
+    /// that are also used while parsing `format`
+    pub(crate) fn into_malformed(self, format: &'static str) -> Self {
+        match self {
+            Error::InvalidArgument(message) => Error::malformed(format, message),
+            other => other,
+        }
+    }
+
+    /// The exit code the CLI reports for this error. 1 is left for panics and 2 for
+    /// command line usage errors reported by clap.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io(_) => 3,
+            Error::Malformed { .. } => 4,
+            Error::Unsupported { .. } => 5,
+            Error::LossyConversion(_) => 6,
+            Error::InvalidArgument(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::Malformed {
+                format,
+                offset: Some(offset),
+                message,
+            } => write!(f, "malformed {format} data at byte {offset}: {message}"),
+            Error::Malformed {
+                format,
+                offset: None,
+                message,
+            } => write!(f, "malformed {format} data: {message}"),
+            Error::Unsupported { format, feature } => {
+                write!(f, "unsupported {format} feature: {feature}")
+            }
+            Error::LossyConversion(message) => write!(f, "refusing a lossy conversion: {message}"),
+            Error::InvalidArgument(message) => write!(f, "invalid argument: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        // errors wrapped up to pass through a `Write` implementation come back out as themselves
+        if !value.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return Error::Io(value);
+        }
+        let kind = value.kind();
+        match value.into_inner().map(|inner| inner.downcast::<Error>()) {
+            Some(Ok(inner)) => *inner,
+            _ => Error::Io(kind.into()),
+        }
+    }
+}
+
+/// Lets errors cross `std::io::Write` implementations, such as the zlib stream feeding
+/// an `IdatWriter`, and come back out as themselves
+impl From<Error> for io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(e) => e,
+            other => io::Error::other(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            Error::from(io::Error::other("disk full")),
+            Error::malformed("PNG", "bad"),
+            Error::unsupported("BMP", "OS/2 headers"),
+            Error::lossy("alpha would be dropped"),
+            Error::invalid_argument("chunk size 0"),
+        ];
+        let mut codes: Vec<u8> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Error::malformed_at("BMP", 14, "unknown header type").to_string(),
+            "malformed BMP data at byte 14: unknown header type"
+        );
+    }
+
+    #[test]
+    fn test_io_round_trip() {
+        let io_error: io::Error = Error::malformed("PNG", "bad").into();
+        assert!(matches!(Error::from(io_error), Error::Malformed { .. }));
+        assert!(matches!(
+            Error::from(io::Error::other("disk full")),
+            Error::Io(_)
+        ));
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{byte_reader::ByteReader, Error, Result};
+
+/// Byte order of a TIFF-structured block, taken from its `II`/`MM` marker
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+/// The value stored in one IFD entry. Types this parser doesn't interpret are kept as raw bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExifValue {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Undefined(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExifField {
+    tag: u16,
+    value: ExifValue,
+}
+
+impl ExifField {
+    pub fn tag(&self) -> u16 {
+        self.tag
+    }
+
+    pub fn value(&self) -> &ExifValue {
+        &self.value
+    }
+}
+
+/// A parsed EXIF block. The original bytes are kept so the block can be carried
+/// over unchanged to any output format that can store EXIF.
+#[derive(Clone, Debug)]
+pub struct Exif {
+    raw: Vec<u8>,
+    byte_order: ByteOrder,
+    fields: Vec<ExifField>,
+}
+
+impl Exif {
+    pub const TAG_MAKE: u16 = 0x010F;
+    pub const TAG_MODEL: u16 = 0x0110;
+    pub const TAG_ORIENTATION: u16 = 0x0112;
+    pub const TAG_X_RESOLUTION: u16 = 0x011A;
+    pub const TAG_Y_RESOLUTION: u16 = 0x011B;
+    pub const TAG_RESOLUTION_UNIT: u16 = 0x0128;
+    pub const TAG_DATE_TIME: u16 = 0x0132;
+    pub const TAG_EXIF_IFD: u16 = 0x8769;
+    pub const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+    /// The raw TIFF-structured bytes, as stored in a PNG eXIf chunk
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Lists the fields of IFD0 followed by those of the EXIF sub-IFD
+    pub fn fields(&self) -> &[ExifField] {
+        &self.fields
+    }
+
+    pub fn field(&self, tag: u16) -> Option<&ExifValue> {
+        self.fields
+            .iter()
+            .find(|field| field.tag == tag)
+            .map(|field| &field.value)
+    }
+
+    /// Returns the orientation tag as defined by EXIF; 1 is upright, 2-8 are flips and rotations
+    pub fn orientation(&self) -> Option<u16> {
+        match self.field(Exif::TAG_ORIENTATION) {
+            Some(ExifValue::Short(values)) => values.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// Returns when the photo was taken, falling back to the file modification date
+    pub fn date_time(&self) -> Option<&str> {
+        self.ascii_field(Exif::TAG_DATE_TIME_ORIGINAL)
+            .or_else(|| self.ascii_field(Exif::TAG_DATE_TIME))
+    }
+
+    pub fn camera_make(&self) -> Option<&str> {
+        self.ascii_field(Exif::TAG_MAKE)
+    }
+
+    pub fn camera_model(&self) -> Option<&str> {
+        self.ascii_field(Exif::TAG_MODEL)
+    }
+
+    /// Returns the horizontal and vertical resolution in pixels per `resolution_unit`
+    pub fn resolution(&self) -> Option<(f64, f64)> {
+        Some((
+            self.rational_field(Exif::TAG_X_RESOLUTION)?,
+            self.rational_field(Exif::TAG_Y_RESOLUTION)?,
+        ))
+    }
+
+    /// Returns the unit used by `resolution`: 2 for inches (the default) or 3 for centimetres
+    pub fn resolution_unit(&self) -> u16 {
+        match self.field(Exif::TAG_RESOLUTION_UNIT) {
+            Some(ExifValue::Short(values)) => values.first().copied().unwrap_or(2),
+            _ => 2,
+        }
+    }
+
+    fn ascii_field(&self, tag: u16) -> Option<&str> {
+        match self.field(tag) {
+            Some(ExifValue::Ascii(value)) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    fn rational_field(&self, tag: u16) -> Option<f64> {
+        match self.field(tag) {
+            Some(ExifValue::Rational(values)) => match values.first() {
+                Some(&(_, 0)) | None => None,
+                Some(&(numerator, denominator)) => Some(numerator as f64 / denominator as f64),
+            },
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Exif {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let byte_order = match value.get(0..2) {
+            Some(b"II") => ByteOrder::LittleEndian,
+            Some(b"MM") => ByteOrder::BigEndian,
+            _ => {
+                return Err(Error::malformed_at(
+                    "EXIF",
+                    0,
+                    "EXIF data must start with a TIFF byte order marker",
+                ))
+            }
+        };
+        let reader = TiffReader {
+            reader: ByteReader::new("EXIF", value),
+            byte_order,
+        };
+        if reader.u16_at(2)? != 42 {
+            return Err(Error::malformed_at("EXIF", 2, "invalid TIFF magic number"));
+        }
+
+        let mut fields = reader.read_ifd(reader.u32_at(4)? as usize)?;
+        let exif_ifd_offset = fields.iter().find_map(|field| match field {
+            ExifField {
+                tag: Exif::TAG_EXIF_IFD,
+                value: ExifValue::Long(offsets),
+            } => offsets.first().copied(),
+            _ => None,
+        });
+        if let Some(offset) = exif_ifd_offset {
+            fields.extend(reader.read_ifd(offset as usize)?);
+        }
+
+        Ok(Exif {
+            raw: value.to_vec(),
+            byte_order,
+            fields,
+        })
+    }
+}
+
+struct TiffReader<'a> {
+    reader: ByteReader<'a>,
+    byte_order: ByteOrder,
+}
+
+impl TiffReader<'_> {
+    fn u16_at(&self, offset: usize) -> Result<u16> {
+        let mut reader = self.reader.at(offset)?;
+        match self.byte_order {
+            ByteOrder::LittleEndian => reader.read_u16_le(),
+            ByteOrder::BigEndian => reader.read_u16_be(),
+        }
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32> {
+        let mut reader = self.reader.at(offset)?;
+        match self.byte_order {
+            ByteOrder::LittleEndian => reader.read_u32_le(),
+            ByteOrder::BigEndian => reader.read_u32_be(),
+        }
+    }
+
+    fn read_ifd(&self, offset: usize) -> Result<Vec<ExifField>> {
+        let entry_count = self.u16_at(offset)? as usize;
+        let mut fields = Vec::with_capacity(entry_count);
+        for idx in 0..entry_count {
+            let entry = offset + 2 + idx * 12;
+            let tag = self.u16_at(entry)?;
+            let field_type = self.u16_at(entry + 2)?;
+            let count = self.u32_at(entry + 4)? as usize;
+            let type_size: usize = match field_type {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                // unknown types can't be sized, so they're skipped as the spec asks
+                _ => continue,
+            };
+            let len = count
+                .checked_mul(type_size)
+                .ok_or_else(|| Error::malformed_at("EXIF", entry, "field is too large"))?;
+            let data_offset = if len <= 4 {
+                entry + 8
+            } else {
+                self.u32_at(entry + 8)? as usize
+            };
+            let value = self.read_value(field_type, count, data_offset, len)?;
+            fields.push(ExifField { tag, value });
+        }
+        Ok(fields)
+    }
+
+    fn read_value(
+        &self,
+        field_type: u16,
+        count: usize,
+        offset: usize,
+        len: usize,
+    ) -> Result<ExifValue> {
+        let data = self.reader.at(offset)?.read_bytes(len)?;
+        Ok(match field_type {
+            1 => ExifValue::Byte(data.to_vec()),
+            2 => {
+                let text = data.split(|&byte| byte == 0).next().unwrap_or_default();
+                ExifValue::Ascii(String::from_utf8_lossy(text).into_owned())
+            }
+            3 => ExifValue::Short(
+                (0..count)
+                    .map(|idx| self.u16_at(offset + idx * 2))
+                    .collect::<Result<_>>()?,
+            ),
+            4 => ExifValue::Long(
+                (0..count)
+                    .map(|idx| self.u32_at(offset + idx * 4))
+                    .collect::<Result<_>>()?,
+            ),
+            5 => ExifValue::Rational(
+                (0..count)
+                    .map(|idx| {
+                        Ok((
+                            self.u32_at(offset + idx * 8)?,
+                            self.u32_at(offset + idx * 8 + 4)?,
+                        ))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            9 => ExifValue::SLong(
+                (0..count)
+                    .map(|idx| Ok(self.u32_at(offset + idx * 4)? as i32))
+                    .collect::<Result<_>>()?,
+            ),
+            10 => ExifValue::SRational(
+                (0..count)
+                    .map(|idx| {
+                        Ok((
+                            self.u32_at(offset + idx * 8)? as i32,
+                            self.u32_at(offset + idx * 8 + 4)? as i32,
+                        ))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            _ => ExifValue::Undefined(data.to_vec()),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a big-endian EXIF block with an orientation, make, resolution and an
+    /// EXIF sub-IFD holding the capture date
+    pub(crate) fn testing_exif_bytes() -> Vec<u8> {
+        let mut bytes: Vec<u8> = b"MM\0\x2a".to_vec();
+        bytes.extend_from_slice(&8_u32.to_be_bytes());
+
+        // IFD0 at offset 8 with 5 entries; its values start after the next-IFD pointer
+        let values_start: u32 = 8 + 2 + 5 * 12 + 4;
+        let make_offset = values_start;
+        let x_res_offset = make_offset + 6;
+        let y_res_offset = x_res_offset + 8;
+        let exif_ifd_offset = y_res_offset + 8;
+        bytes.extend_from_slice(&5_u16.to_be_bytes());
+        let mut entry = |tag: u16, field_type: u16, count: u32, value: [u8; 4]| {
+            bytes.extend_from_slice(&tag.to_be_bytes());
+            bytes.extend_from_slice(&field_type.to_be_bytes());
+            bytes.extend_from_slice(&count.to_be_bytes());
+            bytes.extend_from_slice(&value);
+        };
+        entry(Exif::TAG_MAKE, 2, 6, make_offset.to_be_bytes());
+        entry(Exif::TAG_ORIENTATION, 3, 1, [0, 6, 0, 0]);
+        entry(Exif::TAG_X_RESOLUTION, 5, 1, x_res_offset.to_be_bytes());
+        entry(Exif::TAG_Y_RESOLUTION, 5, 1, y_res_offset.to_be_bytes());
+        entry(Exif::TAG_EXIF_IFD, 4, 1, exif_ifd_offset.to_be_bytes());
+        bytes.extend_from_slice(&0_u32.to_be_bytes());
+
+        bytes.extend_from_slice(b"Canon\0");
+        bytes.extend_from_slice(&300_u32.to_be_bytes());
+        bytes.extend_from_slice(&1_u32.to_be_bytes());
+        bytes.extend_from_slice(&72_u32.to_be_bytes());
+        bytes.extend_from_slice(&1_u32.to_be_bytes());
+
+        let date_offset = exif_ifd_offset + 2 + 12 + 4;
+        bytes.extend_from_slice(&1_u16.to_be_bytes());
+        bytes.extend_from_slice(&Exif::TAG_DATE_TIME_ORIGINAL.to_be_bytes());
+        bytes.extend_from_slice(&2_u16.to_be_bytes());
+        bytes.extend_from_slice(&20_u32.to_be_bytes());
+        bytes.extend_from_slice(&date_offset.to_be_bytes());
+        bytes.extend_from_slice(&0_u32.to_be_bytes());
+        bytes.extend_from_slice(b"2023:06:01 12:30:00\0");
+        bytes
+    }
+
+    #[test]
+    fn test_parse_common_tags() {
+        let exif = Exif::try_from(testing_exif_bytes().as_ref()).unwrap();
+        assert_eq!(exif.byte_order(), ByteOrder::BigEndian);
+        assert_eq!(exif.orientation(), Some(6));
+        assert_eq!(exif.camera_make(), Some("Canon"));
+        assert_eq!(exif.camera_model(), None);
+        assert_eq!(exif.resolution(), Some((300.0, 72.0)));
+        assert_eq!(exif.resolution_unit(), 2);
+        assert_eq!(exif.date_time(), Some("2023:06:01 12:30:00"));
+    }
+
+    #[test]
+    fn test_little_endian() {
+        let mut bytes: Vec<u8> = b"II\x2a\0".to_vec();
+        bytes.extend_from_slice(&8_u32.to_le_bytes());
+        bytes.extend_from_slice(&1_u16.to_le_bytes());
+        bytes.extend_from_slice(&Exif::TAG_ORIENTATION.to_le_bytes());
+        bytes.extend_from_slice(&3_u16.to_le_bytes());
+        bytes.extend_from_slice(&1_u32.to_le_bytes());
+        bytes.extend_from_slice(&[3, 0, 0, 0]);
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+
+        let exif = Exif::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(exif.orientation(), Some(3));
+        assert_eq!(exif.as_bytes(), &bytes[..]);
+    }
+
+    #[test]
+    fn test_invalid_exif() {
+        assert!(Exif::try_from(&b"XX\0\x2a"[..]).is_err());
+        assert!(Exif::try_from(&b"MM\0\x2b\0\0\0\x08"[..]).is_err());
+
+        let mut truncated = testing_exif_bytes();
+        truncated.truncate(40);
+        assert!(Exif::try_from(truncated.as_ref()).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    convert::ConvertOptions,
+    format::{decode_with, encode_with, Codec},
+    image::Image,
+    info::{FormatDetails, ImageInfo, MetadataInfo},
+    metadata::Metadata,
+    png::Png,
+    scanline::RowSource,
+    ConvertibleImage, Result,
+};
+
+use self::header::FarbfeldHeader;
+
+pub mod header;
+pub mod image_data;
+
+/// Registry entry for farbfeld files
+pub const CODEC: Codec = Codec {
+    name: "farbfeld",
+    extensions: &["ff"],
+    magic: &[FarbfeldHeader::MAGIC],
+    check_signature: None,
+    decoder: Some(decode_with::<Farbfeld>),
+    encoder: Some(encode_with::<Farbfeld>),
+    row_decoder: Some(read_farbfeld_rows),
+    row_encoder: Some(write_farbfeld_rows),
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a farbfeld file from its header for the registry
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let header = FarbfeldHeader::read(&mut StreamReader::new("farbfeld", BufReader::new(reader)))?;
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: header.width,
+        height: header.height,
+        color_model: "rgba",
+        bit_depth: 16,
+        compression: "none",
+        interlaced: false,
+        palette_size: None,
+        metadata: MetadataInfo::default(),
+        details: FormatDetails::Farbfeld,
+        warnings: Vec::new(),
+    })
+}
+
+/// Reads a farbfeld file a row at a time for the registry
+fn read_farbfeld_rows<'a>(
+    reader: &'a mut dyn Read,
+    _options: &ConvertOptions,
+) -> Result<Box<dyn RowSource + 'a>> {
+    image_data::decode_rows(reader)
+}
+
+/// Writes rows out as a farbfeld file for the registry; farbfeld has nowhere to put metadata
+fn write_farbfeld_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    _options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    image_data::encode_rows(source, writer)
+}
+
+/// A farbfeld file: a size and 16-bit big-endian RGBA pixels, uncompressed
+pub struct Farbfeld {
+    header: FarbfeldHeader,
+    /// The pixels, as stored
+    data: Vec<u8>,
+}
+
+impl Farbfeld {
+    pub fn header(&self) -> &FarbfeldHeader {
+        &self.header
+    }
+
+    pub fn image_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decodes the pixels into a 16-bit RGBA `Image`
+    pub fn to_image(&self) -> Result<Image> {
+        image_data::decode(self)
+    }
+
+    /// Encodes `image` as a new `Farbfeld`
+    pub fn from_image(image: &Image) -> Result<Farbfeld> {
+        image_data::encode(image)
+    }
+}
+
+impl TryFrom<&[u8]> for Farbfeld {
+    type Error = crate::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Farbfeld::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Farbfeld {
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("farbfeld", BufReader::new(reader));
+        let header = FarbfeldHeader::read(&mut reader)?;
+        Ok(Farbfeld {
+            header,
+            data: reader.read_rest()?,
+        })
+    }
+
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.header.as_bytes())?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Farbfeld::from_image(&png.to_image()?)
+    }
+
+    fn to_png(&self) -> Result<Png> {
+        Png::from_image(&self.to_image()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{image::ColorModel, Error};
+
+    #[test]
+    fn test_round_trip() {
+        let gray = Image::new(
+            3,
+            1,
+            ColorModel::GrayAlpha,
+            8,
+            vec![0, 255, 0x12, 0x80, 255, 0],
+        )
+        .unwrap();
+        let bytes = Farbfeld::from_image(&gray).unwrap().to_bytes();
+        assert_eq!(&bytes[..16], b"farbfeld\0\0\0\x03\0\0\0\x01");
+        assert_eq!(
+            &bytes[24..32],
+            [0x12, 0x12, 0x12, 0x12, 0x12, 0x12, 0x80, 0x80]
+        );
+
+        let farbfeld = Farbfeld::try_from(bytes.as_slice()).unwrap();
+        let image = farbfeld.to_image().unwrap();
+        assert_eq!(
+            (image.color_model(), image.bit_depth()),
+            (ColorModel::Rgba, 16)
+        );
+        assert_eq!(Farbfeld::from_image(&image).unwrap().to_bytes(), bytes);
+        assert_eq!(
+            farbfeld.to_png().unwrap().to_image().unwrap().data(),
+            image.data()
+        );
+
+        let truncated = Farbfeld::try_from(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(truncated.to_image(), Err(Error::Malformed { .. })));
+        assert!(Farbfeld::try_from(&b"farbfeld\0\0\0\0\0\0\0\x01"[..]).is_err());
+
+        // a forged size is caught from the header, or from the pixels that follow it
+        let wide = b"farbfeld\xff\xff\xff\xff\0\0\0\x01";
+        assert!(matches!(
+            Farbfeld::try_from(&wide[..]),
+            Err(Error::Malformed {
+                offset: Some(8),
+                ..
+            })
+        ));
+        let mut tall = bytes.clone();
+        tall[12..16].copy_from_slice(&[0xff; 4]);
+        assert!(matches!(
+            Farbfeld::try_from(tall.as_slice()).unwrap().to_image(),
+            Err(Error::Malformed {
+                offset: Some(40),
+                ..
+            })
+        ));
+    }
+}
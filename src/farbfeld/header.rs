@@ -0,0 +1,63 @@
+use std::io::BufRead;
+
+use crate::{
+    byte_reader::StreamReader,
+    image::ColorModel,
+    scanline::{RowLayout, MAX_ROW_LEN},
+    Error, Result,
+};
+
+/// The 16 bytes a farbfeld file starts with: the magic and the image size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FarbfeldHeader {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FarbfeldHeader {
+    pub const LENGTH: usize = 16;
+    pub const MAGIC: &'static [u8; 8] = b"farbfeld";
+
+    /// Reads the header, leaving `reader` at the first pixel
+    pub fn read<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Self> {
+        if &reader.read_array::<8>()? != FarbfeldHeader::MAGIC {
+            return Err(Error::malformed_at("farbfeld", 0, "invalid signature"));
+        }
+        let width = reader.read_u32_be()?;
+        let height = reader.read_u32_be()?;
+        if width == 0 || height == 0 {
+            return Err(Error::malformed_at(
+                "farbfeld",
+                8,
+                format!("{width}x{height} is not a valid image size"),
+            ));
+        }
+        let header = FarbfeldHeader { width, height };
+        if header.layout().row_len() > MAX_ROW_LEN {
+            return Err(Error::malformed_at(
+                "farbfeld",
+                8,
+                format!("rows {width} pixels wide are too long to read"),
+            ));
+        }
+        Ok(header)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(FarbfeldHeader::LENGTH);
+        bytes.extend(FarbfeldHeader::MAGIC);
+        bytes.extend(self.width.to_be_bytes());
+        bytes.extend(self.height.to_be_bytes());
+        bytes
+    }
+
+    /// How the pixels are laid out, which is always 16-bit RGBA
+    pub fn layout(&self) -> RowLayout {
+        RowLayout {
+            width: self.width,
+            height: self.height,
+            color_model: ColorModel::Rgba,
+            bit_depth: 16,
+        }
+    }
+}
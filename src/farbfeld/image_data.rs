@@ -0,0 +1,116 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    image::Image,
+    metadata::Metadata,
+    scanline::{read_image, ImageRows, RowLayout, RowSource},
+    Error, Result,
+};
+
+use super::{header::FarbfeldHeader, Farbfeld};
+
+/// Decodes the pixels of `farbfeld` into an `Image`
+pub fn decode(farbfeld: &Farbfeld) -> Result<Image> {
+    // the pixels are all in memory, so a size they can't fill is caught before decoding
+    let layout = farbfeld.header().layout();
+    let expected = layout.row_len() as u64 * layout.height as u64;
+    let found = farbfeld.image_data().len();
+    if (found as u64) < expected {
+        return Err(Error::malformed_at(
+            "farbfeld",
+            FarbfeldHeader::LENGTH + found,
+            format!("the pixels end after {found} of {expected} bytes"),
+        ));
+    }
+    read_image(&mut FarbfeldRows::new(
+        *farbfeld.header(),
+        StreamReader::new("farbfeld", farbfeld.image_data()),
+    ))
+}
+
+/// Reads a farbfeld file from `reader` a row at a time
+pub fn decode_rows(reader: &mut dyn Read) -> Result<Box<dyn RowSource + '_>> {
+    let mut reader = StreamReader::new("farbfeld", BufReader::new(reader));
+    let header = FarbfeldHeader::read(&mut reader)?;
+    Ok(Box::new(FarbfeldRows::new(header, reader)))
+}
+
+/// Reads the size of a farbfeld file from its header
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    Ok(FarbfeldHeader::read(&mut StreamReader::new("farbfeld", BufReader::new(reader)))?.layout())
+}
+
+/// Hands out the rows of a farbfeld file, which are already laid out as `Image` keeps them
+struct FarbfeldRows<R> {
+    layout: RowLayout,
+    reader: StreamReader<R>,
+    metadata: Metadata,
+}
+
+impl<R: BufRead> FarbfeldRows<R> {
+    fn new(header: FarbfeldHeader, reader: StreamReader<R>) -> Self {
+        FarbfeldRows {
+            layout: header.layout(),
+            reader,
+            metadata: Metadata::default(),
+        }
+    }
+}
+
+impl<R: BufRead> RowSource for FarbfeldRows<R> {
+    fn layout(&self) -> RowLayout {
+        self.layout
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        self.reader.fill(row)
+    }
+}
+
+/// Encodes `image` as a new `Farbfeld`, widening its samples to 16-bit RGBA
+pub fn encode(image: &Image) -> Result<Farbfeld> {
+    let header = FarbfeldHeader {
+        width: image.width(),
+        height: image.height(),
+    };
+    let mut data: Vec<u8> = Vec::with_capacity(header.layout().row_len() * image.height() as usize);
+    encode_pixels(&mut ImageRows::new(image.clone()), &mut data)?;
+    Ok(Farbfeld { header, data })
+}
+
+/// Writes the rows of `source` out as a farbfeld file, header first
+pub fn encode_rows(source: &mut dyn RowSource, writer: &mut dyn Write) -> Result<()> {
+    let layout = source.layout();
+    let header = FarbfeldHeader {
+        width: layout.width,
+        height: layout.height,
+    };
+    writer.write_all(&header.as_bytes())?;
+    encode_pixels(source, writer)
+}
+
+/// Writes the rows of `source` as 16-bit big-endian RGBA
+fn encode_pixels(source: &mut dyn RowSource, writer: &mut dyn Write) -> Result<()> {
+    let layout = source.layout();
+    let mut row: Vec<u8> = vec![0; layout.row_len()];
+    let mut out: Vec<u8> = Vec::with_capacity(layout.width as usize * 8);
+    for _ in 0..layout.height {
+        source.read_row(&mut row)?;
+        out.clear();
+        for x in 0..layout.width as usize {
+            out.extend(
+                layout
+                    .rgba16(&row, x)
+                    .iter()
+                    .flat_map(|sample| sample.to_be_bytes()),
+            );
+        }
+        writer.write_all(&out)?;
+    }
+    Ok(())
+}
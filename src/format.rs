@@ -0,0 +1,396 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+use crate::{
+    bmp,
+    convert::ConvertOptions,
+    farbfeld, gif, ico,
+    info::ImageInfo,
+    jpeg,
+    metadata::Metadata,
+    netpbm, png,
+    png::Png,
+    qoi, raw,
+    scanline::{RowLayout, RowSource},
+    tga, tiff, webp, ConvertibleImage, Error, Result,
+};
+
+/// Reads a file from a stream into one of the crate's image types. Only formats with no
+/// header to describe their pixels, such as raw dumps, look at the options.
+pub type Decoder = fn(&mut dyn Read, &ConvertOptions) -> Result<Box<dyn ConvertibleImage>>;
+
+/// Writes a `Png`, the format every conversion passes through, out in a codec's own format
+pub type Encoder = fn(Png, &ConvertOptions, &mut dyn Write) -> Result<()>;
+
+/// Reads a file from a stream one row at a time, for images too large to decode whole
+pub type RowDecoder =
+    for<'a> fn(&'a mut dyn Read, &ConvertOptions) -> Result<Box<dyn RowSource + 'a>>;
+
+/// Writes rows out in a codec's own format, recording `Metadata` in place of the source's
+pub type RowEncoder =
+    fn(&mut dyn RowSource, &Metadata, &ConvertOptions, &mut dyn Write) -> Result<()>;
+
+/// Reads just enough of a file to tell how large the image is once decoded. Formats that
+/// only settle on a color model after seeing the pixels report the widest they might use.
+pub type Probe = fn(&mut dyn Read) -> Result<RowLayout>;
+
+/// Describes a file from its headers and other structure, without decoding its pixels
+pub type Inspector = fn(&mut dyn Read) -> Result<ImageInfo>;
+
+/// Describes one image format: how to recognise it and what this crate can do with it
+#[derive(Clone, Copy)]
+pub struct Codec {
+    pub name: &'static str,
+    /// Lowercase file extensions, without the leading dot
+    pub extensions: &'static [&'static str],
+    /// Signatures that files in this format start with
+    pub magic: &'static [&'static [u8]],
+    /// Looks further into the start of a file whose signature matched, for signatures
+    /// short enough to turn up at the start of other formats' files. Sees at most
+    /// `Registry::signature_len` bytes.
+    pub check_signature: Option<fn(&[u8]) -> bool>,
+    pub decoder: Option<Decoder>,
+    pub encoder: Option<Encoder>,
+    pub row_decoder: Option<RowDecoder>,
+    pub row_encoder: Option<RowEncoder>,
+    pub probe: Option<Probe>,
+    pub inspector: Option<Inspector>,
+}
+
+impl Codec {
+    pub fn can_decode(&self) -> bool {
+        self.decoder.is_some()
+    }
+
+    pub fn can_encode(&self) -> bool {
+        self.encoder.is_some()
+    }
+
+    /// Whether images can be read from this format a row at a time
+    pub fn can_decode_rows(&self) -> bool {
+        self.row_decoder.is_some()
+    }
+
+    /// Whether images can be written to this format a row at a time
+    pub fn can_encode_rows(&self) -> bool {
+        self.row_encoder.is_some()
+    }
+
+    /// Whether an image's size can be read from its headers alone
+    pub fn can_probe(&self) -> bool {
+        self.probe.is_some()
+    }
+
+    pub fn matches_extension(&self, extension: &str) -> bool {
+        self.extensions
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(extension))
+    }
+
+    pub fn matches_magic(&self, bytes: &[u8]) -> bool {
+        self.magic.iter().any(|magic| bytes.starts_with(magic))
+            && self.check_signature.is_none_or(|check| check(bytes))
+    }
+
+    pub fn decode(
+        &self,
+        reader: &mut dyn Read,
+        options: &ConvertOptions,
+    ) -> Result<Box<dyn ConvertibleImage>> {
+        match self.decoder {
+            Some(decoder) => decoder(reader, options),
+            None => Err(Error::unsupported(self.name, "decoding")),
+        }
+    }
+
+    pub fn encode(&self, png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+        match self.encoder {
+            Some(encoder) => encoder(png, options, writer),
+            None => Err(Error::unsupported(self.name, "encoding")),
+        }
+    }
+
+    pub fn decode_rows<'a>(
+        &self,
+        reader: &'a mut dyn Read,
+        options: &ConvertOptions,
+    ) -> Result<Box<dyn RowSource + 'a>> {
+        match self.row_decoder {
+            Some(row_decoder) => row_decoder(reader, options),
+            None => Err(Error::unsupported(self.name, "decoding a row at a time")),
+        }
+    }
+
+    pub fn probe(&self, reader: &mut dyn Read) -> Result<RowLayout> {
+        match self.probe {
+            Some(probe) => probe(reader),
+            None => Err(Error::unsupported(
+                self.name,
+                "reading the image size alone",
+            )),
+        }
+    }
+
+    pub fn inspect(&self, reader: &mut dyn Read) -> Result<ImageInfo> {
+        match self.inspector {
+            Some(inspector) => inspector(reader),
+            None => Err(Error::unsupported(self.name, "describing files")),
+        }
+    }
+
+    pub fn encode_rows(
+        &self,
+        source: &mut dyn RowSource,
+        metadata: &Metadata,
+        options: &ConvertOptions,
+        writer: &mut dyn Write,
+    ) -> Result<()> {
+        match self.row_encoder {
+            Some(row_encoder) => row_encoder(source, metadata, options, writer),
+            None => Err(Error::unsupported(self.name, "encoding a row at a time")),
+        }
+    }
+}
+
+/// A `Decoder` for any format whose image type reads itself with `from_reader`
+pub fn decode_with<T: ConvertibleImage + 'static>(
+    reader: &mut dyn Read,
+    _options: &ConvertOptions,
+) -> Result<Box<dyn ConvertibleImage>> {
+    Ok(Box::new(T::from_reader(reader)?))
+}
+
+/// An `Encoder` for any format that is built from a `Png` without further options
+pub fn encode_with<T: ConvertibleImage>(
+    png: Png,
+    _options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    T::from_png(png)?.to_writer(writer)
+}
+
+/// The outcome of working out which format an input file is in
+#[derive(Clone, Copy)]
+pub struct Detection<'a> {
+    pub codec: &'a Codec,
+    /// The format the file's extension points at, when it disagrees with the file's contents
+    pub extension_mismatch: Option<&'a Codec>,
+}
+
+/// The set of formats a conversion can read from or write to
+#[derive(Clone)]
+pub struct Registry {
+    codecs: Vec<Codec>,
+}
+
+impl Registry {
+    /// Creates a registry with no formats in it
+    pub fn empty() -> Self {
+        Registry { codecs: Vec::new() }
+    }
+
+    /// Adds `codec`; formats registered earlier win when extensions or signatures overlap
+    pub fn register(&mut self, codec: Codec) {
+        self.codecs.push(codec);
+    }
+
+    pub fn codecs(&self) -> &[Codec] {
+        &self.codecs
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Codec> {
+        self.codecs
+            .iter()
+            .find(|codec| codec.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn by_extension(&self, extension: &str) -> Option<&Codec> {
+        self.codecs
+            .iter()
+            .find(|codec| codec.matches_extension(extension))
+    }
+
+    /// Looks up a format by its name or one of its extensions, as a user would write it
+    pub fn by_format(&self, format: &str) -> Result<&Codec> {
+        let format = format.strip_prefix('.').unwrap_or(format);
+        self.by_name(format)
+            .or_else(|| self.by_extension(format))
+            .ok_or_else(|| Error::unsupported("image", format!("the \"{format}\" format")))
+    }
+
+    /// Looks up the format for `path` from its extension
+    pub fn by_path(&self, path: &Path) -> Result<&Codec> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .ok_or_else(|| {
+                Error::invalid_argument(format!(
+                    "{} has no file extension to tell its format from",
+                    path.display()
+                ))
+            })?;
+        self.by_extension(extension).ok_or_else(|| {
+            Error::unsupported("image", format!("files with extension .{extension}"))
+        })
+    }
+
+    /// How many bytes from the start of a file `sniff` needs to see to recognise any format
+    pub fn signature_len(&self) -> usize {
+        self.codecs
+            .iter()
+            .flat_map(|codec| codec.magic.iter())
+            .map(|magic| magic.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Looks up the format whose signature `bytes` starts with
+    pub fn sniff(&self, bytes: &[u8]) -> Option<&Codec> {
+        self.codecs.iter().find(|codec| codec.matches_magic(bytes))
+    }
+
+    /// Works out the format of the file at `path` from its contents, falling back to its
+    /// extension for formats without a signature. When the contents fit the extension's
+    /// format, as a P6 file named .pnm does, the extension's format is the one used.
+    pub fn detect(&self, path: &Path, bytes: &[u8]) -> Result<Detection<'_>> {
+        let by_extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.by_extension(extension));
+        let sniffed = match by_extension {
+            Some(codec) if codec.matches_magic(bytes) => Some(codec),
+            _ => self.sniff(bytes),
+        };
+        match (sniffed, by_extension) {
+            (Some(codec), by_extension) => Ok(Detection {
+                codec,
+                extension_mismatch: by_extension.filter(|other| other.name != codec.name),
+            }),
+            (None, Some(codec)) => Ok(Detection {
+                codec,
+                extension_mismatch: None,
+            }),
+            (None, None) => Err(Error::unsupported(
+                "image",
+                format!("{}, which isn't in a recognised format", path.display()),
+            )),
+        }
+    }
+}
+
+impl Default for Registry {
+    /// Creates a registry holding every format this crate supports
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        registry.register(png::CODEC);
+        registry.register(bmp::CODEC);
+        registry.register(netpbm::PBM_CODEC);
+        registry.register(netpbm::PGM_CODEC);
+        registry.register(netpbm::PPM_CODEC);
+        registry.register(netpbm::PAM_CODEC);
+        registry.register(netpbm::PNM_CODEC);
+        registry.register(tga::CODEC);
+        registry.register(qoi::CODEC);
+        registry.register(gif::CODEC);
+        registry.register(ico::ICO_CODEC);
+        registry.register(ico::CUR_CODEC);
+        registry.register(tiff::CODEC);
+        registry.register(jpeg::CODEC);
+        registry.register(webp::CODEC);
+        registry.register(farbfeld::CODEC);
+        registry.register(raw::CODEC);
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let registry = Registry::default();
+        assert_eq!(registry.by_extension("PNG").unwrap().name, "PNG");
+        assert_eq!(registry.by_name("bmp").unwrap().name, "BMP");
+        assert_eq!(
+            registry.by_path(Path::new("photo.dib")).unwrap().name,
+            "BMP"
+        );
+        assert!(matches!(
+            registry.by_path(Path::new("photo.xyz")),
+            Err(Error::Unsupported { .. })
+        ));
+        assert!(matches!(
+            registry.by_path(Path::new("photo")),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert_eq!(registry.by_format("png").unwrap().name, "PNG");
+        assert_eq!(registry.by_format(".DIB").unwrap().name, "BMP");
+        assert!(registry.by_format("xyz").is_err());
+        assert_eq!(registry.signature_len(), Png::STANDARD_HEADER.len());
+    }
+
+    #[test]
+    fn test_detect() {
+        let registry = Registry::default();
+        let png_bytes = Png::STANDARD_HEADER;
+
+        let detection = registry.detect(Path::new("image"), &png_bytes).unwrap();
+        assert_eq!(detection.codec.name, "PNG");
+        assert!(detection.extension_mismatch.is_none());
+
+        let detection = registry.detect(Path::new("image.bmp"), &png_bytes).unwrap();
+        assert_eq!(detection.codec.name, "PNG");
+        assert_eq!(detection.extension_mismatch.unwrap().name, "BMP");
+
+        let detection = registry.detect(Path::new("image.BMP"), b"??").unwrap();
+        assert_eq!(detection.codec.name, "BMP");
+        assert!(registry.detect(Path::new("image"), b"??").is_err());
+    }
+
+    #[test]
+    fn test_probe() {
+        use crate::image::{ColorModel, Image};
+
+        let image = Image::new(3, 2, ColorModel::Rgb, 16, vec![0; 36]).unwrap();
+        let png = Png::from_image(&image).unwrap().to_bytes();
+        let layout = png::CODEC.probe(&mut &png[..]).unwrap();
+        assert_eq!((layout.width, layout.height, layout.bit_depth), (3, 2, 16));
+        // a tRNS chunk could still turn up, so room is left for alpha
+        assert_eq!(layout.color_model, ColorModel::Rgba);
+
+        let mut reduced = image.clone();
+        reduced.reduce_depth();
+        let bmp = bmp::Bmp::from_png(Png::from_image(&reduced).unwrap())
+            .unwrap()
+            .to_bytes();
+        let layout = bmp::CODEC.probe(&mut &bmp[..]).unwrap();
+        assert_eq!((layout.width, layout.height, layout.bit_depth), (3, 2, 8));
+        assert!(png::CODEC.probe(&mut &bmp[..]).is_err());
+    }
+
+    #[test]
+    fn test_missing_capabilities() {
+        let mut registry = Registry::empty();
+        registry.register(Codec {
+            name: "TEST",
+            extensions: &["test"],
+            magic: &[b"TEST"],
+            check_signature: None,
+            decoder: None,
+            encoder: None,
+            row_decoder: None,
+            row_encoder: None,
+            probe: None,
+            inspector: None,
+        });
+        let codec = registry.by_extension("test").unwrap();
+        assert!(codec.matches_magic(b"TEST data"));
+        assert!(matches!(
+            codec.decode(&mut &b"TEST data"[..], &ConvertOptions::default()),
+            Err(Error::Unsupported { .. })
+        ));
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    time::Duration,
+};
+
+use crate::{
+    animation::{Animation, Frame},
+    byte_reader::StreamReader,
+    convert::ConvertOptions,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{FormatDetails, GifDetails, ImageInfo, MetadataInfo},
+    png::{options::PngOptions, Png},
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    block::{
+        Block, ImageBlock, ScreenDescriptor, COMMENT_LABEL, EXTENSION_INTRODUCER, IMAGE_SEPARATOR,
+        TRAILER,
+    },
+    options::GifOptions,
+};
+
+pub mod block;
+pub mod image_data;
+pub mod lzw;
+pub mod options;
+
+/// Registry entry for GIF files
+pub const CODEC: Codec = Codec {
+    name: "GIF",
+    extensions: &["gif"],
+    magic: &[Gif::VERSION_87A, Gif::VERSION_89A],
+    check_signature: None,
+    decoder: Some(decode_with::<Gif>),
+    encoder: Some(write_gif),
+    row_decoder: None,
+    row_encoder: None,
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a GIF from its blocks for the registry, without decompressing any image
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let gif = Gif::from_reader(reader)?;
+    let images: Vec<&ImageBlock> = gif.images().collect();
+    let palette_size = match gif.global_color_table() {
+        [] => images
+            .first()
+            .map(|image| image.local_color_table.len() / 3),
+        table => Some(table.len() / 3),
+    };
+    let comments = gif
+        .blocks()
+        .iter()
+        .filter_map(|block| match block {
+            Block::Extension {
+                label: COMMENT_LABEL,
+                sub_blocks,
+            } => Some(String::from_utf8_lossy(&sub_blocks.concat()).into_owned()),
+            _ => None,
+        })
+        .collect();
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: gif.screen().width as u32,
+        height: gif.screen().height as u32,
+        color_model: "indexed",
+        bit_depth: 8,
+        compression: "LZW",
+        interlaced: images.iter().any(|image| image.interlaced()),
+        palette_size,
+        metadata: MetadataInfo::default(),
+        details: FormatDetails::Gif(GifDetails {
+            version: String::from_utf8_lossy(&gif.version[3..]).into_owned(),
+            frames: images.len(),
+            loop_count: gif.loop_count(),
+            local_color_tables: images
+                .iter()
+                .filter(|image| !image.local_color_table.is_empty())
+                .count(),
+            background_index: gif.screen().background_index,
+            comments,
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// Encodes `png`, animated or not, as a GIF for the registry
+fn write_gif(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    Gif::from_animation_with(&animation_of(&png)?, &options.gif)?.to_writer(writer)
+}
+
+/// The frames of `png`, which is a single frame shown once unless it's an APNG
+fn animation_of(png: &Png) -> Result<Animation> {
+    match png.to_animation()? {
+        Some(animation) => Ok(animation),
+        None => Animation::new(
+            vec![Frame {
+                image: png.to_image()?,
+                delay: Duration::ZERO,
+            }],
+            1,
+        ),
+    }
+}
+
+pub struct Gif {
+    /// "GIF87a" or "GIF89a"
+    version: [u8; 6],
+    screen: ScreenDescriptor,
+    /// RGB triples, empty if there is no global color table
+    global_color_table: Vec<u8>,
+    blocks: Vec<Block>,
+}
+
+impl Gif {
+    pub const VERSION_87A: &'static [u8; 6] = b"GIF87a";
+    pub const VERSION_89A: &'static [u8; 6] = b"GIF89a";
+
+    /// Reads the signature and version, which have to be one of the two this crate knows
+    pub fn read_signature<R: BufRead>(reader: &mut StreamReader<R>) -> Result<[u8; 6]> {
+        let version: [u8; 6] = reader.read_array()?;
+        if &version != Gif::VERSION_87A && &version != Gif::VERSION_89A {
+            return Err(Error::malformed_at("GIF", 0, "invalid signature"));
+        }
+        Ok(version)
+    }
+
+    pub fn version(&self) -> &[u8; 6] {
+        &self.version
+    }
+
+    pub fn screen(&self) -> &ScreenDescriptor {
+        &self.screen
+    }
+
+    pub fn global_color_table(&self) -> &[u8] {
+        &self.global_color_table
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = &ImageBlock> {
+        self.blocks.iter().filter_map(|block| match block {
+            Block::Image(image) => Some(image),
+            Block::Extension { .. } => None,
+        })
+    }
+
+    /// Times to repeat the animation after the first play, from the NETSCAPE2.0
+    /// extension; 0 means forever, and `None` that it plays once
+    pub fn loop_count(&self) -> Option<u16> {
+        self.blocks.iter().find_map(Block::loop_count)
+    }
+
+    /// Decodes every frame, each composited onto the ones before it
+    pub fn to_animation(&self) -> Result<Animation> {
+        image_data::decode(self)
+    }
+
+    /// Decodes the first frame into an `Image`
+    pub fn to_image(&self) -> Result<Image> {
+        let animation = self.to_animation()?;
+        Ok(animation.frames()[0].image.clone())
+    }
+
+    /// Encodes `image` as a new single-frame `Gif`
+    pub fn from_image(image: &Image) -> Result<Gif> {
+        let frame = Frame {
+            image: image.clone(),
+            delay: Duration::ZERO,
+        };
+        image_data::encode(&Animation::new(vec![frame], 1)?, &GifOptions::default())
+    }
+
+    /// Encodes `animation` as a new `Gif` laid out as `options` asks
+    pub fn from_animation_with(animation: &Animation, options: &GifOptions) -> Result<Gif> {
+        image_data::encode(animation, options)
+    }
+}
+
+impl TryFrom<&[u8]> for Gif {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Gif::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Gif {
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("GIF", BufReader::new(reader));
+        let version = Gif::read_signature(&mut reader)?;
+        let screen = ScreenDescriptor::read(&mut reader)?;
+        let global_color_table = match screen.color_table_len() {
+            Some(len) => reader.read_bytes(len * 3)?,
+            None => Vec::new(),
+        };
+        let mut blocks: Vec<Block> = Vec::new();
+        loop {
+            // some encoders stop after the last block, which reads the same as a trailer
+            if reader.is_empty()? {
+                break;
+            }
+            match reader.read_u8()? {
+                IMAGE_SEPARATOR => blocks.push(Block::Image(ImageBlock::read(&mut reader)?)),
+                EXTENSION_INTRODUCER => blocks.push(Block::Extension {
+                    label: reader.read_u8()?,
+                    sub_blocks: block::read_sub_blocks(&mut reader)?,
+                }),
+                TRAILER => break,
+                byte => {
+                    return Err(Error::malformed_at(
+                        "GIF",
+                        reader.position() - 1,
+                        format!("unknown block introducer {byte:#04x}"),
+                    ))
+                }
+            }
+        }
+        Ok(Gif {
+            version,
+            screen,
+            global_color_table,
+            blocks,
+        })
+    }
+
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.version)?;
+        writer.write_all(&self.screen.as_bytes())?;
+        writer.write_all(&self.global_color_table)?;
+        for block in &self.blocks {
+            block.write(writer)?;
+        }
+        writer.write_all(&[TRAILER])?;
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Gif::from_animation_with(&animation_of(&png)?, &GifOptions::default())
+    }
+
+    /// Single images become a plain PNG and animations an APNG
+    fn to_png(&self) -> Result<Png> {
+        let animation = self.to_animation()?;
+        match animation.frames() {
+            [frame] => Png::from_image(&frame.image),
+            _ => Png::from_animation(&animation, &PngOptions::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ColorModel;
+
+    /// Three 4x3 frames cycling a red, green and blue bar across a transparent background
+    fn test_animation() -> Animation {
+        let frames = (0..3)
+            .map(|frame| {
+                let mut data: Vec<u8> = vec![0; 4 * 3 * 4];
+                for y in 0..3 {
+                    let at = (y * 4 + frame) * 4;
+                    data[at + frame] = 255;
+                    data[at + 3] = 255;
+                }
+                Frame {
+                    image: Image::new(4, 3, ColorModel::Rgba, 8, data).unwrap(),
+                    delay: Duration::from_millis(120),
+                }
+            })
+            .collect();
+        Animation::new(frames, 3).unwrap()
+    }
+
+    #[test]
+    fn test_apng_round_trip() {
+        let animation = test_animation();
+        let options = GifOptions { interlace: true };
+        let bytes = Gif::from_animation_with(&animation, &options)
+            .unwrap()
+            .to_bytes();
+        let gif = Gif::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(gif.version(), Gif::VERSION_89A);
+        assert_eq!(gif.loop_count(), Some(2));
+        assert_eq!(gif.images().count(), 3);
+        assert!(gif.images().all(ImageBlock::interlaced));
+
+        let apng = Png::try_from(gif.to_png().unwrap().to_bytes().as_slice()).unwrap();
+        assert!(apng.is_animated());
+        let back = Gif::from_png(apng).unwrap().to_animation().unwrap();
+        assert_eq!(back.plays(), 3);
+        for (back, frame) in back.frames().iter().zip(animation.frames()) {
+            assert_eq!(back.image.data(), frame.image.data());
+            assert_eq!(back.delay, frame.delay);
+        }
+    }
+
+    #[test]
+    fn test_still_image() {
+        let image = Image::new(2, 2, ColorModel::Gray, 8, vec![0, 85, 170, 255]).unwrap();
+        let gif = Gif::from_image(&image).unwrap();
+        assert_eq!(gif.version(), Gif::VERSION_87A);
+        assert_eq!(gif.loop_count(), None);
+        let bytes = gif.to_bytes();
+        let png = Gif::try_from(bytes.as_slice()).unwrap().to_png().unwrap();
+        assert!(!png.is_animated());
+        let decoded = png.to_image().unwrap();
+        assert_eq!(decoded.color_model(), ColorModel::Rgb);
+        assert_eq!(
+            decoded.data(),
+            [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255]
+        );
+
+        let untrailed = Gif::try_from(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            untrailed.to_image().unwrap().data(),
+            gif.to_image().unwrap().data()
+        );
+        assert!(Gif::try_from(&bytes[..bytes.len() - 2]).is_err());
+        let mut bad = bytes.clone();
+        bad[4] = b'8';
+        assert!(matches!(
+            Gif::try_from(bad.as_slice()),
+            Err(Error::Malformed {
+                offset: Some(0),
+                ..
+            })
+        ));
+    }
+}
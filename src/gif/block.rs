@@ -0,0 +1,308 @@
+use std::io::{BufRead, Write};
+
+use crate::{byte_reader::StreamReader, Result};
+
+/// Introduces an extension block
+pub const EXTENSION_INTRODUCER: u8 = 0x21;
+/// Introduces an image descriptor
+pub const IMAGE_SEPARATOR: u8 = 0x2c;
+/// Ends the file
+pub const TRAILER: u8 = 0x3b;
+
+/// Extension labels
+pub const GRAPHIC_CONTROL_LABEL: u8 = 0xf9;
+pub const COMMENT_LABEL: u8 = 0xfe;
+pub const APPLICATION_LABEL: u8 = 0xff;
+
+/// Application identifier and authentication code of the looping extension
+pub const NETSCAPE_LOOP: &[u8; 11] = b"NETSCAPE2.0";
+
+/// Set in a packed field when a color table follows
+const COLOR_TABLE_FLAG: u8 = 0x80;
+/// Set in an image descriptor's packed field when its rows are interlaced
+const INTERLACE_FLAG: u8 = 0x40;
+
+/// The logical screen descriptor, which follows the signature
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScreenDescriptor {
+    pub width: u16,
+    pub height: u16,
+    /// Global color table flag, color resolution, sort flag and table size
+    pub flags: u8,
+    /// Global color table entry the rest of the screen shows
+    pub background_index: u8,
+    pub pixel_aspect: u8,
+}
+
+impl ScreenDescriptor {
+    pub fn read<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Self> {
+        Ok(ScreenDescriptor {
+            width: reader.read_u16_le()?,
+            height: reader.read_u16_le()?,
+            flags: reader.read_u8()?,
+            background_index: reader.read_u8()?,
+            pixel_aspect: reader.read_u8()?,
+        })
+    }
+
+    /// Entries in the global color table, if there is one
+    pub fn color_table_len(&self) -> Option<usize> {
+        color_table_len(self.flags)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(7);
+        bytes.extend(self.width.to_le_bytes());
+        bytes.extend(self.height.to_le_bytes());
+        bytes.extend([self.flags, self.background_index, self.pixel_aspect]);
+        bytes
+    }
+}
+
+/// The packed-field bits that announce a color table of `len` entries, rounded up to the
+/// next power of two the format allows
+pub fn color_table_flags(len: usize) -> u8 {
+    let size_bits = (1..=8).find(|bits| len <= 1 << bits).unwrap_or(8);
+    COLOR_TABLE_FLAG | (size_bits - 1)
+}
+
+fn color_table_len(flags: u8) -> Option<usize> {
+    (flags & COLOR_TABLE_FLAG != 0).then(|| 2 << (flags & 0x07))
+}
+
+/// A graphic control extension, which applies to the image that follows it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GraphicControl {
+    /// What happens to the image's area before the next one: 0 and 1 leave it, 2 clears it
+    /// to the background and 3 restores what was there before
+    pub disposal: u8,
+    pub user_input: bool,
+    /// Color index that isn't drawn
+    pub transparent_index: Option<u8>,
+    /// Hundredths of a second to wait before the next image
+    pub delay: u16,
+}
+
+impl GraphicControl {
+    pub const LEAVE: u8 = 1;
+    pub const RESTORE_BACKGROUND: u8 = 2;
+    pub const RESTORE_PREVIOUS: u8 = 3;
+
+    /// Parses the sub-blocks of a graphic control extension
+    pub fn parse(sub_blocks: &[Vec<u8>]) -> Option<Self> {
+        let data = sub_blocks.first().filter(|data| data.len() >= 4)?;
+        Some(GraphicControl {
+            disposal: data[0] >> 2 & 0x07,
+            user_input: data[0] & 0x02 != 0,
+            transparent_index: (data[0] & 0x01 != 0).then_some(data[3]),
+            delay: u16::from_le_bytes([data[1], data[2]]),
+        })
+    }
+
+    pub fn sub_blocks(&self) -> Vec<Vec<u8>> {
+        let flags = self.disposal << 2
+            | (self.user_input as u8) << 1
+            | self.transparent_index.is_some() as u8;
+        let [low, high] = self.delay.to_le_bytes();
+        vec![vec![flags, low, high, self.transparent_index.unwrap_or(0)]]
+    }
+}
+
+/// One image: where it sits on the screen, its colors and its LZW-compressed indices
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageBlock {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Local color table flag, interlace flag, sort flag and table size
+    pub flags: u8,
+    /// RGB triples, empty if the image uses the global color table
+    pub local_color_table: Vec<u8>,
+    pub min_code_size: u8,
+    /// The compressed data with its sub-block lengths taken out
+    pub data: Vec<u8>,
+}
+
+impl ImageBlock {
+    /// Reads the rest of an image once its separator has been read
+    pub fn read<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Self> {
+        let left = reader.read_u16_le()?;
+        let top = reader.read_u16_le()?;
+        let width = reader.read_u16_le()?;
+        let height = reader.read_u16_le()?;
+        let flags = reader.read_u8()?;
+        let local_color_table = match color_table_len(flags) {
+            Some(len) => reader.read_bytes(len * 3)?,
+            None => Vec::new(),
+        };
+        let min_code_size = reader.read_u8()?;
+        let data = read_sub_blocks(reader)?.concat();
+        Ok(ImageBlock {
+            left,
+            top,
+            width,
+            height,
+            flags,
+            local_color_table,
+            min_code_size,
+            data,
+        })
+    }
+
+    pub fn interlaced(&self) -> bool {
+        self.flags & INTERLACE_FLAG != 0
+    }
+
+    pub fn interlace_flag(interlaced: bool) -> u8 {
+        if interlaced {
+            INTERLACE_FLAG
+        } else {
+            0
+        }
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&[IMAGE_SEPARATOR])?;
+        for value in [self.left, self.top, self.width, self.height] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&[self.flags])?;
+        writer.write_all(&self.local_color_table)?;
+        writer.write_all(&[self.min_code_size])?;
+        write_sub_blocks(writer, self.data.chunks(255))
+    }
+}
+
+/// What follows the screen descriptor and global color table, in file order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Block {
+    Image(ImageBlock),
+    /// Any extension, kept as its label and sub-blocks
+    Extension {
+        label: u8,
+        sub_blocks: Vec<Vec<u8>>,
+    },
+}
+
+impl Block {
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        match self {
+            Block::Image(image) => image.write(writer),
+            Block::Extension { label, sub_blocks } => {
+                writer.write_all(&[EXTENSION_INTRODUCER, *label])?;
+                write_sub_blocks(writer, sub_blocks.iter().map(Vec::as_slice))
+            }
+        }
+    }
+
+    /// The loop count of a NETSCAPE2.0 application extension: how many times to repeat after
+    /// the first play, 0 meaning forever
+    pub fn loop_count(&self) -> Option<u16> {
+        match self {
+            Block::Extension {
+                label: APPLICATION_LABEL,
+                sub_blocks,
+            } if sub_blocks.first().is_some_and(|id| id == NETSCAPE_LOOP) => {
+                match sub_blocks.get(1).map(Vec::as_slice) {
+                    Some([1, low, high, ..]) => Some(u16::from_le_bytes([*low, *high])),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn netscape_loop(loop_count: u16) -> Block {
+        let [low, high] = loop_count.to_le_bytes();
+        Block::Extension {
+            label: APPLICATION_LABEL,
+            sub_blocks: vec![NETSCAPE_LOOP.to_vec(), vec![1, low, high]],
+        }
+    }
+}
+
+/// Reads sub-blocks up to and including the empty one that ends them
+pub fn read_sub_blocks<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Vec<Vec<u8>>> {
+    let mut sub_blocks: Vec<Vec<u8>> = Vec::new();
+    loop {
+        match reader.read_u8()? {
+            0 => return Ok(sub_blocks),
+            len => sub_blocks.push(reader.read_bytes(len as usize)?),
+        }
+    }
+}
+
+/// Writes each piece of `data` as a sub-block, then the empty block that ends them. Pieces
+/// have to be at most 255 bytes.
+fn write_sub_blocks<'a>(
+    writer: &mut dyn Write,
+    data: impl Iterator<Item = &'a [u8]>,
+) -> Result<()> {
+    for piece in data.filter(|piece| !piece.is_empty()) {
+        writer.write_all(&[piece.len() as u8])?;
+        writer.write_all(piece)?;
+    }
+    writer.write_all(&[0])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_round_trip() {
+        let image = ImageBlock {
+            left: 1,
+            top: 2,
+            width: 3,
+            height: 4,
+            flags: color_table_flags(3) | ImageBlock::interlace_flag(true),
+            local_color_table: vec![0; 12],
+            min_code_size: 2,
+            data: vec![7; 600],
+        };
+        assert_eq!(image.flags, 0xc1);
+        let blocks = [
+            Block::Image(image.clone()),
+            Block::netscape_loop(5),
+            Block::Extension {
+                label: GRAPHIC_CONTROL_LABEL,
+                sub_blocks: GraphicControl {
+                    disposal: GraphicControl::RESTORE_PREVIOUS,
+                    user_input: false,
+                    transparent_index: Some(9),
+                    delay: 300,
+                }
+                .sub_blocks(),
+            },
+        ];
+        let mut bytes: Vec<u8> = Vec::new();
+        for block in &blocks {
+            block.write(&mut bytes).unwrap();
+        }
+
+        let mut reader = StreamReader::new("GIF", &bytes[..]);
+        assert_eq!(reader.read_u8().unwrap(), IMAGE_SEPARATOR);
+        let read = ImageBlock::read(&mut reader).unwrap();
+        assert!(read.interlaced());
+        assert_eq!(read, image);
+        assert_eq!(
+            reader.read_array().unwrap(),
+            [EXTENSION_INTRODUCER, APPLICATION_LABEL]
+        );
+        let sub_blocks = read_sub_blocks(&mut reader).unwrap();
+        let looping = Block::Extension {
+            label: APPLICATION_LABEL,
+            sub_blocks,
+        };
+        assert_eq!(looping.loop_count(), Some(5));
+        reader.skip(2).unwrap();
+        let control = GraphicControl::parse(&read_sub_blocks(&mut reader).unwrap()).unwrap();
+        assert_eq!(control.transparent_index, Some(9));
+        assert_eq!(control.disposal, GraphicControl::RESTORE_PREVIOUS);
+        assert_eq!(control.delay, 300);
+        assert!(reader.is_empty().unwrap());
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    io::{BufReader, Read},
+    time::Duration,
+};
+
+use crate::{
+    animation::Animation,
+    byte_reader::StreamReader,
+    image::{ColorModel, Image},
+    scanline::RowLayout,
+    Error, Result,
+};
+
+use super::{
+    block::{
+        color_table_flags, Block, GraphicControl, ImageBlock, ScreenDescriptor,
+        GRAPHIC_CONTROL_LABEL,
+    },
+    lzw,
+    options::GifOptions,
+    Gif,
+};
+
+/// First row and row step of each of the four passes of an interlaced image
+const INTERLACE_PASSES: [(usize, usize); 4] = [(0, 8), (4, 8), (2, 4), (1, 2)];
+
+/// Largest logical screen decoded, 16384x16384 or the like
+const MAX_SCREEN_PIXELS: usize = 1 << 28;
+
+/// Most memory the decoded frames of one file may take up together. Each frame is a whole
+/// screen, while in the file it can take only a few bytes.
+const MAX_DECODED_LEN: u64 = 1 << 32;
+
+/// Color resolution bits claimed in the screen descriptor: 8 bits per primary
+const COLOR_RESOLUTION: u8 = 0x70;
+
+/// Reads the screen size of a GIF, which every frame is drawn within
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let mut reader = StreamReader::new("GIF", BufReader::new(reader));
+    Gif::read_signature(&mut reader)?;
+    let screen = ScreenDescriptor::read(&mut reader)?;
+    Ok(RowLayout {
+        width: screen.width as u32,
+        height: screen.height as u32,
+        color_model: ColorModel::Rgba,
+        bit_depth: 8,
+    })
+}
+
+/// Decodes every image of `gif` onto the logical screen, honouring each graphic control
+/// extension's transparency and disposal, and returns the screen as it looks after each
+/// image. The background shows through as transparent, as browsers draw it.
+pub fn decode(gif: &Gif) -> Result<Animation> {
+    let screen = gif.screen();
+    let (width, height) = (screen.width as usize, screen.height as usize);
+    // checked before the canvas is allocated, as the screen size is all a forged header needs
+    if width * height > MAX_SCREEN_PIXELS {
+        return Err(Error::unsupported(
+            "GIF",
+            format!("{width}x{height} screens, over {MAX_SCREEN_PIXELS} pixels"),
+        ));
+    }
+    let mut canvas: Vec<u8> = vec![0; width * height * 4];
+    let mut canvases: Vec<(Vec<u8>, Duration)> = Vec::new();
+    let mut control: Option<GraphicControl> = None;
+    for block in gif.blocks() {
+        let image = match block {
+            Block::Image(image) => image,
+            Block::Extension {
+                label: GRAPHIC_CONTROL_LABEL,
+                sub_blocks,
+            } => {
+                control = GraphicControl::parse(sub_blocks).or(control);
+                continue;
+            }
+            Block::Extension { .. } => continue,
+        };
+        let control = control.take().unwrap_or_default();
+        let color_table = match image.local_color_table.is_empty() {
+            true => gif.global_color_table(),
+            false => &image.local_color_table,
+        };
+        if color_table.is_empty() {
+            return Err(Error::malformed(
+                "GIF",
+                "image has neither a local nor a global color table",
+            ));
+        }
+        let before = (control.disposal == GraphicControl::RESTORE_PREVIOUS).then(|| canvas.clone());
+
+        let (image_width, image_height) = (image.width as usize, image.height as usize);
+        let pixel_count = image_width * image_height;
+        let indices = lzw::decode(&image.data, image.min_code_size, pixel_count)?;
+        if indices.len() < pixel_count {
+            return Err(Error::malformed(
+                "GIF",
+                format!(
+                    "image data ends after {} of {pixel_count} pixels",
+                    indices.len()
+                ),
+            ));
+        }
+        let rows = row_order(image_height, image.interlaced());
+        // images reaching past the screen are cut off at its edge
+        let visible_width = image_width.min(width.saturating_sub(image.left as usize));
+        for (stored, &row) in rows.iter().enumerate() {
+            let y = image.top as usize + row;
+            if y >= height {
+                continue;
+            }
+            let stored_row = &indices[stored * image_width..][..visible_width];
+            for (x, &index) in stored_row.iter().enumerate() {
+                if control.transparent_index == Some(index) {
+                    continue;
+                }
+                let entry = color_table
+                    .get(index as usize * 3..index as usize * 3 + 3)
+                    .ok_or_else(|| {
+                        Error::malformed(
+                            "GIF",
+                            format!("color index {index} is past the end of the color table"),
+                        )
+                    })?;
+                let at = (y * width + image.left as usize + x) * 4;
+                canvas[at..at + 3].copy_from_slice(entry);
+                canvas[at + 3] = 255;
+            }
+        }
+        if (canvases.len() as u64 + 1) * canvas.len() as u64 > MAX_DECODED_LEN {
+            return Err(Error::unsupported(
+                "GIF",
+                format!("animations over {MAX_DECODED_LEN} bytes once decoded"),
+            ));
+        }
+        canvases.push((
+            canvas.clone(),
+            Duration::from_millis(control.delay as u64 * 10),
+        ));
+
+        match control.disposal {
+            GraphicControl::RESTORE_BACKGROUND => {
+                for row in 0..image_height {
+                    let y = image.top as usize + row;
+                    if y < height && visible_width > 0 {
+                        let start = (y * width + image.left as usize) * 4;
+                        canvas[start..start + visible_width * 4].fill(0);
+                    }
+                }
+            }
+            GraphicControl::RESTORE_PREVIOUS => canvas = before.expect("saved before drawing"),
+            _ => {}
+        }
+    }
+    if canvases.is_empty() {
+        return Err(Error::malformed("GIF", "file has no images"));
+    }
+    let plays = match gif.loop_count() {
+        Some(0) => 0,
+        Some(loop_count) => loop_count as u32 + 1,
+        None => 1,
+    };
+    Animation::from_rgba8(width as u32, height as u32, canvases, plays)
+}
+
+/// The row of the image each stored row belongs to
+fn row_order(height: usize, interlaced: bool) -> Vec<usize> {
+    match interlaced {
+        true => INTERLACE_PASSES
+            .iter()
+            .flat_map(|&(start, step)| (start..height).step_by(step))
+            .collect(),
+        false => (0..height).collect(),
+    }
+}
+
+/// One frame reduced to color table indices
+struct IndexedFrame {
+    /// RGB triples
+    color_table: Vec<u8>,
+    indices: Vec<u8>,
+    transparent_index: Option<u8>,
+}
+
+/// Builds the color table for `image`. GIF has no partial transparency, so translucent
+/// pixels, 16-bit samples and more than 256 colors all fail rather than being approximated.
+fn index_frame(image: &Image, frame: usize) -> Result<IndexedFrame> {
+    image.layout().require_8_bit("GIF")?;
+    let pixel_count = image.width() as usize * image.height() as usize;
+    let mut rgba: Vec<u8> = Vec::with_capacity(pixel_count * 4);
+    for pixel in 0..pixel_count {
+        match image.rgba8(pixel) {
+            // every fully transparent pixel shares the one transparent index
+            [_, _, _, 0] => rgba.extend([0, 0, 0, 0]),
+            color @ [_, _, _, 255] => rgba.extend(color),
+            _ => {
+                return Err(Error::lossy(
+                    "GIF pixels are either opaque or fully transparent, and the image has translucent ones",
+                ))
+            }
+        }
+    }
+    let indexed = Image::new(image.width(), image.height(), ColorModel::Rgba, 8, rgba)?
+        .to_indexed(256)
+        .ok_or_else(|| {
+            Error::lossy(format!(
+                "frame {frame} has more than the 256 colors a GIF color table can hold"
+            ))
+        })?;
+    Ok(IndexedFrame {
+        color_table: indexed
+            .palette
+            .iter()
+            .flat_map(|&[red, green, blue, _]| [red, green, blue])
+            .collect(),
+        transparent_index: indexed
+            .palette
+            .iter()
+            .position(|color| color[3] == 0)
+            .map(|idx| idx as u8),
+        indices: indexed.indices,
+    })
+}
+
+/// Encodes `animation` as a GIF, with one image covering the whole screen per frame. The
+/// first frame's colors become the global color table; later frames with other colors get
+/// a local one.
+pub fn encode(animation: &Animation, options: &GifOptions) -> Result<Gif> {
+    let (width, height) = (animation.width(), animation.height());
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(Error::unsupported(
+            "GIF",
+            format!("{width}x{height} images; the limit is 65535x65535"),
+        ));
+    }
+    let frames = animation
+        .frames()
+        .iter()
+        .enumerate()
+        .map(|(idx, frame)| index_frame(&frame.image, idx))
+        .collect::<Result<Vec<IndexedFrame>>>()?;
+    let animated = frames.len() > 1;
+    // with transparency, each frame has to be cleared away or it would show through the next
+    let disposal = match frames.iter().any(|frame| frame.transparent_index.is_some()) {
+        true => GraphicControl::RESTORE_BACKGROUND,
+        false => GraphicControl::LEAVE,
+    };
+
+    let global_color_table = padded_color_table(&frames[0].color_table);
+    let mut blocks: Vec<Block> = Vec::new();
+    if animated && animation.plays() != 1 {
+        let loop_count = animation.plays().saturating_sub(1).min(u16::MAX as u32) as u16;
+        blocks.push(Block::netscape_loop(loop_count));
+    }
+    for (frame, indexed) in animation.frames().iter().zip(&frames) {
+        if animated || indexed.transparent_index.is_some() {
+            let centiseconds = (frame.delay.as_millis() + 5) / 10;
+            let control = GraphicControl {
+                disposal: if animated { disposal } else { 0 },
+                user_input: false,
+                transparent_index: indexed.transparent_index,
+                delay: centiseconds.min(u16::MAX as u128) as u16,
+            };
+            blocks.push(Block::Extension {
+                label: GRAPHIC_CONTROL_LABEL,
+                sub_blocks: control.sub_blocks(),
+            });
+        }
+        let color_table = padded_color_table(&indexed.color_table);
+        let local_color_table = match color_table == global_color_table {
+            true => Vec::new(),
+            false => color_table,
+        };
+        let table_len = indexed.color_table.len() / 3;
+        let min_code_size = (2..=8).find(|bits| table_len <= 1 << bits).unwrap_or(8);
+        let indices = match options.interlace {
+            true => row_order(height as usize, true)
+                .into_iter()
+                .flat_map(|y| &indexed.indices[y * width as usize..][..width as usize])
+                .copied()
+                .collect(),
+            false => indexed.indices.clone(),
+        };
+        let mut flags = ImageBlock::interlace_flag(options.interlace);
+        if !local_color_table.is_empty() {
+            flags |= color_table_flags(local_color_table.len() / 3);
+        }
+        blocks.push(Block::Image(ImageBlock {
+            left: 0,
+            top: 0,
+            width: width as u16,
+            height: height as u16,
+            flags,
+            local_color_table,
+            min_code_size,
+            data: lzw::encode(&indices, min_code_size),
+        }));
+    }
+
+    let version = match blocks
+        .iter()
+        .any(|block| matches!(block, Block::Extension { .. }))
+    {
+        true => *Gif::VERSION_89A,
+        false => *Gif::VERSION_87A,
+    };
+    Ok(Gif {
+        version,
+        screen: ScreenDescriptor {
+            width: width as u16,
+            height: height as u16,
+            flags: color_table_flags(global_color_table.len() / 3) | COLOR_RESOLUTION,
+            background_index: 0,
+            pixel_aspect: 0,
+        },
+        global_color_table,
+        blocks,
+    })
+}
+
+/// Pads an RGB color table out to the power-of-two length GIF stores, with at least two
+/// entries
+fn padded_color_table(color_table: &[u8]) -> Vec<u8> {
+    let entries = (color_table.len() / 3).max(2).next_power_of_two();
+    let mut padded = color_table.to_vec();
+    padded.resize(entries * 3, 0);
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{animation::Frame, ConvertibleImage};
+
+    #[test]
+    fn test_interlaced_row_order() {
+        assert_eq!(row_order(10, true), [0, 8, 4, 2, 6, 1, 3, 5, 7, 9]);
+        assert_eq!(row_order(3, false), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_index_frame() {
+        let image = Image::new(
+            3,
+            1,
+            ColorModel::Rgba,
+            8,
+            vec![1, 2, 3, 255, 9, 9, 9, 0, 4, 5, 6, 0],
+        )
+        .unwrap();
+        let indexed = index_frame(&image, 0).unwrap();
+        assert_eq!(indexed.color_table, [1, 2, 3, 0, 0, 0]);
+        assert_eq!(indexed.indices, [0, 1, 1]);
+        assert_eq!(indexed.transparent_index, Some(1));
+
+        let translucent = Image::new(1, 1, ColorModel::Rgba, 8, vec![1, 2, 3, 4]).unwrap();
+        assert!(matches!(
+            index_frame(&translucent, 0),
+            Err(Error::LossyConversion(_))
+        ));
+        let colorful: Vec<u8> = (0..300_u32)
+            .flat_map(|idx| [idx as u8, (idx >> 8) as u8, 0])
+            .collect();
+        let colorful = Image::new(300, 1, ColorModel::Rgb, 8, colorful).unwrap();
+        assert!(index_frame(&colorful, 0).is_err());
+    }
+
+    #[test]
+    fn test_disposal() {
+        let frames = [
+            vec![255, 0, 0, 255, 0, 0, 255, 255],
+            vec![0, 0, 0, 0, 0, 255, 0, 255],
+        ]
+        .map(|data| Frame {
+            image: Image::new(2, 1, ColorModel::Rgba, 8, data).unwrap(),
+            delay: Duration::from_millis(50),
+        });
+        let animation = Animation::new(frames.to_vec(), 0).unwrap();
+        let gif = encode(&animation, &GifOptions::default()).unwrap();
+        assert_eq!(&gif.version, Gif::VERSION_89A);
+        // the second frame's transparent pixel must not show the first frame's red
+        let decoded = decode(&gif).unwrap();
+        assert_eq!(decoded.plays(), 0);
+        for (decoded, frame) in decoded.frames().iter().zip(&frames) {
+            assert_eq!(decoded.image.data(), frame.image.data());
+            assert_eq!(decoded.delay, frame.delay);
+        }
+    }
+
+    #[test]
+    fn test_forged_screen_size() {
+        let image = Image::new(1, 1, ColorModel::Rgb, 8, vec![10, 20, 30]).unwrap();
+        let mut bytes = Gif::from_image(&image).unwrap().to_bytes();
+        assert!(bytes.len() < 64);
+        // a 65535x65535 screen around the one pixel
+        bytes[6..10].fill(0xff);
+        let gif = Gif::try_from(bytes.as_slice()).unwrap();
+        assert!(matches!(decode(&gif), Err(Error::Unsupported { .. })));
+    }
+}
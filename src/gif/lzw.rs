@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use crate::{Error, Result};
+
+/// Codes never grow past 12 bits, which caps the table at 4096 entries
+const MAX_CODE_SIZE: u8 = 12;
+const TABLE_SIZE: usize = 1 << MAX_CODE_SIZE;
+
+/// One string in the decoder's table, stored as the code of the string it extends
+#[derive(Clone, Copy)]
+struct Entry {
+    prefix: u16,
+    last: u8,
+    first: u8,
+    len: u16,
+}
+
+/// Decompresses GIF image data: variable-length LZW codes packed least significant bit
+/// first, starting one bit wider than `min_code_size`. Decoding stops at the end code, or
+/// once `max_len` indices are out, so data padded past the image is fine.
+pub fn decode(data: &[u8], min_code_size: u8, max_len: usize) -> Result<Vec<u8>> {
+    if !(2..=8).contains(&min_code_size) {
+        return Err(Error::malformed(
+            "GIF",
+            format!("LZW minimum code size {min_code_size} is not between 2 and 8"),
+        ));
+    }
+    let clear = 1_u16 << min_code_size;
+    let end = clear + 1;
+    let mut table: Vec<Entry> = (0..TABLE_SIZE)
+        .map(|code| Entry {
+            prefix: 0,
+            last: code as u8,
+            first: code as u8,
+            len: 1,
+        })
+        .collect();
+    let mut next = end + 1;
+    let mut code_size = min_code_size + 1;
+    let mut previous: Option<u16> = None;
+
+    // `max_len` comes from the image descriptor, so it's only trusted as far as the data goes
+    let mut out: Vec<u8> = Vec::with_capacity(max_len.min(data.len() * 4));
+    let mut bits: u32 = 0;
+    let mut bit_count: u8 = 0;
+    let mut bytes = data.iter();
+    while out.len() < max_len {
+        while bit_count < code_size {
+            match bytes.next() {
+                Some(&byte) => {
+                    bits |= (byte as u32) << bit_count;
+                    bit_count += 8;
+                }
+                None => return Ok(out),
+            }
+        }
+        let code = (bits & ((1 << code_size) - 1)) as u16;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear {
+            next = end + 1;
+            code_size = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        let first = match previous {
+            _ if code < clear => code as u8,
+            Some(_) if code < next => table[code as usize].first,
+            // the one code the decoder can't know yet: the previous string plus its own
+            // first byte
+            Some(previous) if code == next => table[previous as usize].first,
+            _ => {
+                return Err(Error::malformed(
+                    "GIF",
+                    format!("LZW code {code} isn't in the table yet"),
+                ))
+            }
+        };
+        if let Some(previous) = previous.filter(|_| (next as usize) < TABLE_SIZE) {
+            let prefix = table[previous as usize];
+            table[next as usize] = Entry {
+                prefix: previous,
+                last: first,
+                first: prefix.first,
+                len: prefix.len + 1,
+            };
+            next += 1;
+            if next == 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+        }
+
+        // strings are stored back to front, so they're written from their last byte
+        let entry = table[code as usize];
+        let start = out.len();
+        out.resize(start + entry.len as usize, 0);
+        let mut current = entry;
+        for idx in (start..out.len()).rev() {
+            out[idx] = current.last;
+            current = table[current.prefix as usize];
+        }
+        previous = Some(code);
+    }
+    out.truncate(max_len);
+    Ok(out)
+}
+
+/// Packs codes into bytes least significant bit first
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, code_size: u8) {
+        self.bits |= (code as u32) << self.bit_count;
+        self.bit_count += code_size;
+        while self.bit_count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Compresses `indices`, each below `1 << min_code_size`, the way `decode` reads them back.
+/// The table is cleared and started over whenever it fills up.
+pub fn encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1_u16 << min_code_size;
+    let end = clear + 1;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut code_size = min_code_size + 1;
+    let mut writer = BitWriter {
+        out: Vec::new(),
+        bits: 0,
+        bit_count: 0,
+    };
+    writer.write(clear, code_size);
+
+    let mut current: Option<u16> = None;
+    for &index in indices {
+        let Some(prefix) = current else {
+            current = Some(index as u16);
+            continue;
+        };
+        if let Some(&code) = table.get(&(prefix, index)) {
+            current = Some(code);
+            continue;
+        }
+        writer.write(prefix, code_size);
+        if (next as usize) < TABLE_SIZE {
+            table.insert((prefix, index), next);
+            next += 1;
+            // the decoder adds each entry one code later than this, so it widens its
+            // codes one code later too
+            if next > 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+        } else {
+            writer.write(clear, code_size);
+            table.clear();
+            next = end + 1;
+            code_size = min_code_size + 1;
+        }
+        current = Some(index as u16);
+    }
+    if let Some(code) = current {
+        writer.write(code, code_size);
+    }
+    writer.write(end, code_size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // long enough to fill the table and force clears, with both repetitive and noisy runs
+        let mut indices: Vec<u8> = (0..20_000_u32).map(|idx| (idx / 7 % 16) as u8).collect();
+        indices.extend((0..30_000_u32).map(|idx| (idx.wrapping_mul(2_654_435_761) >> 28) as u8));
+        let encoded = encode(&indices, 4);
+        assert_eq!(decode(&encoded, 4, indices.len()).unwrap(), indices);
+
+        let bytes: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        assert_eq!(decode(&encode(&bytes, 8), 8, 5000).unwrap(), bytes);
+        assert!(decode(&encode(&[], 2), 2, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_known_stream() {
+        // the 10x10 sample image often used to walk through GIF decoding
+        let data = [
+            0x8c, 0x2d, 0x99, 0x87, 0x2a, 0x1c, 0xdc, 0x33, 0xa0, 0x02, 0x75, 0xec, 0x95, 0xfa,
+            0xa8, 0xde, 0x60, 0x8c, 0x04, 0x91, 0x4c, 0x01,
+        ];
+        let indices = decode(&data, 2, 100).unwrap();
+        let expected: Vec<u8> = [
+            "1111122222",
+            "1111122222",
+            "1111122222",
+            "1110000222",
+            "1110000222",
+            "2220000111",
+            "2220000111",
+            "2222211111",
+            "2222211111",
+            "2222211111",
+        ]
+        .concat()
+        .bytes()
+        .map(|byte| byte - b'0')
+        .collect();
+        assert_eq!(indices, expected);
+
+        assert!(decode(&[0xff, 0xff], 2, 10).is_err());
+        assert!(decode(&data, 9, 100).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+/// How GIF output is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GifOptions {
+    /// Store rows in the four-pass interlaced order, so a rough image shows while loading
+    pub interlace: bool,
+}
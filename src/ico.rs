@@ -0,0 +1,334 @@
+use std::io::{Read, Write};
+
+use crate::{
+    bmp,
+    byte_reader::ByteReader,
+    convert::ConvertOptions,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{FormatDetails, IcoDetails, IconEntryInfo, ImageInfo},
+    png::{self, Png},
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    directory::{DirectoryEntry, IconKind},
+    options::IcoOptions,
+};
+
+pub mod directory;
+pub mod image_data;
+pub mod options;
+
+/// Registry entry for Windows icon files
+pub const ICO_CODEC: Codec = Codec {
+    name: "ICO",
+    extensions: &["ico"],
+    magic: &[&[0, 0, 1, 0]],
+    check_signature: Some(IconKind::is_plausible_start),
+    decoder: Some(decode_with::<Ico>),
+    encoder: Some(write_ico),
+    row_decoder: None,
+    row_encoder: None,
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Registry entry for Windows cursor files, which are icons with a hotspot
+pub const CUR_CODEC: Codec = Codec {
+    name: "CUR",
+    extensions: &["cur"],
+    magic: &[&[0, 0, 2, 0]],
+    check_signature: Some(IconKind::is_plausible_start),
+    decoder: Some(decode_with::<Ico>),
+    encoder: Some(write_cur),
+    row_decoder: None,
+    row_encoder: None,
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes an icon or cursor from its directory, and its largest image from that image's
+/// own headers
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let ico = Ico::from_reader(reader)?;
+    let format = ico.kind().format();
+    let largest = &ico.entries()[ico.largest()];
+    let mut info = match largest.is_png() {
+        true => png::CODEC.inspect(&mut largest.data.as_slice())?,
+        false => {
+            bmp::CODEC.inspect(&mut image_data::dib_as_bmp(&largest.data, format)?.as_slice())?
+        }
+    };
+    info.format = format;
+    info.details = FormatDetails::Ico(IcoDetails {
+        kind: match ico.kind() {
+            IconKind::Icon => "icon",
+            IconKind::Cursor => "cursor",
+        },
+        entries: ico
+            .entries()
+            .iter()
+            .map(|entry| IconEntryInfo {
+                width: entry.directory.pixel_width(),
+                height: entry.directory.pixel_height(),
+                format: if entry.is_png() { "PNG" } else { "BMP" },
+                bits_per_pixel: (ico.kind() == IconKind::Icon).then_some(entry.directory.bit_count),
+                hotspot: (ico.kind() == IconKind::Cursor).then(|| {
+                    let (x, y) = entry.hotspot();
+                    [x, y]
+                }),
+                size: entry.directory.size,
+            })
+            .collect(),
+    });
+    Ok(info)
+}
+
+/// Encodes `png` as an icon for the registry
+fn write_ico(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    Ico::from_image_with(&png.to_image()?, IconKind::Icon, &options.ico)?.to_writer(writer)
+}
+
+/// Encodes `png` as a cursor for the registry
+fn write_cur(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    Ico::from_image_with(&png.to_image()?, IconKind::Cursor, &options.ico)?.to_writer(writer)
+}
+
+/// One image of an icon or cursor, as its directory entry describes it
+#[derive(Clone, Debug)]
+pub struct IconEntry {
+    pub directory: DirectoryEntry,
+    /// A whole PNG file, or a DIB followed by its AND mask
+    pub data: Vec<u8>,
+}
+
+impl IconEntry {
+    pub fn is_png(&self) -> bool {
+        self.data.starts_with(&Png::STANDARD_HEADER)
+    }
+
+    /// The pixel a cursor points with; icons keep other fields in its place
+    pub fn hotspot(&self) -> (u16, u16) {
+        (self.directory.planes, self.directory.bit_count)
+    }
+}
+
+/// An ICO or CUR file: a directory of images, usually the same picture at several sizes
+pub struct Ico {
+    kind: IconKind,
+    entries: Vec<IconEntry>,
+}
+
+impl Ico {
+    /// Bundles `entries` into a file. Their offsets are worked out when it's written.
+    pub fn new(kind: IconKind, entries: Vec<IconEntry>) -> Self {
+        Ico { kind, entries }
+    }
+
+    pub fn kind(&self) -> IconKind {
+        self.kind
+    }
+
+    pub fn entries(&self) -> &[IconEntry] {
+        &self.entries
+    }
+
+    /// Index of the entry with the most pixels, the deepest one among those that tie
+    pub fn largest(&self) -> usize {
+        let area = |entry: &IconEntry| {
+            let directory = &entry.directory;
+            (
+                directory.pixel_width() * directory.pixel_height(),
+                directory.bit_count,
+            )
+        };
+        (0..self.entries.len())
+            .max_by_key(|&index| area(&self.entries[index]))
+            .unwrap_or(0)
+    }
+
+    /// Decodes entry `index` into an `Image`, applying a DIB's AND mask as alpha
+    pub fn to_image(&self, index: usize) -> Result<Image> {
+        match self.entries.get(index) {
+            Some(entry) => image_data::decode(entry, self.kind.format()),
+            None => Err(Error::no_such_image(index, self.entries.len())),
+        }
+    }
+
+    /// Encodes `image` as an icon or cursor with the sizes `options` asks for
+    pub fn from_image_with(image: &Image, kind: IconKind, options: &IcoOptions) -> Result<Ico> {
+        image_data::encode(image, kind, options)
+    }
+}
+
+impl TryFrom<&[u8]> for Ico {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Ico::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Ico {
+    fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        // entries can sit anywhere in the file, so all of it is read first
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let (kind, count) = IconKind::read_header(&mut ByteReader::new("ICO", &bytes))?;
+        let mut reader = ByteReader::new(kind.format(), &bytes).at(IconKind::HEADER_LENGTH)?;
+        let mut entries: Vec<IconEntry> = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let at = reader.position();
+            let directory = DirectoryEntry::read(&mut reader)?;
+            let data = reader
+                .at(directory.offset as usize)
+                .and_then(|mut data| data.read_bytes(directory.size as usize))
+                .map_err(|_| {
+                    Error::malformed_at(
+                        kind.format(),
+                        at + 8,
+                        format!("the data of image {index} runs past the end of the file"),
+                    )
+                })?;
+            entries.push(IconEntry {
+                directory,
+                data: data.to_vec(),
+            });
+        }
+        Ok(Ico { kind, entries })
+    }
+
+    /// Lays the images out one after another behind the directory
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.kind.header_bytes(self.entries.len() as u16))?;
+        let mut offset = IconKind::HEADER_LENGTH + self.entries.len() * DirectoryEntry::LENGTH;
+        for entry in &self.entries {
+            let directory = DirectoryEntry {
+                size: entry.data.len() as u32,
+                offset: offset as u32,
+                ..entry.directory
+            };
+            writer.write_all(&directory.as_bytes())?;
+            offset += entry.data.len();
+        }
+        for entry in &self.entries {
+            writer.write_all(&entry.data)?;
+        }
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Ico::from_image_with(&png.to_image()?, IconKind::Icon, &IcoOptions::default())
+    }
+
+    /// Converts the largest image
+    fn to_png(&self) -> Result<Png> {
+        self.image_to_png(self.largest())
+    }
+
+    fn image_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn image_to_png(&self, index: usize) -> Result<Png> {
+        Png::from_image(&self.to_image(index)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ColorModel;
+
+    /// A 4x2 image: an opaque red left half and a transparent right half
+    fn test_image() -> Image {
+        let data = [
+            [255, 0, 0, 255],
+            [255, 0, 0, 255],
+            [0, 0, 0, 0],
+            [0, 0, 0, 0],
+        ]
+        .repeat(2)
+        .concat();
+        Image::new(4, 2, ColorModel::Rgba, 8, data).unwrap()
+    }
+
+    #[test]
+    fn test_icon_sizes() {
+        let options = IcoOptions {
+            sizes: vec![2, 4, 256],
+            ..IcoOptions::default()
+        };
+        let bytes = Ico::from_image_with(&test_image(), IconKind::Icon, &options)
+            .unwrap()
+            .to_bytes();
+        let ico = Ico::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(ico.kind(), IconKind::Icon);
+        assert_eq!(ico.image_count(), 3);
+        assert_eq!(ico.largest(), 2);
+        assert!(!ico.entries()[1].is_png());
+        assert!(ico.entries()[2].is_png());
+        assert_eq!(ico.entries()[2].directory.width, 0);
+
+        // the 4x2 image sits in the middle of the 4x4 square
+        let square = ico.to_image(1).unwrap();
+        assert_eq!((square.width(), square.height()), (4, 4));
+        assert_eq!(square.rgba8(4), [255, 0, 0, 255]);
+        assert_eq!(square.rgba8(6), [0, 0, 0, 0]);
+        assert_eq!(square.rgba8(0), [0, 0, 0, 0]);
+        let largest = ico.to_png().unwrap().to_image().unwrap();
+        assert_eq!(largest.width(), 256);
+        assert!(matches!(
+            ico.image_to_png(3),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        assert!(Ico::try_from(&bytes[..bytes.len() - 1]).is_err());
+        let too_big = IcoOptions {
+            sizes: vec![512],
+            ..IcoOptions::default()
+        };
+        assert!(Ico::from_image_with(&test_image(), IconKind::Icon, &too_big).is_err());
+    }
+
+    #[test]
+    fn test_cursor_hotspot() {
+        let options = IcoOptions {
+            sizes: vec![4, 8],
+            png: true,
+            hotspot: (1, 1),
+        };
+        let bytes = Ico::from_image_with(&test_image(), IconKind::Cursor, &options)
+            .unwrap()
+            .to_bytes();
+        assert_eq!(bytes[..4], [0, 0, 2, 0]);
+        let cur = Ico::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(cur.kind(), IconKind::Cursor);
+        assert!(cur.entries().iter().all(IconEntry::is_png));
+        assert_eq!(cur.entries()[0].hotspot(), (1, 2));
+        assert_eq!(cur.entries()[1].hotspot(), (2, 4));
+
+        let outside = IcoOptions {
+            hotspot: (4, 0),
+            ..options
+        };
+        assert!(Ico::from_image_with(&test_image(), IconKind::Cursor, &outside).is_err());
+    }
+
+    #[test]
+    fn test_truecolor_tga_not_taken_for_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tga, png) = (dir.path().join("image.tga"), dir.path().join("image.png"));
+        let image = test_image();
+        let bytes = crate::tga::Tga::from_image(&image).unwrap().to_bytes();
+        assert!(bytes.starts_with(&[0, 0, 2, 0]));
+        std::fs::write(&tga, bytes).unwrap();
+
+        let conversion = crate::convert(&tga, &png, &ConvertOptions::default()).unwrap();
+        assert_eq!(conversion.source, "TGA");
+        assert!(conversion.warnings.is_empty());
+        let decoded = Png::from_file(&png).unwrap().to_image().unwrap();
+        assert_eq!(decoded.data(), image.data());
+    }
+}
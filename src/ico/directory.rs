@@ -0,0 +1,186 @@
+use crate::{byte_reader::ByteReader, Error, Result};
+
+/// Whether a file holds icons or cursors, from the type field of its header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IconKind {
+    Icon,
+    Cursor,
+}
+
+impl IconKind {
+    /// Bytes taken up by the reserved, type and count fields at the start of the file
+    pub const HEADER_LENGTH: usize = 6;
+
+    /// The type field for this kind of file
+    pub fn resource_type(&self) -> u16 {
+        match self {
+            IconKind::Icon => 1,
+            IconKind::Cursor => 2,
+        }
+    }
+
+    /// The format name errors about this kind of file use
+    pub fn format(&self) -> &'static str {
+        match self {
+            IconKind::Icon => "ICO",
+            IconKind::Cursor => "CUR",
+        }
+    }
+
+    /// Reads the header, returning the kind of file and the number of directory entries
+    pub fn read_header(reader: &mut ByteReader) -> Result<(IconKind, u16)> {
+        if reader.read_u16_le()? != 0 {
+            return Err(Error::malformed_at("ICO", 0, "the reserved field isn't 0"));
+        }
+        let kind = match reader.read_u16_le()? {
+            1 => IconKind::Icon,
+            2 => IconKind::Cursor,
+            other => {
+                return Err(Error::malformed_at(
+                    "ICO",
+                    2,
+                    format!("unknown resource type {other}"),
+                ))
+            }
+        };
+        match reader.read_u16_le()? {
+            0 => Err(Error::malformed_at(
+                kind.format(),
+                4,
+                "the file holds no images",
+            )),
+            count => Ok((kind, count)),
+        }
+    }
+
+    /// Whether `bytes`, the start of a file whose first four bytes are an icon or cursor
+    /// signature, go on as a directory would. The signature alone is also how every
+    /// uncompressed truecolor TGA starts, but those follow it with a zero count.
+    pub fn is_plausible_start(bytes: &[u8]) -> bool {
+        let mut reader = ByteReader::new("ICO", bytes);
+        let Ok((_, count)) = IconKind::read_header(&mut reader) else {
+            return false;
+        };
+        // only the first entry, and only as much of it as `bytes` holds, can be checked
+        let directory_end = IconKind::HEADER_LENGTH + count as usize * DirectoryEntry::LENGTH;
+        match DirectoryEntry::read(&mut reader) {
+            Ok(entry) => {
+                entry.reserved == 0 && entry.size > 0 && entry.offset as usize >= directory_end
+            }
+            Err(_) => bytes.get(9).is_none_or(|&reserved| reserved == 0),
+        }
+    }
+
+    pub fn header_bytes(&self, count: u16) -> [u8; 6] {
+        let [type_low, type_high] = self.resource_type().to_le_bytes();
+        let [count_low, count_high] = count.to_le_bytes();
+        [0, 0, type_low, type_high, count_low, count_high]
+    }
+}
+
+/// One 16-byte entry of the directory that follows the header, locating one image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    /// Width in pixels, with 0 standing for 256
+    pub width: u8,
+    /// Height in pixels, with 0 standing for 256
+    pub height: u8,
+    /// Palette entries, or 0 for images without a palette
+    pub color_count: u8,
+    pub reserved: u8,
+    /// Color planes in icons; the hotspot's x coordinate in cursors
+    pub planes: u16,
+    /// Bits per pixel in icons; the hotspot's y coordinate in cursors
+    pub bit_count: u16,
+    /// Bytes of image data
+    pub size: u32,
+    /// Where the image data starts, from the start of the file
+    pub offset: u32,
+}
+
+impl DirectoryEntry {
+    pub const LENGTH: usize = 16;
+
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        Ok(DirectoryEntry {
+            width: reader.read_u8()?,
+            height: reader.read_u8()?,
+            color_count: reader.read_u8()?,
+            reserved: reader.read_u8()?,
+            planes: reader.read_u16_le()?,
+            bit_count: reader.read_u16_le()?,
+            size: reader.read_u32_le()?,
+            offset: reader.read_u32_le()?,
+        })
+    }
+
+    pub fn pixel_width(&self) -> u32 {
+        match self.width {
+            0 => 256,
+            width => width as u32,
+        }
+    }
+
+    pub fn pixel_height(&self) -> u32 {
+        match self.height {
+            0 => 256,
+            height => height as u32,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(DirectoryEntry::LENGTH);
+        bytes.extend([self.width, self.height, self.color_count, self.reserved]);
+        bytes.extend(self.planes.to_le_bytes());
+        bytes.extend(self.bit_count.to_le_bytes());
+        bytes.extend(self.size.to_le_bytes());
+        bytes.extend(self.offset.to_le_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_round_trip() {
+        let mut bytes = IconKind::Cursor.header_bytes(1).to_vec();
+        let entry = DirectoryEntry {
+            width: 0,
+            height: 48,
+            color_count: 0,
+            reserved: 0,
+            planes: 3,
+            bit_count: 7,
+            size: 1000,
+            offset: 22,
+        };
+        bytes.extend(entry.as_bytes());
+        assert_eq!(
+            bytes.len(),
+            IconKind::HEADER_LENGTH + DirectoryEntry::LENGTH
+        );
+
+        let mut reader = ByteReader::new("ICO", &bytes);
+        assert_eq!(
+            IconKind::read_header(&mut reader).unwrap(),
+            (IconKind::Cursor, 1)
+        );
+        let read = DirectoryEntry::read(&mut reader).unwrap();
+        assert_eq!(read, entry);
+        assert_eq!((read.pixel_width(), read.pixel_height()), (256, 48));
+        assert!(IconKind::is_plausible_start(&bytes));
+        assert!(IconKind::is_plausible_start(&bytes[..8]));
+        // the start of an uncompressed truecolor TGA
+        assert!(!IconKind::is_plausible_start(&[0, 0, 2, 0, 0, 0, 0, 0]));
+        let mut misplaced = bytes.clone();
+        misplaced[18] = 6;
+        assert!(!IconKind::is_plausible_start(&misplaced));
+
+        bytes[2] = 3;
+        assert!(IconKind::read_header(&mut ByteReader::new("ICO", &bytes)).is_err());
+        let empty = IconKind::Icon.header_bytes(0);
+        assert!(IconKind::read_header(&mut ByteReader::new("ICO", &empty)).is_err());
+    }
+}
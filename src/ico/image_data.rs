@@ -0,0 +1,293 @@
+use std::io::Read;
+
+use crate::{
+    bmp::{
+        bmp_file_header::BmpFileHeader,
+        bmp_info_header::{BitmapInfoHeader, BmpInfoHeader},
+        image_data::row_stride,
+        Bmp,
+    },
+    byte_reader::ByteReader,
+    image::{ColorModel, Image},
+    png::Png,
+    scanline::RowLayout,
+    ConvertibleImage, Error, Result,
+};
+
+use super::{
+    directory::{DirectoryEntry, IconKind},
+    options::IcoOptions,
+    Ico, IconEntry,
+};
+
+/// Reports the size of the largest image in the directory, without decoding anything
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let ico = Ico::from_reader(reader)?;
+    let largest = &ico.entries()[ico.largest()];
+    Ok(RowLayout {
+        width: largest.directory.pixel_width(),
+        height: largest.directory.pixel_height(),
+        color_model: ColorModel::Rgba,
+        bit_depth: 16,
+    })
+}
+
+/// Decodes one image of an icon, stored either as a whole PNG file or as a DIB: a BMP
+/// without its file header, followed by a 1-bit AND mask marking transparent pixels
+pub fn decode(entry: &IconEntry, format: &'static str) -> Result<Image> {
+    match entry.is_png() {
+        true => Png::try_from(entry.data.as_slice())?.to_image(),
+        false => decode_dib(&entry.data, format),
+    }
+}
+
+/// Rebuilds a DIB as a BMP file by putting a file header in front of it. The info header's
+/// height, which counts the AND mask's rows too, is halved to cover just the color pixels.
+pub fn dib_as_bmp(dib: &[u8], format: &'static str) -> Result<Vec<u8>> {
+    let mut reader = ByteReader::new(format, dib);
+    let header_length = reader.read_u32_le()?;
+    reader.skip(4)?;
+    let height = reader.read_i32_le()?;
+    if height <= 0 || height % 2 != 0 {
+        return Err(Error::malformed_at(
+            format,
+            8,
+            format!("DIB height {height} isn't twice the height of a bottom-up image"),
+        ));
+    }
+    reader.skip(2)?;
+    let bits_per_pixel = reader.read_u16_le()?;
+    let num_colors = reader.at(32)?.read_u32_le()?;
+    let table_length = match (bits_per_pixel, num_colors) {
+        (1 | 4 | 8, 0) => 1 << bits_per_pixel,
+        (_, num_colors) => num_colors as usize,
+    } * 4;
+    let pixel_offset = BmpFileHeader::LENGTH + header_length as usize + table_length;
+
+    let mut bytes = BmpFileHeader::new(
+        (BmpFileHeader::LENGTH + dib.len()) as u32,
+        [0; 4],
+        pixel_offset as u32,
+    )
+    .as_bytes();
+    bytes.extend(dib);
+    let height_at = BmpFileHeader::LENGTH + 8;
+    bytes[height_at..height_at + 4].copy_from_slice(&(height / 2).to_le_bytes());
+    Ok(bytes)
+}
+
+/// Decodes a DIB and its AND mask. The mask only matters for images without alpha of their
+/// own; set bits make pixels transparent.
+fn decode_dib(dib: &[u8], format: &'static str) -> Result<Image> {
+    let bmp = Bmp::try_from(dib_as_bmp(dib, format)?.as_slice())?;
+    let image = bmp.to_image()?;
+    if image.color_model().has_alpha() {
+        return Ok(image);
+    }
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels_at = bmp.file_header().img_offset() as usize - BmpFileHeader::LENGTH;
+    let mask_at =
+        pixels_at + row_stride(image.width(), bmp.info_header().bits_per_pixel()) * height;
+    let stride = row_stride(image.width(), 1);
+    let mask = dib
+        .get(mask_at..mask_at + stride * height)
+        .ok_or_else(|| Error::malformed_at(format, dib.len(), "the AND mask is cut short"))?;
+
+    // the mask is stored bottom-up, like the pixels
+    let transparent = |pixel: usize| {
+        let (x, y) = (pixel % width, pixel / width);
+        mask[(height - 1 - y) * stride + x / 8] & 0x80 >> (x % 8) != 0
+    };
+    if !(0..width * height).any(transparent) {
+        return Ok(image);
+    }
+    let data = (0..width * height)
+        .flat_map(|pixel| {
+            let [red, green, blue, _] = image.rgba8(pixel);
+            [red, green, blue, if transparent(pixel) { 0 } else { 255 }]
+        })
+        .collect();
+    Image::new(image.width(), image.height(), ColorModel::Rgba, 8, data)
+}
+
+/// Encodes `image` as a DIB of 32-bit BGRA pixels, followed by an AND mask that marks the
+/// fully transparent pixels for readers that ignore alpha
+pub fn encode_dib(image: &Image) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mask_stride = row_stride(image.width(), 1);
+    let header = BitmapInfoHeader::new(
+        width as i32,
+        height as i32 * 2,
+        32,
+        None,
+        ((width * 4 + mask_stride) * height) as u32,
+        0,
+        0,
+        0,
+        0,
+    );
+    let mut bytes = header.as_bytes();
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let [red, green, blue, alpha] = image.rgba8(y * width + x);
+            bytes.extend([blue, green, red, alpha]);
+        }
+    }
+    for y in (0..height).rev() {
+        let mut row: Vec<u8> = vec![0; mask_stride];
+        for x in 0..width {
+            if image.rgba8(y * width + x)[3] == 0 {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        bytes.extend(row);
+    }
+    bytes
+}
+
+/// Builds an icon or cursor holding `image` at each size `options` asks for
+pub fn encode(image: &Image, kind: IconKind, options: &IcoOptions) -> Result<Ico> {
+    options.validate()?;
+    let (hotspot_x, hotspot_y) = options.hotspot;
+    if kind == IconKind::Cursor && (hotspot_x >= image.width() || hotspot_y >= image.height()) {
+        return Err(Error::invalid_argument(format!(
+            "the hotspot {hotspot_x},{hotspot_y} is outside the {}x{} image",
+            image.width(),
+            image.height()
+        )));
+    }
+    let sizes: Vec<Option<u32>> = match options.sizes.as_slice() {
+        [] if image.width() <= 256 && image.height() <= 256 => vec![None],
+        [] => vec![Some(256)],
+        sizes => sizes.iter().copied().map(Some).collect(),
+    };
+
+    let mut entries: Vec<IconEntry> = Vec::with_capacity(sizes.len());
+    for size in sizes {
+        let (sized, placement) = match size {
+            Some(size) => fit(image, size)?,
+            None => (image.clone(), Placement::whole(image)),
+        };
+        let (width, height) = (sized.width(), sized.height());
+        let png = options.png || width == 256 || height == 256;
+        let data = match png {
+            true => Png::from_image(&sized)?.to_bytes(),
+            false => {
+                // PNG entries keep 16-bit samples, but DIBs have no room for them
+                sized.layout().require_8_bit("a BMP icon entry")?;
+                encode_dib(&sized)
+            }
+        };
+        let (planes, bit_count) = match kind {
+            IconKind::Icon if png => (
+                1,
+                sized.color_model().channels() as u16 * sized.bit_depth() as u16,
+            ),
+            IconKind::Icon => (1, 32),
+            IconKind::Cursor => placement.place(image, options.hotspot),
+        };
+        entries.push(IconEntry {
+            directory: DirectoryEntry {
+                // 256 wraps around to 0, which is how the directory stores it
+                width: width as u8,
+                height: height as u8,
+                color_count: 0,
+                reserved: 0,
+                planes,
+                bit_count,
+                size: data.len() as u32,
+                offset: 0,
+            },
+            data,
+        });
+    }
+    Ok(Ico::new(kind, entries))
+}
+
+/// Where a scaled copy of an image sits on its square canvas
+struct Placement {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Placement {
+    fn whole(image: &Image) -> Self {
+        Placement {
+            left: 0,
+            top: 0,
+            width: image.width(),
+            height: image.height(),
+        }
+    }
+
+    /// Maps a pixel of the source image onto the canvas
+    fn place(&self, image: &Image, (x, y): (u32, u32)) -> (u16, u16) {
+        let x = self.left + x * self.width / image.width();
+        let y = self.top + y * self.height / image.height();
+        (x as u16, y as u16)
+    }
+}
+
+/// Scales `image` to fit a `size` pixel square, keeping its aspect ratio, and centres it
+/// on a transparent canvas if it isn't square itself
+fn fit(image: &Image, size: u32) -> Result<(Image, Placement)> {
+    let (width, height) = (image.width(), image.height());
+    let longest = width.max(height);
+    let scaled_size = |side: u32| {
+        ((side as u64 * size as u64 + longest as u64 / 2) / longest as u64).max(1) as u32
+    };
+    let placement = Placement {
+        left: (size - scaled_size(width)) / 2,
+        top: (size - scaled_size(height)) / 2,
+        width: scaled_size(width),
+        height: scaled_size(height),
+    };
+    let scaled = match (placement.width, placement.height) == (width, height) {
+        true => image.clone(),
+        false => image.resize(placement.width, placement.height)?,
+    };
+    if placement.width == size && placement.height == size {
+        return Ok((scaled, placement));
+    }
+
+    let mut canvas: Vec<u8> = vec![0; size as usize * size as usize * 4];
+    for y in 0..placement.height {
+        for x in 0..placement.width {
+            let pixel = scaled.rgba8((y * placement.width + x) as usize);
+            let at = ((placement.top + y) * size + placement.left + x) as usize * 4;
+            canvas[at..at + 4].copy_from_slice(&pixel);
+        }
+    }
+    let mut canvas = Image::new(size, size, ColorModel::Rgba, 8, canvas)?;
+    canvas.set_metadata(image.metadata().clone());
+    Ok((canvas, placement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_and_mask() {
+        // a 2x2 1-bit DIB whose palette is black and white, with the top-left pixel masked out
+        let mut dib = BitmapInfoHeader::new(2, 4, 1, None, 0, 0, 0, 2, 0).as_bytes();
+        dib.extend([0, 0, 0, 0, 255, 255, 255, 0]);
+        // color rows, bottom-up: white black, then black white
+        dib.extend([0x80, 0, 0, 0, 0x40, 0, 0, 0]);
+        // mask rows, bottom-up
+        dib.extend([0, 0, 0, 0, 0x80, 0, 0, 0]);
+
+        let image = decode_dib(&dib, "ICO").unwrap();
+        assert_eq!(image.color_model(), ColorModel::Rgba);
+        assert_eq!(
+            image.data(),
+            [0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 255]
+        );
+        assert!(decode_dib(&dib[..dib.len() - 1], "ICO").is_err());
+
+        let back = decode_dib(&encode_dib(&image), "ICO").unwrap();
+        assert_eq!(back.data(), image.data());
+    }
+}
@@ -0,0 +1,26 @@
+use crate::{Error, Result};
+
+/// How ICO and CUR output is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IcoOptions {
+    /// Square sizes to store, in order, each from 1 to 256. Empty stores the image at its own
+    /// size, scaled down to fit 256 pixels if it's larger.
+    pub sizes: Vec<u32>,
+    /// Store every size as PNG; only 256-pixel images are stored that way otherwise, since
+    /// older readers only understand BMP data
+    pub png: bool,
+    /// Pixel of the source image a cursor points with, scaled along with each size
+    pub hotspot: (u32, u32),
+}
+
+impl IcoOptions {
+    /// Checks for settings that are out of range
+    pub fn validate(&self) -> Result<()> {
+        match self.sizes.iter().find(|size| !(1..=256).contains(*size)) {
+            Some(size) => Err(Error::invalid_argument(format!(
+                "icon sizes go from 1 to 256 pixels, not {size}"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::{metadata::Metadata, scanline::RowLayout, Error, Result};
+
+/// The channels stored for each pixel of an `Image`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorModel {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+}
+
+impl ColorModel {
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorModel::Gray => 1,
+            ColorModel::GrayAlpha => 2,
+            ColorModel::Rgb => 3,
+            ColorModel::Rgba => 4,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorModel::GrayAlpha | ColorModel::Rgba)
+    }
+}
+
+/// An opaque color with 16-bit channels, independent of any image's bit depth
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+}
+
+impl Color {
+    pub const WHITE: Color = Color {
+        red: u16::MAX,
+        green: u16::MAX,
+        blue: u16::MAX,
+    };
+
+    pub fn from_rgb8(red: u8, green: u8, blue: u8) -> Self {
+        Color {
+            red: red as u16 * 257,
+            green: green as u16 * 257,
+            blue: blue as u16 * 257,
+        }
+    }
+
+    pub fn is_gray(&self) -> bool {
+        self.red == self.green && self.green == self.blue
+    }
+}
+
+impl fmt::Display for Color {
+    /// Writes the color as `#rrggbb`, the form `from_str` reads
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (red, green, blue) = (self.red >> 8, self.green >> 8, self.blue >> 8);
+        write!(f, "#{red:02x}{green:02x}{blue:02x}")
+    }
+}
+
+impl FromStr for Color {
+    type Err = Error;
+
+    /// Parses a hex color written as `#rrggbb`; the `#` is optional
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(Error::invalid_argument(format!(
+                "\"{s}\" is not a color written as #rrggbb"
+            )));
+        }
+        let channel = |idx: usize| {
+            u8::from_str_radix(&hex[idx..idx + 2], 16).expect("checked to be hex digits")
+        };
+        Ok(Color::from_rgb8(channel(0), channel(2), channel(4)))
+    }
+}
+
+/// Pixels stored as indices into a palette of up to 256 colors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedPixels {
+    /// 8-bit RGBA colors, in the order they first appear in the image
+    pub palette: Vec<[u8; 4]>,
+    /// One palette index per pixel, row by row from the top
+    pub indices: Vec<u8>,
+}
+
+/// A decoded image that every format converts through. Pixels are stored row by row
+/// starting from the top, with no padding between rows; 16-bit samples are big-endian.
+#[derive(Clone, Debug)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    color_model: ColorModel,
+    bit_depth: u8,
+    data: Vec<u8>,
+    metadata: Metadata,
+}
+
+impl Image {
+    pub fn new(
+        width: u32,
+        height: u32,
+        color_model: ColorModel,
+        bit_depth: u8,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        if bit_depth != 8 && bit_depth != 16 {
+            return Err(Error::invalid_argument(format!(
+                "images hold 8 or 16-bit samples, not {bit_depth}-bit"
+            )));
+        }
+        let expected_len =
+            width as usize * height as usize * color_model.channels() * (bit_depth as usize / 8);
+        if data.len() != expected_len {
+            return Err(Error::invalid_argument(format!(
+                "a {width}x{height} {color_model:?} image needs {expected_len} bytes of {bit_depth}-bit samples, but got {}",
+                data.len()
+            )));
+        }
+        Ok(Image {
+            width,
+            height,
+            color_model,
+            bit_depth,
+            data,
+            metadata: Metadata::default(),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn color_model(&self) -> ColorModel {
+        self.color_model
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = metadata;
+    }
+
+    /// The shape of this image's rows
+    pub fn layout(&self) -> RowLayout {
+        RowLayout {
+            width: self.width,
+            height: self.height,
+            color_model: self.color_model,
+            bit_depth: self.bit_depth,
+        }
+    }
+
+    /// Bytes taken up by one row of pixels
+    pub fn row_len(&self) -> usize {
+        self.layout().row_len()
+    }
+
+    pub fn row(&self, y: u32) -> &[u8] {
+        let row_len = self.row_len();
+        &self.data[y as usize * row_len..(y as usize + 1) * row_len]
+    }
+
+    /// Reads the sample at `idx` (counted in samples, not bytes) at the image's bit depth
+    pub fn sample(&self, idx: usize) -> u16 {
+        self.layout().sample(&self.data, idx)
+    }
+
+    /// Reads pixel `pixel` (counted row by row from the top) as 8-bit RGBA
+    pub fn rgba8(&self, pixel: usize) -> [u8; 4] {
+        // rows aren't padded, so the whole image reads like one long row
+        self.layout().rgba8(&self.data, pixel)
+    }
+
+    /// Collects the distinct colors of the image into a palette, reducing 16-bit samples to
+    /// 8 bits. Returns `None` if the image has more than `max_colors` colors (or over 256).
+    pub fn to_indexed(&self, max_colors: usize) -> Option<IndexedPixels> {
+        let max_colors = max_colors.min(256);
+        let pixel_count = self.width as usize * self.height as usize;
+        let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+        let mut palette: Vec<[u8; 4]> = Vec::new();
+        let mut indices: Vec<u8> = Vec::with_capacity(pixel_count);
+        for pixel in 0..pixel_count {
+            let color = self.rgba8(pixel);
+            let index = match lookup.get(&color) {
+                Some(&index) => index,
+                None if palette.len() < max_colors => {
+                    let index = palette.len() as u8;
+                    palette.push(color);
+                    lookup.insert(color, index);
+                    index
+                }
+                None => return None,
+            };
+            indices.push(index);
+        }
+        Some(IndexedPixels { palette, indices })
+    }
+
+    /// Scales the image to `width` by `height` with a box filter: each new pixel averages
+    /// the pixels it covers, weighting colors by alpha so transparent pixels don't bleed in.
+    /// Enlarging repeats pixels.
+    pub fn resize(&self, width: u32, height: u32) -> Result<Image> {
+        if width == 0 || height == 0 {
+            return Err(Error::invalid_argument(format!(
+                "can't resize an image to {width}x{height}"
+            )));
+        }
+        let channels = self.color_model.channels();
+        let alpha = self.color_model.has_alpha().then_some(channels - 1);
+        let sample_len = self.bit_depth as usize / 8;
+        // the source pixels new pixel `idx` of `len` covers along a side of `source_len`
+        let span = |idx: u32, len: u32, source_len: u32| {
+            let start = idx as u64 * source_len as u64 / len as u64;
+            let end = ((idx as u64 + 1) * source_len as u64).div_ceil(len as u64);
+            start as usize..end.max(start + 1) as usize
+        };
+        let mut data: Vec<u8> =
+            Vec::with_capacity(width as usize * height as usize * channels * sample_len);
+        let mut sums: Vec<u64> = vec![0; channels];
+        for y in 0..height {
+            let rows = span(y, height, self.height);
+            for x in 0..width {
+                let columns = span(x, width, self.width);
+                sums.fill(0);
+                let mut count: u64 = 0;
+                for source_y in rows.clone() {
+                    for source_x in columns.clone() {
+                        let base = (source_y * self.width as usize + source_x) * channels;
+                        let weight = alpha.map_or(1, |alpha| self.sample(base + alpha) as u64);
+                        for (channel, sum) in sums.iter_mut().enumerate() {
+                            let value = self.sample(base + channel) as u64;
+                            *sum += match Some(channel) == alpha {
+                                true => value,
+                                false => value * weight,
+                            };
+                        }
+                        count += 1;
+                    }
+                }
+                let total_weight = alpha.map_or(count, |alpha| sums[alpha]);
+                for (channel, &sum) in sums.iter().enumerate() {
+                    let value = match (Some(channel) == alpha, total_weight) {
+                        (true, _) => (sum + count / 2) / count,
+                        (false, 0) => 0,
+                        (false, total_weight) => (sum + total_weight / 2) / total_weight,
+                    };
+                    match self.bit_depth {
+                        16 => data.extend((value as u16).to_be_bytes()),
+                        _ => data.push(value as u8),
+                    }
+                }
+            }
+        }
+        let mut image = Image::new(width, height, self.color_model, self.bit_depth, data)?;
+        image.set_metadata(self.metadata.clone());
+        Ok(image)
+    }
+
+    /// Reduces 16-bit samples to 8 bits by keeping their high byte, as `rgba8` reads them.
+    /// 8-bit images are left as they are.
+    pub fn reduce_depth(&mut self) {
+        if self.bit_depth == 16 {
+            self.data = self.data.iter().step_by(2).copied().collect();
+            self.bit_depth = 8;
+        }
+    }
+
+    /// Composites every pixel onto an opaque matte and drops the alpha channel. Without an
+    /// explicit `matte` the image's background color is used, falling back to white.
+    pub fn flatten_alpha(&mut self, matte: Option<Color>) {
+        let matte = matte.or(self.metadata.background()).unwrap_or(Color::WHITE);
+        if let Some(matte) = AlphaMatte::new(self.color_model, self.bit_depth, matte) {
+            let pixel_count = self.width as usize * self.height as usize;
+            let mut data: Vec<u8> =
+                vec![
+                    0;
+                    pixel_count * matte.color_model().channels() * (self.bit_depth as usize / 8)
+                ];
+            matte.flatten(&self.data, &mut data);
+            self.color_model = matte.color_model();
+            self.data = data;
+        }
+    }
+}
+
+/// Composites pixels with alpha onto an opaque color. A gray image stays gray on a gray
+/// matte; on a colored one it becomes RGB.
+#[derive(Clone, Debug)]
+pub struct AlphaMatte {
+    in_channels: usize,
+    color_model: ColorModel,
+    bit_depth: u8,
+    channels: Vec<u32>,
+}
+
+impl AlphaMatte {
+    /// Returns `None` for color models without alpha, which have nothing to flatten
+    pub fn new(color_model: ColorModel, bit_depth: u8, matte: Color) -> Option<Self> {
+        if !color_model.has_alpha() {
+            return None;
+        }
+        let scale = |channel: u16| -> u32 {
+            match bit_depth {
+                16 => channel as u32,
+                _ => channel as u32 >> 8,
+            }
+        };
+        let channels = vec![scale(matte.red), scale(matte.green), scale(matte.blue)];
+        let (out_model, channels) = match (color_model, matte.is_gray()) {
+            (ColorModel::GrayAlpha, true) => (ColorModel::Gray, channels[..1].to_vec()),
+            _ => (ColorModel::Rgb, channels),
+        };
+        Some(AlphaMatte {
+            in_channels: color_model.channels(),
+            color_model: out_model,
+            bit_depth,
+            channels,
+        })
+    }
+
+    /// The color model flattened pixels come out in
+    pub fn color_model(&self) -> ColorModel {
+        self.color_model
+    }
+
+    /// Flattens the pixels in `data` into `out`, which must have room for every one of them
+    pub fn flatten(&self, data: &[u8], out: &mut [u8]) {
+        let max = if self.bit_depth == 16 { 65535 } else { 255 };
+        let sample_len = self.bit_depth as usize / 8;
+        let sample = |idx: usize| -> u32 {
+            match self.bit_depth {
+                16 => u16::from_be_bytes([data[idx * 2], data[idx * 2 + 1]]) as u32,
+                _ => data[idx] as u32,
+            }
+        };
+        let pixel_count = data.len() / (self.in_channels * sample_len);
+        let mut out_idx = 0;
+        for pixel in 0..pixel_count {
+            let base = pixel * self.in_channels;
+            let alpha = sample(base + self.in_channels - 1);
+            for (channel, &matte_channel) in self.channels.iter().enumerate() {
+                // gray sources are spread across all three channels of a colored matte
+                let source_channel = if self.in_channels == 2 { 0 } else { channel };
+                let value = sample(base + source_channel);
+                let blended = (value * alpha + matte_channel * (max - alpha) + max / 2) / max;
+                match self.bit_depth {
+                    16 => {
+                        out[out_idx..out_idx + 2].copy_from_slice(&(blended as u16).to_be_bytes())
+                    }
+                    _ => out[out_idx] = blended as u8,
+                }
+                out_idx += sample_len;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_checks_length() {
+        assert!(Image::new(2, 2, ColorModel::Rgb, 8, vec![0; 12]).is_ok());
+        assert!(Image::new(2, 2, ColorModel::Rgb, 8, vec![0; 11]).is_err());
+        assert!(Image::new(2, 2, ColorModel::Rgb, 16, vec![0; 12]).is_err());
+        assert!(Image::new(2, 2, ColorModel::Rgb, 4, vec![0; 6]).is_err());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(
+            "#ff8000".parse::<Color>().unwrap(),
+            Color::from_rgb8(255, 128, 0)
+        );
+        assert_eq!(
+            "00FF00".parse::<Color>().unwrap(),
+            Color::from_rgb8(0, 255, 0)
+        );
+        assert!("#fff".parse::<Color>().is_err());
+        assert!("#gg0000".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_to_indexed() {
+        let image = Image::new(
+            3,
+            1,
+            ColorModel::GrayAlpha,
+            16,
+            vec![0, 0, 255, 255, 0, 0, 255, 255, 255, 255, 0, 0],
+        )
+        .unwrap();
+        let indexed = image.to_indexed(256).unwrap();
+        assert_eq!(indexed.palette, vec![[0, 0, 0, 255], [255, 255, 255, 0]]);
+        assert_eq!(indexed.indices, vec![0, 0, 1]);
+        assert!(image.to_indexed(1).is_none());
+    }
+
+    #[test]
+    fn test_flatten_onto_matte() {
+        let mut image = Image::new(
+            2,
+            1,
+            ColorModel::Rgba,
+            8,
+            vec![255, 0, 0, 255, 255, 0, 0, 0],
+        )
+        .unwrap();
+        image.flatten_alpha(Some(Color::from_rgb8(0, 0, 255)));
+        assert_eq!(image.color_model(), ColorModel::Rgb);
+        assert_eq!(image.data(), &[255, 0, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_flatten_uses_background() {
+        let mut image = Image::new(1, 1, ColorModel::GrayAlpha, 8, vec![0, 0]).unwrap();
+        image
+            .metadata_mut()
+            .set_background(Some(Color::from_rgb8(10, 20, 30)));
+        image.flatten_alpha(None);
+        assert_eq!(image.color_model(), ColorModel::Rgb);
+        assert_eq!(image.data(), &[10, 20, 30]);
+
+        let mut image = Image::new(1, 1, ColorModel::GrayAlpha, 16, vec![0, 0, 0, 0]).unwrap();
+        image.flatten_alpha(None);
+        assert_eq!(image.color_model(), ColorModel::Gray);
+        assert_eq!(image.data(), &[255, 255]);
+    }
+
+    #[test]
+    fn test_resize() {
+        let image = Image::new(
+            4,
+            2,
+            ColorModel::GrayAlpha,
+            8,
+            vec![
+                100, 255, 200, 255, 0, 0, 90, 0, //
+                100, 255, 200, 255, 50, 255, 70, 255,
+            ],
+        )
+        .unwrap();
+        let smaller = image.resize(2, 1).unwrap();
+        // the transparent pixels don't darken the average, but do thin out its alpha
+        assert_eq!(smaller.data(), &[150, 255, 60, 128]);
+
+        let larger = Image::new(1, 1, ColorModel::Rgb, 16, vec![1, 2, 3, 4, 5, 6])
+            .unwrap()
+            .resize(2, 2)
+            .unwrap();
+        assert_eq!(larger.data(), [1, 2, 3, 4, 5, 6].repeat(4));
+        assert!(image.resize(0, 1).is_err());
+    }
+}
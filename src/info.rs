@@ -0,0 +1,649 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    convert::{identify, Input},
+    format::Registry,
+    metadata::Metadata,
+    png::chunk_type::PngChunkType,
+    Result,
+};
+
+/// What an image file holds, as far as can be told without decoding its pixels
+#[derive(Clone, Debug, Serialize)]
+pub struct ImageInfo {
+    /// Name of the format the file was read as
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// How the file stores its pixels: "gray", "gray+alpha", "rgb", "rgba" or "indexed"
+    pub color_model: &'static str,
+    /// Bits per sample, or per palette index for indexed images
+    pub bit_depth: u8,
+    pub compression: &'static str,
+    pub interlaced: bool,
+    /// Colors in the palette, for files that have one
+    pub palette_size: Option<usize>,
+    pub metadata: MetadataInfo,
+    /// What only this format records
+    pub details: FormatDetails,
+    /// Problems that didn't stop the file being read, worded for showing to a user
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// The parts of `Metadata` worth showing
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MetadataInfo {
+    /// Background color as `#rrggbb`
+    pub background: Option<String>,
+    pub exif: Option<ExifInfo>,
+    /// Pixel density, such as "300x300 pixels per inch"
+    pub resolution: Option<String>,
+    /// Size in bytes of the embedded ICC profile
+    pub icc_profile: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ExifInfo {
+    /// Number of entries in the EXIF block
+    pub fields: usize,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub date_time: Option<String>,
+    pub orientation: Option<u16>,
+}
+
+impl From<&Metadata> for MetadataInfo {
+    fn from(metadata: &Metadata) -> Self {
+        MetadataInfo {
+            background: metadata.background().map(|color| color.to_string()),
+            exif: metadata.exif().map(|exif| ExifInfo {
+                fields: exif.fields().len(),
+                camera_make: exif.camera_make().map(str::to_string),
+                camera_model: exif.camera_model().map(str::to_string),
+                date_time: exif.date_time().map(str::to_string),
+                orientation: exif.orientation(),
+            }),
+            resolution: metadata
+                .resolution()
+                .map(|resolution| resolution.to_string()),
+            icc_profile: metadata.icc_profile().map(<[u8]>::len),
+        }
+    }
+}
+
+/// Structure particular to one format
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatDetails {
+    Png(PngDetails),
+    Bmp(BmpDetails),
+    Netpbm(NetpbmDetails),
+    Tga(TgaDetails),
+    Qoi(QoiDetails),
+    Gif(GifDetails),
+    Ico(IcoDetails),
+    Tiff(TiffDetails),
+    Jpeg(JpegDetails),
+    Webp(WebpDetails),
+    /// farbfeld headers hold nothing beyond the image size
+    Farbfeld,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PngDetails {
+    /// Every chunk in the file, in order
+    pub chunks: Vec<ChunkInfo>,
+    /// When the image was last changed, from the tIME chunk
+    pub modified: Option<String>,
+}
+
+/// One chunk of a PNG and the properties its type name encodes
+#[derive(Clone, Debug, Serialize)]
+pub struct ChunkInfo {
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+    /// Bytes of data, not counting the length, type and CRC
+    pub length: u32,
+    pub critical: bool,
+    pub public: bool,
+    pub reserved_bit_valid: bool,
+    pub safe_to_copy: bool,
+}
+
+impl ChunkInfo {
+    pub fn new(chunk_type: &PngChunkType, length: u32) -> Self {
+        ChunkInfo {
+            chunk_type: chunk_type.to_string(),
+            length,
+            critical: chunk_type.is_critical(),
+            public: chunk_type.is_public(),
+            reserved_bit_valid: chunk_type.is_reserved_bit_valid(),
+            safe_to_copy: chunk_type.is_safe_to_copy(),
+        }
+    }
+}
+
+/// The fields of a BMP's file and info headers
+#[derive(Clone, Debug, Serialize)]
+pub struct BmpDetails {
+    /// File size the file header claims
+    pub file_size: u32,
+    /// Where the pixel data starts
+    pub pixel_offset: u32,
+    /// Name of the info header version, such as BITMAPINFOHEADER
+    pub header: &'static str,
+    pub header_length: u32,
+    pub bits_per_pixel: u16,
+    /// Whether the top row is stored first
+    pub top_down: bool,
+    /// Size of the pixel data the info header claims; 0 is allowed for uncompressed images
+    pub image_size: u32,
+    /// Horizontal and vertical resolution
+    pub pixels_per_meter: [i32; 2],
+    pub colors_used: u32,
+    pub important_colors: u32,
+    /// Red, green, blue and alpha masks, for images that store pixels through masks
+    pub bit_masks: Option<[u32; 4]>,
+}
+
+/// The header fields of a PBM, PGM, PPM or PAM file
+#[derive(Clone, Debug, Serialize)]
+pub struct NetpbmDetails {
+    /// P1 to P7
+    pub magic: &'static str,
+    /// Whether samples are written as decimal text
+    pub plain: bool,
+    pub maxval: u16,
+    /// Channels per pixel
+    pub depth: u32,
+    /// PAM's description of its channels
+    pub tuple_type: Option<String>,
+}
+
+/// The header fields of a TGA, and what its extension area says about it
+#[derive(Clone, Debug, Serialize)]
+pub struct TgaDetails {
+    /// "2.0" for files with the TGA 2.0 footer, "1.0" for the rest
+    pub version: &'static str,
+    /// 1 to 3 for color-mapped, truecolor and grayscale, plus 8 when run-length encoded
+    pub image_type: u8,
+    pub image_id: Option<String>,
+    /// Corner of the image the first stored pixel belongs to, such as "bottom-left"
+    pub origin: &'static str,
+    pub alpha_bits: u8,
+    /// Bits per color map entry, for files that have one
+    pub color_map_entry_size: Option<u8>,
+    pub author: Option<String>,
+    pub comments: Option<String>,
+    pub job: Option<String>,
+    pub software: Option<String>,
+    /// When the extension area says the file was saved
+    pub saved: Option<String>,
+    /// What the alpha channel means, from the extension area
+    pub attributes_type: Option<u8>,
+}
+
+/// The header fields of a QOI file
+#[derive(Clone, Debug, Serialize)]
+pub struct QoiDetails {
+    /// 3 for RGB, 4 for RGBA
+    pub channels: u8,
+    /// "sRGB" or "linear"
+    pub colorspace: &'static str,
+}
+
+/// The structure of a GIF file
+#[derive(Clone, Debug, Serialize)]
+pub struct GifDetails {
+    /// "87a" or "89a"
+    pub version: String,
+    pub frames: usize,
+    /// Times the animation repeats after its first play, from the NETSCAPE2.0 extension;
+    /// 0 means forever
+    pub loop_count: Option<u16>,
+    /// How many frames bring their own color table
+    pub local_color_tables: usize,
+    pub background_index: u8,
+    pub comments: Vec<String>,
+}
+
+/// The directory of an ICO or CUR file
+#[derive(Clone, Debug, Serialize)]
+pub struct IcoDetails {
+    /// "icon" or "cursor"
+    pub kind: &'static str,
+    pub entries: Vec<IconEntryInfo>,
+}
+
+/// One image listed in an icon or cursor's directory
+#[derive(Clone, Debug, Serialize)]
+pub struct IconEntryInfo {
+    pub width: u32,
+    pub height: u32,
+    /// "PNG" or "BMP", as the image is stored
+    pub format: &'static str,
+    /// Bits per pixel the directory gives, which only icons have room for
+    pub bits_per_pixel: Option<u16>,
+    /// Pixel a cursor points with
+    pub hotspot: Option<[u16; 2]>,
+    /// Bytes of image data
+    pub size: u32,
+}
+
+/// The pages of a TIFF file
+#[derive(Clone, Debug, Serialize)]
+pub struct TiffDetails {
+    /// "little-endian" or "big-endian"
+    pub byte_order: &'static str,
+    pub pages: Vec<TiffPageInfo>,
+    pub software: Option<String>,
+    pub date_time: Option<String>,
+}
+
+/// What one page's IFD says about how it's stored
+#[derive(Clone, Debug, Serialize)]
+pub struct TiffPageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub color_model: &'static str,
+    pub bits_per_sample: u16,
+    pub samples_per_pixel: u16,
+    pub compression: &'static str,
+    /// Whether samples are stored as differences from their left neighbor
+    pub predictor: bool,
+    /// Tile width and height, for tiled pages
+    pub tiles: Option<[u32; 2]>,
+    /// Number of strips or tiles
+    pub segments: usize,
+}
+
+/// The frame header of a JPEG, and the segments around it
+#[derive(Clone, Debug, Serialize)]
+pub struct JpegDetails {
+    /// Coding process named by the SOF marker, such as "baseline" or "progressive"
+    pub process: &'static str,
+    /// Version from the JFIF segment, such as "1.01"
+    pub jfif_version: Option<String>,
+    /// Color transform from the Adobe segment: 0 for none, 1 for YCbCr and 2 for YCCK
+    pub adobe_transform: Option<u8>,
+    /// Chroma subsampling such as "4:2:0", for three-component images
+    pub subsampling: Option<&'static str>,
+    pub components: Vec<JpegComponentInfo>,
+    pub scans: usize,
+    pub comments: Vec<String>,
+}
+
+/// One component of a JPEG frame
+#[derive(Clone, Debug, Serialize)]
+pub struct JpegComponentInfo {
+    pub id: u8,
+    /// Horizontal and vertical sampling factors
+    pub sampling: [u8; 2],
+    pub quantization_table: u8,
+}
+
+/// The chunks of a WebP file
+#[derive(Clone, Debug, Serialize)]
+pub struct WebpDetails {
+    /// Whether the file starts with a VP8X chunk
+    pub extended: bool,
+    /// FourCC of every top-level chunk, in order
+    pub chunks: Vec<String>,
+    pub frames: usize,
+    /// Times the animation plays, from the ANIM chunk; 0 means forever
+    pub loop_count: Option<u16>,
+}
+
+/// Describes the image at `path`, whose format is worked out as `convert` does
+pub fn inspect<P: AsRef<Path>>(path: P) -> Result<ImageInfo> {
+    inspect_io(Input::Path(path.as_ref()), None)
+}
+
+/// Like `inspect`, but the image can come from a stream, and `from` can name its format
+pub fn inspect_io(input: Input, from: Option<&str>) -> Result<ImageInfo> {
+    inspect_with(&Registry::default(), input, from)
+}
+
+/// Like `inspect_io`, but only considers the formats in `registry`
+pub fn inspect_with(registry: &Registry, input: Input, from: Option<&str>) -> Result<ImageInfo> {
+    let mut file_reader: BufReader<File>;
+    let (reader, input_path): (&mut dyn Read, Option<&Path>) = match input {
+        Input::Path(path) => {
+            file_reader = BufReader::new(File::open(path)?);
+            (&mut file_reader, Some(path))
+        }
+        Input::Reader(reader) => (reader, None),
+    };
+    let mut warnings: Vec<String> = Vec::new();
+    let (codec, signature) = identify(registry, reader, input_path, from, &mut warnings)?;
+    let mut info = codec.inspect(&mut signature.as_slice().chain(reader))?;
+    info.warnings = warnings;
+    Ok(info)
+}
+
+impl fmt::Display for ImageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        writeln!(f, "format:        {}", self.format)?;
+        writeln!(f, "dimensions:    {}x{}", self.width, self.height)?;
+        writeln!(f, "color model:   {}", self.color_model)?;
+        writeln!(f, "bit depth:     {}", self.bit_depth)?;
+        writeln!(f, "compression:   {}", self.compression)?;
+        writeln!(f, "interlaced:    {}", yes_no(self.interlaced))?;
+        if let Some(palette_size) = self.palette_size {
+            writeln!(f, "palette size:  {palette_size}")?;
+        }
+        if let Some(background) = &self.metadata.background {
+            writeln!(f, "background:    {background}")?;
+        }
+        if let Some(resolution) = &self.metadata.resolution {
+            writeln!(f, "resolution:    {resolution}")?;
+        }
+        if let Some(icc_profile) = self.metadata.icc_profile {
+            writeln!(f, "icc profile:   {icc_profile} bytes")?;
+        }
+        if let Some(exif) = &self.metadata.exif {
+            writeln!(f, "exif:          {} fields", exif.fields)?;
+            let camera = [exif.camera_make.as_deref(), exif.camera_model.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            if !camera.is_empty() {
+                writeln!(f, "  camera:      {camera}")?;
+            }
+            if let Some(date_time) = &exif.date_time {
+                writeln!(f, "  taken:       {date_time}")?;
+            }
+            if let Some(orientation) = exif.orientation {
+                writeln!(f, "  orientation: {orientation}")?;
+            }
+        }
+        match &self.details {
+            FormatDetails::Png(png) => {
+                if let Some(modified) = &png.modified {
+                    writeln!(f, "modified:      {modified}")?;
+                }
+                writeln!(f, "chunks:")?;
+                for chunk in &png.chunks {
+                    let mut properties = vec![
+                        if chunk.critical {
+                            "critical"
+                        } else {
+                            "ancillary"
+                        },
+                        if chunk.public { "public" } else { "private" },
+                        if chunk.safe_to_copy {
+                            "safe to copy"
+                        } else {
+                            "unsafe to copy"
+                        },
+                    ];
+                    if !chunk.reserved_bit_valid {
+                        properties.push("reserved bit set");
+                    }
+                    writeln!(
+                        f,
+                        "  {} {:>10} bytes  {}",
+                        chunk.chunk_type,
+                        chunk.length,
+                        properties.join(", ")
+                    )?;
+                }
+            }
+            FormatDetails::Bmp(bmp) => {
+                writeln!(
+                    f,
+                    "header:        {} ({} bytes)",
+                    bmp.header, bmp.header_length
+                )?;
+                writeln!(f, "bits/pixel:    {}", bmp.bits_per_pixel)?;
+                let order = if bmp.top_down {
+                    "top-down"
+                } else {
+                    "bottom-up"
+                };
+                writeln!(f, "row order:     {order}")?;
+                writeln!(f, "file size:     {} bytes", bmp.file_size)?;
+                writeln!(f, "pixel offset:  {}", bmp.pixel_offset)?;
+                writeln!(f, "image size:    {} bytes", bmp.image_size)?;
+                let [horizontal, vertical] = bmp.pixels_per_meter;
+                writeln!(f, "resolution:    {horizontal}x{vertical} pixels/m")?;
+                writeln!(f, "colors used:   {}", bmp.colors_used)?;
+                writeln!(f, "important:     {}", bmp.important_colors)?;
+                if let Some([red, green, blue, alpha]) = bmp.bit_masks {
+                    writeln!(
+                        f,
+                        "bit masks:     red {red:#010x}, green {green:#010x}, blue {blue:#010x}, alpha {alpha:#010x}"
+                    )?;
+                }
+            }
+            FormatDetails::Netpbm(netpbm) => {
+                let form = if netpbm.plain { "plain" } else { "binary" };
+                writeln!(f, "magic:         {} ({form})", netpbm.magic)?;
+                writeln!(f, "maxval:        {}", netpbm.maxval)?;
+                writeln!(f, "depth:         {}", netpbm.depth)?;
+                if let Some(tuple_type) = &netpbm.tuple_type {
+                    writeln!(f, "tuple type:    {tuple_type}")?;
+                }
+            }
+            FormatDetails::Tga(tga) => {
+                writeln!(f, "version:       TGA {}", tga.version)?;
+                writeln!(f, "image type:    {}", tga.image_type)?;
+                writeln!(f, "origin:        {}", tga.origin)?;
+                writeln!(f, "alpha bits:    {}", tga.alpha_bits)?;
+                if let Some(entry_size) = tga.color_map_entry_size {
+                    writeln!(f, "map entries:   {entry_size} bits")?;
+                }
+                let fields = [
+                    ("image id:     ", &tga.image_id),
+                    ("author:       ", &tga.author),
+                    ("job:          ", &tga.job),
+                    ("software:     ", &tga.software),
+                    ("saved:        ", &tga.saved),
+                ];
+                for (label, value) in fields {
+                    if let Some(value) = value {
+                        writeln!(f, "{label} {value}")?;
+                    }
+                }
+                if let Some(comments) = &tga.comments {
+                    for line in comments.lines() {
+                        writeln!(f, "comment:       {line}")?;
+                    }
+                }
+                if let Some(attributes_type) = tga.attributes_type {
+                    writeln!(f, "attributes:    {attributes_type}")?;
+                }
+            }
+            FormatDetails::Qoi(qoi) => {
+                writeln!(f, "channels:      {}", qoi.channels)?;
+                writeln!(f, "colorspace:    {}", qoi.colorspace)?;
+            }
+            FormatDetails::Gif(gif) => {
+                writeln!(f, "version:       GIF {}", gif.version)?;
+                writeln!(f, "frames:        {}", gif.frames)?;
+                match gif.loop_count {
+                    Some(0) => writeln!(f, "loops:         forever")?,
+                    Some(count) => writeln!(f, "loops:         {} plays", count as u32 + 1)?,
+                    None => {}
+                }
+                writeln!(f, "local tables:  {}", gif.local_color_tables)?;
+                writeln!(f, "background:    index {}", gif.background_index)?;
+                for comment in &gif.comments {
+                    writeln!(f, "comment:       {comment}")?;
+                }
+            }
+            FormatDetails::Ico(ico) => {
+                writeln!(f, "kind:          {}", ico.kind)?;
+                for (index, entry) in ico.entries.iter().enumerate() {
+                    write!(
+                        f,
+                        "image {index:<7} {}x{} {}",
+                        entry.width, entry.height, entry.format
+                    )?;
+                    if let Some(bits) = entry.bits_per_pixel {
+                        write!(f, ", {bits} bits")?;
+                    }
+                    if let Some([x, y]) = entry.hotspot {
+                        write!(f, ", hotspot {x},{y}")?;
+                    }
+                    writeln!(f, ", {} bytes", entry.size)?;
+                }
+            }
+            FormatDetails::Tiff(tiff) => {
+                writeln!(f, "byte order:    {}", tiff.byte_order)?;
+                if let Some(software) = &tiff.software {
+                    writeln!(f, "software:      {software}")?;
+                }
+                if let Some(date_time) = &tiff.date_time {
+                    writeln!(f, "saved:         {date_time}")?;
+                }
+                for (index, page) in tiff.pages.iter().enumerate() {
+                    write!(
+                        f,
+                        "page {index:<8} {}x{} {}, {}x{} bits, {}",
+                        page.width,
+                        page.height,
+                        page.color_model,
+                        page.samples_per_pixel,
+                        page.bits_per_sample,
+                        page.compression
+                    )?;
+                    if page.predictor {
+                        write!(f, " with predictor")?;
+                    }
+                    match page.tiles {
+                        Some([width, height]) => {
+                            writeln!(f, ", {} {width}x{height} tiles", page.segments)?
+                        }
+                        None => writeln!(f, ", {} strips", page.segments)?,
+                    }
+                }
+            }
+            FormatDetails::Jpeg(jpeg) => {
+                writeln!(f, "process:       {}", jpeg.process)?;
+                if let Some(version) = &jpeg.jfif_version {
+                    writeln!(f, "jfif:          {version}")?;
+                }
+                if let Some(transform) = jpeg.adobe_transform {
+                    writeln!(f, "adobe:         transform {transform}")?;
+                }
+                if let Some(subsampling) = jpeg.subsampling {
+                    writeln!(f, "subsampling:   {subsampling}")?;
+                }
+                for component in &jpeg.components {
+                    let [horizontal, vertical] = component.sampling;
+                    writeln!(
+                        f,
+                        "component {:<3} {horizontal}x{vertical} sampling, quantization table {}",
+                        component.id, component.quantization_table
+                    )?;
+                }
+                writeln!(f, "scans:         {}", jpeg.scans)?;
+                for comment in &jpeg.comments {
+                    writeln!(f, "comment:       {comment}")?;
+                }
+            }
+            FormatDetails::Webp(webp) => {
+                let kind = if webp.extended { "extended" } else { "simple" };
+                writeln!(f, "layout:        {kind}")?;
+                writeln!(f, "chunks:        {}", webp.chunks.join(", "))?;
+                writeln!(f, "frames:        {}", webp.frames)?;
+                match webp.loop_count {
+                    Some(0) => writeln!(f, "loops:         forever")?,
+                    Some(count) => writeln!(f, "loops:         {count} plays")?,
+                    None => {}
+                }
+            }
+            FormatDetails::Farbfeld => {}
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning:       {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bmp::{options::BmpOptions, Bmp},
+        image::{Color, ColorModel, Image},
+        png::{time::Time, Png},
+        ConvertibleImage,
+    };
+
+    #[test]
+    fn test_inspect_png() {
+        let image = Image::new(3, 2, ColorModel::GrayAlpha, 16, vec![7; 24]).unwrap();
+        let mut metadata = Metadata::default();
+        metadata.set_background(Some(Color::from_rgb8(0x80, 0x80, 0x80)));
+        let mut png = Png::from_image(&image).unwrap();
+        png.set_metadata(&metadata).unwrap();
+        png.set_modification_time(Some(Time::new(2024, 2, 29, 12, 0, 0).unwrap()))
+            .unwrap();
+        let mut bytes = png.to_bytes();
+        // the image data isn't read, so a broken IDAT CRC goes unnoticed
+        let idat = bytes
+            .windows(4)
+            .position(|window| window == b"IDAT")
+            .unwrap();
+        let crc = idat + 4 + u32::from_be_bytes(bytes[idat - 4..idat].try_into().unwrap()) as usize;
+        bytes[crc] ^= 0xff;
+
+        let info = inspect_io(Input::Reader(&mut &bytes[..]), None).unwrap();
+        assert_eq!((info.format, info.width, info.height), ("PNG", 3, 2));
+        assert_eq!((info.color_model, info.bit_depth), ("gray+alpha", 16));
+        assert_eq!(info.metadata.background.as_deref(), Some("#808080"));
+        let FormatDetails::Png(details) = &info.details else {
+            panic!("PNG details expected");
+        };
+        assert_eq!(details.modified.as_deref(), Some("2024-02-29T12:00:00Z"));
+        let types: Vec<&str> = details
+            .chunks
+            .iter()
+            .map(|chunk| chunk.chunk_type.as_str())
+            .collect();
+        assert_eq!(types, ["IHDR", "bKGD", "tIME", "IDAT", "IEND"]);
+        assert!(!details.chunks[1].critical);
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["details"]["png"]["chunks"][0]["type"], "IHDR");
+        assert!(json.get("warnings").is_none());
+    }
+
+    #[test]
+    fn test_inspect_bmp() {
+        let image = Image::new(2, 2, ColorModel::Rgba, 8, vec![9; 16]).unwrap();
+        let options = BmpOptions {
+            top_down: true,
+            ..BmpOptions::default()
+        };
+        let bytes = Bmp::from_image_with(&image, &options).unwrap().to_bytes();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("named-wrong.png");
+        std::fs::write(&path, &bytes).unwrap();
+        let info = inspect(&path).unwrap();
+        assert_eq!(
+            (info.format, info.color_model, info.bit_depth),
+            ("BMP", "rgba", 8)
+        );
+        let FormatDetails::Bmp(details) = &info.details else {
+            panic!("BMP details expected");
+        };
+        assert!(details.top_down);
+        assert_eq!(details.bits_per_pixel, 32);
+        assert_eq!(info.warnings.len(), 1);
+    }
+}
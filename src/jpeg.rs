@@ -0,0 +1,354 @@
+use std::io::{BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    convert::ConvertOptions,
+    exif::Exif,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{FormatDetails, ImageInfo, JpegComponentInfo, JpegDetails, MetadataInfo},
+    metadata::Metadata,
+    png::Png,
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    header::{FrameHeader, Jfif, ADOBE, EXIF, ICC_PROFILE, JFIF},
+    image_data::ColorTransform,
+    marker::{Segment, APP0, APP1, APP14, APP2, COM, EOI, SOI, SOS},
+    options::JpegOptions,
+};
+
+pub mod dct;
+pub mod header;
+pub mod huffman;
+pub mod image_data;
+pub mod marker;
+pub mod options;
+
+/// Registry entry for JPEG, including JFIF and EXIF files
+pub const CODEC: Codec = Codec {
+    name: "JPEG",
+    extensions: &["jpg", "jpeg", "jpe", "jfif"],
+    magic: &[&[0xff, SOI, 0xff]],
+    check_signature: None,
+    decoder: Some(decode_with::<Jpeg>),
+    encoder: Some(write_jpeg),
+    row_decoder: None,
+    row_encoder: None,
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a JPEG from its frame header and other segments, without decoding any scans
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let jpeg = Jpeg::from_reader(reader)?;
+    let frame = jpeg.frame()?;
+    let jfif = jpeg.jfif()?;
+    let adobe = jpeg.adobe_transform()?;
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: frame.width as u32,
+        height: frame.height as u32,
+        color_model: ColorTransform::of(&frame, jfif.is_some(), adobe).name(),
+        bit_depth: frame.precision,
+        compression: match frame.marker {
+            0xc3 | 0xc7 | 0xcb | 0xcf => "lossless",
+            _ => "DCT",
+        },
+        interlaced: frame.is_progressive(),
+        palette_size: None,
+        metadata: MetadataInfo::from(&jpeg.metadata()?),
+        details: FormatDetails::Jpeg(JpegDetails {
+            process: frame.process(),
+            jfif_version: jfif.map(|jfif| format!("{}.{:02}", jfif.version.0, jfif.version.1)),
+            adobe_transform: adobe,
+            subsampling: frame.subsampling(),
+            components: frame
+                .components
+                .iter()
+                .map(|component| JpegComponentInfo {
+                    id: component.id,
+                    sampling: [component.horizontal, component.vertical],
+                    quantization_table: component.table,
+                })
+                .collect(),
+            scans: jpeg
+                .segments()
+                .iter()
+                .filter(|segment| segment.marker == SOS)
+                .count(),
+            comments: jpeg.comments(),
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// Encodes `png` as a JPEG for the registry, flattening any alpha onto the background
+/// color the conversion asks for
+fn write_jpeg(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    let mut image = png.to_image()?;
+    image.flatten_alpha(options.background);
+    Jpeg::from_image_with(&image, &options.jpeg)?.to_writer(writer)
+}
+
+/// A JPEG file, kept as its marker segments so metadata can be read without decoding
+#[derive(Clone, Debug)]
+pub struct Jpeg {
+    segments: Vec<Segment>,
+}
+
+impl Jpeg {
+    /// Bundles `segments`, which go between the SOI and EOI markers
+    pub fn new(segments: Vec<Segment>) -> Self {
+        Jpeg { segments }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The first frame header; files only have more than one in hierarchical mode
+    pub fn frame(&self) -> Result<FrameHeader> {
+        self.segments
+            .iter()
+            .find(|segment| marker::is_sof(segment.marker))
+            .map(|segment| FrameHeader::read(segment.marker, &segment.data))
+            .unwrap_or_else(|| Err(Error::malformed("JPEG", "the file has no frame header")))
+    }
+
+    /// The JFIF segment, if there is one
+    pub fn jfif(&self) -> Result<Option<Jfif>> {
+        self.segments
+            .iter()
+            .find_map(|segment| segment.payload(APP0, JFIF))
+            .map(Jfif::read)
+            .transpose()
+    }
+
+    /// The color transform an Adobe segment records, if there is one
+    pub fn adobe_transform(&self) -> Result<Option<u8>> {
+        self.segments
+            .iter()
+            .find_map(|segment| segment.payload(APP14, ADOBE))
+            .map(header::read_adobe_transform)
+            .transpose()
+    }
+
+    /// Text of every comment segment
+    pub fn comments(&self) -> Vec<String> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.marker == COM)
+            .map(|segment| String::from_utf8_lossy(&segment.data).into_owned())
+            .collect()
+    }
+
+    /// Collects the EXIF block, the ICC profile and the JFIF resolution
+    pub fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::default();
+        if let Some(exif) = self
+            .segments
+            .iter()
+            .find_map(|segment| segment.payload(APP1, EXIF))
+        {
+            metadata.set_exif(Some(Exif::try_from(exif)?));
+        }
+        metadata.set_resolution(self.jfif()?.and_then(|jfif| jfif.to_resolution()));
+        let icc_chunks: Vec<&[u8]> = self
+            .segments
+            .iter()
+            .filter_map(|segment| segment.payload(APP2, ICC_PROFILE))
+            .collect();
+        if !icc_chunks.is_empty() {
+            metadata.set_icc_profile(Some(header::join_icc_profile(&icc_chunks)?));
+        }
+        Ok(metadata)
+    }
+
+    /// Encodes `image` as a new `Jpeg` at the default quality
+    pub fn from_image(image: &Image) -> Result<Jpeg> {
+        image_data::encode(image, &JpegOptions::default())
+    }
+
+    /// Encodes `image` as a new `Jpeg` with the quality and tables `options` asks for
+    pub fn from_image_with(image: &Image, options: &JpegOptions) -> Result<Jpeg> {
+        image_data::encode(image, options)
+    }
+
+    /// Decodes the pixels into an 8-bit gray or RGB `Image`
+    pub fn to_image(&self) -> Result<Image> {
+        let mut image = image_data::decode(self)?;
+        image.set_metadata(self.metadata()?);
+        Ok(image)
+    }
+}
+
+impl TryFrom<&[u8]> for Jpeg {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Jpeg::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Jpeg {
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("JPEG", BufReader::new(reader));
+        if reader.read_array::<2>()? != [0xff, SOI] {
+            return Err(Error::malformed_at("JPEG", 0, "no start of image marker"));
+        }
+        let mut segments: Vec<Segment> = Vec::new();
+        // scans are read up to the marker after them, so it's already known
+        let mut pending: Option<u8> = None;
+        loop {
+            let marker = match pending.take() {
+                Some(marker) => marker,
+                // files cut off between segments still hold everything before the cut
+                None if reader.is_empty()? => break,
+                None => {
+                    if reader.read_u8()? != 0xff {
+                        return Err(reader.error("expected a marker"));
+                    }
+                    let mut marker = reader.read_u8()?;
+                    while marker == 0xff {
+                        marker = reader.read_u8()?;
+                    }
+                    marker
+                }
+            };
+            match marker {
+                EOI => break,
+                marker if marker::is_standalone(marker) => {}
+                marker => {
+                    let (segment, next) = Segment::read(&mut reader, marker)?;
+                    segments.push(segment);
+                    pending = next;
+                }
+            }
+        }
+        Ok(Jpeg { segments })
+    }
+
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&[0xff, SOI])?;
+        for segment in &self.segments {
+            segment.write(writer)?;
+        }
+        writer.write_all(&[0xff, EOI])?;
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Jpeg::from_image(&png.to_image()?)
+    }
+
+    fn to_png(&self) -> Result<Png> {
+        Png::from_image(&self.to_image()?)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Jpeg::metadata(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image::ColorModel,
+        metadata::{Resolution, ResolutionUnit},
+    };
+
+    #[test]
+    fn test_segments() {
+        let jfif = Jfif::from_resolution(Some(Resolution {
+            x: 72.0,
+            y: 72.0,
+            unit: ResolutionUnit::Inch,
+        }));
+        let mut sos = Segment::new(SOS, vec![1, 1, 0, 0, 63, 0]);
+        sos.scan_data = vec![0x12, 0xff, 0x00, 0x34, 0xff, 0xd3, 0x56];
+        let jpeg = Jpeg::new(vec![
+            Segment::new(APP0, jfif.as_bytes()),
+            Segment::new(COM, b"hello".to_vec()),
+            sos,
+        ]);
+        let bytes = jpeg.to_bytes();
+        // a fill byte before the end marker, and data after it, are both ignored
+        let mut padded = bytes.clone();
+        padded.insert(padded.len() - 2, 0xff);
+        padded.extend(b"trailing");
+        let read = Jpeg::try_from(padded.as_slice()).unwrap();
+        assert_eq!(read.segments(), jpeg.segments());
+        assert_eq!(read.to_bytes(), bytes);
+        assert_eq!(read.comments(), ["hello"]);
+        assert_eq!(read.jfif().unwrap(), Some(jfif));
+        assert_eq!(
+            read.metadata().unwrap().resolution().unwrap().to_string(),
+            "72x72 pixels per inch"
+        );
+        assert!(matches!(read.frame(), Err(Error::Malformed { .. })));
+
+        // a file cut off in the middle of a scan keeps what came before
+        let cut = Jpeg::try_from(&bytes[..bytes.len() - 4]).unwrap();
+        assert_eq!(cut.segments()[2].scan_data, [0x12, 0xff, 0x00, 0x34]);
+        assert!(Jpeg::try_from(&bytes[2..]).is_err());
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let mut image = Image::new(8, 8, ColorModel::Rgb, 8, vec![90; 192]).unwrap();
+        let exif_bytes = crate::exif::tests::testing_exif_bytes();
+        // big enough to be split across two segments
+        let profile: Vec<u8> = (0..70000).map(|idx| idx as u8).collect();
+        let metadata = image.metadata_mut();
+        metadata.set_exif(Some(Exif::try_from(exif_bytes.as_ref()).unwrap()));
+        metadata.set_icc_profile(Some(profile.clone()));
+        metadata.set_resolution(Some(Resolution {
+            x: 300.0,
+            y: 300.0,
+            unit: ResolutionUnit::Inch,
+        }));
+
+        let jpeg = Jpeg::from_image(&image).unwrap();
+        let read = Jpeg::try_from(jpeg.to_bytes().as_slice()).unwrap();
+        let icc_segments: Vec<&Segment> = read
+            .segments()
+            .iter()
+            .filter(|segment| segment.marker == APP2)
+            .collect();
+        assert_eq!(icc_segments.len(), 2);
+        let metadata = read.metadata().unwrap();
+        assert_eq!(metadata.exif().unwrap().as_bytes(), &exif_bytes[..]);
+        assert_eq!(metadata.icc_profile(), Some(profile.as_slice()));
+        assert_eq!(metadata.resolution().unwrap().x, 300.0);
+        assert_eq!(
+            read.to_image()
+                .unwrap()
+                .metadata()
+                .icc_profile()
+                .map(<[u8]>::len),
+            Some(70000)
+        );
+
+        // the chunks of a profile can come in any order
+        let mut segments = read.segments().to_vec();
+        segments.swap(2, 3);
+        let swapped = Jpeg::new(segments).metadata().unwrap();
+        assert_eq!(swapped.icc_profile(), Some(profile.as_slice()));
+
+        let stripped = Jpeg::from_image_with(
+            &image,
+            &JpegOptions {
+                exif: false,
+                icc_profile: false,
+                ..JpegOptions::default()
+            },
+        )
+        .unwrap();
+        let metadata = stripped.metadata().unwrap();
+        assert!(metadata.exif().is_none() && metadata.icc_profile().is_none());
+        assert!(metadata.resolution().is_some());
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::OnceLock;
+
+/// Natural (row by row) position of each coefficient in zigzag order
+pub const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// `COSINES[x][u]` is the weight of frequency `u` at position `x`, scale factor included
+fn cosines() -> &'static [[f32; 8]; 8] {
+    static COSINES: OnceLock<[[f32; 8]; 8]> = OnceLock::new();
+    COSINES.get_or_init(|| {
+        let mut table = [[0.0; 8]; 8];
+        for (x, row) in table.iter_mut().enumerate() {
+            for (u, weight) in row.iter_mut().enumerate() {
+                let scale = if u == 0 {
+                    std::f32::consts::FRAC_1_SQRT_2
+                } else {
+                    1.0
+                };
+                *weight = scale
+                    * 0.5
+                    * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos();
+            }
+        }
+        table
+    })
+}
+
+/// Turns dequantized coefficients, in natural order, into 8x8 samples, level shifted back
+/// to 0..=255
+pub fn inverse(coefficients: &[f32; 64], out: &mut [u8; 64]) {
+    let cosines = cosines();
+    // rows first, then columns, since the transform is separable
+    let mut rows = [0.0_f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8)
+                .map(|u| cosines[x][u] * coefficients[v * 8 + u])
+                .sum();
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let sample: f32 = (0..8).map(|v| cosines[y][v] * rows[v * 8 + x]).sum();
+            out[y * 8 + x] = (sample + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Turns 8x8 samples into coefficients in natural order, level shifting them to be centered
+/// on 0 first
+pub fn forward(samples: &[u8; 64], out: &mut [f32; 64]) {
+    let cosines = cosines();
+    let mut rows = [0.0_f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8)
+                .map(|x| cosines[x][u] * (samples[y * 8 + x] as f32 - 128.0))
+                .sum();
+        }
+    }
+    for v in 0..8 {
+        for u in 0..8 {
+            out[v * 8 + u] = (0..8).map(|y| cosines[y][v] * rows[y * 8 + u]).sum();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transforms() {
+        // a lone DC coefficient is a flat block at an eighth of its value
+        let mut coefficients = [0.0; 64];
+        coefficients[0] = 80.0;
+        let mut out = [0; 64];
+        inverse(&coefficients, &mut out);
+        assert!(out.iter().all(|&sample| sample == 138));
+
+        // the first horizontal frequency falls from left to right, the same on every row
+        coefficients[1] = 100.0;
+        inverse(&coefficients, &mut out);
+        assert!(out[0] > out[3] && out[3] > out[4] && out[4] > out[7]);
+        assert_eq!(out[..8], out[56..]);
+
+        // and transforming forward gets the coefficients back, give or take the rounding of
+        // each sample, which moves a coefficient by 4 at most
+        let mut forward_coefficients = [0.0; 64];
+        forward(&out, &mut forward_coefficients);
+        for (found, expected) in forward_coefficients.iter().zip(coefficients) {
+            assert!((found - expected).abs() < 4.0);
+        }
+        let mut zigzag = ZIGZAG;
+        zigzag.sort_unstable();
+        assert_eq!(zigzag, std::array::from_fn(|idx| idx));
+    }
+}
@@ -0,0 +1,452 @@
+use crate::{
+    byte_reader::ByteReader,
+    metadata::{Resolution, ResolutionUnit},
+    Error, Result,
+};
+
+use super::{
+    dct::ZIGZAG,
+    marker::{MAX_SEGMENT_LEN, SOF0, SOF1, SOF2},
+};
+
+/// One color component of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Component {
+    pub id: u8,
+    /// Horizontal and vertical sampling factors, 1 to 4
+    pub horizontal: u8,
+    pub vertical: u8,
+    /// Quantization table the component's blocks use
+    pub table: u8,
+}
+
+/// The start of frame segment, which gives the image's size, components and coding process
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The SOF marker, which names the coding process
+    pub marker: u8,
+    /// Bits per sample
+    pub precision: u8,
+    pub height: u16,
+    pub width: u16,
+    pub components: Vec<Component>,
+}
+
+impl FrameHeader {
+    pub fn read(marker: u8, data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new("JPEG", data);
+        let precision = reader.read_u8()?;
+        let height = reader.read_u16_be()?;
+        let width = reader.read_u16_be()?;
+        let count = reader.read_u8()?;
+        let components = (0..count)
+            .map(|_| {
+                let [id, sampling, table] = reader.read_array()?;
+                Ok(Component {
+                    id,
+                    horizontal: sampling >> 4,
+                    vertical: sampling & 0x0f,
+                    table,
+                })
+            })
+            .collect::<Result<Vec<Component>>>()?;
+        if components.iter().any(|component| {
+            !(1..=4).contains(&component.horizontal)
+                || !(1..=4).contains(&component.vertical)
+                || component.table > 3
+        }) {
+            return Err(Error::malformed(
+                "JPEG",
+                "a component has a sampling factor or quantization table out of range",
+            ));
+        }
+        Ok(FrameHeader {
+            marker,
+            precision,
+            height,
+            width,
+            components,
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![self.precision];
+        bytes.extend(self.height.to_be_bytes());
+        bytes.extend(self.width.to_be_bytes());
+        bytes.push(self.components.len() as u8);
+        for component in &self.components {
+            bytes.extend([
+                component.id,
+                component.horizontal << 4 | component.vertical,
+                component.table,
+            ]);
+        }
+        bytes
+    }
+
+    pub fn is_progressive(&self) -> bool {
+        self.marker == SOF2
+    }
+
+    /// Name of the coding process the SOF marker stands for
+    pub fn process(&self) -> &'static str {
+        match self.marker {
+            SOF0 => "baseline",
+            SOF1 => "extended sequential",
+            SOF2 => "progressive",
+            0xc3 => "lossless",
+            0xc5..=0xc7 => "hierarchical",
+            _ => "arithmetic-coded",
+        }
+    }
+
+    /// Largest horizontal and vertical sampling factors, which set the size of an MCU
+    pub fn max_sampling(&self) -> (u8, u8) {
+        let horizontal = self.components.iter().map(|c| c.horizontal).max();
+        let vertical = self.components.iter().map(|c| c.vertical).max();
+        (horizontal.unwrap_or(1), vertical.unwrap_or(1))
+    }
+
+    /// Chroma subsampling in J:a:b notation, for three-component images whose second and
+    /// third components match
+    pub fn subsampling(&self) -> Option<&'static str> {
+        let [luma, chroma, other] = self.components.as_slice() else {
+            return None;
+        };
+        if (chroma.horizontal, chroma.vertical) != (other.horizontal, other.vertical) {
+            return None;
+        }
+        match (
+            luma.horizontal / chroma.horizontal,
+            luma.vertical / chroma.vertical,
+            luma.horizontal % chroma.horizontal + luma.vertical % chroma.vertical,
+        ) {
+            (1, 1, 0) => Some("4:4:4"),
+            (2, 1, 0) => Some("4:2:2"),
+            (2, 2, 0) => Some("4:2:0"),
+            (1, 2, 0) => Some("4:4:0"),
+            (4, 1, 0) => Some("4:1:1"),
+            _ => None,
+        }
+    }
+
+    /// Fails for frames `image_data::decode` can't handle
+    pub fn check_supported(&self) -> Result<()> {
+        if !matches!(self.marker, SOF0 | SOF1 | SOF2) {
+            return Err(Error::unsupported(
+                "JPEG",
+                format!("{} JPEGs", self.process()),
+            ));
+        }
+        if self.precision != 8 {
+            return Err(Error::unsupported(
+                "JPEG",
+                format!("{}-bit samples", self.precision),
+            ));
+        }
+        if self.height == 0 {
+            return Err(Error::unsupported("JPEG", "heights given by a DNL marker"));
+        }
+        if self.width == 0 {
+            return Err(Error::malformed("JPEG", "the image is 0 pixels wide"));
+        }
+        if !matches!(self.components.len(), 1 | 3 | 4) {
+            return Err(Error::unsupported(
+                "JPEG",
+                format!("images with {} components", self.components.len()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// One component's entry in a scan header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanComponent {
+    /// Index of the component in the frame, not its ID
+    pub index: usize,
+    pub dc_table: u8,
+    pub ac_table: u8,
+}
+
+/// The start of scan segment, which says which components and coefficients a scan codes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanHeader {
+    pub components: Vec<ScanComponent>,
+    /// First and last coefficients in zigzag order; 0 and 63 for sequential scans
+    pub spectral_start: u8,
+    pub spectral_end: u8,
+    /// Bit position of the previous pass over these coefficients, 0 for the first, and of
+    /// this one
+    pub approximation_high: u8,
+    pub approximation_low: u8,
+}
+
+impl ScanHeader {
+    pub fn read(data: &[u8], frame: &FrameHeader) -> Result<Self> {
+        let mut reader = ByteReader::new("JPEG", data);
+        let count = reader.read_u8()?;
+        if !(1..=4).contains(&count) {
+            return Err(Error::malformed(
+                "JPEG",
+                format!("a scan can't hold {count} components"),
+            ));
+        }
+        let components = (0..count)
+            .map(|_| {
+                let [id, tables] = reader.read_array()?;
+                let index = frame
+                    .components
+                    .iter()
+                    .position(|component| component.id == id)
+                    .ok_or_else(|| {
+                        Error::malformed(
+                            "JPEG",
+                            format!("a scan names component {id}, which the frame doesn't have"),
+                        )
+                    })?;
+                Ok(ScanComponent {
+                    index,
+                    dc_table: tables >> 4,
+                    ac_table: tables & 0x0f,
+                })
+            })
+            .collect::<Result<Vec<ScanComponent>>>()?;
+        let [spectral_start, spectral_end, approximation] = reader.read_array()?;
+        let scan = ScanHeader {
+            components,
+            spectral_start,
+            spectral_end,
+            approximation_high: approximation >> 4,
+            approximation_low: approximation & 0x0f,
+        };
+        if scan.spectral_end > 63
+            || scan.spectral_start > scan.spectral_end
+            || (scan.spectral_start == 0) != (scan.spectral_end == 0) && frame.is_progressive()
+            || scan
+                .components
+                .iter()
+                .any(|c| c.dc_table > 3 || c.ac_table > 3)
+            || scan.approximation_high > 13
+            || scan.approximation_low > 13
+        {
+            return Err(Error::malformed(
+                "JPEG",
+                format!(
+                    "a scan codes coefficients {} to {}, which isn't allowed",
+                    scan.spectral_start, scan.spectral_end
+                ),
+            ));
+        }
+        Ok(scan)
+    }
+
+    pub fn as_bytes(&self, frame: &FrameHeader) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![self.components.len() as u8];
+        for component in &self.components {
+            bytes.extend([
+                frame.components[component.index].id,
+                component.dc_table << 4 | component.ac_table,
+            ]);
+        }
+        bytes.extend([
+            self.spectral_start,
+            self.spectral_end,
+            self.approximation_high << 4 | self.approximation_low,
+        ]);
+        bytes
+    }
+}
+
+/// Reads the quantization tables of a DQT segment, returning each table's slot and its
+/// values in natural (row by row) order
+pub fn read_quantization_tables(data: &[u8]) -> Result<Vec<(u8, [u16; 64])>> {
+    let mut reader = ByteReader::new("JPEG", data);
+    let mut tables: Vec<(u8, [u16; 64])> = Vec::new();
+    while !reader.is_empty() {
+        let info = reader.read_u8()?;
+        let (precision, slot) = (info >> 4, info & 0x0f);
+        if slot > 3 || precision > 1 {
+            return Err(Error::malformed(
+                "JPEG",
+                format!("quantization table {slot} has precision {precision}"),
+            ));
+        }
+        let mut table: [u16; 64] = [0; 64];
+        for &at in &ZIGZAG {
+            table[at] = match precision {
+                0 => reader.read_u8()? as u16,
+                _ => reader.read_u16_be()?,
+            };
+        }
+        tables.push((slot, table));
+    }
+    Ok(tables)
+}
+
+/// Encodes 8-bit quantization tables, given in natural order, as DQT segment data
+pub fn quantization_tables_bytes(tables: &[(u8, [u16; 64])]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for (slot, table) in tables {
+        bytes.push(*slot);
+        bytes.extend(ZIGZAG.iter().map(|&at| table[at] as u8));
+    }
+    bytes
+}
+
+/// Identifier of the JFIF APP0 segment
+pub const JFIF: &[u8] = b"JFIF\0";
+/// Identifier of the EXIF APP1 segment
+pub const EXIF: &[u8] = b"Exif\0\0";
+/// Identifier of the ICC profile APP2 segments
+pub const ICC_PROFILE: &[u8] = b"ICC_PROFILE\0";
+/// Identifier of the Adobe APP14 segment
+pub const ADOBE: &[u8] = b"Adobe";
+
+/// Profile bytes each APP2 segment can hold, after its identifier and chunk numbers
+const ICC_CHUNK_LEN: usize = MAX_SEGMENT_LEN - ICC_PROFILE.len() - 2;
+
+/// Joins an ICC profile split across APP2 segments, given their payloads in any order. Each
+/// starts with its chunk number, counting from 1, and the number of chunks.
+pub fn join_icc_profile(payloads: &[&[u8]]) -> Result<Vec<u8>> {
+    let mut chunks: Vec<(u8, &[u8])> = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        match payload {
+            [number, count, chunk @ ..] if *count as usize == payloads.len() => {
+                chunks.push((*number, chunk))
+            }
+            _ => {
+                return Err(Error::malformed(
+                    "JPEG",
+                    "the ICC profile's segments are misnumbered",
+                ))
+            }
+        }
+    }
+    chunks.sort_by_key(|&(number, _)| number);
+    if chunks
+        .iter()
+        .enumerate()
+        .any(|(idx, &(number, _))| number as usize != idx + 1)
+    {
+        return Err(Error::malformed(
+            "JPEG",
+            "the ICC profile's segments are misnumbered",
+        ));
+    }
+    Ok(chunks
+        .into_iter()
+        .flat_map(|(_, chunk)| chunk)
+        .copied()
+        .collect())
+}
+
+/// Splits `profile` into APP2 segment data, identifier included
+pub fn split_icc_profile(profile: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let count = profile.len().div_ceil(ICC_CHUNK_LEN);
+    if count > u8::MAX as usize {
+        return Err(Error::unsupported(
+            "JPEG",
+            format!("ICC profiles of {} bytes", profile.len()),
+        ));
+    }
+    Ok(profile
+        .chunks(ICC_CHUNK_LEN)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let mut data = ICC_PROFILE.to_vec();
+            data.extend([idx as u8 + 1, count as u8]);
+            data.extend(chunk);
+            data
+        })
+        .collect())
+}
+
+/// The fields of a JFIF APP0 segment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Jfif {
+    pub version: (u8, u8),
+    /// 0 for an aspect ratio only, 1 for dots per inch and 2 for dots per centimeter
+    pub units: u8,
+    pub x_density: u16,
+    pub y_density: u16,
+}
+
+impl Jfif {
+    pub fn read(payload: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new("JPEG", payload);
+        let [major, minor, units] = reader.read_array()?;
+        Ok(Jfif {
+            version: (major, minor),
+            units,
+            x_density: reader.read_u16_be()?,
+            y_density: reader.read_u16_be()?,
+        })
+    }
+
+    /// The segment data, identifier included, without a thumbnail
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = JFIF.to_vec();
+        bytes.extend([self.version.0, self.version.1, self.units]);
+        bytes.extend(self.x_density.to_be_bytes());
+        bytes.extend(self.y_density.to_be_bytes());
+        bytes.extend([0, 0]);
+        bytes
+    }
+
+    /// The density recorded, if any; square pixels with no unit, which is what writers put
+    /// when they don't know, count as none
+    pub fn to_resolution(&self) -> Option<Resolution> {
+        let square = self.units == 0 && self.x_density == self.y_density;
+        (self.x_density > 0 && self.y_density > 0 && !square).then_some(Resolution {
+            x: self.x_density as f64,
+            y: self.y_density as f64,
+            unit: match self.units {
+                1 => ResolutionUnit::Inch,
+                2 => ResolutionUnit::Centimeter,
+                _ => ResolutionUnit::Unknown,
+            },
+        })
+    }
+
+    /// A JFIF 1.02 segment recording `resolution`, in dots per inch unless it was given per
+    /// centimeter or meter
+    pub fn from_resolution(resolution: Option<Resolution>) -> Self {
+        let (units, resolution) = match resolution {
+            None => (0, None),
+            Some(resolution) => match resolution.unit {
+                ResolutionUnit::Unknown => (0, Some(resolution)),
+                ResolutionUnit::Inch => (1, Some(resolution)),
+                ResolutionUnit::Centimeter | ResolutionUnit::Meter => {
+                    (2, resolution.in_unit(ResolutionUnit::Centimeter))
+                }
+            },
+        };
+        let density = |value: f64| value.round().clamp(1.0, u16::MAX as f64) as u16;
+        Jfif {
+            version: (1, 2),
+            units,
+            x_density: resolution.map_or(1, |resolution| density(resolution.x)),
+            y_density: resolution.map_or(1, |resolution| density(resolution.y)),
+        }
+    }
+}
+
+/// The color transform an Adobe APP14 segment records: 0 for none (RGB or CMYK), 1 for
+/// YCbCr and 2 for YCCK
+pub fn read_adobe_transform(payload: &[u8]) -> Result<u8> {
+    let mut reader = ByteReader::new("JPEG", payload);
+    // version, then two sets of flags
+    reader.skip(6)?;
+    reader.read_u8()
+}
+
+/// Reads a DRI segment, which gives the MCUs between restart markers
+pub fn read_restart_interval(data: &[u8]) -> Result<u16> {
+    ByteReader::new("JPEG", data).read_u16_be()
+}
+
+/// Whether a restart marker is due before MCU `mcu`
+pub fn restart_due(restart_interval: u16, mcu: usize) -> bool {
+    restart_interval > 0 && mcu > 0 && mcu.is_multiple_of(restart_interval as usize)
+}
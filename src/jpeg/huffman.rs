@@ -0,0 +1,524 @@
+use crate::{byte_reader::ByteReader, Error, Result};
+
+/// Bits looked up at once when decoding; longer codes are found by length
+const LOOKUP_BITS: u32 = 9;
+
+/// Whether a Huffman table codes DC differences or AC run and size pairs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableClass {
+    Dc,
+    Ac,
+}
+
+/// Code counts and values of the example tables in Annex K of the standard, which most
+/// encoders use as they are: DC and AC for luma, then DC and AC for chroma
+const STANDARD_COUNTS: [[u8; 16]; 4] = [
+    [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+];
+const STANDARD_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const STANDARD_LUMA_AC_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const STANDARD_CHROMA_AC_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// A Huffman table as a DHT segment defines it: how many codes there are of each length,
+/// and the values they stand for, shortest codes first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HuffmanTable {
+    pub counts: [u8; 16],
+    pub values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    /// Reads the tables of a DHT segment, along with each one's class and slot
+    pub fn read_all(data: &[u8]) -> Result<Vec<(TableClass, u8, HuffmanTable)>> {
+        let mut reader = ByteReader::new("JPEG", data);
+        let mut tables: Vec<(TableClass, u8, HuffmanTable)> = Vec::new();
+        while !reader.is_empty() {
+            let info = reader.read_u8()?;
+            let class = match info >> 4 {
+                0 => TableClass::Dc,
+                1 => TableClass::Ac,
+                _ => return Err(reader.error(format!("Huffman table class {}", info >> 4))),
+            };
+            let slot = info & 0x0f;
+            if slot > 3 {
+                return Err(reader.error(format!("Huffman table slot {slot}")));
+            }
+            let counts: [u8; 16] = reader.read_array()?;
+            let total: usize = counts.iter().map(|&count| count as usize).sum();
+            let values = reader.read_bytes(total)?.to_vec();
+            tables.push((class, slot, HuffmanTable { counts, values }));
+        }
+        Ok(tables)
+    }
+
+    /// The example table from the standard for `class`, for luma or for chroma
+    pub fn standard(class: TableClass, chroma: bool) -> Self {
+        let idx = chroma as usize * 2 + (class == TableClass::Ac) as usize;
+        let values: &[u8] = match (class, chroma) {
+            (TableClass::Dc, _) => &STANDARD_DC_VALUES,
+            (TableClass::Ac, false) => &STANDARD_LUMA_AC_VALUES,
+            (TableClass::Ac, true) => &STANDARD_CHROMA_AC_VALUES,
+        };
+        HuffmanTable {
+            counts: STANDARD_COUNTS[idx],
+            values: values.to_vec(),
+        }
+    }
+
+    /// Builds the table that codes values occurring `frequencies` times in the fewest bits,
+    /// with codes no longer than 16 bits, following Annex K.2 of the standard
+    pub fn optimal(frequencies: &[u32; 256]) -> Self {
+        // one extra value, which takes the code of all ones that isn't allowed
+        let mut frequencies: Vec<u64> = frequencies.iter().map(|&freq| freq as u64).collect();
+        frequencies.push(1);
+        let mut code_sizes = [0_usize; 257];
+        let mut others = [None::<usize>; 257];
+        loop {
+            // the two least frequent trees, preferring later values among equals
+            let mut least: Option<usize> = None;
+            let mut second: Option<usize> = None;
+            for (value, &freq) in frequencies.iter().enumerate() {
+                if freq == 0 {
+                    continue;
+                }
+                if least.is_none_or(|least| freq <= frequencies[least]) {
+                    second = least;
+                    least = Some(value);
+                } else if second.is_none_or(|second| freq <= frequencies[second]) {
+                    second = Some(value);
+                }
+            }
+            let (Some(mut first), Some(mut other)) = (least, second) else {
+                break;
+            };
+            frequencies[first] += frequencies[other];
+            frequencies[other] = 0;
+            // every value in both trees gets a bit longer
+            code_sizes[first] += 1;
+            while let Some(next) = others[first] {
+                first = next;
+                code_sizes[first] += 1;
+            }
+            others[first] = Some(other);
+            code_sizes[other] += 1;
+            while let Some(next) = others[other] {
+                other = next;
+                code_sizes[other] += 1;
+            }
+        }
+
+        let mut counts = [0_u32; 33];
+        for &size in code_sizes.iter().filter(|&&size| size > 0) {
+            counts[size] += 1;
+        }
+        // codes over 16 bits are shortened in pairs, each pair's prefix taking the place of
+        // a shorter code whose length grows by one
+        for len in (17..=32).rev() {
+            while counts[len] > 0 {
+                let mut shorter = len - 2;
+                while counts[shorter] == 0 {
+                    shorter -= 1;
+                }
+                counts[len] -= 2;
+                counts[len - 1] += 1;
+                counts[shorter + 1] += 2;
+                counts[shorter] -= 1;
+            }
+        }
+        // the extra value has the longest code, which is dropped
+        if let Some(longest) = (1..=16).rev().find(|&len| counts[len] > 0) {
+            counts[longest] -= 1;
+        }
+
+        let mut values: Vec<u8> = Vec::new();
+        for len in 1..=32 {
+            values.extend((0..=255).filter(|&value| code_sizes[value as usize] == len));
+        }
+        HuffmanTable {
+            counts: std::array::from_fn(|idx| counts[idx + 1] as u8),
+            values,
+        }
+    }
+
+    /// The table as DHT segment data, after its class and slot byte
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.counts.to_vec();
+        bytes.extend(&self.values);
+        bytes
+    }
+
+    /// The code and its length for each value, in the order the values are listed. Fails if
+    /// the counts call for more codes of some length than there are bit patterns.
+    pub fn codes(&self) -> Result<Vec<(u16, u8)>> {
+        let mut codes: Vec<(u16, u8)> = Vec::with_capacity(self.values.len());
+        let mut code: u32 = 0;
+        for (len, &count) in (1..=16).zip(&self.counts) {
+            for _ in 0..count {
+                if code >= 1 << len {
+                    return Err(Error::malformed(
+                        "JPEG",
+                        "a Huffman table has more codes than fit their lengths",
+                    ));
+                }
+                codes.push((code as u16, len));
+                code += 1;
+            }
+            code <<= 1;
+        }
+        Ok(codes)
+    }
+}
+
+/// A Huffman table arranged for decoding
+pub struct Decoder {
+    /// Value and code length for every `LOOKUP_BITS`-bit pattern starting with a short code;
+    /// a length of 0 means the code is longer
+    lookup: Vec<(u8, u8)>,
+    /// For each length, the largest code of that length, or -1 if there are none, and the
+    /// index in `values` of the first code of that length minus that code
+    max_code: [i32; 17],
+    offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(table: &HuffmanTable) -> Result<Self> {
+        let codes = table.codes()?;
+        let mut lookup: Vec<(u8, u8)> = vec![(0, 0); 1 << LOOKUP_BITS];
+        let mut max_code = [-1; 17];
+        let mut offset = [0; 17];
+        for (idx, (&(code, len), &value)) in codes.iter().zip(&table.values).enumerate() {
+            let len32 = len as u32;
+            if max_code[len as usize] < 0 {
+                offset[len as usize] = idx as i32 - code as i32;
+            }
+            max_code[len as usize] = code as i32;
+            if len32 <= LOOKUP_BITS {
+                let first = (code as usize) << (LOOKUP_BITS - len32);
+                lookup[first..first + (1 << (LOOKUP_BITS - len32))].fill((value, len));
+            }
+        }
+        Ok(Decoder {
+            lookup,
+            max_code,
+            offset,
+            values: table.values.clone(),
+        })
+    }
+}
+
+/// Reads bits from entropy-coded data, most significant first, skipping the zero bytes
+/// stuffed after every 0xff. At a marker, or the end of the data, it reads zeros.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            position: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.count <= 56 {
+            let byte = match self.data.get(self.position) {
+                Some(&0xff) => match self.data.get(self.position + 1) {
+                    Some(0) => {
+                        self.position += 2;
+                        0xff
+                    }
+                    // a marker: stay in front of it
+                    _ => 0,
+                },
+                Some(&byte) => {
+                    self.position += 1;
+                    byte
+                }
+                None => 0,
+            };
+            self.bits |= (byte as u64) << (56 - self.count);
+            self.count += 8;
+        }
+    }
+
+    fn peek(&mut self, len: u32) -> u32 {
+        if self.count < len {
+            self.fill();
+        }
+        (self.bits >> (64 - len)) as u32
+    }
+
+    fn consume(&mut self, len: u32) {
+        self.bits <<= len;
+        self.count -= len;
+    }
+
+    pub fn read_bit(&mut self) -> bool {
+        let bit = self.peek(1) == 1;
+        self.consume(1);
+        bit
+    }
+
+    /// Reads `len` bits as an unsigned number
+    pub fn read_bits(&mut self, len: u8) -> u16 {
+        if len == 0 {
+            return 0;
+        }
+        let value = self.peek(len as u32);
+        self.consume(len as u32);
+        value as u16
+    }
+
+    /// Reads `size` bits holding a number of that magnitude category, whose sign is given by
+    /// its top bit
+    pub fn read_signed(&mut self, size: u8) -> i32 {
+        let value = self.read_bits(size) as i32;
+        match size {
+            0 => 0,
+            _ if value < 1 << (size - 1) => value - (1 << size) + 1,
+            _ => value,
+        }
+    }
+
+    pub fn decode(&mut self, decoder: &Decoder) -> Result<u8> {
+        let (value, len) = decoder.lookup[self.peek(LOOKUP_BITS) as usize];
+        if len > 0 {
+            self.consume(len as u32);
+            return Ok(value);
+        }
+        for len in LOOKUP_BITS + 1..=16 {
+            let code = self.peek(len) as i32;
+            if code <= decoder.max_code[len as usize] {
+                self.consume(len);
+                let idx = (decoder.offset[len as usize] + code) as usize;
+                return Ok(decoder.values[idx]);
+            }
+        }
+        Err(Error::malformed(
+            "JPEG",
+            "a scan holds an invalid Huffman code",
+        ))
+    }
+
+    /// Skips to the byte after the next restart marker, dropping the bits of the byte in
+    /// progress. Returns false if there isn't one.
+    pub fn restart(&mut self) -> bool {
+        self.bits = 0;
+        self.count = 0;
+        while self.position + 1 < self.data.len() {
+            let found = self.data[self.position] == 0xff
+                && (0xd0..=0xd7).contains(&self.data[self.position + 1]);
+            self.position += 1;
+            if found {
+                self.position += 1;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// A Huffman table arranged for encoding: the code and its length for each value, with a
+/// length of 0 for values the table has no code for
+pub struct Encoder {
+    codes: [(u16, u8); 256],
+}
+
+impl Encoder {
+    pub fn new(table: &HuffmanTable) -> Result<Self> {
+        let mut codes = [(0, 0); 256];
+        for (code, &value) in table.codes()?.into_iter().zip(&table.values) {
+            codes[value as usize] = code;
+        }
+        Ok(Encoder { codes })
+    }
+}
+
+/// The magnitude category of `value`, which is the number of bits `BitWriter::write_signed`
+/// takes for it
+pub fn magnitude(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Collects entropy-coded data, most significant bit first, stuffing a zero byte after
+/// every 0xff
+#[derive(Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter::default()
+    }
+
+    /// Writes the low `len` bits of `bits`, up to 16 of them
+    pub fn write_bits(&mut self, bits: u16, len: u8) {
+        self.bits = self.bits << len | (bits as u32 & ((1 << len) - 1));
+        self.count += len as u32;
+        while self.count >= 8 {
+            self.count -= 8;
+            let byte = (self.bits >> self.count) as u8;
+            self.data.push(byte);
+            if byte == 0xff {
+                self.data.push(0);
+            }
+        }
+        self.bits &= (1 << self.count) - 1;
+    }
+
+    /// Writes `value` in `magnitude(value)` bits, negative values as their one's complement
+    pub fn write_signed(&mut self, value: i32) {
+        let bits = match value < 0 {
+            true => value - 1,
+            false => value,
+        };
+        self.write_bits(bits as u16, magnitude(value));
+    }
+
+    pub fn encode(&mut self, encoder: &Encoder, value: u8) -> Result<()> {
+        match encoder.codes[value as usize] {
+            (_, 0) => Err(Error::malformed(
+                "JPEG",
+                format!("a Huffman table has no code for {value:#04x}"),
+            )),
+            (code, len) => {
+                self.write_bits(code, len);
+                Ok(())
+            }
+        }
+    }
+
+    /// Pads the last byte with ones and returns the data
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.write_bits(0xff, 8 - self.count as u8);
+        }
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // two 2-bit codes, 00 and 01, then a 3-bit code, 100, and a 12-bit code
+        let mut counts = [0; 16];
+        counts[1] = 2;
+        counts[2] = 1;
+        counts[11] = 1;
+        let table = HuffmanTable {
+            counts,
+            values: vec![5, 6, 7, 8],
+        };
+        assert_eq!(
+            table.codes().unwrap(),
+            [(0, 2), (1, 2), (4, 3), (0b1010_0000_0000, 12)]
+        );
+        let decoder = Decoder::new(&table).unwrap();
+        // 01 100 00 then 1010 0000 0000, with the 0xff stuffing byte in between skipped
+        let data = [0b0110_0001, 0b0100_0000, 0b0000_0111, 0xff, 0x00];
+        let mut reader = BitReader::new(&data);
+        let decoded: Vec<u8> = (0..4).map(|_| reader.decode(&decoder).unwrap()).collect();
+        assert_eq!(decoded, [6, 7, 5, 8]);
+        assert_eq!(reader.read_signed(3), -6);
+        assert_eq!(reader.read_bits(8), 0xff);
+
+        let mut reader = BitReader::new(&[0xaa, 0xff, 0xd3, 0x80]);
+        assert_eq!(reader.read_signed(2), 2);
+        assert!(reader.restart());
+        assert!(reader.read_bit());
+        assert!(!reader.restart());
+    }
+
+    #[test]
+    fn test_encode() {
+        // the standard tables hold every symbol a baseline scan can need
+        for (class, symbols) in [(TableClass::Dc, 12), (TableClass::Ac, 162)] {
+            for chroma in [false, true] {
+                let table = HuffmanTable::standard(class, chroma);
+                assert_eq!(table.values.len(), symbols);
+                assert!(table.codes().is_ok());
+            }
+        }
+
+        let mut frequencies = [0; 256];
+        frequencies[0] = 1000;
+        frequencies[1] = 10;
+        frequencies[2..20].fill(1);
+        let table = HuffmanTable::optimal(&frequencies);
+        assert_eq!(table.values.len(), 20);
+        assert_eq!(table.values[..2], [0, 1]);
+        assert_eq!(table.counts[0], 1);
+        // the code of all ones is never handed out
+        let codes = table.codes().unwrap();
+        assert!(codes.iter().all(|&(code, len)| code != (1 << len) - 1));
+
+        // lopsided frequencies would make codes over 16 bits without the length limit
+        let fibonacci: Vec<u32> = (0..30)
+            .scan((1, 1), |state, _| {
+                *state = (state.1, state.0 + state.1);
+                Some(state.0)
+            })
+            .collect();
+        frequencies = [0; 256];
+        frequencies[..30].copy_from_slice(&fibonacci);
+        let table = HuffmanTable::optimal(&frequencies);
+        assert_eq!(table.values.len(), 30);
+        assert!(table.codes().is_ok());
+
+        let encoder = Encoder::new(&table).unwrap();
+        let decoder = Decoder::new(&table).unwrap();
+        let mut writer = BitWriter::new();
+        for value in [29, 0, 5] {
+            writer.encode(&encoder, value).unwrap();
+            writer.write_signed(value as i32 - 3);
+        }
+        assert!(writer.encode(&encoder, 30).is_err());
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        for value in [29, 0, 5] {
+            assert_eq!(reader.decode(&decoder).unwrap(), value);
+            let expected = value as i32 - 3;
+            assert_eq!(reader.read_signed(magnitude(expected)), expected);
+        }
+    }
+}
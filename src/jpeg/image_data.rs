@@ -0,0 +1,1158 @@
+use std::{borrow::Cow, io::Read};
+
+use crate::{
+    image::{ColorModel, Image},
+    scanline::RowLayout,
+    ConvertibleImage, Error, Result,
+};
+
+use super::{
+    dct::{self, ZIGZAG},
+    header::{self, Component, FrameHeader, Jfif, ScanComponent, ScanHeader, ADOBE, EXIF, JFIF},
+    huffman::{self, BitReader, BitWriter, Decoder, Encoder, HuffmanTable, TableClass},
+    marker::{Segment, APP0, APP1, APP14, APP2, DHT, DQT, DRI, MAX_SEGMENT_LEN, SOF0, SOS},
+    options::JpegOptions,
+    Jpeg,
+};
+
+/// Reports the frame's size, without decoding any scans
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let frame = Jpeg::from_reader(reader)?.frame()?;
+    Ok(RowLayout {
+        width: frame.width as u32,
+        height: frame.height as u32,
+        color_model: match frame.components.len() {
+            1 => ColorModel::Gray,
+            _ => ColorModel::Rgb,
+        },
+        bit_depth: 8,
+    })
+}
+
+/// How a frame's components turn into RGB
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorTransform {
+    Gray,
+    YCbCr,
+    Rgb,
+    /// Ink amounts, stored inverted when an Adobe segment is present
+    Cmyk {
+        inverted: bool,
+    },
+    /// CMY stored as YCbCr, with black alongside
+    Ycck,
+}
+
+impl ColorTransform {
+    /// Works out the transform the way libjpeg does: from the Adobe segment if there is one,
+    /// and otherwise from the number of components and, lacking JFIF, their IDs
+    pub fn of(frame: &FrameHeader, jfif: bool, adobe: Option<u8>) -> Self {
+        let ids: Vec<u8> = frame
+            .components
+            .iter()
+            .map(|component| component.id)
+            .collect();
+        match (frame.components.len(), adobe) {
+            (1, _) => ColorTransform::Gray,
+            (3, Some(0)) => ColorTransform::Rgb,
+            (3, None) if !jfif && ids == b"RGB" => ColorTransform::Rgb,
+            (3, _) => ColorTransform::YCbCr,
+            (_, Some(2)) => ColorTransform::Ycck,
+            (_, adobe) => ColorTransform::Cmyk {
+                inverted: adobe.is_some(),
+            },
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorTransform::Gray => "gray",
+            ColorTransform::YCbCr => "ycbcr",
+            ColorTransform::Rgb => "rgb",
+            ColorTransform::Cmyk { .. } => "cmyk",
+            ColorTransform::Ycck => "ycck",
+        }
+    }
+}
+
+/// Converts a YCbCr sample to RGB, as JFIF defines it
+pub fn ycbcr_to_rgb(y: f32, cb: f32, cr: f32) -> [f32; 3] {
+    let (cb, cr) = (cb - 128.0, cr - 128.0);
+    [
+        y + 1.402 * cr,
+        y - 0.344_136 * cb - 0.714_136 * cr,
+        y + 1.772 * cb,
+    ]
+}
+
+/// One component's coefficients, and later its samples
+struct Plane {
+    /// Size in samples, before padding out to whole blocks
+    width: usize,
+    height: usize,
+    /// Blocks in each row, padded out to whole MCUs
+    blocks_across: usize,
+    /// 64 coefficients for each block, in natural order
+    coefficients: Vec<i16>,
+    /// The table in force when the component's first scan started
+    quantization: Option<[u16; 64]>,
+}
+
+impl Plane {
+    fn block(&mut self, x: usize, y: usize) -> &mut [i16] {
+        let at = (y * self.blocks_across + x) * 64;
+        &mut self.coefficients[at..at + 64]
+    }
+
+    /// Dequantizes and transforms every block, returning the samples with rows
+    /// `blocks_across * 8` long
+    fn samples(&self) -> Vec<u8> {
+        let stride = self.blocks_across * 8;
+        let mut samples: Vec<u8> = vec![0; stride * self.height.div_ceil(8) * 8];
+        let table = self.quantization.unwrap_or([1; 64]);
+        let mut coefficients = [0.0_f32; 64];
+        let mut block = [0_u8; 64];
+        for y in 0..self.height.div_ceil(8) {
+            for x in 0..self.width.div_ceil(8) {
+                let at = (y * self.blocks_across + x) * 64;
+                for (idx, coefficient) in coefficients.iter_mut().enumerate() {
+                    *coefficient = self.coefficients[at + idx] as f32 * table[idx] as f32;
+                }
+                dct::inverse(&coefficients, &mut block);
+                for row in 0..8 {
+                    let start = (y * 8 + row) * stride + x * 8;
+                    samples[start..start + 8].copy_from_slice(&block[row * 8..row * 8 + 8]);
+                }
+            }
+        }
+        samples
+    }
+}
+
+/// Which pass over the coefficients a scan makes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pass {
+    /// Every coefficient at once
+    Sequential,
+    DcFirst,
+    DcRefine,
+    AcFirst,
+    AcRefine,
+}
+
+/// Tables and settings that segments define for the scans after them
+#[derive(Default)]
+struct Tables {
+    quantization: [Option<[u16; 64]>; 4],
+    dc: [Option<Decoder>; 4],
+    ac: [Option<Decoder>; 4],
+    restart_interval: u16,
+}
+
+/// Decodes the frame and every scan into an `Image`, 8-bit gray or RGB
+pub fn decode(jpeg: &Jpeg) -> Result<Image> {
+    let frame = jpeg.frame()?;
+    frame.check_supported()?;
+    let (max_horizontal, max_vertical) = frame.max_sampling();
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let mcus_across = width.div_ceil(8 * max_horizontal as usize);
+    let mcus_down = height.div_ceil(8 * max_vertical as usize);
+
+    // checked before allocating, so a forged size can't exhaust memory: every block takes at
+    // least one bit of some scan
+    let scan_bytes: usize = jpeg.segments().iter().map(|s| s.scan_data.len()).sum();
+    let blocks: usize = frame
+        .components
+        .iter()
+        .map(|c| mcus_across * c.horizontal as usize * mcus_down * c.vertical as usize)
+        .sum();
+    if blocks > scan_bytes.saturating_mul(8).saturating_add(1 << 16) {
+        return Err(Error::malformed(
+            "JPEG",
+            format!("{scan_bytes} bytes of scan data can't hold a {width}x{height} image"),
+        ));
+    }
+    let mut planes: Vec<Plane> = frame
+        .components
+        .iter()
+        .map(|component| {
+            let (horizontal, vertical) =
+                (component.horizontal as usize, component.vertical as usize);
+            let blocks_across = mcus_across * horizontal;
+            let blocks_down = mcus_down * vertical;
+            Plane {
+                width: (width * horizontal).div_ceil(max_horizontal as usize),
+                height: (height * vertical).div_ceil(max_vertical as usize),
+                blocks_across,
+                coefficients: vec![0; blocks_across * blocks_down * 64],
+                quantization: None,
+            }
+        })
+        .collect();
+
+    let mut tables = Tables::default();
+    let mut jfif = false;
+    let mut adobe: Option<u8> = None;
+    let mut scans = 0;
+    for segment in jpeg.segments() {
+        match segment.marker {
+            DQT => {
+                for (slot, table) in header::read_quantization_tables(&segment.data)? {
+                    tables.quantization[slot as usize] = Some(table);
+                }
+            }
+            DHT => {
+                for (class, slot, table) in HuffmanTable::read_all(&segment.data)? {
+                    let decoder = Some(Decoder::new(&table)?);
+                    match class {
+                        TableClass::Dc => tables.dc[slot as usize] = decoder,
+                        TableClass::Ac => tables.ac[slot as usize] = decoder,
+                    }
+                }
+            }
+            DRI => tables.restart_interval = header::read_restart_interval(&segment.data)?,
+            APP0 if segment.payload(APP0, JFIF).is_some() => jfif = true,
+            APP14 => {
+                if let Some(payload) = segment.payload(APP14, ADOBE) {
+                    adobe = Some(header::read_adobe_transform(payload)?);
+                }
+            }
+            SOS => {
+                let scan = ScanHeader::read(&segment.data, &frame)?;
+                for component in &scan.components {
+                    let plane = &mut planes[component.index];
+                    if plane.quantization.is_none() {
+                        let slot = frame.components[component.index].table as usize;
+                        plane.quantization = Some(tables.quantization[slot].ok_or_else(|| {
+                            Error::malformed(
+                                "JPEG",
+                                format!("quantization table {slot} is used before it's defined"),
+                            )
+                        })?);
+                    }
+                }
+                decode_scan(
+                    segment,
+                    &scan,
+                    &frame,
+                    &tables,
+                    &mut planes,
+                    (mcus_across, mcus_down),
+                )?;
+                scans += 1;
+            }
+            _ => {}
+        }
+    }
+    if scans == 0 {
+        return Err(Error::malformed("JPEG", "the file has no scans"));
+    }
+    to_image(&frame, &planes, ColorTransform::of(&frame, jfif, adobe))
+}
+
+/// Decodes the entropy-coded data of one scan into the coefficients of its components
+fn decode_scan(
+    segment: &Segment,
+    scan: &ScanHeader,
+    frame: &FrameHeader,
+    tables: &Tables,
+    planes: &mut [Plane],
+    (mcus_across, mcus_down): (usize, usize),
+) -> Result<()> {
+    let pass = match (
+        frame.is_progressive(),
+        scan.spectral_start,
+        scan.approximation_high,
+    ) {
+        (false, ..) => Pass::Sequential,
+        (true, 0, 0) => Pass::DcFirst,
+        (true, 0, _) => Pass::DcRefine,
+        (true, _, 0) => Pass::AcFirst,
+        (true, ..) => Pass::AcRefine,
+    };
+    if matches!(pass, Pass::AcFirst | Pass::AcRefine) && scan.components.len() > 1 {
+        return Err(Error::malformed(
+            "JPEG",
+            "a progressive AC scan holds more than one component",
+        ));
+    }
+    let decoders = scan
+        .components
+        .iter()
+        .map(|component| {
+            Ok((
+                table(
+                    &tables.dc,
+                    component.dc_table,
+                    matches!(pass, Pass::Sequential | Pass::DcFirst),
+                )?,
+                table(
+                    &tables.ac,
+                    component.ac_table,
+                    matches!(pass, Pass::Sequential | Pass::AcFirst | Pass::AcRefine),
+                )?,
+            ))
+        })
+        .collect::<Result<Vec<(Option<&Decoder>, Option<&Decoder>)>>>()?;
+
+    // a scan of one component goes block by block over just that component's area, while
+    // others go MCU by MCU over the padded image
+    let single = scan.components.len() == 1;
+    let (units_across, units_down) = match single {
+        true => {
+            let plane = &planes[scan.components[0].index];
+            (plane.width.div_ceil(8), plane.height.div_ceil(8))
+        }
+        false => (mcus_across, mcus_down),
+    };
+    let mut reader = BitReader::new(&segment.scan_data);
+    let mut predictions: Vec<i32> = vec![0; scan.components.len()];
+    let mut eob_run: u32 = 0;
+    for unit in 0..units_across * units_down {
+        if header::restart_due(tables.restart_interval, unit) {
+            reader.restart();
+            predictions.fill(0);
+            eob_run = 0;
+        }
+        let (unit_x, unit_y) = (unit % units_across, unit / units_across);
+        for (idx, component) in scan.components.iter().enumerate() {
+            let sampling = &frame.components[component.index];
+            let (across, down) = match single {
+                true => (1, 1),
+                false => (sampling.horizontal as usize, sampling.vertical as usize),
+            };
+            let (dc, ac) = decoders[idx];
+            for block_y in 0..down {
+                for block_x in 0..across {
+                    let block = planes[component.index]
+                        .block(unit_x * across + block_x, unit_y * down + block_y);
+                    let prediction = &mut predictions[idx];
+                    match pass {
+                        Pass::Sequential => {
+                            decode_dc(&mut reader, dc, prediction, block, 0)?;
+                            decode_ac(&mut reader, ac, block, 1, 63, 0, &mut eob_run)?;
+                        }
+                        Pass::DcFirst => {
+                            let shift = scan.approximation_low;
+                            decode_dc(&mut reader, dc, prediction, block, shift)?;
+                        }
+                        Pass::DcRefine => {
+                            if reader.read_bit() {
+                                block[0] |= 1 << scan.approximation_low;
+                            }
+                        }
+                        Pass::AcFirst => decode_ac(
+                            &mut reader,
+                            ac,
+                            block,
+                            scan.spectral_start,
+                            scan.spectral_end,
+                            scan.approximation_low,
+                            &mut eob_run,
+                        )?,
+                        Pass::AcRefine => refine_ac(&mut reader, ac, block, scan, &mut eob_run)?,
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The Huffman table in `slot`, which is an error if it's `needed` but not defined
+fn table(decoders: &[Option<Decoder>; 4], slot: u8, needed: bool) -> Result<Option<&Decoder>> {
+    match (&decoders[slot as usize], needed) {
+        (Some(decoder), _) => Ok(Some(decoder)),
+        (None, false) => Ok(None),
+        (None, true) => Err(Error::malformed(
+            "JPEG",
+            format!("a scan uses Huffman table {slot}, which isn't defined"),
+        )),
+    }
+}
+
+/// Decodes a DC difference and adds it to the prediction, which becomes the coefficient
+fn decode_dc(
+    reader: &mut BitReader,
+    decoder: Option<&Decoder>,
+    prediction: &mut i32,
+    block: &mut [i16],
+    shift: u8,
+) -> Result<()> {
+    let size = reader.decode(decoder.expect("checked with the scan"))?;
+    if size > 11 {
+        return Err(Error::malformed(
+            "JPEG",
+            format!("DC difference of {size} bits"),
+        ));
+    }
+    *prediction = prediction.wrapping_add(reader.read_signed(size));
+    block[0] = (*prediction << shift) as i16;
+    Ok(())
+}
+
+/// Decodes coefficients `start` to `end` as runs of zeros and values, or skips the block
+/// if it's part of a run of empty ones
+fn decode_ac(
+    reader: &mut BitReader,
+    decoder: Option<&Decoder>,
+    block: &mut [i16],
+    start: u8,
+    end: u8,
+    shift: u8,
+    eob_run: &mut u32,
+) -> Result<()> {
+    if *eob_run > 0 {
+        *eob_run -= 1;
+        return Ok(());
+    }
+    let decoder = decoder.expect("checked with the scan");
+    let mut k = start as usize;
+    while k <= end as usize {
+        let symbol = reader.decode(decoder)?;
+        let (run, size) = (symbol >> 4, symbol & 0x0f);
+        if size == 0 {
+            if run < 15 {
+                // the rest of this block, and of `eob_run` blocks after it, is zero
+                *eob_run = (1 << run) - 1 + reader.read_bits(run) as u32;
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run as usize;
+        if k > end as usize {
+            return Err(Error::malformed(
+                "JPEG",
+                "a run of zeros passes the end of a block",
+            ));
+        }
+        block[ZIGZAG[k]] = (reader.read_signed(size) * (1 << shift)) as i16;
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Adds one bit of precision to coefficients already known to be nonzero, and places the
+/// ones that become nonzero at this bit
+fn refine_ac(
+    reader: &mut BitReader,
+    decoder: Option<&Decoder>,
+    block: &mut [i16],
+    scan: &ScanHeader,
+    eob_run: &mut u32,
+) -> Result<()> {
+    let decoder = decoder.expect("checked with the scan");
+    let (start, end) = (scan.spectral_start as usize, scan.spectral_end as usize);
+    let positive = 1_i16 << scan.approximation_low;
+    let negative = -1_i16 << scan.approximation_low;
+    let refine = |reader: &mut BitReader, coefficient: &mut i16| {
+        if reader.read_bit() && *coefficient & positive == 0 {
+            let bit = if *coefficient >= 0 {
+                positive
+            } else {
+                negative
+            };
+            // corrupt earlier passes can leave coefficients at the edge of the range
+            *coefficient = coefficient.wrapping_add(bit);
+        }
+    };
+    let mut k = start;
+    if *eob_run == 0 {
+        while k <= end {
+            let symbol = reader.decode(decoder)?;
+            let (mut run, size) = (symbol >> 4, symbol & 0x0f);
+            let value = match size {
+                0 if run < 15 => {
+                    *eob_run = (1 << run) + reader.read_bits(run) as u32;
+                    break;
+                }
+                0 => 0,
+                _ if reader.read_bit() => positive,
+                _ => negative,
+            };
+            // skip `run` zeros, refining the nonzero coefficients passed on the way
+            while k <= end {
+                let coefficient = &mut block[ZIGZAG[k]];
+                if *coefficient != 0 {
+                    refine(reader, coefficient);
+                } else if run == 0 {
+                    break;
+                } else {
+                    run -= 1;
+                }
+                k += 1;
+            }
+            if value != 0 {
+                if k > end {
+                    return Err(Error::malformed(
+                        "JPEG",
+                        "a run of zeros passes the end of a block",
+                    ));
+                }
+                block[ZIGZAG[k]] = value;
+            }
+            k += 1;
+        }
+    }
+    if *eob_run > 0 {
+        for &at in &ZIGZAG[k.min(end + 1)..=end] {
+            if block[at] != 0 {
+                refine(reader, &mut block[at]);
+            }
+        }
+        *eob_run -= 1;
+    }
+    Ok(())
+}
+
+/// Weights for resampling one axis of a subsampled plane to the full image, as the nearest
+/// two samples and how far toward the second each pixel sits. Samples sit at the centers
+/// of the pixels they cover, as JFIF places them.
+fn resample_weights(
+    full: usize,
+    scaled: usize,
+    factor: usize,
+    max_factor: usize,
+) -> Vec<(usize, usize, f32)> {
+    (0..full)
+        .map(|position| {
+            let at = ((position as f32 + 0.5) * factor as f32 / max_factor as f32 - 0.5)
+                .clamp(0.0, (scaled - 1) as f32);
+            let first = at as usize;
+            (first, (first + 1).min(scaled - 1), at - first as f32)
+        })
+        .collect()
+}
+
+/// Upsamples each plane to the full image and converts the result to gray or RGB
+fn to_image(frame: &FrameHeader, planes: &[Plane], transform: ColorTransform) -> Result<Image> {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let (max_horizontal, max_vertical) = frame.max_sampling();
+    // every plane at full size, one after another
+    let mut full: Vec<Vec<u8>> = Vec::with_capacity(planes.len());
+    for (plane, component) in planes.iter().zip(&frame.components) {
+        let samples = plane.samples();
+        let stride = plane.blocks_across * 8;
+        if (component.horizontal, component.vertical) == (max_horizontal, max_vertical) {
+            full.push(
+                (0..height)
+                    .flat_map(|y| &samples[y * stride..y * stride + width])
+                    .copied()
+                    .collect(),
+            );
+            continue;
+        }
+        let columns = resample_weights(
+            width,
+            plane.width,
+            component.horizontal as usize,
+            max_horizontal as usize,
+        );
+        let rows = resample_weights(
+            height,
+            plane.height,
+            component.vertical as usize,
+            max_vertical as usize,
+        );
+        let mut out: Vec<u8> = Vec::with_capacity(width * height);
+        for &(top, bottom, down) in &rows {
+            for &(left, right, across) in &columns {
+                let at = |x: usize, y: usize| samples[y * stride + x] as f32;
+                let upper = at(left, top) + (at(right, top) - at(left, top)) * across;
+                let lower = at(left, bottom) + (at(right, bottom) - at(left, bottom)) * across;
+                out.push((upper + (lower - upper) * down).round() as u8);
+            }
+        }
+        full.push(out);
+    }
+
+    let to_u8 = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    let (color_model, data): (ColorModel, Vec<u8>) = match transform {
+        ColorTransform::Gray => (ColorModel::Gray, full.swap_remove(0)),
+        ColorTransform::Rgb => (
+            ColorModel::Rgb,
+            (0..width * height)
+                .flat_map(|idx| [full[0][idx], full[1][idx], full[2][idx]])
+                .collect(),
+        ),
+        ColorTransform::YCbCr => (
+            ColorModel::Rgb,
+            (0..width * height)
+                .flat_map(|idx| {
+                    let [y, cb, cr] = [full[0][idx], full[1][idx], full[2][idx]];
+                    ycbcr_to_rgb(y as f32, cb as f32, cr as f32).map(to_u8)
+                })
+                .collect(),
+        ),
+        ColorTransform::Cmyk { .. } | ColorTransform::Ycck => (
+            ColorModel::Rgb,
+            (0..width * height)
+                .flat_map(|idx| {
+                    let samples = [full[0][idx], full[1][idx], full[2][idx]].map(|s| s as f32);
+                    let black = full[3][idx] as f32;
+                    // each channel ends up as the fraction of light left by its ink and black
+                    let (light, black_light) = match transform {
+                        // the color part codes the complement of inverted CMY, so the
+                        // converted values are inks to invert again
+                        ColorTransform::Ycck => {
+                            let [y, cb, cr] = samples;
+                            (ycbcr_to_rgb(y, cb, cr).map(|ink| 255.0 - ink), black)
+                        }
+                        ColorTransform::Cmyk { inverted: true } => (samples, black),
+                        _ => (samples.map(|ink| 255.0 - ink), 255.0 - black),
+                    };
+                    light.map(|light| to_u8(light.clamp(0.0, 255.0) * black_light / 255.0))
+                })
+                .collect(),
+        ),
+    };
+    Image::new(
+        frame.width as u32,
+        frame.height as u32,
+        color_model,
+        8,
+        data,
+    )
+}
+
+/// The example quantization tables from Annex K of the standard, in natural order, which
+/// quality 50 uses unscaled
+const LUMA_QUANTIZATION: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+const CHROMA_QUANTIZATION: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// Scales a base quantization table for `quality`, 1 to 100, the way libjpeg does
+pub fn quantization_table(base: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = match quality < 50 {
+        true => 5000 / quality,
+        false => 200 - quality * 2,
+    };
+    base.map(|value| ((value as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+/// Converts an RGB sample to YCbCr, as JFIF defines it
+pub fn rgb_to_ycbcr(r: f32, g: f32, b: f32) -> [f32; 3] {
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0,
+        0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0,
+    ]
+}
+
+/// One component's quantized coefficients, ready to be entropy coded
+struct QuantizedPlane {
+    blocks_across: usize,
+    horizontal: usize,
+    vertical: usize,
+    /// 0 for luma's quantization and Huffman tables, 1 for chroma's
+    tables: usize,
+    /// 64 coefficients for each block, in zigzag order
+    blocks: Vec<[i16; 64]>,
+}
+
+/// Averages each `horizontal` by `vertical` box of samples, in rows `width` long
+fn downsample(samples: &[u8], width: usize, horizontal: usize, vertical: usize) -> Vec<u8> {
+    let height = samples.len() / width;
+    let area = (horizontal * vertical) as u32;
+    let mut out: Vec<u8> = Vec::with_capacity(samples.len() / area as usize);
+    for y in (0..height).step_by(vertical) {
+        for x in (0..width).step_by(horizontal) {
+            let sum: u32 = (y..y + vertical)
+                .flat_map(|row| &samples[row * width + x..row * width + x + horizontal])
+                .map(|&sample| sample as u32)
+                .sum();
+            out.push(((sum + area / 2) / area) as u8);
+        }
+    }
+    out
+}
+
+/// Transforms and quantizes samples, in rows `width` long, a whole number of blocks in
+/// either direction
+fn quantize(samples: &[u8], width: usize, table: &[u16; 64]) -> Vec<[i16; 64]> {
+    let (blocks_across, blocks_down) = (width / 8, samples.len() / width / 8);
+    let mut blocks: Vec<[i16; 64]> = Vec::with_capacity(blocks_across * blocks_down);
+    let mut block = [0_u8; 64];
+    let mut coefficients = [0.0_f32; 64];
+    for y in 0..blocks_down {
+        for x in 0..blocks_across {
+            for row in 0..8 {
+                let start = (y * 8 + row) * width + x * 8;
+                block[row * 8..row * 8 + 8].copy_from_slice(&samples[start..start + 8]);
+            }
+            dct::forward(&block, &mut coefficients);
+            blocks.push(std::array::from_fn(|idx| {
+                let at = ZIGZAG[idx];
+                // the most a baseline Huffman table can code
+                let limit = if idx == 0 { 2047.0 } else { 1023.0 };
+                (coefficients[at] / table[at] as f32)
+                    .round()
+                    .clamp(-limit, limit) as i16
+            }));
+        }
+    }
+    blocks
+}
+
+/// Runs through the coefficients MCU by MCU, passing each Huffman-coded value to `emit`
+/// along with its table (luma DC, luma AC, chroma DC, then chroma AC) and the number
+/// written in the bits after it
+fn for_each_symbol(
+    planes: &[QuantizedPlane],
+    (mcus_across, mcus_down): (usize, usize),
+    mut emit: impl FnMut(usize, u8, i32) -> Result<()>,
+) -> Result<()> {
+    let mut predictions: Vec<i32> = vec![0; planes.len()];
+    for mcu_y in 0..mcus_down {
+        for mcu_x in 0..mcus_across {
+            for (plane, prediction) in planes.iter().zip(&mut predictions) {
+                let (dc, ac) = (plane.tables * 2, plane.tables * 2 + 1);
+                for block_y in 0..plane.vertical {
+                    for block_x in 0..plane.horizontal {
+                        let x = mcu_x * plane.horizontal + block_x;
+                        let y = mcu_y * plane.vertical + block_y;
+                        let block = &plane.blocks[y * plane.blocks_across + x];
+                        let difference = block[0] as i32 - *prediction;
+                        *prediction = block[0] as i32;
+                        emit(dc, huffman::magnitude(difference), difference)?;
+                        let mut run = 0;
+                        for &coefficient in &block[1..] {
+                            if coefficient == 0 {
+                                run += 1;
+                                continue;
+                            }
+                            while run > 15 {
+                                emit(ac, 0xf0, 0)?;
+                                run -= 16;
+                            }
+                            let value = coefficient as i32;
+                            emit(ac, run << 4 | huffman::magnitude(value), value)?;
+                            run = 0;
+                        }
+                        if run > 0 {
+                            emit(ac, 0x00, 0)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encodes `image` as a baseline JPEG with one interleaved scan, in gray or YCbCr. Alpha is
+/// flattened onto the image's background color, or white, since JPEG can't store it.
+pub fn encode(image: &Image, options: &JpegOptions) -> Result<Jpeg> {
+    options.validate()?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(Error::unsupported(
+            "JPEG",
+            format!("images of {width}x{height} pixels"),
+        ));
+    }
+    let image = match image.color_model().has_alpha() {
+        true => {
+            let mut flattened = image.clone();
+            flattened.flatten_alpha(None);
+            Cow::Owned(flattened)
+        }
+        false => Cow::Borrowed(image),
+    };
+    let gray = image.color_model() == ColorModel::Gray;
+    let (luma_horizontal, luma_vertical) = match gray {
+        true => (1, 1),
+        false => options.subsampling.luma_sampling(),
+    };
+    let (horizontal, vertical) = (luma_horizontal as usize, luma_vertical as usize);
+    let mcus = (
+        width.div_ceil(8 * horizontal),
+        height.div_ceil(8 * vertical),
+    );
+    let (padded_width, padded_height) = (mcus.0 * 8 * horizontal, mcus.1 * 8 * vertical);
+
+    // each component at full size, with the edge pixels repeated out to whole MCUs
+    let mut full: Vec<Vec<u8>> =
+        vec![Vec::with_capacity(padded_width * padded_height); if gray { 1 } else { 3 }];
+    for y in 0..padded_height {
+        for x in 0..padded_width {
+            let [r, g, b, _] = image.rgba8(y.min(height - 1) * width + x.min(width - 1));
+            if gray {
+                full[0].push(r);
+                continue;
+            }
+            let ycbcr = rgb_to_ycbcr(r as f32, g as f32, b as f32);
+            for (plane, sample) in full.iter_mut().zip(ycbcr) {
+                plane.push(sample.round().clamp(0.0, 255.0) as u8);
+            }
+        }
+    }
+    let quantization = [
+        quantization_table(&LUMA_QUANTIZATION, options.quality),
+        quantization_table(&CHROMA_QUANTIZATION, options.quality),
+    ];
+    let planes: Vec<QuantizedPlane> = full
+        .iter()
+        .enumerate()
+        .map(|(idx, samples)| match idx {
+            0 => QuantizedPlane {
+                blocks_across: padded_width / 8,
+                horizontal,
+                vertical,
+                tables: 0,
+                blocks: quantize(samples, padded_width, &quantization[0]),
+            },
+            _ => QuantizedPlane {
+                blocks_across: mcus.0,
+                horizontal: 1,
+                vertical: 1,
+                tables: 1,
+                blocks: quantize(
+                    &downsample(samples, padded_width, horizontal, vertical),
+                    padded_width / horizontal,
+                    &quantization[1],
+                ),
+            },
+        })
+        .collect();
+    drop(full);
+
+    let table_count = if gray { 2 } else { 4 };
+    let huffman: Vec<HuffmanTable> = match options.optimize_huffman {
+        true => {
+            let mut frequencies = vec![[0_u32; 256]; table_count];
+            for_each_symbol(&planes, mcus, |table, symbol, _| {
+                frequencies[table][symbol as usize] += 1;
+                Ok(())
+            })?;
+            frequencies.iter().map(HuffmanTable::optimal).collect()
+        }
+        false => (0..table_count)
+            .map(|idx| {
+                let class = [TableClass::Dc, TableClass::Ac][idx % 2];
+                HuffmanTable::standard(class, idx >= 2)
+            })
+            .collect(),
+    };
+    let encoders = huffman
+        .iter()
+        .map(Encoder::new)
+        .collect::<Result<Vec<Encoder>>>()?;
+    let mut writer = BitWriter::new();
+    for_each_symbol(&planes, mcus, |table, symbol, value| {
+        writer.encode(&encoders[table], symbol)?;
+        writer.write_signed(value);
+        Ok(())
+    })?;
+
+    let frame = FrameHeader {
+        marker: SOF0,
+        precision: 8,
+        height: height as u16,
+        width: width as u16,
+        components: planes
+            .iter()
+            .enumerate()
+            .map(|(idx, plane)| Component {
+                id: idx as u8 + 1,
+                horizontal: plane.horizontal as u8,
+                vertical: plane.vertical as u8,
+                table: plane.tables as u8,
+            })
+            .collect(),
+    };
+    let scan = ScanHeader {
+        components: planes
+            .iter()
+            .enumerate()
+            .map(|(index, plane)| ScanComponent {
+                index,
+                dc_table: plane.tables as u8,
+                ac_table: plane.tables as u8,
+            })
+            .collect(),
+        spectral_start: 0,
+        spectral_end: 63,
+        approximation_high: 0,
+        approximation_low: 0,
+    };
+    let mut dht: Vec<u8> = Vec::new();
+    for (idx, table) in huffman.iter().enumerate() {
+        // class in the high nibble, slot in the low
+        dht.push(((idx % 2) << 4 | (idx / 2)) as u8);
+        dht.extend(table.as_bytes());
+    }
+    let quantization: Vec<(u8, [u16; 64])> = quantization
+        .into_iter()
+        .take(table_count / 2)
+        .enumerate()
+        .map(|(slot, table)| (slot as u8, table))
+        .collect();
+
+    let metadata = image.metadata();
+    let jfif = Jfif::from_resolution(metadata.resolution());
+    let mut segments: Vec<Segment> = vec![Segment::new(APP0, jfif.as_bytes())];
+    if let Some(exif) = metadata.exif().filter(|_| options.exif) {
+        if EXIF.len() + exif.as_bytes().len() > MAX_SEGMENT_LEN {
+            return Err(Error::unsupported(
+                "JPEG",
+                format!("EXIF blocks of {} bytes", exif.as_bytes().len()),
+            ));
+        }
+        segments.push(Segment::new(APP1, [EXIF, exif.as_bytes()].concat()));
+    }
+    if let Some(profile) = metadata.icc_profile().filter(|_| options.icc_profile) {
+        for data in header::split_icc_profile(profile)? {
+            segments.push(Segment::new(APP2, data));
+        }
+    }
+    let mut sos = Segment::new(SOS, scan.as_bytes(&frame));
+    sos.scan_data = writer.finish();
+    segments.extend([
+        Segment::new(DQT, header::quantization_tables_bytes(&quantization)),
+        Segment::new(SOF0, frame.as_bytes()),
+        Segment::new(DHT, dht),
+        sos,
+    ]);
+    Ok(Jpeg::new(segments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg::{marker::SOF2, options::JpegSubsampling};
+
+    /// A 16x8 gray JPEG of two flat blocks, 200 and 64, with a restart marker between them
+    fn two_blocks() -> Jpeg {
+        let frame = FrameHeader {
+            marker: SOF0,
+            precision: 8,
+            height: 8,
+            width: 16,
+            components: vec![Component {
+                id: 1,
+                horizontal: 1,
+                vertical: 1,
+                table: 0,
+            }],
+        };
+        let mut dc_counts = [0; 16];
+        dc_counts[3] = 12;
+        let mut ac_counts = [0; 16];
+        ac_counts[0] = 1;
+        let mut dht = vec![0x00];
+        dht.extend(
+            HuffmanTable {
+                counts: dc_counts,
+                values: (0..12).collect(),
+            }
+            .as_bytes(),
+        );
+        dht.push(0x10);
+        dht.extend(
+            HuffmanTable {
+                counts: ac_counts,
+                values: vec![0],
+            }
+            .as_bytes(),
+        );
+        let mut sos = Segment::new(SOS, vec![1, 1, 0x00, 0, 63, 0]);
+        // DC category 10 (code 1010), 576 or -512, then the end of block code and padding
+        sos.scan_data = vec![0xa9, 0x01, 0xff, 0xd0, 0xa7, 0xfd];
+        Jpeg::new(vec![
+            Segment::new(DQT, header::quantization_tables_bytes(&[(0, [1; 64])])),
+            Segment::new(SOF0, frame.as_bytes()),
+            Segment::new(DHT, dht),
+            Segment::new(DRI, vec![0, 1]),
+            sos,
+        ])
+    }
+
+    #[test]
+    fn test_restart_markers() {
+        let jpeg = two_blocks();
+        let image = decode(&jpeg).unwrap();
+        assert_eq!(image.color_model(), ColorModel::Gray);
+        assert!(image.row(3)[..8].iter().all(|&sample| sample == 200));
+        assert!(image.row(7)[8..].iter().all(|&sample| sample == 64));
+
+        // without the restart interval the marker reads as the end of the data, which
+        // leaves the first block as it was
+        let mut segments = jpeg.segments().to_vec();
+        segments.retain(|segment| segment.marker != DRI);
+        let image = decode(&Jpeg::new(segments)).unwrap();
+        assert!(image.row(0)[..8].iter().all(|&sample| sample == 200));
+
+        // a forged size that the scan data can't cover is caught before allocating
+        let mut segments = jpeg.segments().to_vec();
+        segments[1].data[1..5].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(matches!(
+            decode(&Jpeg::new(segments)),
+            Err(Error::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_encode() {
+        // a smooth gradient, with its top left pixel transparent
+        let data: Vec<u8> = (0..12)
+            .flat_map(|y| (0..20).map(move |x| [x * 12, y * 20, 128, 255]))
+            .enumerate()
+            .flat_map(|(idx, [r, g, b, a])| [r, g, b, if idx == 0 { 0 } else { a }])
+            .collect();
+        let image = Image::new(20, 12, ColorModel::Rgba, 8, data).unwrap();
+        for subsampling in [
+            JpegSubsampling::Chroma444,
+            JpegSubsampling::Chroma422,
+            JpegSubsampling::Chroma420,
+        ] {
+            let options = JpegOptions {
+                quality: 90,
+                subsampling,
+                ..JpegOptions::default()
+            };
+            let jpeg = encode(&image, &options).unwrap();
+            let frame = jpeg.frame().unwrap();
+            assert_eq!(
+                frame.components[0].horizontal,
+                subsampling.luma_sampling().0
+            );
+            let decoded = decode(&jpeg).unwrap();
+            assert_eq!(decoded.color_model(), ColorModel::Rgb);
+            assert_eq!((decoded.width(), decoded.height()), (20, 12));
+            let error: u32 = (1..240)
+                .flat_map(|pixel| {
+                    let (found, expected) = (decoded.rgba8(pixel), image.rgba8(pixel));
+                    (0..3).map(move |channel| found[channel].abs_diff(expected[channel]) as u32)
+                })
+                .sum();
+            assert!(error / (239 * 3) < 4, "{subsampling:?}: {error}");
+            // the transparent pixel lands on white, blurred by its neighbors
+            assert!(decoded.rgba8(0).iter().all(|&channel| channel > 128));
+
+            // tables built for the image code it in fewer bits, and decode the same
+            let optimized = encode(
+                &image,
+                &JpegOptions {
+                    optimize_huffman: true,
+                    ..options
+                },
+            )
+            .unwrap();
+            let scan_len = |jpeg: &Jpeg| jpeg.segments().last().unwrap().scan_data.len();
+            assert!(scan_len(&optimized) < scan_len(&jpeg));
+            assert_eq!(decode(&optimized).unwrap().data(), decoded.data());
+        }
+
+        let gray = Image::new(9, 9, ColorModel::Gray, 8, vec![77; 81]).unwrap();
+        let jpeg = encode(&gray, &JpegOptions::default()).unwrap();
+        assert_eq!(jpeg.frame().unwrap().components.len(), 1);
+        assert!(decode(&jpeg)
+            .unwrap()
+            .data()
+            .iter()
+            .all(|&sample| sample == 77));
+        let too_low = JpegOptions {
+            quality: 0,
+            ..JpegOptions::default()
+        };
+        assert!(matches!(
+            encode(&gray, &too_low),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    /// Decodes a fixture from `testdata` and checks pixels against what libjpeg gives for
+    /// them, allowing for its integer transforms and different upsampling
+    fn assert_decodes_to(bytes: &[u8], size: (u32, u32), expected: &[((u32, u32), [u8; 3])]) {
+        let image = decode(&Jpeg::try_from(bytes).unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), size);
+        for &((x, y), rgb) in expected {
+            let found = image.rgba8((y * size.0 + x) as usize);
+            assert!(
+                (0..3).all(|channel| found[channel].abs_diff(rgb[channel]) <= 3),
+                "({x}, {y}): {found:?}, expected {rgb:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_progressive() {
+        // libjpeg's standard progression: DC with a refinement pass, AC split in bands and
+        // refined twice, with runs of empty blocks, all 4:2:0
+        let bytes = include_bytes!("testdata/progressive.jpg");
+        let jpeg = Jpeg::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(jpeg.frame().unwrap().marker, SOF2);
+        assert!(jpeg.segments().iter().filter(|s| s.marker == SOS).count() > 6);
+        assert_decodes_to(
+            bytes,
+            (48, 32),
+            &[
+                ((0, 0), [0, 0, 59]),
+                ((23, 10), [110, 67, 94]),
+                ((24, 10), [124, 73, 165]),
+                ((47, 31), [235, 217, 197]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_chroma_upsampling() {
+        // 4:2:2, 20x12, so the last MCU column and row are partial
+        assert_decodes_to(
+            include_bytes!("testdata/subsampled.jpg"),
+            (20, 12),
+            &[
+                ((0, 0), [0, 0, 112]),
+                ((7, 3), [82, 46, 110]),
+                ((12, 8), [158, 212, 118]),
+                ((19, 11), [85, 0, 80]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_adobe_transforms() {
+        // the same inverted CMYK, stored as is and as YCCK, which is also progressive
+        for (bytes, transform) in [
+            (
+                include_bytes!("testdata/cmyk.jpg").as_slice(),
+                ColorTransform::Cmyk { inverted: true },
+            ),
+            (include_bytes!("testdata/ycck.jpg"), ColorTransform::Ycck),
+        ] {
+            let jpeg = Jpeg::try_from(bytes).unwrap();
+            let (frame, adobe) = (jpeg.frame().unwrap(), jpeg.adobe_transform().unwrap());
+            assert_eq!(ColorTransform::of(&frame, false, adobe), transform);
+        }
+        assert_decodes_to(
+            include_bytes!("testdata/cmyk.jpg"),
+            (20, 12),
+            &[
+                ((0, 0), [0, 0, 0]),
+                ((7, 3), [31, 19, 71]),
+                ((12, 8), [109, 143, 140]),
+                ((19, 11), [6, 23, 209]),
+            ],
+        );
+        assert_decodes_to(
+            include_bytes!("testdata/ycck.jpg"),
+            (20, 12),
+            &[
+                ((0, 0), [0, 0, 4]),
+                ((7, 3), [33, 22, 47]),
+                ((12, 8), [116, 146, 96]),
+                ((19, 11), [97, 0, 100]),
+            ],
+        );
+    }
+}
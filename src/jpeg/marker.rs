@@ -0,0 +1,129 @@
+//! Marker codes, the second byte of every `0xff xx` pair that structures a JPEG
+
+use std::io::{BufRead, Write};
+
+use crate::{byte_reader::StreamReader, Result};
+
+/// Start of image, the first marker of every file
+pub const SOI: u8 = 0xd8;
+/// End of image
+pub const EOI: u8 = 0xd9;
+/// Start of frame markers, one for each coding process
+pub const SOF0: u8 = 0xc0;
+pub const SOF1: u8 = 0xc1;
+pub const SOF2: u8 = 0xc2;
+pub const SOF15: u8 = 0xcf;
+/// Define Huffman tables
+pub const DHT: u8 = 0xc4;
+/// Define arithmetic coding conditioning, which also sits among the SOF codes
+pub const DAC: u8 = 0xcc;
+/// Start of scan
+pub const SOS: u8 = 0xda;
+/// Define quantization tables
+pub const DQT: u8 = 0xdb;
+/// Define number of lines
+pub const DNL: u8 = 0xdc;
+/// Define restart interval
+pub const DRI: u8 = 0xdd;
+/// The first and last restart markers, which cycle through the eight codes in between
+pub const RST0: u8 = 0xd0;
+pub const RST7: u8 = 0xd7;
+/// Application segments: APP0 holds JFIF, APP1 EXIF, APP2 ICC profiles and APP14 Adobe's
+/// color transform
+pub const APP0: u8 = 0xe0;
+pub const APP1: u8 = 0xe1;
+pub const APP2: u8 = 0xe2;
+pub const APP14: u8 = 0xee;
+/// Comment
+pub const COM: u8 = 0xfe;
+/// Reserved for temporary private use, and like SOI and the restart markers has no length
+pub const TEM: u8 = 0x01;
+
+/// Whether `marker` starts a frame, rather than being one of the codes mixed in among them
+pub fn is_sof(marker: u8) -> bool {
+    (SOF0..=SOF15).contains(&marker) && marker != DHT && marker != DAC && marker != 0xc8
+}
+
+/// Whether `marker` stands alone, with no length or data after it
+pub fn is_standalone(marker: u8) -> bool {
+    matches!(marker, SOI | EOI | TEM | RST0..=RST7)
+}
+
+/// Most data a segment can hold, since its length counts itself
+pub const MAX_SEGMENT_LEN: usize = u16::MAX as usize - 2;
+
+/// One marker segment, and for scans the entropy-coded data that follows it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub marker: u8,
+    /// Everything after the length
+    pub data: Vec<u8>,
+    /// Entropy-coded data as stored, with its stuffed zero bytes and restart markers;
+    /// empty for every segment but SOS
+    pub scan_data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn new(marker: u8, data: Vec<u8>) -> Self {
+        Segment {
+            marker,
+            data,
+            scan_data: Vec::new(),
+        }
+    }
+
+    /// Reads the length and data of a segment whose marker has just been read. Scans are
+    /// read up to the marker after them, which is returned; the end of the file counts as
+    /// an EOI, since truncated files are common and their scans still partly decode.
+    pub fn read<R: BufRead>(
+        reader: &mut StreamReader<R>,
+        marker: u8,
+    ) -> Result<(Segment, Option<u8>)> {
+        let len = reader.read_u16_be()?;
+        if len < 2 {
+            return Err(reader.error(format!("segment length {len} is too short")));
+        }
+        let mut segment = Segment::new(marker, reader.read_bytes(len as usize - 2)?);
+        if marker != SOS {
+            return Ok((segment, None));
+        }
+        loop {
+            let Some(byte) = reader.peek_u8()? else {
+                return Ok((segment, Some(EOI)));
+            };
+            reader.read_u8()?;
+            if byte != 0xff {
+                segment.scan_data.push(byte);
+                continue;
+            }
+            // fill bytes can pad out the space before a marker
+            let mut next = 0xff;
+            while next == 0xff {
+                match reader.peek_u8()? {
+                    Some(_) => next = reader.read_u8()?,
+                    None => return Ok((segment, Some(EOI))),
+                }
+            }
+            match next {
+                0 | RST0..=RST7 => segment.scan_data.extend([0xff, next]),
+                marker => return Ok((segment, Some(marker))),
+            }
+        }
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&[0xff, self.marker])?;
+        writer.write_all(&(self.data.len() as u16 + 2).to_be_bytes())?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.scan_data)?;
+        Ok(())
+    }
+
+    /// The data of an application segment after its NUL-terminated identifier, if the
+    /// identifier is `identifier`
+    pub fn payload(&self, marker: u8, identifier: &[u8]) -> Option<&[u8]> {
+        (self.marker == marker)
+            .then(|| self.data.strip_prefix(identifier))
+            .flatten()
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{Error, Result};
+
+/// How many chroma samples JPEG output keeps, relative to luma
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JpegSubsampling {
+    /// Full chroma resolution
+    Chroma444,
+    /// Half the chroma columns
+    Chroma422,
+    /// Half the chroma columns and rows
+    #[default]
+    Chroma420,
+}
+
+impl JpegSubsampling {
+    /// Luma sampling factors, horizontal then vertical, against chroma's 1 and 1
+    pub fn luma_sampling(&self) -> (u8, u8) {
+        match self {
+            JpegSubsampling::Chroma444 => (1, 1),
+            JpegSubsampling::Chroma422 => (2, 1),
+            JpegSubsampling::Chroma420 => (2, 2),
+        }
+    }
+}
+
+/// How JPEG output is encoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JpegOptions {
+    /// 1 to 100, scaling the standard quantization tables the way libjpeg does
+    pub quality: u8,
+    /// Ignored for gray images, which have no chroma
+    pub subsampling: JpegSubsampling,
+    /// Build Huffman tables from the image's own statistics instead of using the standard
+    /// ones, which takes a second pass but makes the file smaller
+    pub optimize_huffman: bool,
+    /// Embed the source's EXIF block, if it has one
+    pub exif: bool,
+    /// Embed the source's ICC profile, if it has one
+    pub icc_profile: bool,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        JpegOptions {
+            quality: 75,
+            subsampling: JpegSubsampling::default(),
+            optimize_huffman: false,
+            exif: true,
+            icc_profile: true,
+        }
+    }
+}
+
+impl JpegOptions {
+    /// Checks for settings that are out of range
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=100).contains(&self.quality) {
+            return Err(Error::invalid_argument(format!(
+                "JPEG quality runs from 1 to 100, not {}",
+                self.quality
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Converts images between formats by way of PNG. [`convert()`] handles a whole file
+//! conversion; the format modules and [`image::Image`] are there for finer control.
+
+pub mod animation;
+pub mod batch;
+pub mod bmp;
+pub mod byte_reader;
+pub mod compression;
+pub mod convert;
+pub mod error;
+pub mod exif;
+pub mod farbfeld;
+pub mod format;
+pub mod gif;
+pub mod ico;
+pub mod image;
+pub mod info;
+pub mod jpeg;
+pub mod metadata;
+pub mod netpbm;
+pub mod png;
+pub mod qoi;
+pub mod raw;
+pub mod scanline;
+pub mod tga;
+pub mod tiff;
+pub mod webp;
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+pub use batch::{convert_batch, BatchOptions};
+pub use convert::{convert, convert_io, ConvertOptions, Input, Output};
+pub use error::Error;
+pub use info::{inspect, inspect_io, ImageInfo};
+
+//error handling types
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Represents an image type that can be converted to and from the PNG type
+pub trait ConvertibleImage {
+    fn from_file<P: AsRef<Path>>(path: P) -> Result<Box<Self>>
+    where
+        Self: Sized,
+    {
+        Self::from_reader(File::open(path)?).map(Box::new)
+    }
+    /// Reads an image from `reader`, which is consumed front to back in a single pass
+    fn from_reader<R: Read>(reader: R) -> Result<Self>
+    where
+        Self: Sized;
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        self.to_writer(&mut bytes)
+            .expect("writing to a Vec can't fail");
+        bytes
+    }
+    /// Writes the image out in its own format, piece by piece rather than as one buffer
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()>;
+    fn to_png(&self) -> Result<png::Png>;
+    /// Returns the metadata to carry over to the target format; formats that can't
+    /// store any keep the default
+    fn metadata(&self) -> Result<metadata::Metadata> {
+        Ok(metadata::Metadata::default())
+    }
+    fn from_png(png: png::Png) -> Result<Self>
+    where
+        Self: Sized;
+    /// Number of separate images the file holds, such as the sizes stored in an icon
+    fn image_count(&self) -> usize {
+        1
+    }
+    /// Converts image `index` of a file holding several, where `to_png` picks one itself
+    fn image_to_png(&self, index: usize) -> Result<png::Png> {
+        match index {
+            0 => self.to_png(),
+            _ => Err(Error::no_such_image(index, self.image_count())),
+        }
+    }
+}
@@ -1,69 +1,113 @@
-use std::{fs, path::Path};
+use std::{
+    io::{self, Write},
+    path::Path,
+    process::ExitCode,
+};
 
-use clap::Parser;
-use png::Png;
+use modular_image_converter::{
+    convert_batch, convert_io, inspect_io, BatchOptions, Error, Input, Output,
+};
 
-use crate::cli::Cli;
+use crate::cli::{Cli, Command};
 
-mod bmp;
 mod cli;
-mod compression;
-mod png;
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    //get first file
-    let input: Box<dyn ConvertibleImage> = match &cli
-        .source
-        .extension()
-        .expect("Invalid source; files must have an extension")
-        .to_str()
-    {
-        Some("png") => Png::from_file(&cli.source)?,
-        Some(default) => {
-            println!("Files with extension {} are not supported at this time; please select a different sourcefile type", default);
-            return Ok(());
+fn main() -> ExitCode {
+    let cli = Cli::parse_args();
+    if let Some(Command::Info { path, from, json }) = &cli.command {
+        return run_info(path, from.as_deref(), *json);
+    }
+    if let Some(options) = cli.batch_options() {
+        return run_batch(&cli, &options);
+    }
+    let (source, target) = (&cli.paths[0], &cli.paths[1]);
+    let (mut stdin, mut stdout) = (io::stdin().lock(), io::stdout().lock());
+    let input = match Cli::is_stdio(source) {
+        true => Input::Reader(&mut stdin),
+        false => Input::Path(source),
+    };
+    let output = match Cli::is_stdio(target) {
+        true => Output::Writer(&mut stdout),
+        false => Output::Path(target),
+    };
+    match convert_io(input, output, &cli.convert_options()) {
+        Ok(conversion) => {
+            for warning in conversion.warnings {
+                eprintln!("warning: {warning}");
+            }
+            ExitCode::SUCCESS
         }
-        None => {
-            println!("Unable to read file extension for source");
-            return Ok(());
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(e.exit_code())
         }
-    };
+    }
+}
 
-    //check second file type
-    let output_bytes: Vec<u8> = match &cli
-        .target
-        .extension()
-        .expect("Invalid target; files must have an extension")
-        .to_str()
-    {
-        Some("png") => input.to_png(cli.flags).to_bytes(),
-        Some(default) => {
-            println!("Files with extension {} are not supported at this time; please select a different target file type", default);
-            return Ok(());
-        }
-        None => {
-            println!("Unable to read file extension for target");
-            return Ok(());
+/// Prints what the image at `path` holds, as text or as JSON
+fn run_info(path: &Path, from: Option<&str>, json: bool) -> ExitCode {
+    let mut stdin = io::stdin().lock();
+    let input = match Cli::is_stdio(path) {
+        true => Input::Reader(&mut stdin),
+        false => Input::Path(path),
+    };
+    let info = match inspect_io(input, from) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(e.exit_code());
         }
     };
-
-    fs::write(cli.target, output_bytes)?;
-
-    return Ok(());
+    let mut stdout = io::stdout().lock();
+    let printed = match json {
+        true => writeln!(
+            stdout,
+            "{}",
+            serde_json::to_string_pretty(&info).expect("image info is plain data")
+        ),
+        false => write!(stdout, "{info}"),
+    };
+    match printed.and_then(|_| stdout.flush()) {
+        Ok(()) => ExitCode::SUCCESS,
+        // whatever reads the output, such as head, can stop early without that being an error
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            let e = Error::from(e);
+            eprintln!("error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
 }
 
-//error handling types
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
-
-/// Represents an image type that can be converted to and from the PNG type
-trait ConvertibleImage {
-    fn from_file<P: AsRef<Path>>(path: P) -> Result<Box<Self>>
-    where
-        Self: Sized;
-    fn to_bytes(&self) -> Vec<u8>;
-    fn to_png(&self, flags: Option<Vec<String>>) -> png::Png;
-    fn from_png(png: png::Png) -> Self
-    where
-        Self: Sized;
+/// Converts every file the command line names, printing a line per file and a summary. The
+/// exit code is that of the first file to fail.
+fn run_batch(cli: &Cli, options: &BatchOptions) -> ExitCode {
+    let report = match convert_batch(&cli.paths, options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    for file in &report.files {
+        match (&file.result, &file.output) {
+            (Ok(conversion), Some(output)) => {
+                println!("ok: {} -> {}", file.input.display(), output.display());
+                for warning in &conversion.warnings {
+                    eprintln!("warning: {}: {warning}", file.input.display());
+                }
+            }
+            (Ok(_), None) => println!("ok: {}", file.input.display()),
+            (Err(e), _) => println!("failed: {}: {e}", file.input.display()),
+        }
+    }
+    eprintln!(
+        "converted {} of {} files; {} failed",
+        report.succeeded(),
+        report.files.len(),
+        report.failed()
+    );
+    match report.first_error() {
+        Some(e) => ExitCode::from(e.exit_code()),
+        None => ExitCode::SUCCESS,
+    }
 }
@@ -0,0 +1,116 @@
+use std::fmt;
+
+use crate::{exif::Exif, image::Color};
+
+/// The length unit a `Resolution` counts pixels in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolutionUnit {
+    /// Only the ratio of the two densities, the pixels' aspect ratio, is known
+    Unknown,
+    Inch,
+    Centimeter,
+    Meter,
+}
+
+impl ResolutionUnit {
+    /// Meters in one unit, or `None` for `Unknown`
+    fn meters(&self) -> Option<f64> {
+        match self {
+            ResolutionUnit::Unknown => None,
+            ResolutionUnit::Inch => Some(0.0254),
+            ResolutionUnit::Centimeter => Some(0.01),
+            ResolutionUnit::Meter => Some(1.0),
+        }
+    }
+}
+
+/// How densely the pixels are meant to be printed or shown, along each axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resolution {
+    /// Pixels per unit horizontally
+    pub x: f64,
+    /// Pixels per unit vertically
+    pub y: f64,
+    pub unit: ResolutionUnit,
+}
+
+impl Resolution {
+    /// The same density counted in `unit`. Densities with an unknown unit can't be converted
+    /// to a known one, and known ones become a bare aspect ratio.
+    pub fn in_unit(&self, unit: ResolutionUnit) -> Option<Resolution> {
+        let scale = match (self.unit.meters(), unit.meters()) {
+            (Some(from), Some(to)) => to / from,
+            (None, None) | (Some(_), None) => 1.0,
+            (None, Some(_)) => return None,
+        };
+        Some(Resolution {
+            x: self.x * scale,
+            y: self.y * scale,
+            unit,
+        })
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            ResolutionUnit::Unknown => return write!(f, "{}:{} aspect ratio", self.x, self.y),
+            ResolutionUnit::Inch => "inch",
+            ResolutionUnit::Centimeter => "centimeter",
+            ResolutionUnit::Meter => "meter",
+        };
+        write!(f, "{}x{} pixels per {unit}", self.x, self.y)
+    }
+}
+
+/// Information about an image that isn't pixel data, kept independent of any one format
+/// so it can be carried from the source file to the target file
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    exif: Option<Exif>,
+    background: Option<Color>,
+    resolution: Option<Resolution>,
+    icc_profile: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    pub fn exif(&self) -> Option<&Exif> {
+        self.exif.as_ref()
+    }
+
+    pub fn set_exif(&mut self, exif: Option<Exif>) {
+        self.exif = exif;
+    }
+
+    /// The color the image prefers to be shown against, used as the matte when flattening alpha
+    pub fn background(&self) -> Option<Color> {
+        self.background
+    }
+
+    pub fn set_background(&mut self, background: Option<Color>) {
+        self.background = background;
+    }
+
+    pub fn resolution(&self) -> Option<Resolution> {
+        self.resolution
+    }
+
+    pub fn set_resolution(&mut self, resolution: Option<Resolution>) {
+        self.resolution = resolution;
+    }
+
+    /// The ICC profile describing the image's color space, as raw profile bytes
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.icc_profile.as_deref()
+    }
+
+    pub fn set_icc_profile(&mut self, icc_profile: Option<Vec<u8>>) {
+        self.icc_profile = icc_profile;
+    }
+
+    /// Drops the EXIF block, which can hold camera serial numbers, GPS positions and other
+    /// details that shouldn't leave the machine
+    pub fn strip_exif(&mut self) {
+        self.exif = None;
+    }
+}
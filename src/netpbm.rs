@@ -0,0 +1,419 @@
+use std::io::{BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    convert::ConvertOptions,
+    format::{decode_with, Codec},
+    image::{ColorModel, Image},
+    info::{FormatDetails, ImageInfo, MetadataInfo, NetpbmDetails},
+    metadata::Metadata,
+    png::Png,
+    scanline::RowSource,
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    header::{NetpbmHeader, Variant},
+    options::NetpbmOptions,
+};
+
+pub mod header;
+pub mod image_data;
+pub mod options;
+
+/// Registry entry for black and white PBM files
+pub const PBM_CODEC: Codec = Codec {
+    name: "PBM",
+    extensions: &["pbm"],
+    magic: &[b"P1", b"P4"],
+    check_signature: None,
+    encoder: Some(write_pbm),
+    row_encoder: Some(write_pbm_rows),
+    ..PNM_CODEC
+};
+
+/// Registry entry for grayscale PGM files
+pub const PGM_CODEC: Codec = Codec {
+    name: "PGM",
+    extensions: &["pgm"],
+    magic: &[b"P2", b"P5"],
+    check_signature: None,
+    encoder: Some(write_pgm),
+    row_encoder: Some(write_pgm_rows),
+    ..PNM_CODEC
+};
+
+/// Registry entry for RGB PPM files
+pub const PPM_CODEC: Codec = Codec {
+    name: "PPM",
+    extensions: &["ppm"],
+    magic: &[b"P3", b"P6"],
+    check_signature: None,
+    encoder: Some(write_ppm),
+    row_encoder: Some(write_ppm_rows),
+    ..PNM_CODEC
+};
+
+/// Registry entry for PAM files, which can hold alpha
+pub const PAM_CODEC: Codec = Codec {
+    name: "PAM",
+    extensions: &["pam"],
+    magic: &[b"P7"],
+    check_signature: None,
+    encoder: Some(write_pam),
+    row_encoder: Some(write_pam_rows),
+    ..PNM_CODEC
+};
+
+/// Registry entry for .pnm files, which can be any of the Netpbm formats. Images are
+/// written as PGM, PPM or PAM, whichever holds them as they are.
+pub const PNM_CODEC: Codec = Codec {
+    name: "PNM",
+    extensions: &["pnm"],
+    magic: &[b"P1", b"P2", b"P3", b"P4", b"P5", b"P6", b"P7"],
+    check_signature: None,
+    decoder: Some(decode_with::<Netpbm>),
+    encoder: Some(write_pnm),
+    row_decoder: Some(read_netpbm_rows),
+    row_encoder: Some(write_pnm_rows),
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Encodes `png` as `variant` for the registry
+fn write(
+    png: Png,
+    variant: Option<Variant>,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    image_data::encode(&png.to_image()?, variant, &options.netpbm)?.to_writer(writer)
+}
+
+fn write_pbm(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    write(png, Some(Variant::Bitmap), options, writer)
+}
+
+fn write_pgm(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    write(png, Some(Variant::Graymap), options, writer)
+}
+
+fn write_ppm(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    write(png, Some(Variant::Pixmap), options, writer)
+}
+
+fn write_pam(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    write(png, Some(Variant::Arbitrary), options, writer)
+}
+
+fn write_pnm(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    write(png, None, options, writer)
+}
+
+/// Reads a Netpbm file a row at a time for the registry
+fn read_netpbm_rows<'a>(
+    reader: &'a mut dyn Read,
+    _options: &ConvertOptions,
+) -> Result<Box<dyn RowSource + 'a>> {
+    image_data::decode_rows(reader)
+}
+
+/// Writes rows out as `variant` for the registry; Netpbm has nowhere to put metadata
+fn write_rows(
+    source: &mut dyn RowSource,
+    variant: Option<Variant>,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    image_data::encode_rows(source, variant, &options.netpbm, writer)
+}
+
+fn write_pbm_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    write_rows(source, Some(Variant::Bitmap), options, writer)
+}
+
+fn write_pgm_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    write_rows(source, Some(Variant::Graymap), options, writer)
+}
+
+fn write_ppm_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    write_rows(source, Some(Variant::Pixmap), options, writer)
+}
+
+fn write_pam_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    write_rows(source, Some(Variant::Arbitrary), options, writer)
+}
+
+fn write_pnm_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    write_rows(source, None, options, writer)
+}
+
+/// Describes a Netpbm file from its header for the registry
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let header = NetpbmHeader::read(&mut StreamReader::new("Netpbm", BufReader::new(reader)))?;
+    let layout = header.layout()?;
+    Ok(ImageInfo {
+        format: header.variant.name(),
+        width: header.width,
+        height: header.height,
+        color_model: match layout.color_model {
+            ColorModel::Gray => "gray",
+            ColorModel::GrayAlpha => "gray+alpha",
+            ColorModel::Rgb => "rgb",
+            ColorModel::Rgba => "rgba",
+        },
+        // the fewest bits that can hold the maxval
+        bit_depth: (u16::BITS - header.maxval.leading_zeros()) as u8,
+        compression: "none",
+        interlaced: false,
+        palette_size: None,
+        metadata: MetadataInfo::default(),
+        details: FormatDetails::Netpbm(NetpbmDetails {
+            magic: header.variant.magic(header.plain),
+            plain: header.plain,
+            maxval: header.maxval,
+            depth: header.depth,
+            tuple_type: header.tuple_type,
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// A PBM, PGM, PPM or PAM file
+pub struct Netpbm {
+    header: NetpbmHeader,
+    /// The raster as stored, binary or text
+    data: Vec<u8>,
+}
+
+impl Netpbm {
+    pub fn header(&self) -> &NetpbmHeader {
+        &self.header
+    }
+
+    pub fn image_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decodes the raster into an `Image`
+    pub fn to_image(&self) -> Result<Image> {
+        image_data::decode(self)
+    }
+
+    /// Encodes `image` as whichever of PGM, PPM and PAM holds it as it is
+    pub fn from_image(image: &Image) -> Result<Netpbm> {
+        image_data::encode(image, None, &NetpbmOptions::default())
+    }
+
+    /// Encodes `image` as `variant`, written as `options` asks
+    pub fn from_image_with(
+        image: &Image,
+        variant: Variant,
+        options: &NetpbmOptions,
+    ) -> Result<Netpbm> {
+        image_data::encode(image, Some(variant), options)
+    }
+}
+
+impl TryFrom<&[u8]> for Netpbm {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Netpbm::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Netpbm {
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("Netpbm", BufReader::new(reader));
+        let header = NetpbmHeader::read(&mut reader)?;
+        Ok(Netpbm {
+            header,
+            data: reader.read_rest()?,
+        })
+    }
+
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.header.as_bytes())?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Netpbm::from_image(&png.to_image()?)
+    }
+
+    fn to_png(&self) -> Result<Png> {
+        Png::from_image(&self.to_image()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Registry;
+
+    fn gradient(color_model: ColorModel, bit_depth: u8) -> Image {
+        let len = 5 * 3 * color_model.channels() * (bit_depth as usize / 8);
+        let data: Vec<u8> = (0..len).map(|idx| (idx * 37 % 256) as u8).collect();
+        Image::new(5, 3, color_model, bit_depth, data).unwrap()
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for (color_model, bit_depth, variant) in [
+            (ColorModel::Gray, 8, Variant::Graymap),
+            (ColorModel::Gray, 16, Variant::Graymap),
+            (ColorModel::Rgb, 8, Variant::Pixmap),
+            (ColorModel::Rgb, 16, Variant::Pixmap),
+            (ColorModel::GrayAlpha, 8, Variant::Arbitrary),
+            (ColorModel::Rgba, 16, Variant::Arbitrary),
+        ] {
+            let image = gradient(color_model, bit_depth);
+            for plain in [false, true] {
+                if plain && variant == Variant::Arbitrary {
+                    continue;
+                }
+                let options = NetpbmOptions { plain };
+                let bytes = Netpbm::from_image_with(&image, variant, &options)
+                    .unwrap()
+                    .to_bytes();
+                let decoded = Netpbm::try_from(bytes.as_slice())
+                    .unwrap()
+                    .to_image()
+                    .unwrap();
+                assert_eq!(decoded.layout(), image.layout());
+                assert_eq!(decoded.data(), image.data());
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_samples() {
+        // plain PBM digits can run together, and 1 is black
+        let pbm = Netpbm::try_from(&b"P1\n# tiny\n3 2\n010\n1 1 0"[..]).unwrap();
+        assert_eq!(pbm.to_image().unwrap().data(), [255, 0, 255, 0, 0, 255]);
+
+        let pbm =
+            Netpbm::try_from(&[b"P4\n10 1\n".as_slice(), &[0b1000_0000, 0b0100_0000]].concat()[..])
+                .unwrap();
+        let image = pbm.to_image().unwrap();
+        assert_eq!(image.data()[..2], [0, 255]);
+        assert_eq!(image.data()[9], 0);
+
+        // samples are scaled from the maxval up to the full range
+        let pgm = Netpbm::try_from(&b"P2 2 1 4 0 4"[..]).unwrap();
+        assert_eq!(pgm.to_image().unwrap().data(), [0, 255]);
+        let pgm = Netpbm::try_from(&b"P5 1 1 1000 \x03\xe8"[..]).unwrap();
+        assert_eq!(pgm.to_image().unwrap().data(), [0xff, 0xff]);
+
+        let too_bright = Netpbm::try_from(&b"P2 1 1 4 5"[..]).unwrap();
+        assert!(matches!(
+            too_bright.to_image(),
+            Err(Error::Malformed { .. })
+        ));
+        let short = Netpbm::try_from(&b"P6 2 2 255 \x01\x02\x03"[..]).unwrap();
+        assert!(short.to_image().is_err());
+    }
+
+    #[test]
+    fn test_write_variants() {
+        let black_and_white = Image::new(3, 1, ColorModel::Gray, 8, vec![0, 255, 0]).unwrap();
+        let pbm =
+            Netpbm::from_image_with(&black_and_white, Variant::Bitmap, &NetpbmOptions::default())
+                .unwrap();
+        assert_eq!(pbm.to_bytes(), b"P4\n3 1\n\xa0");
+        let plain = NetpbmOptions { plain: true };
+        let pbm = Netpbm::from_image_with(&black_and_white, Variant::Bitmap, &plain).unwrap();
+        assert_eq!(pbm.to_bytes(), b"P1\n3 1\n1 0 1\n");
+
+        let gray = gradient(ColorModel::Gray, 8);
+        assert!(matches!(
+            Netpbm::from_image_with(&gray, Variant::Bitmap, &plain),
+            Err(Error::LossyConversion(_))
+        ));
+        let color = gradient(ColorModel::Rgb, 8);
+        assert!(matches!(
+            Netpbm::from_image_with(&color, Variant::Graymap, &plain),
+            Err(Error::LossyConversion(_))
+        ));
+        let alpha = gradient(ColorModel::Rgba, 8);
+        assert!(matches!(
+            Netpbm::from_image_with(&alpha, Variant::Pixmap, &plain),
+            Err(Error::LossyConversion(_))
+        ));
+        assert!(Netpbm::from_image_with(&alpha, Variant::Arbitrary, &plain).is_err());
+        assert_eq!(
+            Netpbm::from_image(&alpha).unwrap().header().variant,
+            Variant::Arbitrary
+        );
+        assert_eq!(
+            Netpbm::from_image(&gray).unwrap().header().variant,
+            Variant::Graymap
+        );
+
+        // long rows of plain samples are broken into lines of at most 70 characters
+        let wide = Image::new(40, 1, ColorModel::Gray, 8, vec![200; 40]).unwrap();
+        let bytes = Netpbm::from_image_with(&wide, Variant::Graymap, &plain)
+            .unwrap()
+            .to_bytes();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.lines().all(|line| line.len() <= 70));
+    }
+
+    #[test]
+    fn test_pam_tuple_types() {
+        let bytes = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE_ALPHA\nENDHDR\n\x01\x00";
+        let image = Netpbm::try_from(&bytes[..]).unwrap().to_image().unwrap();
+        assert_eq!(image.color_model(), ColorModel::GrayAlpha);
+        assert_eq!(image.data(), [255, 0]);
+
+        // a tuple type this crate doesn't know is read by its depth
+        let bytes = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE SOMETHING_ELSE\nENDHDR\n\x01\x02\x03";
+        let image = Netpbm::try_from(&bytes[..]).unwrap().to_image().unwrap();
+        assert_eq!(image.color_model(), ColorModel::Rgb);
+
+        let bytes = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\nENDHDR\n\x01\x02\x03\x04\x05";
+        assert!(matches!(
+            Netpbm::try_from(&bytes[..]).unwrap().to_image(),
+            Err(Error::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::default();
+        assert_eq!(registry.sniff(b"P6\n1 1\n255\n").unwrap().name, "PPM");
+        assert_eq!(registry.by_extension("pnm").unwrap().name, "PNM");
+        let detection = registry
+            .detect(std::path::Path::new("scan.pnm"), b"P5\n1 1\n255\n")
+            .unwrap();
+        assert_eq!(detection.codec.name, "PNM");
+        assert!(detection.extension_mismatch.is_none());
+    }
+}
@@ -0,0 +1,322 @@
+use std::io::BufRead;
+
+use crate::{
+    byte_reader::StreamReader,
+    image::ColorModel,
+    scanline::{RowLayout, MAX_ROW_LEN},
+    Error, Result,
+};
+
+/// The Netpbm formats, told apart by the magic number a file starts with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    /// PBM: black and white, P1 or P4
+    Bitmap,
+    /// PGM: grayscale, P2 or P5
+    Graymap,
+    /// PPM: RGB, P3 or P6
+    Pixmap,
+    /// PAM: any number of channels described by a tuple type, P7
+    Arbitrary,
+}
+
+impl Variant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Bitmap => "PBM",
+            Variant::Graymap => "PGM",
+            Variant::Pixmap => "PPM",
+            Variant::Arbitrary => "PAM",
+        }
+    }
+
+    /// The magic number for this variant stored as `plain` decimal text or as binary
+    pub fn magic(&self, plain: bool) -> &'static str {
+        match (self, plain) {
+            (Variant::Bitmap, true) => "P1",
+            (Variant::Graymap, true) => "P2",
+            (Variant::Pixmap, true) => "P3",
+            (Variant::Bitmap, false) => "P4",
+            (Variant::Graymap, false) => "P5",
+            (Variant::Pixmap, false) => "P6",
+            (Variant::Arbitrary, _) => "P7",
+        }
+    }
+}
+
+/// Everything ahead of a Netpbm file's raster
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetpbmHeader {
+    pub variant: Variant,
+    /// Samples are written as decimal text (P1 to P3) rather than binary
+    pub plain: bool,
+    pub width: u32,
+    pub height: u32,
+    /// Channels per pixel: 1 for PBM and PGM, 3 for PPM, anything for PAM
+    pub depth: u32,
+    /// The value of a full-intensity sample, up to 65535; always 1 for PBM
+    pub maxval: u16,
+    /// What PAM's channels mean, such as RGB_ALPHA
+    pub tuple_type: Option<String>,
+}
+
+/// Longest PAM header line read before giving up on the file
+const MAX_LINE_LENGTH: usize = 1024;
+
+impl NetpbmHeader {
+    /// Reads the header, leaving `reader` at the first byte of the raster
+    pub fn read<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Self> {
+        let magic: [u8; 2] = reader.read_array()?;
+        let (variant, plain) = match &magic {
+            b"P1" => (Variant::Bitmap, true),
+            b"P2" => (Variant::Graymap, true),
+            b"P3" => (Variant::Pixmap, true),
+            b"P4" => (Variant::Bitmap, false),
+            b"P5" => (Variant::Graymap, false),
+            b"P6" => (Variant::Pixmap, false),
+            b"P7" => return NetpbmHeader::read_pam(reader),
+            _ => return Err(Error::malformed_at("Netpbm", 0, "invalid magic number")),
+        };
+        let width = read_number(reader, "width")?;
+        let height = read_number(reader, "height")?;
+        let maxval = match variant {
+            Variant::Bitmap => 1,
+            _ => {
+                let maxval = read_number(reader, "maxval")?;
+                read_maxval(reader, maxval)?
+            }
+        };
+        // a single whitespace character separates the header from the raster
+        if !reader.read_u8()?.is_ascii_whitespace() {
+            return Err(reader.error("the header doesn't end in whitespace"));
+        }
+        let header = NetpbmHeader {
+            variant,
+            plain,
+            width,
+            height,
+            depth: if variant == Variant::Pixmap { 3 } else { 1 },
+            maxval,
+            tuple_type: None,
+        };
+        header.validate(reader)?;
+        Ok(header)
+    }
+
+    /// Reads the lines of a PAM header that follow its magic number
+    fn read_pam<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Self> {
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+        let mut tuple_type: Option<String> = None;
+        loop {
+            let line = read_line(reader)?;
+            let line = line.trim();
+            let (keyword, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            let number = |name: &str| {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| reader.error(format!("{name} \"{value}\" is not a number")))
+            };
+            match keyword {
+                "" => {}
+                _ if keyword.starts_with('#') => {}
+                "ENDHDR" => break,
+                "WIDTH" => width = Some(number("WIDTH")?),
+                "HEIGHT" => height = Some(number("HEIGHT")?),
+                "DEPTH" => depth = Some(number("DEPTH")?),
+                "MAXVAL" => maxval = Some(read_maxval(reader, number("MAXVAL")?)?),
+                // repeated TUPLTYPE lines add to the type, separated by a space
+                "TUPLTYPE" => {
+                    tuple_type = Some(match tuple_type {
+                        Some(earlier) => format!("{earlier} {value}"),
+                        None => value.to_string(),
+                    })
+                }
+                _ => return Err(reader.error(format!("unknown PAM header line \"{line}\""))),
+            }
+        }
+        let missing = |name: &str| reader.error(format!("the PAM header has no {name} line"));
+        let header = NetpbmHeader {
+            variant: Variant::Arbitrary,
+            plain: false,
+            width: width.ok_or_else(|| missing("WIDTH"))?,
+            height: height.ok_or_else(|| missing("HEIGHT"))?,
+            depth: depth.ok_or_else(|| missing("DEPTH"))?,
+            maxval: maxval.ok_or_else(|| missing("MAXVAL"))?,
+            tuple_type,
+        };
+        header.validate(reader)?;
+        Ok(header)
+    }
+
+    fn validate<R: BufRead>(&self, reader: &StreamReader<R>) -> Result<()> {
+        if self.width == 0 || self.height == 0 || self.depth == 0 {
+            return Err(reader.error(format!(
+                "{}x{} with {} channels is not a valid image size",
+                self.width, self.height, self.depth
+            )));
+        }
+        Ok(())
+    }
+
+    /// The header as it's written to a file
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let magic = self.variant.magic(self.plain);
+        let text = match self.variant {
+            Variant::Bitmap => format!("{magic}\n{} {}\n", self.width, self.height),
+            Variant::Graymap | Variant::Pixmap => {
+                format!("{magic}\n{} {}\n{}\n", self.width, self.height, self.maxval)
+            }
+            Variant::Arbitrary => {
+                let tuple_type = match &self.tuple_type {
+                    Some(tuple_type) => format!("TUPLTYPE {tuple_type}\n"),
+                    None => String::new(),
+                };
+                format!(
+                    "{magic}\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\n{tuple_type}ENDHDR\n",
+                    self.width, self.height, self.depth, self.maxval
+                )
+            }
+        };
+        text.into_bytes()
+    }
+
+    /// Bytes each binary sample takes up
+    pub fn sample_len(&self) -> usize {
+        if self.maxval > 255 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// How the samples are laid out once read. Samples are scaled up to 8 or 16 bits, and
+    /// PAM files are read by their depth; tuple types this crate doesn't know of don't matter.
+    pub fn layout(&self) -> Result<RowLayout> {
+        let color_model = match self.depth {
+            1 => ColorModel::Gray,
+            2 => ColorModel::GrayAlpha,
+            3 => ColorModel::Rgb,
+            4 => ColorModel::Rgba,
+            depth => {
+                return Err(Error::unsupported(
+                    self.variant.name(),
+                    format!("{depth}-channel tuples"),
+                ))
+            }
+        };
+        let layout = RowLayout {
+            width: self.width,
+            height: self.height,
+            color_model,
+            bit_depth: if self.maxval > 255 { 16 } else { 8 },
+        };
+        if layout.row_len() > MAX_ROW_LEN {
+            return Err(Error::malformed(
+                self.variant.name(),
+                format!("rows {} pixels wide are too long to read", self.width),
+            ));
+        }
+        Ok(layout)
+    }
+}
+
+/// Moves past whitespace and comments, which run from a # to the end of the line
+pub fn skip_space<R: BufRead>(reader: &mut StreamReader<R>) -> Result<()> {
+    let mut in_comment = false;
+    while let Some(byte) = reader.peek_u8()? {
+        match byte {
+            b'\n' | b'\r' => in_comment = false,
+            b'#' => in_comment = true,
+            _ if in_comment || byte.is_ascii_whitespace() => {}
+            _ => return Ok(()),
+        }
+        reader.read_u8()?;
+    }
+    Ok(())
+}
+
+/// Reads a decimal number written as text, after any whitespace and comments
+pub fn read_number<R: BufRead>(reader: &mut StreamReader<R>, what: &str) -> Result<u32> {
+    skip_space(reader)?;
+    let mut value: Option<u32> = None;
+    while let Some(byte @ b'0'..=b'9') = reader.peek_u8()? {
+        value = value
+            .unwrap_or(0)
+            .checked_mul(10)
+            .and_then(|value| value.checked_add((byte - b'0') as u32))
+            .map(Some)
+            .ok_or_else(|| reader.error(format!("the {what} is too large")))?;
+        reader.read_u8()?;
+    }
+    value.ok_or_else(|| reader.error(format!("expected the {what} as a number")))
+}
+
+fn read_maxval<R: BufRead>(reader: &StreamReader<R>, maxval: u32) -> Result<u16> {
+    match maxval {
+        1..=65535 => Ok(maxval as u16),
+        _ => Err(reader.error(format!(
+            "maxval {maxval} is outside the allowed range of 1 to 65535"
+        ))),
+    }
+}
+
+/// Reads up to the end of the line, for PAM headers
+fn read_line<R: BufRead>(reader: &mut StreamReader<R>) -> Result<String> {
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        match reader.read_u8()? {
+            b'\n' => break,
+            _ if line.len() == MAX_LINE_LENGTH => {
+                return Err(reader.error("PAM header line is too long"))
+            }
+            byte => line.push(byte),
+        }
+    }
+    String::from_utf8(line).map_err(|_| reader.error("PAM header line is not text"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Result<NetpbmHeader> {
+        NetpbmHeader::read(&mut StreamReader::new("Netpbm", bytes))
+    }
+
+    #[test]
+    fn test_read_header() {
+        let header = read(b"P5 # a comment\n3\n# another\n 2 1000\n\x01").unwrap();
+        assert_eq!(header.variant, Variant::Graymap);
+        assert_eq!((header.width, header.height, header.maxval), (3, 2, 1000));
+        assert_eq!(header.sample_len(), 2);
+
+        let header = read(b"P1\n4 4\n0101").unwrap();
+        assert!(header.plain);
+        assert_eq!(header.maxval, 1);
+
+        assert!(read(b"P6\n4 4\n0\n").is_err());
+        assert!(read(b"P6\n4 4\n65536\n").is_err());
+        assert!(read(b"P6\n0 4\n255\n").is_err());
+        assert!(read(b"P6\n99999999999 4\n255\n").is_err());
+        assert!(read(b"P9\n4 4\n255\n").is_err());
+        let forged = read(b"P6\n2000000000 2000000000\n255\n").unwrap();
+        assert!(matches!(forged.layout(), Err(Error::Malformed { .. })));
+    }
+
+    #[test]
+    fn test_pam_header() {
+        let bytes =
+            b"P7\nWIDTH 2\nHEIGHT 1\n# comment\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB\nTUPLTYPE _ALPHA\nENDHDR\n";
+        let header = read(bytes).unwrap();
+        assert_eq!(header.tuple_type.as_deref(), Some("RGB _ALPHA"));
+        assert_eq!(header.layout().unwrap().color_model, ColorModel::Rgba);
+
+        let written = NetpbmHeader {
+            tuple_type: Some("RGB_ALPHA".to_string()),
+            ..header
+        };
+        assert_eq!(read(&written.as_bytes()).unwrap(), written);
+        assert!(read(b"P7\nWIDTH 2\nHEIGHT 1\nMAXVAL 255\nENDHDR\n").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    image::{ColorModel, Image},
+    metadata::Metadata,
+    scanline::{read_image, ImageRows, RowLayout, RowSource},
+    Error, Result,
+};
+
+use super::{
+    header::{read_number, skip_space, NetpbmHeader, Variant},
+    options::NetpbmOptions,
+    Netpbm,
+};
+
+/// Longest line written in the plain formats, as the Netpbm specification asks
+const MAX_PLAIN_LINE: usize = 70;
+
+/// Decodes the raster of `netpbm` into an `Image`
+pub fn decode(netpbm: &Netpbm) -> Result<Image> {
+    let mut rows = NetpbmRows::new(
+        netpbm.header.clone(),
+        StreamReader::new("Netpbm", &netpbm.data[..]),
+    )?;
+    read_image(&mut rows)
+}
+
+/// Reads a Netpbm file from `reader` a row at a time; nothing has to be held back, as rows
+/// are stored from the top with no compression
+pub fn decode_rows(reader: &mut dyn Read) -> Result<Box<dyn RowSource + '_>> {
+    let mut reader = StreamReader::new("Netpbm", BufReader::new(reader));
+    let header = NetpbmHeader::read(&mut reader)?;
+    Ok(Box::new(NetpbmRows::new(header, reader)?))
+}
+
+/// Reads the size of a Netpbm file from its header
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    NetpbmHeader::read(&mut StreamReader::new("Netpbm", BufReader::new(reader)))?.layout()
+}
+
+/// Hands out the rows of a Netpbm raster, scaling samples up to 8 or 16 bits as it goes
+struct NetpbmRows<R> {
+    header: NetpbmHeader,
+    layout: RowLayout,
+    reader: StreamReader<R>,
+    /// One row as stored, for the binary formats
+    raw: Vec<u8>,
+    metadata: Metadata,
+}
+
+impl<R: BufRead> NetpbmRows<R> {
+    fn new(header: NetpbmHeader, reader: StreamReader<R>) -> Result<Self> {
+        let layout = header.layout()?;
+        let raw_len = match (header.variant, header.plain) {
+            (_, true) => 0,
+            (Variant::Bitmap, false) => (layout.width as usize).div_ceil(8),
+            (_, false) => layout.width as usize * header.depth as usize * header.sample_len(),
+        };
+        Ok(NetpbmRows {
+            header,
+            layout,
+            reader,
+            raw: vec![0; raw_len],
+            metadata: Metadata::default(),
+        })
+    }
+
+    /// Stores `value` as sample `idx` of `row`, scaled from the file's maxval to the
+    /// row's bit depth
+    fn put_sample(&self, row: &mut [u8], idx: usize, value: u32) -> Result<()> {
+        let maxval = self.header.maxval as u32;
+        if value > maxval {
+            return Err(self.reader.error(format!(
+                "sample {value} is larger than the maxval of {maxval}"
+            )));
+        }
+        match self.layout.bit_depth {
+            16 => {
+                let value = (value * 65535 + maxval / 2) / maxval;
+                row[idx * 2..idx * 2 + 2].copy_from_slice(&(value as u16).to_be_bytes());
+            }
+            _ => row[idx] = ((value * 255 + maxval / 2) / maxval) as u8,
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> RowSource for NetpbmRows<R> {
+    fn layout(&self) -> RowLayout {
+        self.layout
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        let samples = self.layout.width as usize * self.header.depth as usize;
+        match (self.header.variant, self.header.plain) {
+            // PBM stores 1 for black, the other way round from every other format
+            (Variant::Bitmap, false) => {
+                self.reader.fill(&mut self.raw)?;
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let bit = self.raw[x / 8] >> (7 - x % 8) & 1;
+                    *pixel = if bit == 1 { 0 } else { 255 };
+                }
+            }
+            // plain PBM digits don't need whitespace between them
+            (Variant::Bitmap, true) => {
+                for pixel in row.iter_mut() {
+                    skip_space(&mut self.reader)?;
+                    *pixel = match self.reader.read_u8()? {
+                        b'0' => 255,
+                        b'1' => 0,
+                        _ => return Err(self.reader.error("PBM pixels have to be 0 or 1")),
+                    };
+                }
+            }
+            (_, true) => {
+                for idx in 0..samples {
+                    let value = read_number(&mut self.reader, "sample")?;
+                    self.put_sample(row, idx, value)?;
+                }
+            }
+            (_, false) => {
+                self.reader.fill(&mut self.raw)?;
+                for idx in 0..samples {
+                    let value = match self.header.sample_len() {
+                        2 => u16::from_be_bytes([self.raw[idx * 2], self.raw[idx * 2 + 1]]) as u32,
+                        _ => self.raw[idx] as u32,
+                    };
+                    self.put_sample(row, idx, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Works out the header for writing an image laid out as `layout` in `variant`, or in
+/// whichever of PGM, PPM and PAM fits it best if no variant is given. Anything the variant
+/// can't hold, like alpha outside PAM, is refused here; colors that don't fit are only
+/// found as the rows are written.
+pub fn header_for(
+    layout: RowLayout,
+    variant: Option<Variant>,
+    options: &NetpbmOptions,
+) -> Result<NetpbmHeader> {
+    let variant = variant.unwrap_or(match layout.color_model {
+        ColorModel::Gray => Variant::Graymap,
+        ColorModel::Rgb => Variant::Pixmap,
+        ColorModel::GrayAlpha | ColorModel::Rgba => Variant::Arbitrary,
+    });
+    if variant == Variant::Arbitrary && options.plain {
+        return Err(Error::invalid_argument("PAM has no plain (text) form"));
+    }
+    if variant != Variant::Arbitrary && layout.color_model.has_alpha() {
+        return Err(Error::lossy(format!(
+            "{} has no alpha channel; flatten the image or write PAM instead",
+            variant.name()
+        )));
+    }
+    let (depth, tuple_type) = match (variant, layout.color_model) {
+        (Variant::Bitmap | Variant::Graymap, _) => (1, None),
+        (Variant::Pixmap, _) => (3, None),
+        (Variant::Arbitrary, ColorModel::Gray) => (1, Some("GRAYSCALE")),
+        (Variant::Arbitrary, ColorModel::GrayAlpha) => (2, Some("GRAYSCALE_ALPHA")),
+        (Variant::Arbitrary, ColorModel::Rgb) => (3, Some("RGB")),
+        (Variant::Arbitrary, ColorModel::Rgba) => (4, Some("RGB_ALPHA")),
+    };
+    Ok(NetpbmHeader {
+        variant,
+        plain: options.plain,
+        width: layout.width,
+        height: layout.height,
+        depth,
+        maxval: match (variant, layout.bit_depth) {
+            (Variant::Bitmap, _) => 1,
+            (_, 16) => 65535,
+            _ => 255,
+        },
+        tuple_type: tuple_type.map(str::to_string),
+    })
+}
+
+/// Encodes `image` as a new `Netpbm`
+pub fn encode(image: &Image, variant: Option<Variant>, options: &NetpbmOptions) -> Result<Netpbm> {
+    let header = header_for(image.layout(), variant, options)?;
+    let mut data: Vec<u8> = Vec::new();
+    encode_raster(&mut ImageRows::new(image.clone()), &header, &mut data)?;
+    Ok(Netpbm { header, data })
+}
+
+/// Writes the rows of `source` out as a Netpbm file, header first
+pub fn encode_rows(
+    source: &mut dyn RowSource,
+    variant: Option<Variant>,
+    options: &NetpbmOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let header = header_for(source.layout(), variant, options)?;
+    writer.write_all(&header.as_bytes())?;
+    encode_raster(source, &header, writer)
+}
+
+/// Writes the rows of `source` as the raster `header` describes
+fn encode_raster(
+    source: &mut dyn RowSource,
+    header: &NetpbmHeader,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let layout = source.layout();
+    let mut row: Vec<u8> = vec![0; layout.row_len()];
+    let mut samples: Vec<u16> = Vec::with_capacity(layout.width as usize * header.depth as usize);
+    let mut out: Vec<u8> = Vec::new();
+    for _ in 0..layout.height {
+        source.read_row(&mut row)?;
+        samples.clear();
+        out.clear();
+        for x in 0..layout.width as usize {
+            pixel_samples(&layout, header, &row, x, &mut samples)?;
+        }
+        match (header.variant, header.plain) {
+            (Variant::Bitmap, false) => {
+                out.resize((layout.width as usize).div_ceil(8), 0);
+                for (x, &sample) in samples.iter().enumerate() {
+                    out[x / 8] |= (sample as u8) << (7 - x % 8);
+                }
+            }
+            (_, false) => {
+                for &sample in &samples {
+                    match header.sample_len() {
+                        2 => out.extend(sample.to_be_bytes()),
+                        _ => out.push(sample as u8),
+                    }
+                }
+            }
+            (_, true) => write_plain_row(&samples, &mut out),
+        }
+        writer.write_all(&out)?;
+    }
+    Ok(())
+}
+
+/// Adds the samples `header` stores for pixel `x` of `row`, failing for colors the
+/// variant can't hold
+fn pixel_samples(
+    layout: &RowLayout,
+    header: &NetpbmHeader,
+    row: &[u8],
+    x: usize,
+    samples: &mut Vec<u16>,
+) -> Result<()> {
+    let channels = layout.color_model.channels();
+    let sample = |channel: usize| layout.sample(row, x * channels + channel);
+    match (header.variant, layout.color_model) {
+        (Variant::Bitmap, _) => {
+            let [red, green, blue, _] = layout.rgba8(row, x);
+            match (red, green, blue) {
+                (0, 0, 0) => samples.push(1),
+                (255, 255, 255) => samples.push(0),
+                _ => {
+                    return Err(Error::lossy(
+                        "PBM only holds black and white, and the image has other colors",
+                    ))
+                }
+            }
+        }
+        (Variant::Graymap, ColorModel::Rgb) => {
+            if sample(0) != sample(1) || sample(1) != sample(2) {
+                return Err(Error::lossy(
+                    "PGM only holds shades of gray, and the image has other colors",
+                ));
+            }
+            samples.push(sample(0));
+        }
+        (Variant::Pixmap, ColorModel::Gray) => samples.extend([sample(0); 3]),
+        _ => samples.extend((0..channels).map(sample)),
+    }
+    Ok(())
+}
+
+/// Appends `samples` as decimal text, breaking lines before they grow too long
+fn write_plain_row(samples: &[u16], out: &mut Vec<u8>) {
+    let mut line_len = 0;
+    for sample in samples {
+        let text = sample.to_string();
+        if line_len > 0 && line_len + 1 + text.len() > MAX_PLAIN_LINE {
+            out.push(b'\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            out.push(b' ');
+            line_len += 1;
+        }
+        out.extend(text.as_bytes());
+        line_len += text.len();
+    }
+    out.push(b'\n');
+}
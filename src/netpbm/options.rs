@@ -0,0 +1,7 @@
+/// How Netpbm output is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetpbmOptions {
+    /// Write samples as decimal text (P1 to P3) instead of binary. The files are several
+    /// times larger, but can be read and edited by hand. PAM has no plain form.
+    pub plain: bool,
+}
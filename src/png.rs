@@ -1,11 +1,145 @@
+pub mod animation;
+pub mod background;
 pub mod chunk;
 pub mod chunk_type;
+pub mod histogram;
+pub mod icc_profile;
+pub mod idat_writer;
+pub mod image_data;
+pub mod image_header;
+pub mod options;
+pub mod physical_dimensions;
+pub mod significant_bits;
+pub mod suggested_palette;
+pub mod time;
+
+use std::io::{BufReader, Read, Write};
+
+use crate::{
+    animation::Animation,
+    byte_reader::StreamReader,
+    convert::ConvertOptions,
+    exif::Exif,
+    format::{decode_with, Codec},
+    image::{Color, Image},
+    info::{ChunkInfo, FormatDetails, ImageInfo, MetadataInfo, PngDetails},
+    metadata::Metadata,
+    scanline::RowSource,
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    background::Background,
+    chunk::Chunk,
+    chunk_type::PngChunkType,
+    histogram::Histogram,
+    icc_profile::IccProfile,
+    idat_writer::IdatWriter,
+    image_header::{ColorType, ImageHeader},
+    options::PngOptions,
+    physical_dimensions::PhysicalDimensions,
+    significant_bits::SignificantBits,
+    suggested_palette::SuggestedPalette,
+    time::Time,
+};
+
+/// Registry entry for PNG files
+pub const CODEC: Codec = Codec {
+    name: "PNG",
+    extensions: &["png"],
+    magic: &[&Png::STANDARD_HEADER],
+    check_signature: None,
+    decoder: Some(decode_with::<Png>),
+    encoder: Some(write_png),
+    row_decoder: Some(read_png_rows),
+    row_encoder: Some(write_png_rows),
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a PNG from its chunks for the registry. IDAT chunks are only listed: their data
+/// is skipped over unread, so their CRCs aren't checked.
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let mut reader = StreamReader::new("PNG", BufReader::new(reader));
+    if reader.read_array::<8>()? != Png::STANDARD_HEADER {
+        return Err(Error::malformed_at("PNG", 0, "invalid signature"));
+    }
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut listing: Vec<ChunkInfo> = Vec::new();
+    while !reader.is_empty()? {
+        let (length, chunk_type) = Chunk::read_header(&mut reader)?;
+        listing.push(ChunkInfo::new(&chunk_type, length));
+        match chunk_type == PngChunkType::IDAT {
+            true => reader.skip(length as usize + 4)?,
+            false => chunks.push(Chunk::read_data(&mut reader, length, chunk_type)?),
+        }
+    }
+    let png = Png::from_chunks(chunks);
+    let header = png.image_header()?;
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: header.width(),
+        height: header.height(),
+        color_model: match header.color_type() {
+            ColorType::Grayscale => "gray",
+            ColorType::GrayscaleAlpha => "gray+alpha",
+            ColorType::Truecolor => "rgb",
+            ColorType::TruecolorAlpha => "rgba",
+            ColorType::Indexed => "indexed",
+        },
+        bit_depth: header.bit_depth(),
+        compression: "deflate",
+        interlaced: header.interlaced(),
+        palette_size: png
+            .chunk_by_type("PLTE")
+            .map(|chunk| chunk.data().len() / 3),
+        metadata: MetadataInfo::from(&png.metadata()?),
+        details: FormatDetails::Png(PngDetails {
+            chunks: listing,
+            modified: png.modification_time()?.map(|time| time.to_string()),
+        }),
+        warnings: Vec::new(),
+    })
+}
 
-use std::{fs, path::Path};
+/// Writes `png` out for the registry, re-encoding its image data first if the options ask to
+fn write_png(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    let png = match options.png.reencodes() {
+        true => {
+            let mut png = match png.to_animation()? {
+                Some(animation) => Png::from_animation(&animation, &options.png)?,
+                None => Png::from_image_with(&png.to_image()?, &options.png)?,
+            };
+            if options.update_time {
+                png.touch()?;
+            }
+            png
+        }
+        false => png,
+    };
+    png.write_to(writer, options.png.max_idat_size)
+}
 
-use crate::{ConvertibleImage, Error, Result};
+/// Reads a PNG file a row at a time for the registry
+fn read_png_rows<'a>(
+    reader: &'a mut dyn Read,
+    _options: &ConvertOptions,
+) -> Result<Box<dyn RowSource + 'a>> {
+    image_data::decode_rows(reader)
+}
 
-use self::chunk::Chunk;
+/// Writes rows out as a PNG for the registry. The time is only recorded when the pixels
+/// change, matching `write_png`.
+fn write_png_rows(
+    source: &mut dyn RowSource,
+    metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let touch = options.update_time
+        && (options.flatten_alpha || options.reduce_depth || options.png.reencodes());
+    image_data::encode_rows(source, metadata, &options.png, touch, writer)
+}
 
 /// based on the design used in the PNGMe tutorial, which can be found at <https://picklenerd.github.io/pngme_book/>
 #[derive(Clone, Debug)]
@@ -22,13 +156,258 @@ impl Png {
 
     /// Lists the `Chunk`s stored in this `Png`
     pub fn chunks(&self) -> &[Chunk] {
-        &self.chunks.as_slice()
+        self.chunks.as_slice()
     }
 
     pub fn header_chunk(&self) -> Option<&Chunk> {
         self.chunk_by_type("IHDR")
     }
 
+    pub fn image_header(&self) -> Result<ImageHeader> {
+        match self.header_chunk() {
+            Some(chunk) => ImageHeader::try_from(chunk.data()),
+            None => Err(Error::malformed("PNG", "no IHDR chunk")),
+        }
+    }
+
+    /// Decodes this `Png`'s image data
+    pub fn to_image(&self) -> Result<Image> {
+        image_data::decode(self)
+    }
+
+    /// Encodes `image` as a new `Png`, including the metadata it carries
+    pub fn from_image(image: &Image) -> Result<Png> {
+        image_data::encode(image, &PngOptions::default())
+    }
+
+    /// Encodes `image` as a new `Png` laid out as `options` asks
+    pub fn from_image_with(image: &Image, options: &PngOptions) -> Result<Png> {
+        image_data::encode(image, options)
+    }
+
+    /// Whether this `Png` has an acTL chunk, making it an APNG
+    pub fn is_animated(&self) -> bool {
+        self.chunk_by_type("acTL").is_some()
+    }
+
+    /// Decodes every frame of an animated PNG; returns `None` if this `Png` isn't animated
+    pub fn to_animation(&self) -> Result<Option<Animation>> {
+        animation::decode(self)
+    }
+
+    /// Encodes `animation` as a new animated `Png` laid out as `options` asks
+    pub fn from_animation(animation: &Animation, options: &PngOptions) -> Result<Png> {
+        animation::encode(animation, options)
+    }
+
+    /// Collects the metadata stored in this `Png`'s ancillary chunks
+    pub fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::default();
+        if let Some(chunk) = self.chunk_by_type("eXIf") {
+            metadata.set_exif(Some(Exif::try_from(chunk.data())?));
+        }
+        if let Some(background) = self.background()? {
+            let palette = self.chunk_by_type("PLTE").map(|chunk| chunk.data());
+            let bit_depth = self.image_header()?.bit_depth();
+            metadata.set_background(background.to_color(bit_depth, palette));
+        }
+        if let Some(dimensions) = self.physical_dimensions()? {
+            metadata.set_resolution(Some(dimensions.to_resolution()));
+        }
+        if let Some(icc) = self.ancillary_chunk::<IccProfile>("iCCP")? {
+            metadata.set_icc_profile(Some(icc.profile));
+        }
+        Ok(metadata)
+    }
+
+    /// Replaces this `Png`'s metadata chunks with ones describing `metadata`
+    pub fn set_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.remove_chunks(&PngChunkType::EXIF);
+        if let Some(exif) = metadata.exif() {
+            let chunk = Chunk::new(PngChunkType::EXIF, exif.as_bytes().to_vec())?;
+            self.insert_ancillary_chunk(chunk);
+        }
+        let background = match metadata.background() {
+            Some(color) => self.background_for(color)?,
+            None => None,
+        };
+        self.set_background(background)?;
+        self.replace_ancillary_chunk(
+            PngChunkType::ICCP,
+            metadata
+                .icc_profile()
+                .map(|profile| IccProfile::new(profile.to_vec()).as_bytes())
+                .transpose()?,
+        )?;
+        self.set_physical_dimensions(
+            metadata
+                .resolution()
+                .and_then(PhysicalDimensions::from_resolution),
+        )
+    }
+
+    /// Finds how `color` would be written in this `Png`'s bKGD chunk, if it can be at all
+    fn background_for(&self, color: Color) -> Result<Option<Background>> {
+        let header = self.image_header()?;
+        let scale = |channel: u16| {
+            let max = (1_u32 << header.bit_depth()) - 1;
+            ((channel as u32 * max + 32767) / 65535) as u16
+        };
+        Ok(match header.color_type() {
+            ColorType::Indexed => self.chunk_by_type("PLTE").and_then(|palette| {
+                palette
+                    .data()
+                    .chunks_exact(3)
+                    .position(|entry| Color::from_rgb8(entry[0], entry[1], entry[2]) == color)
+                    .map(|idx| Background::PaletteIndex(idx as u8))
+            }),
+            ColorType::Grayscale | ColorType::GrayscaleAlpha if color.is_gray() => {
+                Some(Background::Gray(scale(color.red)))
+            }
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => None,
+            ColorType::Truecolor | ColorType::TruecolorAlpha => Some(Background::Rgb(
+                scale(color.red),
+                scale(color.green),
+                scale(color.blue),
+            )),
+        })
+    }
+
+    pub fn background(&self) -> Result<Option<Background>> {
+        self.ancillary_chunk("bKGD")
+    }
+
+    pub fn set_background(&mut self, background: Option<Background>) -> Result<()> {
+        self.replace_ancillary_chunk(PngChunkType::BKGD, background.map(|bkgd| bkgd.as_bytes()))
+    }
+
+    pub fn physical_dimensions(&self) -> Result<Option<PhysicalDimensions>> {
+        self.ancillary_chunk("pHYs")
+    }
+
+    pub fn set_physical_dimensions(
+        &mut self,
+        dimensions: Option<PhysicalDimensions>,
+    ) -> Result<()> {
+        self.replace_ancillary_chunk(PngChunkType::PHYS, dimensions.map(|phys| phys.as_bytes()))
+    }
+
+    pub fn significant_bits(&self) -> Result<Option<SignificantBits>> {
+        self.ancillary_chunk("sBIT")
+    }
+
+    pub fn set_significant_bits(&mut self, bits: Option<SignificantBits>) -> Result<()> {
+        self.replace_ancillary_chunk(PngChunkType::SBIT, bits.map(|sbit| sbit.as_bytes()))
+    }
+
+    pub fn histogram(&self) -> Result<Option<Histogram>> {
+        self.ancillary_chunk("hIST")
+    }
+
+    pub fn set_histogram(&mut self, histogram: Option<Histogram>) -> Result<()> {
+        self.replace_ancillary_chunk(PngChunkType::HIST, histogram.map(|hist| hist.as_bytes()))
+    }
+
+    pub fn modification_time(&self) -> Result<Option<Time>> {
+        self.ancillary_chunk("tIME")
+    }
+
+    pub fn set_modification_time(&mut self, time: Option<Time>) -> Result<()> {
+        self.replace_ancillary_chunk(PngChunkType::TIME, time.map(|time| time.as_bytes()))
+    }
+
+    /// Stamps this `Png` with the current time as its last modification
+    pub fn touch(&mut self) -> Result<()> {
+        self.set_modification_time(Some(Time::now()))
+    }
+
+    /// Lists every sPLT chunk; unlike the other ancillary chunks, a `Png` may hold several
+    pub fn suggested_palettes(&self) -> Result<Vec<SuggestedPalette>> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type() == &PngChunkType::SPLT)
+            .map(|chunk| SuggestedPalette::try_from(chunk.data()))
+            .collect()
+    }
+
+    pub fn set_suggested_palettes(&mut self, palettes: &[SuggestedPalette]) -> Result<()> {
+        self.remove_chunks(&PngChunkType::SPLT);
+        for palette in palettes {
+            self.insert_ancillary_chunk(Chunk::new(PngChunkType::SPLT, palette.as_bytes())?);
+        }
+        Ok(())
+    }
+
+    fn ancillary_chunk<'a, T>(&'a self, chunk_type: &str) -> Result<Option<T>>
+    where
+        T: TryFrom<&'a [u8], Error = Error>,
+    {
+        self.chunk_by_type(chunk_type)
+            .map(|chunk| T::try_from(chunk.data()))
+            .transpose()
+    }
+
+    fn replace_ancillary_chunk(
+        &mut self,
+        chunk_type: PngChunkType,
+        data: Option<Vec<u8>>,
+    ) -> Result<()> {
+        self.remove_chunks(&chunk_type);
+        if let Some(data) = data {
+            self.insert_ancillary_chunk(Chunk::new(chunk_type, data)?);
+        }
+        Ok(())
+    }
+
+    fn remove_chunks(&mut self, chunk_type: &PngChunkType) {
+        self.chunks.retain(|chunk| chunk.chunk_type() != chunk_type);
+    }
+
+    /// Inserts `chunk` before the image data and any animation frames, which is where every
+    /// ancillary chunk this crate writes is allowed to go; sBIT and iCCP additionally have to
+    /// come before any palette.
+    fn insert_ancillary_chunk(&mut self, chunk: Chunk) {
+        let must_precede = |other: &Chunk| {
+            other.chunk_type() == &PngChunkType::IDAT
+                || other.chunk_type() == &PngChunkType::FCTL
+                || ((chunk.chunk_type() == &PngChunkType::SBIT
+                    || chunk.chunk_type() == &PngChunkType::ICCP)
+                    && other.chunk_type() == &PngChunkType::PLTE)
+        };
+        let idx = self
+            .chunks
+            .iter()
+            .position(must_precede)
+            .unwrap_or(self.chunks.len().saturating_sub(1));
+        self.chunks.insert(idx, chunk);
+    }
+
+    /// Streams this `Png` out to `writer` one `Chunk` at a time. When `max_idat_size` is
+    /// set, the image data is re-split into IDAT chunks of at most that many bytes;
+    /// otherwise the existing IDAT chunks are written unchanged.
+    pub fn write_to<W: Write>(&self, mut writer: W, max_idat_size: Option<u32>) -> Result<()> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        let mut chunks = self.chunks.iter().peekable();
+        while let Some(chunk) = chunks.next() {
+            match max_idat_size {
+                Some(size) if chunk.chunk_type() == &PngChunkType::IDAT => {
+                    // IDAT chunks are consecutive, so the whole zlib stream is re-split in one go
+                    let mut idat_writer = IdatWriter::new(&mut writer, size)?;
+                    idat_writer.write_all(chunk.data())?;
+                    while let Some(next) =
+                        chunks.next_if(|next| next.chunk_type() == &PngChunkType::IDAT)
+                    {
+                        idat_writer.write_all(next.data())?;
+                    }
+                    idat_writer.finish()?;
+                }
+                _ => chunk.write_to(&mut writer)?,
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
     /// Searches for a `Chunk` with the specified `chunk_type` and returns the first
     /// matching `Chunk` from this `Png`.
     fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
@@ -42,51 +421,37 @@ impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Png> {
-        let mut chunks: Vec<Chunk> = Vec::new();
-        if bytes.len() < 8 {
-            return Err(format!(
-                "not enough bytes; a valid png needs at least enough bytes for a header"
-            )
-            .into());
-        }
-        let header_bytes = &bytes[0..8];
-        if !header_bytes.eq(&Png::STANDARD_HEADER) {
-            return Err(format!("invalid header").into());
-        }
-        let mut pointer: usize = 8;
-        while pointer < bytes.len() {
-            //println!("Pointer is {pointer}");
-            //println!("Chunk bytes are {:?}", &bytes[pointer..]);
-            let chunk = Chunk::try_from(&bytes[pointer..])?;
-            pointer += 12 + chunk.length() as usize;
-            chunks.push(chunk);
-        }
-        Ok(Png { chunks })
+        Png::from_reader(bytes)
     }
 }
 
 impl ConvertibleImage for Png {
-    fn from_file<P: AsRef<Path>>(path: P) -> Result<Box<Self>> {
-        let file_bytes = fs::read(path)?;
-        match Png::try_from(&file_bytes[..]) {
-            Ok(png) => Ok(Box::new(png)),
-            Err(e) => Err(e),
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("PNG", BufReader::new(reader));
+        let header_bytes: [u8; 8] = reader.read_array()?;
+        if header_bytes != Png::STANDARD_HEADER {
+            return Err(Error::malformed_at("PNG", 0, "invalid signature"));
         }
-    }
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut byte_vec: Vec<u8> = Vec::new();
-        byte_vec.extend_from_slice(&Png::STANDARD_HEADER);
-        for chunk in &self.chunks {
-            byte_vec.extend_from_slice(chunk.as_bytes().as_slice());
+        let mut chunks: Vec<Chunk> = Vec::new();
+        while !reader.is_empty()? {
+            chunks.push(Chunk::read(&mut reader)?);
         }
-        byte_vec
+        Ok(Png { chunks })
+    }
+
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        self.write_to(writer, None)
     }
-    fn from_png(png: Png) -> Self {
-        png
+    fn from_png(png: Png) -> Result<Self> {
+        Ok(png)
     }
 
-    fn to_png(&self, _flags: Option<Vec<String>>) -> Png {
-        self.clone()
+    fn metadata(&self) -> Result<Metadata> {
+        Png::metadata(self)
+    }
+
+    fn to_png(&self) -> Result<Png> {
+        Ok(self.clone())
     }
 }
 
@@ -94,23 +459,22 @@ impl ConvertibleImage for Png {
 mod tests {
     use std::str::FromStr;
 
-    use super::{chunk_type::PngChunkType, *};
+    use super::*;
+    use crate::image::ColorModel;
 
     fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
         let chunk_type = PngChunkType::from_str(chunk_type)?;
         let data: Vec<u8> = data.bytes().collect();
 
-        Ok(Chunk::new(chunk_type, data))
+        Chunk::new(chunk_type, data)
     }
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("IHDR", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("IDAT", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("IEND", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("IHDR", "I am the first chunk").unwrap(),
+            chunk_from_strings("IDAT", "I am another chunk").unwrap(),
+            chunk_from_strings("IEND", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -159,7 +523,14 @@ mod tests {
 
         let png = Png::try_from(bytes.as_ref());
 
-        assert!(png.is_err());
+        assert!(matches!(
+            png,
+            Err(Error::Malformed {
+                format: "PNG",
+                offset: Some(0),
+                ..
+            })
+        ));
     }
 
     #[test]
@@ -169,6 +540,117 @@ mod tests {
         assert_eq!(chunks.len(), 3);
     }
 
+    #[test]
+    fn test_write_to_preserves_chunks() {
+        let png = testing_png();
+        let mut bytes: Vec<u8> = Vec::new();
+        png.write_to(&mut bytes, None).unwrap();
+        assert_eq!(bytes, png.to_bytes());
+    }
+
+    #[test]
+    fn test_write_to_splits_idat() {
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "some image").unwrap(),
+            chunk_from_strings("IDAT", " data").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+        let mut bytes: Vec<u8> = Vec::new();
+        png.write_to(&mut bytes, Some(4)).unwrap();
+
+        let written = Png::try_from(bytes.as_ref()).unwrap();
+        let types: Vec<String> = written
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "IDAT", "IDAT", "IDAT", "IEND"]);
+        let image_data: Vec<u8> = written
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.chunk_type() == &PngChunkType::IDAT)
+            .flat_map(|chunk| chunk.data().to_vec())
+            .collect();
+        assert_eq!(image_data, b"some image data");
+    }
+
+    #[test]
+    fn test_exif_metadata() {
+        let mut png = testing_png();
+        assert!(png.metadata().unwrap().exif().is_none());
+
+        let exif_bytes = crate::exif::tests::testing_exif_bytes();
+        let mut metadata = Metadata::default();
+        metadata.set_exif(Some(Exif::try_from(exif_bytes.as_ref()).unwrap()));
+        png.set_metadata(&metadata).unwrap();
+        assert_eq!(png.chunks()[1].chunk_type(), &PngChunkType::EXIF);
+
+        let reread = Png::try_from(png.to_bytes().as_ref()).unwrap();
+        let exif = reread.metadata().unwrap().exif().cloned().unwrap();
+        assert_eq!(exif.orientation(), Some(6));
+        assert_eq!(exif.as_bytes(), &exif_bytes[..]);
+
+        metadata.strip_exif();
+        png.set_metadata(&metadata).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_ancillary_chunks() {
+        let mut png = testing_png();
+        assert_eq!(png.background().unwrap(), None);
+
+        png.set_background(Some(Background::Gray(3))).unwrap();
+        png.set_significant_bits(Some(SignificantBits::new(vec![4]).unwrap()))
+            .unwrap();
+        png.set_histogram(Some(Histogram::new(vec![1, 2]).unwrap()))
+            .unwrap();
+        let time = Time::new(2023, 6, 1, 12, 30, 5).unwrap();
+        png.set_modification_time(Some(time)).unwrap();
+        let palette = SuggestedPalette::new("test".to_string(), 8, vec![]).unwrap();
+        png.set_suggested_palettes(&[palette.clone(), palette.clone()])
+            .unwrap();
+
+        let reread = Png::try_from(png.to_bytes().as_ref()).unwrap();
+        assert_eq!(reread.background().unwrap(), Some(Background::Gray(3)));
+        assert_eq!(reread.significant_bits().unwrap().unwrap().bits(), &[4]);
+        assert_eq!(reread.histogram().unwrap().unwrap().frequencies(), &[1, 2]);
+        assert_eq!(reread.modification_time().unwrap(), Some(time));
+        assert_eq!(reread.suggested_palettes().unwrap().len(), 2);
+        assert_eq!(
+            reread.chunks().last().unwrap().chunk_type(),
+            &PngChunkType::IEND
+        );
+
+        png.set_background(None).unwrap();
+        assert_eq!(png.background().unwrap(), None);
+    }
+
+    #[test]
+    fn test_flatten_onto_bkgd() {
+        let data = vec![0, 0, 0, 0, 200, 100, 50, 255];
+        let image = Image::new(2, 1, ColorModel::Rgba, 8, data).unwrap();
+        let mut png = Png::from_image(&image).unwrap();
+        png.set_background(Some(Background::Rgb(10, 20, 30)))
+            .unwrap();
+
+        let mut image = png.to_image().unwrap();
+        assert_eq!(
+            image.metadata().background(),
+            Some(Color::from_rgb8(10, 20, 30))
+        );
+        image.flatten_alpha(None);
+        assert_eq!(image.data(), &[10, 20, 30, 200, 100, 50]);
+
+        // the background survives re-encoding even though the color type changed
+        let flattened = Png::from_image(&image).unwrap();
+        assert_eq!(
+            flattened.background().unwrap(),
+            Some(Background::Rgb(10, 20, 30))
+        );
+    }
+
     #[test]
     fn test_invalid_chunk() {
         let mut chunk_bytes: Vec<u8> = testing_chunks()
@@ -190,4 +672,29 @@ mod tests {
 
         assert!(png.is_err());
     }
+
+    /// Hands out its data a few bytes at a time, like a pipe or socket would
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let png = testing_png();
+        let bytes = png.to_bytes();
+        let reread = Png::from_reader(Trickle(&bytes)).unwrap();
+        assert_eq!(reread.to_bytes(), bytes);
+
+        assert!(matches!(
+            Png::from_reader(Trickle(&bytes[..bytes.len() - 2])),
+            Err(Error::Malformed { format: "PNG", .. })
+        ));
+    }
 }
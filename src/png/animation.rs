@@ -0,0 +1,491 @@
+use std::time::Duration;
+
+use crate::{animation::Animation, byte_reader::ByteReader, image::Image, Error, Result};
+
+use super::{
+    chunk::Chunk, chunk_type::PngChunkType, image_data, image_header::ImageHeader,
+    options::PngOptions, Png,
+};
+
+/// The contents of an acTL chunk, which marks a PNG as animated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub frame_count: u32,
+    /// Times to play the animation through, or 0 to loop forever
+    pub plays: u32,
+}
+
+impl AnimationControl {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [self.frame_count, self.plays]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+}
+
+impl TryFrom<&[u8]> for AnimationControl {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != 8 {
+            return Err(Error::malformed("PNG", "acTL chunks must be 8 bytes long"));
+        }
+        let mut reader = ByteReader::new("PNG", value);
+        let control = AnimationControl {
+            frame_count: reader.read_u32_be()?,
+            plays: reader.read_u32_be()?,
+        };
+        if control.frame_count == 0 {
+            return Err(Error::malformed("PNG", "acTL chunk declares no frames"));
+        }
+        Ok(control)
+    }
+}
+
+/// What happens to a frame's region once the next frame is due
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dispose {
+    /// It stays as it is
+    None,
+    /// It's cleared to transparent black
+    Background,
+    /// It goes back to how it was before the frame was drawn
+    Previous,
+}
+
+/// How a frame's pixels are drawn onto the canvas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blend {
+    /// They replace what's there, alpha included
+    Source,
+    /// They're composited over what's there
+    Over,
+}
+
+/// The contents of an fcTL chunk: where a frame goes on the canvas and for how long
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    /// The delay is `delay_numerator / delay_denominator` seconds; a denominator of 0
+    /// stands for 100
+    pub delay_numerator: u16,
+    pub delay_denominator: u16,
+    pub dispose: Dispose,
+    pub blend: Blend,
+}
+
+impl FrameControl {
+    /// A frame covering the whole canvas that replaces whatever came before it
+    fn full_canvas(sequence: u32, width: u32, height: u32, delay: Duration) -> Self {
+        // whole milliseconds are exact enough for every format this crate converts from
+        let (delay_numerator, delay_denominator) = match u16::try_from(delay.as_millis()) {
+            Ok(millis) => (millis, 1000),
+            Err(_) => (delay.as_secs().min(u16::MAX as u64) as u16, 1),
+        };
+        FrameControl {
+            sequence,
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_numerator,
+            delay_denominator,
+            dispose: Dispose::None,
+            blend: Blend::Source,
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        let denominator = match self.delay_denominator {
+            0 => 100,
+            denominator => denominator as u64,
+        };
+        Duration::from_nanos(self.delay_numerator as u64 * 1_000_000_000 / denominator)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(26);
+        for value in [
+            self.sequence,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+        ] {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes.extend(self.delay_numerator.to_be_bytes());
+        bytes.extend(self.delay_denominator.to_be_bytes());
+        bytes.push(self.dispose as u8);
+        bytes.push(self.blend as u8);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for FrameControl {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != 26 {
+            return Err(Error::malformed("PNG", "fcTL chunks must be 26 bytes long"));
+        }
+        let mut reader = ByteReader::new("PNG", value);
+        Ok(FrameControl {
+            sequence: reader.read_u32_be()?,
+            width: reader.read_u32_be()?,
+            height: reader.read_u32_be()?,
+            x_offset: reader.read_u32_be()?,
+            y_offset: reader.read_u32_be()?,
+            delay_numerator: reader.read_u16_be()?,
+            delay_denominator: reader.read_u16_be()?,
+            dispose: match reader.read_u8()? {
+                0 => Dispose::None,
+                1 => Dispose::Background,
+                2 => Dispose::Previous,
+                op => return Err(reader.error(format!("unknown dispose op {op}"))),
+            },
+            blend: match reader.read_u8()? {
+                0 => Blend::Source,
+                1 => Blend::Over,
+                op => return Err(reader.error(format!("unknown blend op {op}"))),
+            },
+        })
+    }
+}
+
+/// Decodes the frames of an animated PNG, compositing each onto the canvas. Returns `None`
+/// for PNGs without an acTL chunk. Frames come out as 8-bit RGB or RGBA whatever the PNG's
+/// color type, since frames are drawn over each other.
+pub fn decode(png: &Png) -> Result<Option<Animation>> {
+    let control: AnimationControl = match png.chunk_by_type("acTL") {
+        Some(chunk) => AnimationControl::try_from(chunk.data())?,
+        None => return Ok(None),
+    };
+    let header = png.image_header()?;
+
+    // each frame's control chunk with its zlib stream, gathered from IDAT or fdAT chunks
+    let mut frames: Vec<(FrameControl, Vec<u8>)> = Vec::new();
+    let mut next_sequence: u32 = 0;
+    let mut check_sequence = |sequence: u32| {
+        if sequence != next_sequence {
+            return Err(Error::malformed(
+                "PNG",
+                format!("expected animation chunk {next_sequence}, found {sequence}"),
+            ));
+        }
+        next_sequence += 1;
+        Ok(())
+    };
+    let mut seen_fdat = false;
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        if chunk_type == &PngChunkType::FCTL {
+            let frame = FrameControl::try_from(chunk.data())?;
+            check_sequence(frame.sequence)?;
+            frames.push((frame, Vec::new()));
+        } else if chunk_type == &PngChunkType::IDAT {
+            // without an fcTL ahead of it, the default image isn't part of the animation
+            if let Some((_, data)) = frames.last_mut().filter(|_| !seen_fdat) {
+                data.extend(chunk.data());
+            }
+        } else if chunk_type == &PngChunkType::FDAT {
+            let mut reader = ByteReader::new("PNG", chunk.data());
+            check_sequence(reader.read_u32_be()?)?;
+            let (_, data) = frames
+                .last_mut()
+                .ok_or_else(|| Error::malformed("PNG", "fdAT chunk before any fcTL chunk"))?;
+            data.extend(&chunk.data()[4..]);
+            seen_fdat = true;
+        }
+    }
+    if frames.len() != control.frame_count as usize {
+        return Err(Error::malformed(
+            "PNG",
+            format!(
+                "acTL declares {} frames, but there are {}",
+                control.frame_count,
+                frames.len()
+            ),
+        ));
+    }
+
+    let (width, height) = (header.width() as usize, header.height() as usize);
+    let mut canvas: Vec<u8> = vec![0; width * height * 4];
+    let mut canvases: Vec<(Vec<u8>, Duration)> = Vec::with_capacity(frames.len());
+    for (idx, (frame, data)) in frames.into_iter().enumerate() {
+        if frame.x_offset as u64 + frame.width as u64 > width as u64
+            || frame.y_offset as u64 + frame.height as u64 > height as u64
+        {
+            return Err(Error::malformed(
+                "PNG",
+                format!("frame {idx} reaches past the edge of the canvas"),
+            ));
+        }
+        let image = decode_frame(png, &header, &frame, data)?;
+        let region = |x: usize, y: usize| {
+            ((frame.y_offset as usize + y) * width + frame.x_offset as usize + x) * 4
+        };
+        // the first frame has nothing earlier to go back to, so it's cleared instead
+        let dispose = match frame.dispose {
+            Dispose::Previous if idx == 0 => Dispose::Background,
+            dispose => dispose,
+        };
+        let before = (dispose == Dispose::Previous).then(|| canvas.clone());
+
+        for y in 0..frame.height as usize {
+            for x in 0..frame.width as usize {
+                let source = image.rgba8(y * frame.width as usize + x);
+                let at = region(x, y);
+                let pixel = &mut canvas[at..at + 4];
+                match frame.blend {
+                    Blend::Source => pixel.copy_from_slice(&source),
+                    Blend::Over => blend_over(pixel, source),
+                }
+            }
+        }
+        canvases.push((canvas.clone(), frame.delay()));
+
+        match dispose {
+            Dispose::None => {}
+            Dispose::Background => {
+                for y in 0..frame.height as usize {
+                    let start = region(0, y);
+                    canvas[start..start + frame.width as usize * 4].fill(0);
+                }
+            }
+            Dispose::Previous => canvas = before.expect("saved before drawing"),
+        }
+    }
+
+    let mut animation =
+        Animation::from_rgba8(header.width(), header.height(), canvases, control.plays)?;
+    animation.set_metadata(png.metadata()?);
+    Ok(Some(animation))
+}
+
+/// Decodes one frame's zlib stream by treating it as the image data of a PNG the frame's size
+fn decode_frame(
+    png: &Png,
+    header: &ImageHeader,
+    frame: &FrameControl,
+    data: Vec<u8>,
+) -> Result<Image> {
+    let frame_header = ImageHeader::new(
+        frame.width,
+        frame.height,
+        header.bit_depth(),
+        header.color_type(),
+        header.interlaced(),
+    )
+    .map_err(|e| e.into_malformed("PNG"))?;
+    let mut chunks: Vec<Chunk> = vec![Chunk::new(PngChunkType::IHDR, frame_header.as_bytes())?];
+    for chunk_type in ["PLTE", "tRNS"] {
+        chunks.extend(png.chunk_by_type(chunk_type).cloned());
+    }
+    chunks.push(Chunk::new(PngChunkType::IDAT, data)?);
+    image_data::decode(&Png::from_chunks(chunks))
+}
+
+/// Composites an 8-bit RGBA `source` pixel over `pixel`
+fn blend_over(pixel: &mut [u8], source: [u8; 4]) {
+    let source_alpha = source[3] as u32;
+    match source_alpha {
+        255 => pixel.copy_from_slice(&source),
+        0 => {}
+        _ => {
+            let under_alpha = pixel[3] as u32 * (255 - source_alpha) / 255;
+            let alpha = source_alpha + under_alpha;
+            for channel in 0..3 {
+                pixel[channel] = ((source[channel] as u32 * source_alpha
+                    + pixel[channel] as u32 * under_alpha
+                    + alpha / 2)
+                    / alpha) as u8;
+            }
+            pixel[3] = alpha as u8;
+        }
+    }
+}
+
+/// Encodes `animation` as an animated PNG. Every frame covers the whole canvas, and the
+/// first is also the default image that viewers without APNG support show.
+pub fn encode(animation: &Animation, options: &PngOptions) -> Result<Png> {
+    if options.palette {
+        return Err(Error::unsupported("PNG", "palettes in animated images"));
+    }
+    let (width, height) = (animation.width(), animation.height());
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut sequence: u32 = 0;
+    for (idx, frame) in animation.frames().iter().enumerate() {
+        let encoded = image_data::encode(&frame.image, options)?;
+        if idx == 0 {
+            chunks.push(encoded.header_chunk().cloned().expect("just encoded"));
+            let control = AnimationControl {
+                frame_count: animation.frames().len() as u32,
+                plays: animation.plays(),
+            };
+            chunks.push(Chunk::new(PngChunkType::ACTL, control.as_bytes())?);
+        }
+        let control = FrameControl::full_canvas(sequence, width, height, frame.delay);
+        chunks.push(Chunk::new(PngChunkType::FCTL, control.as_bytes())?);
+        sequence += 1;
+        for chunk in encoded
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.chunk_type() == &PngChunkType::IDAT)
+        {
+            match idx {
+                0 => chunks.push(chunk.clone()),
+                _ => {
+                    let data = [&sequence.to_be_bytes(), chunk.data()].concat();
+                    chunks.push(Chunk::new(PngChunkType::FDAT, data)?);
+                    sequence += 1;
+                }
+            }
+        }
+    }
+    chunks.push(Chunk::new(PngChunkType::IEND, Vec::new())?);
+
+    let mut png = Png::from_chunks(chunks);
+    png.set_metadata(animation.metadata())?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        animation::Frame, image::ColorModel, png::image_header::ColorType, ConvertibleImage,
+    };
+
+    fn frame(data: Vec<u8>, millis: u64) -> Frame {
+        Frame {
+            image: Image::new(2, 1, ColorModel::Rgba, 8, data).unwrap(),
+            delay: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frames = vec![
+            frame(vec![255, 0, 0, 255, 0, 0, 0, 0], 100),
+            frame(vec![0, 255, 0, 255, 0, 0, 255, 128], 70_000),
+        ];
+        let animation = Animation::new(frames.clone(), 3).unwrap();
+        let png = encode(&animation, &PngOptions::default()).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]
+        );
+        // viewers without APNG support see the first frame
+        let reread = Png::try_from(png.to_bytes().as_slice()).unwrap();
+        assert_eq!(reread.to_image().unwrap().data(), frames[0].image.data());
+
+        let decoded = decode(&reread).unwrap().unwrap();
+        assert_eq!(decoded.plays(), 3);
+        assert_eq!(decoded.frames().len(), 2);
+        for (decoded, frame) in decoded.frames().iter().zip(&frames) {
+            assert_eq!(decoded.image.data(), frame.image.data());
+        }
+        assert_eq!(decoded.frames()[0].delay, Duration::from_millis(100));
+        assert_eq!(decoded.frames()[1].delay, Duration::from_secs(70));
+
+        assert!(decode(&Png::from_image(&frames[0].image).unwrap())
+            .unwrap()
+            .is_none());
+        let options = PngOptions {
+            palette: true,
+            ..PngOptions::default()
+        };
+        assert!(matches!(
+            encode(&animation, &options),
+            Err(Error::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_compositing() {
+        let header = ImageHeader::new(2, 2, 8, ColorType::TruecolorAlpha, false).unwrap();
+        let zlib = |image: &Image| -> Vec<u8> {
+            let png = Png::from_image(image).unwrap();
+            png.chunks()
+                .iter()
+                .filter(|chunk| chunk.chunk_type() == &PngChunkType::IDAT)
+                .flat_map(|chunk| chunk.data().to_vec())
+                .collect()
+        };
+        let red = Image::new(2, 2, ColorModel::Rgba, 8, [255, 0, 0, 255].repeat(4)).unwrap();
+        let blue = Image::new(1, 1, ColorModel::Rgba, 8, vec![0, 0, 255, 128]).unwrap();
+        let control = |sequence, dispose, blend| FrameControl {
+            sequence,
+            width: 1,
+            height: 1,
+            x_offset: 1,
+            y_offset: 1,
+            delay_numerator: 1,
+            delay_denominator: 0,
+            dispose,
+            blend,
+        };
+        let first = FrameControl {
+            width: 2,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            ..control(0, Dispose::None, Blend::Source)
+        };
+        let fdat = |sequence: u32, image: &Image| {
+            let data = [&sequence.to_be_bytes()[..], &zlib(image)].concat();
+            Chunk::new(PngChunkType::FDAT, data).unwrap()
+        };
+        let png = Png::from_chunks(vec![
+            Chunk::new(PngChunkType::IHDR, header.as_bytes()).unwrap(),
+            Chunk::new(
+                PngChunkType::ACTL,
+                AnimationControl {
+                    frame_count: 3,
+                    plays: 0,
+                }
+                .as_bytes(),
+            )
+            .unwrap(),
+            Chunk::new(PngChunkType::FCTL, first.as_bytes()).unwrap(),
+            Chunk::new(PngChunkType::IDAT, zlib(&red)).unwrap(),
+            Chunk::new(
+                PngChunkType::FCTL,
+                control(1, Dispose::Background, Blend::Over).as_bytes(),
+            )
+            .unwrap(),
+            fdat(2, &blue),
+            Chunk::new(
+                PngChunkType::FCTL,
+                control(3, Dispose::None, Blend::Source).as_bytes(),
+            )
+            .unwrap(),
+            fdat(4, &blue),
+            Chunk::new(PngChunkType::IEND, Vec::new()).unwrap(),
+        ]);
+        let animation = decode(&png).unwrap().unwrap();
+        let corner = |frame: usize| animation.frames()[frame].image.rgba8(3);
+        assert_eq!(animation.plays(), 0);
+        assert_eq!(animation.frames()[0].delay, Duration::from_millis(10));
+        assert_eq!(corner(0), [255, 0, 0, 255]);
+        assert_eq!(corner(1), [127, 0, 128, 255]);
+        // the second frame's region was cleared, so the third is drawn onto nothing
+        assert_eq!(corner(2), [0, 0, 255, 128]);
+        assert_eq!(animation.frames()[2].image.rgba8(0), [255, 0, 0, 255]);
+
+        let mut out_of_order = png.clone();
+        out_of_order.chunks.swap(5, 7);
+        assert!(decode(&out_of_order).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{image::Color, Error, Result};
+
+/// The contents of a bKGD chunk, whose layout depends on the image's color type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+    PaletteIndex(u8),
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+impl Background {
+    /// Resolves this background to a color, given the image's bit depth and its PLTE data
+    pub fn to_color(self, bit_depth: u8, palette: Option<&[u8]>) -> Option<Color> {
+        let max = (1_u32 << bit_depth) - 1;
+        let scale = |sample: u16| (sample as u32 * 65535 / max) as u16;
+        match self {
+            Background::PaletteIndex(idx) => {
+                let entry = palette?.get(idx as usize * 3..idx as usize * 3 + 3)?;
+                Some(Color::from_rgb8(entry[0], entry[1], entry[2]))
+            }
+            Background::Gray(gray) => Some(Color {
+                red: scale(gray),
+                green: scale(gray),
+                blue: scale(gray),
+            }),
+            Background::Rgb(red, green, blue) => Some(Color {
+                red: scale(red),
+                green: scale(green),
+                blue: scale(blue),
+            }),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match *self {
+            Background::PaletteIndex(idx) => vec![idx],
+            Background::Gray(gray) => gray.to_be_bytes().to_vec(),
+            Background::Rgb(red, green, blue) => red
+                .to_be_bytes()
+                .iter()
+                .chain(green.to_be_bytes().iter())
+                .chain(blue.to_be_bytes().iter())
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Background {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let sample = |idx: usize| u16::from_be_bytes([value[idx], value[idx + 1]]);
+        match value.len() {
+            1 => Ok(Background::PaletteIndex(value[0])),
+            2 => Ok(Background::Gray(sample(0))),
+            6 => Ok(Background::Rgb(sample(0), sample(2), sample(4))),
+            len => Err(Error::malformed(
+                "PNG",
+                format!("bKGD chunks hold 1, 2 or 6 bytes, not {len}"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for background in [
+            Background::PaletteIndex(3),
+            Background::Gray(700),
+            Background::Rgb(1, 2, 3),
+        ] {
+            let bytes = background.as_bytes();
+            assert_eq!(Background::try_from(bytes.as_ref()).unwrap(), background);
+        }
+        assert!(Background::try_from(&[0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn test_to_color() {
+        assert_eq!(Background::Gray(1).to_color(1, None), Some(Color::WHITE));
+        assert_eq!(
+            Background::Rgb(255, 0, 0).to_color(8, None),
+            Some(Color::from_rgb8(255, 0, 0))
+        );
+        let palette = [0, 0, 0, 10, 20, 30];
+        assert_eq!(
+            Background::PaletteIndex(1).to_color(8, Some(&palette)),
+            Some(Color::from_rgb8(10, 20, 30))
+        );
+        assert_eq!(
+            Background::PaletteIndex(2).to_color(8, Some(&palette)),
+            None
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use std::{
     fmt,
-    str::{from_utf8, FromStr},
+    io::{self, BufRead, Write},
 };
 
 use crc::CRC_32_ISO_HDLC;
 
+use crate::{byte_reader::StreamReader, Error, Result};
+
 use super::chunk_type::PngChunkType;
 
 /// based on the design used in the PNGMe tutorial, which can be found at <https://picklenerd.github.io/pngme_book/>
@@ -22,8 +24,20 @@ fn generate_crc(data: &[u8]) -> u32 {
 }
 
 impl Chunk {
-    pub fn new(chunk_type: PngChunkType, data: Vec<u8>) -> Chunk {
-        let length: u32 = data.len().try_into().unwrap();
+    /// The largest data length a chunk may declare, as set by the PNG spec
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
+
+    pub fn new(chunk_type: PngChunkType, data: Vec<u8>) -> Result<Chunk> {
+        let length: u32 = match u32::try_from(data.len()) {
+            Ok(length) if length <= Chunk::MAX_LENGTH => length,
+            _ => {
+                return Err(Error::invalid_argument(format!(
+                    "Chunk data is {} bytes long, but chunks can hold at most {} bytes",
+                    data.len(),
+                    Chunk::MAX_LENGTH
+                )))
+            }
+        };
         let full_data_bytes: Vec<u8> = chunk_type
             .to_string()
             .as_bytes()
@@ -32,12 +46,12 @@ impl Chunk {
             .copied()
             .collect();
         let crc = generate_crc(&full_data_bytes[..]);
-        Chunk {
+        Ok(Chunk {
             length,
             chunk_type,
             data,
             crc,
-        }
+        })
     }
 
     pub fn length(&self) -> u32 {
@@ -66,30 +80,68 @@ impl Chunk {
             .copied()
             .collect()
     }
+
+    /// Writes this `Chunk` in its on-disk layout without building an intermediate buffer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes())?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())
+    }
 }
 impl TryFrom<&[u8]> for Chunk {
-    type Error = String;
-
-    fn try_from(value: &[u8]) -> std::result::Result<Self, Self::Error> {
-        let mut buf: [u8; 4] = [0; 4];
-        //println!("length of value is {}", value.len());
-        buf.copy_from_slice(&value[0..4]);
-        let length = u32::from_be_bytes(buf);
-        buf.copy_from_slice(&value[4..8]);
-        let chunk_type = match PngChunkType::try_from(buf) {
-            Ok(val) => val,
-            Err(e) => return Err(e),
-        };
-        let crc_offset: usize = 8 + length as usize;
-        buf.copy_from_slice(&value[crc_offset..crc_offset + 4]);
-        let crc = u32::from_be_bytes(buf);
-        let data = value[8..crc_offset].to_vec();
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Chunk::read(&mut StreamReader::new("PNG", value))
+    }
+}
+
+impl Chunk {
+    /// Reads one chunk from `reader`, leaving it positioned just after the chunk's CRC
+    pub fn read<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Chunk> {
+        let (length, chunk_type) = Chunk::read_header(reader)?;
+        Chunk::read_data(reader, length, chunk_type)
+    }
 
-        let actual_crc = generate_crc(&value[4..crc_offset]);
+    /// Reads the length and type that start a chunk, leaving the reader at its data
+    pub fn read_header<R: BufRead>(reader: &mut StreamReader<R>) -> Result<(u32, PngChunkType)> {
+        let start = reader.position();
+        let length = reader.read_u32_be()?;
+        if length > Chunk::MAX_LENGTH {
+            return Err(reader.error(format!(
+                "chunk length {length} is larger than the PNG limit of {}",
+                Chunk::MAX_LENGTH
+            )));
+        }
+        let type_bytes: [u8; 4] = reader.read_array()?;
+        match PngChunkType::try_from(type_bytes) {
+            Ok(chunk_type) => Ok((length, chunk_type)),
+            Err(Error::Malformed { message, .. }) => {
+                Err(Error::malformed_at("PNG", start + 4, message))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the data and CRC that follow a header read with `read_header`
+    pub fn read_data<R: BufRead>(
+        reader: &mut StreamReader<R>,
+        length: u32,
+        chunk_type: PngChunkType,
+    ) -> Result<Chunk> {
+        let data = reader.read_bytes(length as usize)?;
+        let crc = reader.read_u32_be()?;
+
+        let crc_gen = crc::Crc::<u32>::new(&CRC_32_ISO_HDLC);
+        let mut digest = crc_gen.digest();
+        digest.update(&chunk_type.bytes());
+        digest.update(&data);
+        let actual_crc = digest.finalize();
         if actual_crc != crc {
-            return Err(format!(
+            return Err(reader.error(format!(
                 "Invalid crc; the passed crc was {crc} but the actual crc should be {actual_crc}"
-            ));
+            )));
         }
 
         Ok(Chunk {
@@ -103,12 +155,14 @@ impl TryFrom<&[u8]> for Chunk {
 
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        std::fmt::Display::fmt(from_utf8(&self.data).unwrap(), f)
+        std::fmt::Display::fmt(&String::from_utf8_lossy(&self.data), f)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
     use super::*;
     fn testing_chunk() -> Chunk {
         let data_length: u32 = 35;
@@ -132,11 +186,19 @@ mod tests {
     fn test_new_chunk() {
         let chunk_type = PngChunkType::from_str("IHDR").unwrap();
         let data = "A string for some sample bytes here".as_bytes().to_vec();
-        let chunk = Chunk::new(chunk_type, data);
+        let chunk = Chunk::new(chunk_type, data).unwrap();
         assert_eq!(chunk.length(), 35);
         assert_eq!(chunk.crc(), 1984488028);
     }
 
+    #[test]
+    fn test_chunk_write_to() {
+        let chunk = testing_chunk();
+        let mut written: Vec<u8> = Vec::new();
+        chunk.write_to(&mut written).unwrap();
+        assert_eq!(written, chunk.as_bytes());
+    }
+
     #[test]
     fn test_chunk_length() {
         let chunk = testing_chunk();
@@ -198,4 +260,16 @@ mod tests {
 
         assert!(chunk.is_err());
     }
+
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let chunk_bytes = testing_chunk().as_bytes();
+        for len in [0, 3, 7, 20, chunk_bytes.len() - 1] {
+            assert!(Chunk::try_from(&chunk_bytes[..len]).is_err());
+        }
+
+        let mut oversized = chunk_bytes.clone();
+        oversized[0..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(Chunk::try_from(oversized.as_ref()).is_err());
+    }
 }
@@ -3,6 +3,8 @@ use std::{
     str::{from_utf8, FromStr},
 };
 
+use crate::Error;
+
 /// based on the design used in the PNGMe tutorial, which can be found at <https://picklenerd.github.io/pngme_book/>
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct PngChunkType {
@@ -10,6 +12,23 @@ pub struct PngChunkType {
 }
 
 impl PngChunkType {
+    pub const IHDR: PngChunkType = PngChunkType { code: *b"IHDR" };
+    pub const PLTE: PngChunkType = PngChunkType { code: *b"PLTE" };
+    pub const TRNS: PngChunkType = PngChunkType { code: *b"tRNS" };
+    pub const IDAT: PngChunkType = PngChunkType { code: *b"IDAT" };
+    pub const IEND: PngChunkType = PngChunkType { code: *b"IEND" };
+    pub const EXIF: PngChunkType = PngChunkType { code: *b"eXIf" };
+    pub const BKGD: PngChunkType = PngChunkType { code: *b"bKGD" };
+    pub const SBIT: PngChunkType = PngChunkType { code: *b"sBIT" };
+    pub const HIST: PngChunkType = PngChunkType { code: *b"hIST" };
+    pub const ICCP: PngChunkType = PngChunkType { code: *b"iCCP" };
+    pub const PHYS: PngChunkType = PngChunkType { code: *b"pHYs" };
+    pub const TIME: PngChunkType = PngChunkType { code: *b"tIME" };
+    pub const SPLT: PngChunkType = PngChunkType { code: *b"sPLT" };
+    pub const ACTL: PngChunkType = PngChunkType { code: *b"acTL" };
+    pub const FCTL: PngChunkType = PngChunkType { code: *b"fcTL" };
+    pub const FDAT: PngChunkType = PngChunkType { code: *b"fdAT" };
+
     pub fn bytes(&self) -> [u8; 4] {
         self.code
     }
@@ -45,11 +64,11 @@ impl Display for PngChunkType {
 }
 
 impl TryFrom<[u8; 4]> for PngChunkType {
-    type Error = String;
+    type Error = Error;
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
         for byte in value {
             if !byte.is_ascii_alphabetic() {
-                return Err(format!("All bytes must be valid alphabetic ASCII; the byte {byte} in the input array {value:?} is not valid ASCII"));
+                return Err(Error::malformed("PNG", format!("All bytes must be valid alphabetic ASCII; the byte {byte} in the input array {value:?} is not valid ASCII")));
             }
         }
         Ok(PngChunkType { code: value })
@@ -57,20 +76,20 @@ impl TryFrom<[u8; 4]> for PngChunkType {
 }
 
 impl FromStr for PngChunkType {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.len() != 4 {
-            return Err("Chunk types must contain 4 bytes");
+            return Err(Error::invalid_argument("Chunk types must contain 4 bytes"));
         }
         for char in s.chars() {
             if !char.is_ascii_alphabetic() {
-                return Err("All bytes must be valid alphabetic ASCII");
+                return Err(Error::invalid_argument(
+                    "All bytes must be valid alphabetic ASCII",
+                ));
             }
         }
         let mut code: [u8; 4] = [0; 4];
-        for idx in 0..4 {
-            code[idx] = s.as_bytes()[idx]
-        }
+        code.copy_from_slice(s.as_bytes());
         Ok(PngChunkType { code })
     }
 }
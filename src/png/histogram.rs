@@ -0,0 +1,66 @@
+use crate::{Error, Result};
+
+/// The contents of a hIST chunk: an approximate usage count for each PLTE entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    frequencies: Vec<u16>,
+}
+
+impl Histogram {
+    pub fn new(frequencies: Vec<u16>) -> Result<Self> {
+        if frequencies.is_empty() || frequencies.len() > 256 {
+            return Err(Error::invalid_argument(format!(
+                "hIST chunks hold one entry per palette color, so {} entries is invalid",
+                frequencies.len()
+            )));
+        }
+        Ok(Histogram { frequencies })
+    }
+
+    pub fn frequencies(&self) -> &[u16] {
+        &self.frequencies
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.frequencies
+            .iter()
+            .flat_map(|frequency| frequency.to_be_bytes())
+            .collect()
+    }
+}
+
+impl TryFrom<&[u8]> for Histogram {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if !value.len().is_multiple_of(2) {
+            return Err(Error::malformed(
+                "PNG",
+                "hIST chunks must hold whole 2-byte entries",
+            ));
+        }
+        Histogram::new(
+            value
+                .chunks_exact(2)
+                .map(|entry| u16::from_be_bytes([entry[0], entry[1]]))
+                .collect(),
+        )
+        .map_err(|e| e.into_malformed("PNG"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let histogram = Histogram::new(vec![0, 12, 65535]).unwrap();
+        assert_eq!(
+            Histogram::try_from(histogram.as_bytes().as_ref()).unwrap(),
+            histogram
+        );
+        assert!(Histogram::try_from(&[0, 1, 2][..]).is_err());
+        assert!(Histogram::new(vec![0; 257]).is_err());
+    }
+}
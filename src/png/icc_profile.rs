@@ -0,0 +1,78 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{Error, Result};
+
+/// Largest profile an iCCP chunk is inflated to, so a small chunk can't exhaust memory
+const MAX_PROFILE_LEN: u64 = 16 << 20;
+
+/// The contents of an iCCP chunk: a named ICC profile, stored deflated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IccProfile {
+    /// Latin-1 name of 1 to 79 characters, only there for showing to people
+    pub name: String,
+    pub profile: Vec<u8>,
+}
+
+impl IccProfile {
+    /// Wraps `profile` under a generic name
+    pub fn new(profile: Vec<u8>) -> Self {
+        IccProfile {
+            name: "ICC profile".to_string(),
+            profile,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes: Vec<u8> = self.name.bytes().collect();
+        // compression method 0, deflate
+        bytes.extend([0, 0]);
+        let mut encoder = ZlibEncoder::new(bytes, Compression::default());
+        encoder.write_all(&self.profile)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+impl TryFrom<&[u8]> for IccProfile {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let name_len = value
+            .iter()
+            .take(80)
+            .position(|&byte| byte == 0)
+            .filter(|&len| len > 0)
+            .ok_or_else(|| Error::malformed("PNG", "the iCCP chunk's profile name is invalid"))?;
+        if value.get(name_len + 1) != Some(&0) {
+            return Err(Error::malformed(
+                "PNG",
+                "the iCCP chunk uses an unknown compression method",
+            ));
+        }
+        let mut profile: Vec<u8> = Vec::new();
+        ZlibDecoder::new(&value[name_len + 2..])
+            .take(MAX_PROFILE_LEN)
+            .read_to_end(&mut profile)
+            .map_err(|_| Error::malformed("PNG", "the iCCP chunk's profile doesn't inflate"))?;
+        Ok(IccProfile {
+            name: value[..name_len].iter().map(|&byte| byte as char).collect(),
+            profile,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let icc = IccProfile::new((0..=255).cycle().take(1000).collect());
+        let bytes = icc.as_bytes().unwrap();
+        assert!(bytes.starts_with(b"ICC profile\0\0"));
+        assert_eq!(IccProfile::try_from(bytes.as_slice()).unwrap(), icc);
+        assert!(IccProfile::try_from(&bytes[11..]).is_err());
+        assert!(IccProfile::try_from(&bytes[..20]).is_err());
+    }
+}
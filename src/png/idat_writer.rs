@@ -0,0 +1,119 @@
+use std::io::{self, Write};
+
+use crate::{Error, Result};
+
+use super::{chunk::Chunk, chunk_type::PngChunkType};
+
+/// Splits a zlib stream into IDAT `Chunk`s of at most `max_chunk_size` bytes, writing
+/// each one out as soon as it fills up rather than holding the whole stream in memory.
+pub struct IdatWriter<W: Write> {
+    writer: W,
+    max_chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> IdatWriter<W> {
+    /// The IDAT size used when no other size is requested; matches what libpng emits
+    pub const DEFAULT_CHUNK_SIZE: u32 = 8192;
+
+    pub fn new(writer: W, max_chunk_size: u32) -> Result<Self> {
+        if max_chunk_size == 0 || max_chunk_size > Chunk::MAX_LENGTH {
+            return Err(Error::invalid_argument(format!(
+                "IDAT chunk size must be between 1 and {} bytes, not {max_chunk_size}",
+                Chunk::MAX_LENGTH
+            )));
+        }
+        Ok(IdatWriter {
+            writer,
+            max_chunk_size: max_chunk_size as usize,
+            buffer: Vec::with_capacity(max_chunk_size.min(Self::DEFAULT_CHUNK_SIZE) as usize),
+        })
+    }
+
+    /// Writes out any partially filled chunk and hands back the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() {
+            self.write_chunk()?;
+        }
+        Ok(self.writer)
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        let data = std::mem::take(&mut self.buffer);
+        let chunk = Chunk::new(PngChunkType::IDAT, data)?;
+        chunk.write_to(&mut self.writer)
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let space_left = self.max_chunk_size - self.buffer.len();
+        let taken = buf.len().min(space_left);
+        self.buffer.extend_from_slice(&buf[..taken]);
+        if self.buffer.len() == self.max_chunk_size {
+            self.write_chunk()?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written_chunks(mut bytes: &[u8]) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        while !bytes.is_empty() {
+            let chunk = Chunk::try_from(bytes).unwrap();
+            bytes = &bytes[12 + chunk.length() as usize..];
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_splits_at_max_size() {
+        let mut idat_writer = IdatWriter::new(Vec::new(), 4).unwrap();
+        idat_writer
+            .write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10])
+            .unwrap();
+        let bytes = idat_writer.finish().unwrap();
+
+        let chunks = written_chunks(&bytes);
+        let lengths: Vec<u32> = chunks.iter().map(|chunk| chunk.length()).collect();
+        assert_eq!(lengths, vec![4, 4, 2]);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.chunk_type() == &PngChunkType::IDAT));
+        let data: Vec<u8> = chunks
+            .iter()
+            .flat_map(|chunk| chunk.data().to_vec())
+            .collect();
+        assert_eq!(data, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn test_streams_full_chunks_before_finish() {
+        let mut written: Vec<u8> = Vec::new();
+        let mut idat_writer = IdatWriter::new(&mut written, 3).unwrap();
+        idat_writer.write_all(&[1, 2, 3, 4]).unwrap();
+        drop(idat_writer);
+        assert_eq!(written_chunks(&written).len(), 1);
+    }
+
+    #[test]
+    fn test_empty_stream_writes_nothing() {
+        let idat_writer = IdatWriter::new(Vec::new(), 4).unwrap();
+        assert!(idat_writer.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_chunk_size() {
+        assert!(IdatWriter::new(Vec::new(), 0).is_err());
+        assert!(IdatWriter::new(Vec::new(), Chunk::MAX_LENGTH + 1).is_err());
+    }
+}
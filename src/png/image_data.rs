@@ -0,0 +1,840 @@
+use std::io::{self, BufReader, Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    byte_reader::{ByteReader, StreamReader},
+    image::{ColorModel, Image},
+    metadata::Metadata,
+    scanline::{read_image, ImageRows, RowLayout, RowSource},
+    Error, Result,
+};
+
+use super::{
+    chunk::Chunk,
+    chunk_type::PngChunkType,
+    idat_writer::IdatWriter,
+    image_header::{ColorType, ImageHeader},
+    options::PngOptions,
+    Png,
+};
+
+/// Starting column, starting row, column step and row step of each Adam7 pass
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Decodes the IDAT stream of `png` into an `Image`. Palettes are expanded to RGB,
+/// transparency from tRNS becomes an alpha channel and samples below 8 bits are scaled up.
+pub fn decode(png: &Png) -> Result<Image> {
+    let header = png.image_header()?;
+    let palette = png.chunk_by_type("PLTE").map(|chunk| chunk.data());
+    let transparency = png.chunk_by_type("tRNS").map(|chunk| chunk.data());
+    if header.color_type() == ColorType::Indexed && palette.is_none() {
+        return Err(Error::malformed("PNG", "indexed image has no PLTE chunk"));
+    }
+
+    let compressed: Vec<u8> = png
+        .chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type() == &PngChunkType::IDAT)
+        .flat_map(|chunk| chunk.data().iter().copied())
+        .collect();
+    let mut filtered: Vec<u8> = Vec::new();
+    ZlibDecoder::new(&compressed[..]).read_to_end(&mut filtered)?;
+
+    let passes: &[(u32, u32, u32, u32)] = if header.interlaced() {
+        &ADAM7_PASSES
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+    // checked before allocating the output, so a forged IHDR size can't exhaust memory
+    let expected_len: usize = passes
+        .iter()
+        .filter(|&&(x0, y0, _, _)| x0 < header.width() && y0 < header.height())
+        .map(|&(x0, y0, dx, dy)| {
+            let pass_width = (header.width() - x0).div_ceil(dx) as usize;
+            let pass_height = (header.height() - y0).div_ceil(dy) as usize;
+            (stride(&header, pass_width) + 1) * pass_height
+        })
+        .sum();
+    if filtered.len() < expected_len {
+        return Err(Error::malformed(
+            "PNG",
+            format!(
+                "image data holds {} bytes, but a {}x{} image needs {expected_len}",
+                filtered.len(),
+                header.width(),
+                header.height()
+            ),
+        ));
+    }
+
+    let (color_model, out_depth) = output_format(&header, transparency.is_some());
+    let out_pixel_len = color_model.channels() * out_depth as usize / 8;
+    let width = header.width() as usize;
+    let mut data: Vec<u8> = vec![0; width * header.height() as usize * out_pixel_len];
+
+    let expander = PixelExpander {
+        header: &header,
+        palette,
+        transparency,
+    };
+    let mut reader = ByteReader::new("PNG image data", &filtered);
+    for &(x0, y0, dx, dy) in passes {
+        if x0 >= header.width() || y0 >= header.height() {
+            continue;
+        }
+        let pass_width = (header.width() - x0).div_ceil(dx) as usize;
+        let pass_height = (header.height() - y0).div_ceil(dy);
+        let row_len = stride(&header, pass_width);
+        let mut previous: Vec<u8> = vec![0; row_len];
+        for pass_y in 0..pass_height {
+            let filter_type = reader.read_u8()?;
+            let mut row = reader.read_bytes(row_len)?.to_vec();
+            unfilter(filter_type, bytes_per_pixel(&header), &mut row, &previous)?;
+
+            let y = (y0 + pass_y * dy) as usize;
+            for pass_x in 0..pass_width {
+                let x = x0 as usize + pass_x * dx as usize;
+                let out = (y * width + x) * out_pixel_len;
+                expander.expand(&row, pass_x, &mut data[out..out + out_pixel_len]);
+            }
+            previous = row;
+        }
+    }
+
+    let mut image = Image::new(
+        header.width(),
+        header.height(),
+        color_model,
+        out_depth,
+        data,
+    )?;
+    image.set_metadata(png.metadata()?);
+    Ok(image)
+}
+
+/// Encodes `image` as a PNG laid out as `options` asks, choosing a filter for each row with
+/// the minimum-sum-of-absolute-differences heuristic
+pub fn encode(image: &Image, options: &PngOptions) -> Result<Png> {
+    options.validate()?;
+    let indexed = match options.palette {
+        true => Some(image.to_indexed(256).ok_or_else(|| {
+            Error::lossy("the image has more than the 256 colors a PNG palette can hold")
+        })?),
+        false => None,
+    };
+    let (color_type, bit_depth) = match &indexed {
+        Some(indexed) => {
+            let needed = [1, 2, 4, 8]
+                .into_iter()
+                .find(|&bit_depth| indexed.palette.len() <= 1 << bit_depth)
+                .unwrap_or(8);
+            let bit_depth = options.bit_depth.unwrap_or(needed);
+            if bit_depth < needed {
+                return Err(Error::lossy(format!(
+                    "the image's {} colors don't fit in a {bit_depth}-bit palette",
+                    indexed.palette.len()
+                )));
+            }
+            (ColorType::Indexed, bit_depth)
+        }
+        None => (
+            color_type(image.color_model()),
+            options.bit_depth.unwrap_or(image.bit_depth()),
+        ),
+    };
+    let header = ImageHeader::new(
+        image.width(),
+        image.height(),
+        bit_depth,
+        color_type,
+        options.interlace,
+    )?;
+
+    // every sample of the image at the output bit depth, row by row
+    let samples: Vec<u16> = match &indexed {
+        Some(indexed) => indexed.indices.iter().map(|&idx| idx as u16).collect(),
+        None => {
+            let sample_count =
+                image.row_len() / (image.bit_depth() as usize / 8) * image.height() as usize;
+            (0..sample_count)
+                .map(|idx| rescale(image.sample(idx), image.bit_depth(), bit_depth))
+                .collect()
+        }
+    };
+
+    let mut idat_bytes: Vec<u8> = Vec::new();
+    let idat_writer = IdatWriter::new(&mut idat_bytes, IdatWriter::<Vec<u8>>::DEFAULT_CHUNK_SIZE)?;
+    let mut encoder = ZlibEncoder::new(idat_writer, compression(options));
+    let (width, height) = (image.width(), image.height());
+    let channels = color_type.channels();
+    let pixel_len = bytes_per_pixel(&header);
+    let passes: &[(u32, u32, u32, u32)] = if options.interlace {
+        &ADAM7_PASSES
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+    for &(x0, y0, dx, dy) in passes {
+        if x0 >= width || y0 >= height {
+            continue;
+        }
+        let pass_width = (width - x0).div_ceil(dx) as usize;
+        let mut previous: Vec<u8> = vec![0; stride(&header, pass_width)];
+        for y in (y0..height).step_by(dy as usize) {
+            let row_samples = (x0..width).step_by(dx as usize).flat_map(|x| {
+                let base = (y as usize * width as usize + x as usize) * channels;
+                samples[base..base + channels].iter().copied()
+            });
+            let row = pack_samples(bit_depth, row_samples);
+            let (filter_type, filtered) = best_filter(pixel_len, &row, &previous);
+            encoder.write_all(&[filter_type])?;
+            encoder.write_all(&filtered)?;
+            previous = row;
+        }
+    }
+    encoder.finish()?.finish()?;
+
+    let mut chunks: Vec<Chunk> = vec![Chunk::new(PngChunkType::IHDR, header.as_bytes())?];
+    if let Some(indexed) = &indexed {
+        let palette: Vec<u8> = indexed
+            .palette
+            .iter()
+            .flat_map(|&[red, green, blue, _]| [red, green, blue])
+            .collect();
+        chunks.push(Chunk::new(PngChunkType::PLTE, palette)?);
+        // entries past the last translucent one are opaque, so they're left out
+        let mut alpha: Vec<u8> = indexed.palette.iter().map(|color| color[3]).collect();
+        while alpha.last() == Some(&255) {
+            alpha.pop();
+        }
+        if !alpha.is_empty() {
+            chunks.push(Chunk::new(PngChunkType::TRNS, alpha)?);
+        }
+    }
+    let mut reader = StreamReader::new("PNG", &idat_bytes[..]);
+    while !reader.is_empty()? {
+        chunks.push(Chunk::read(&mut reader)?);
+    }
+    chunks.push(Chunk::new(PngChunkType::IEND, Vec::new())?);
+
+    let mut png = Png::from_chunks(chunks);
+    png.set_metadata(image.metadata())?;
+    Ok(png)
+}
+
+/// Reads the size of a PNG from its IHDR chunk, which has to come first. Whether a tRNS
+/// chunk adds an alpha channel isn't known yet, so the layout assumes one does.
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let mut reader = StreamReader::new("PNG", BufReader::new(reader));
+    if reader.read_array::<8>()? != Png::STANDARD_HEADER {
+        return Err(Error::malformed_at("PNG", 0, "invalid signature"));
+    }
+    let chunk = Chunk::read(&mut reader)?;
+    if chunk.chunk_type() != &PngChunkType::IHDR {
+        return Err(Error::malformed_at(
+            "PNG",
+            8,
+            format!("the first chunk is {}, not IHDR", chunk.chunk_type()),
+        ));
+    }
+    let header = ImageHeader::try_from(chunk.data())?;
+    let (color_model, bit_depth) = output_format(&header, true);
+    Ok(RowLayout {
+        width: header.width(),
+        height: header.height(),
+        color_model,
+        bit_depth,
+    })
+}
+
+/// Reads a PNG from `reader` a row at a time, inflating its IDAT chunks as the rows are asked
+/// for. Only the chunks ahead of the image data are looked at, so metadata stored after it
+/// is lost. Interlaced images are decoded whole, as no row is finished before the last pass.
+pub fn decode_rows(reader: &mut dyn Read) -> Result<Box<dyn RowSource + '_>> {
+    let mut reader = StreamReader::new("PNG", BufReader::new(reader));
+    if reader.read_array::<8>()? != Png::STANDARD_HEADER {
+        return Err(Error::malformed_at("PNG", 0, "invalid signature"));
+    }
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let first_idat = loop {
+        if reader.is_empty()? {
+            return Err(reader.error("the file ends before any image data"));
+        }
+        let chunk = Chunk::read(&mut reader)?;
+        if chunk.chunk_type() == &PngChunkType::IDAT {
+            break chunk;
+        }
+        chunks.push(chunk);
+    };
+    let png = Png::from_chunks(chunks);
+    let header = png.image_header()?;
+    if header.interlaced() {
+        let mut chunks = png.chunks;
+        chunks.push(first_idat);
+        while !reader.is_empty()? {
+            chunks.push(Chunk::read(&mut reader)?);
+        }
+        return Ok(Box::new(ImageRows::new(decode(&Png::from_chunks(chunks))?)));
+    }
+
+    let palette = png.chunk_by_type("PLTE").map(|chunk| chunk.data().to_vec());
+    let transparency = png.chunk_by_type("tRNS").map(|chunk| chunk.data().to_vec());
+    if header.color_type() == ColorType::Indexed && palette.is_none() {
+        return Err(Error::malformed("PNG", "indexed image has no PLTE chunk"));
+    }
+    let (color_model, bit_depth) = output_format(&header, transparency.is_some());
+    let row_len = stride(&header, header.width() as usize);
+    Ok(Box::new(PngRows {
+        layout: RowLayout {
+            width: header.width(),
+            height: header.height(),
+            color_model,
+            bit_depth,
+        },
+        metadata: png.metadata()?,
+        header,
+        palette,
+        transparency,
+        inflater: ZlibDecoder::new(IdatReader {
+            data: first_idat.data().to_vec(),
+            reader,
+            position: 0,
+            finished: false,
+        }),
+        previous: vec![0; row_len],
+        current: vec![0; row_len],
+        y: 0,
+    }))
+}
+
+/// Encodes the rows of `source` as a PNG laid out as `options` asks, writing each row out as
+/// it arrives. Palettes and interlacing need the whole image, so with either of those the
+/// rows are gathered up first. `touch` records the current time as the modification time.
+pub fn encode_rows(
+    source: &mut dyn RowSource,
+    metadata: &Metadata,
+    options: &PngOptions,
+    touch: bool,
+    mut writer: &mut dyn Write,
+) -> Result<()> {
+    options.validate()?;
+    if options.palette || options.interlace {
+        let mut image = read_image(source)?;
+        image.set_metadata(metadata.clone());
+        let mut png = encode(&image, options)?;
+        if touch {
+            png.touch()?;
+        }
+        return png.write_to(writer, options.max_idat_size);
+    }
+
+    let layout = source.layout();
+    let bit_depth = options.bit_depth.unwrap_or(layout.bit_depth);
+    let header = ImageHeader::new(
+        layout.width,
+        layout.height,
+        bit_depth,
+        color_type(layout.color_model),
+        false,
+    )?;
+    // the chunks around the image data, laid out the way a whole `Png` would have them
+    let mut outline = Png::from_chunks(vec![
+        Chunk::new(PngChunkType::IHDR, header.as_bytes())?,
+        Chunk::new(PngChunkType::IEND, Vec::new())?,
+    ]);
+    outline.set_metadata(metadata)?;
+    if touch {
+        outline.touch()?;
+    }
+    let (iend, leading) = outline.chunks.split_last().expect("IEND was added above");
+
+    writer.write_all(&Png::STANDARD_HEADER)?;
+    for chunk in leading {
+        chunk.write_to(&mut writer)?;
+    }
+    let max_idat_size = options
+        .max_idat_size
+        .unwrap_or(IdatWriter::<Vec<u8>>::DEFAULT_CHUNK_SIZE);
+    let idat_writer = IdatWriter::new(&mut writer, max_idat_size)?;
+    let mut encoder = ZlibEncoder::new(idat_writer, compression(options));
+    let pixel_len = bytes_per_pixel(&header);
+    let sample_count = layout.row_len() / (layout.bit_depth as usize / 8);
+    let mut row: Vec<u8> = vec![0; layout.row_len()];
+    let mut previous: Vec<u8> = vec![0; stride(&header, layout.width as usize)];
+    for _ in 0..layout.height {
+        source.read_row(&mut row)?;
+        let samples = (0..sample_count)
+            .map(|idx| rescale(layout.sample(&row, idx), layout.bit_depth, bit_depth));
+        let packed = pack_samples(bit_depth, samples);
+        let (filter_type, filtered) = best_filter(pixel_len, &packed, &previous);
+        encoder.write_all(&[filter_type])?;
+        encoder.write_all(&filtered)?;
+        previous = packed;
+    }
+    encoder.finish()?.finish()?;
+    iend.write_to(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Produces the rows of a non-interlaced PNG as its image data streams in
+struct PngRows<'a> {
+    layout: RowLayout,
+    metadata: Metadata,
+    header: ImageHeader,
+    palette: Option<Vec<u8>>,
+    transparency: Option<Vec<u8>>,
+    inflater: ZlibDecoder<IdatReader<'a>>,
+    previous: Vec<u8>,
+    current: Vec<u8>,
+    y: u32,
+}
+
+impl RowSource for PngRows<'_> {
+    fn layout(&self) -> RowLayout {
+        self.layout
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        if self.y >= self.layout.height {
+            return Err(Error::invalid_argument(
+                "read past the last row of the image",
+            ));
+        }
+        let mut filter_type = [0];
+        let read = self
+            .inflater
+            .read_exact(&mut filter_type)
+            .and_then(|_| self.inflater.read_exact(&mut self.current));
+        if let Err(e) = read {
+            return Err(match e.kind() {
+                io::ErrorKind::UnexpectedEof => Error::malformed(
+                    "PNG",
+                    format!(
+                        "image data ends after {} of the image's {} rows",
+                        self.y, self.layout.height
+                    ),
+                ),
+                _ => e.into(),
+            });
+        }
+        unfilter(
+            filter_type[0],
+            bytes_per_pixel(&self.header),
+            &mut self.current,
+            &self.previous,
+        )?;
+
+        let expander = PixelExpander {
+            header: &self.header,
+            palette: self.palette.as_deref(),
+            transparency: self.transparency.as_deref(),
+        };
+        let pixel_len = self.layout.row_len() / self.layout.width as usize;
+        for (x, out) in row.chunks_exact_mut(pixel_len).enumerate() {
+            expander.expand(&self.current, x, out);
+        }
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.y += 1;
+        Ok(())
+    }
+}
+
+/// Joins the data of consecutive IDAT chunks back into one zlib stream, reading each chunk
+/// only once the previous one has been used up
+struct IdatReader<'a> {
+    reader: StreamReader<BufReader<&'a mut dyn Read>>,
+    data: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl Read for IdatReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.data.len() {
+            if self.finished || self.reader.is_empty()? {
+                return Ok(0);
+            }
+            let chunk = Chunk::read(&mut self.reader)?;
+            if chunk.chunk_type() != &PngChunkType::IDAT {
+                self.finished = true;
+                return Ok(0);
+            }
+            self.data = chunk.data().to_vec();
+            self.position = 0;
+        }
+        let len = buf.len().min(self.data.len() - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// The color model and bit depth `Image`s decoded from a PNG with `header` come out in
+fn output_format(header: &ImageHeader, has_transparency: bool) -> (ColorModel, u8) {
+    let color_model = match (header.color_type(), has_transparency) {
+        (ColorType::Grayscale, false) => ColorModel::Gray,
+        (ColorType::Grayscale, true) | (ColorType::GrayscaleAlpha, _) => ColorModel::GrayAlpha,
+        (ColorType::Truecolor | ColorType::Indexed, false) => ColorModel::Rgb,
+        _ => ColorModel::Rgba,
+    };
+    (color_model, if header.bit_depth() == 16 { 16 } else { 8 })
+}
+
+/// The PNG color type that stores `color_model` without a palette
+fn color_type(color_model: ColorModel) -> ColorType {
+    match color_model {
+        ColorModel::Gray => ColorType::Grayscale,
+        ColorModel::GrayAlpha => ColorType::GrayscaleAlpha,
+        ColorModel::Rgb => ColorType::Truecolor,
+        ColorModel::Rgba => ColorType::TruecolorAlpha,
+    }
+}
+
+fn compression(options: &PngOptions) -> Compression {
+    options
+        .compression_level
+        .map_or(Compression::default(), |level| {
+            Compression::new(level as u32)
+        })
+}
+
+/// Scales `sample` from `from` bits to `to` bits, rounding to the nearest value
+fn rescale(sample: u16, from: u8, to: u8) -> u16 {
+    let from_max = (1_u32 << from) - 1;
+    let to_max = (1_u32 << to) - 1;
+    ((sample as u32 * to_max + from_max / 2) / from_max) as u16
+}
+
+/// Packs samples into bytes at `bit_depth`, filling bytes from the most significant bit
+fn pack_samples(bit_depth: u8, samples: impl Iterator<Item = u16>) -> Vec<u8> {
+    match bit_depth {
+        16 => samples.flat_map(|sample| sample.to_be_bytes()).collect(),
+        8 => samples.map(|sample| sample as u8).collect(),
+        _ => {
+            let per_byte = 8 / bit_depth as usize;
+            let samples: Vec<u16> = samples.collect();
+            samples
+                .chunks(per_byte)
+                .map(|byte_samples| {
+                    byte_samples
+                        .iter()
+                        .enumerate()
+                        .fold(0, |byte, (idx, &sample)| {
+                            byte | (sample as u8) << (8 - bit_depth as usize * (idx + 1))
+                        })
+                })
+                .collect()
+        }
+    }
+}
+
+/// Bytes in one row of packed samples, not counting the filter type byte
+fn stride(header: &ImageHeader, width: usize) -> usize {
+    (width * header.color_type().channels() * header.bit_depth() as usize).div_ceil(8)
+}
+
+/// The distance filters look back to find the corresponding byte of the previous pixel
+fn bytes_per_pixel(header: &ImageHeader) -> usize {
+    (header.color_type().channels() * header.bit_depth() as usize)
+        .div_ceil(8)
+        .max(1)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let left_distance = (estimate - left as i16).abs();
+    let up_distance = (estimate - up as i16).abs();
+    let up_left_distance = (estimate - up_left as i16).abs();
+    if left_distance <= up_distance && left_distance <= up_left_distance {
+        left
+    } else if up_distance <= up_left_distance {
+        up
+    } else {
+        up_left
+    }
+}
+
+fn unfilter(filter_type: u8, pixel_len: usize, row: &mut [u8], previous: &[u8]) -> Result<()> {
+    for idx in 0..row.len() {
+        let left = if idx >= pixel_len {
+            row[idx - pixel_len]
+        } else {
+            0
+        };
+        let up = previous[idx];
+        let up_left = if idx >= pixel_len {
+            previous[idx - pixel_len]
+        } else {
+            0
+        };
+        let prediction = match filter_type {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => {
+                return Err(Error::malformed(
+                    "PNG",
+                    format!("unknown filter type {filter_type}"),
+                ))
+            }
+        };
+        row[idx] = row[idx].wrapping_add(prediction);
+    }
+    Ok(())
+}
+
+fn filter(filter_type: u8, pixel_len: usize, row: &[u8], previous: &[u8]) -> Vec<u8> {
+    (0..row.len())
+        .map(|idx| {
+            let left = if idx >= pixel_len {
+                row[idx - pixel_len]
+            } else {
+                0
+            };
+            let up = previous[idx];
+            let up_left = if idx >= pixel_len {
+                previous[idx - pixel_len]
+            } else {
+                0
+            };
+            let prediction = match filter_type {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                _ => paeth(left, up, up_left),
+            };
+            row[idx].wrapping_sub(prediction)
+        })
+        .collect()
+}
+
+fn best_filter(pixel_len: usize, row: &[u8], previous: &[u8]) -> (u8, Vec<u8>) {
+    (0..5)
+        .map(|filter_type| (filter_type, filter(filter_type, pixel_len, row, previous)))
+        .min_by_key(|(_, filtered)| {
+            filtered
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum::<u64>()
+        })
+        .expect("there is always at least one filter type")
+}
+
+/// Turns the packed samples of one unfiltered row into `Image` pixels
+struct PixelExpander<'a> {
+    header: &'a ImageHeader,
+    palette: Option<&'a [u8]>,
+    transparency: Option<&'a [u8]>,
+}
+
+impl PixelExpander<'_> {
+    /// Reads sample `idx` of `row` at the header's bit depth
+    fn sample(&self, row: &[u8], idx: usize) -> u16 {
+        match self.header.bit_depth() {
+            16 => u16::from_be_bytes([row[idx * 2], row[idx * 2 + 1]]),
+            8 => row[idx] as u16,
+            bit_depth => {
+                let bit_offset = idx * bit_depth as usize;
+                let shift = 8 - bit_depth as usize - bit_offset % 8;
+                (row[bit_offset / 8] >> shift) as u16 & ((1 << bit_depth) - 1)
+            }
+        }
+    }
+
+    fn expand(&self, row: &[u8], x: usize, out: &mut [u8]) {
+        let bit_depth = self.header.bit_depth();
+        let channels = self.header.color_type().channels();
+        let max = (1_u32 << bit_depth) - 1;
+        let mut samples: Vec<u16> = (0..channels)
+            .map(|channel| self.sample(row, x * channels + channel))
+            .collect();
+
+        match self.header.color_type() {
+            ColorType::Indexed => {
+                let idx = samples[0] as usize;
+                let palette = self.palette.unwrap_or_default();
+                let entry = palette.get(idx * 3..idx * 3 + 3).unwrap_or(&[0, 0, 0]);
+                samples = entry.iter().map(|&sample| sample as u16).collect();
+                if let Some(transparency) = self.transparency {
+                    samples.push(transparency.get(idx).copied().unwrap_or(255) as u16);
+                }
+            }
+            ColorType::Grayscale | ColorType::Truecolor => {
+                if let Some(transparency) = self.transparency {
+                    let key: Vec<u16> = transparency
+                        .chunks_exact(2)
+                        .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+                        .collect();
+                    let alpha = if key == samples { 0 } else { max as u16 };
+                    samples.push(alpha);
+                }
+                if bit_depth < 8 {
+                    for sample in samples.iter_mut() {
+                        *sample = (*sample as u32 * 255 / max) as u16;
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        for (idx, sample) in samples.iter().enumerate() {
+            match bit_depth {
+                16 => out[idx * 2..idx * 2 + 2].copy_from_slice(&sample.to_be_bytes()),
+                _ => out[idx] = *sample as u8,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::write::ZlibEncoder;
+
+    use super::*;
+
+    fn png_from_raw(header: ImageHeader, extra_chunks: Vec<Chunk>, raw: &[u8]) -> Png {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw).unwrap();
+        let mut chunks = vec![Chunk::new(PngChunkType::IHDR, header.as_bytes()).unwrap()];
+        chunks.extend(extra_chunks);
+        chunks.push(Chunk::new(PngChunkType::IDAT, encoder.finish().unwrap()).unwrap());
+        chunks.push(Chunk::new(PngChunkType::IEND, Vec::new()).unwrap());
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..5 * 3 * 4).map(|idx| (idx * 7 % 256) as u8).collect();
+        let image = Image::new(5, 3, ColorModel::Rgba, 8, data).unwrap();
+        let decoded = decode(&encode(&image, &PngOptions::default()).unwrap()).unwrap();
+        assert_eq!(decoded.color_model(), ColorModel::Rgba);
+        assert_eq!(decoded.data(), image.data());
+
+        let data: Vec<u8> = (0..4 * 4 * 2).map(|idx| (idx * 13 % 256) as u8).collect();
+        let image = Image::new(4, 4, ColorModel::Gray, 16, data).unwrap();
+        let decoded = decode(&encode(&image, &PngOptions::default()).unwrap()).unwrap();
+        assert_eq!(decoded.data(), image.data());
+    }
+
+    #[test]
+    fn test_round_trip_with_options() {
+        let data: Vec<u8> = (0..9 * 7)
+            .flat_map(|idx| {
+                [
+                    (idx % 3 * 100) as u8,
+                    0,
+                    50,
+                    if idx % 2 == 0 { 255 } else { 0 },
+                ]
+            })
+            .collect();
+        let image = Image::new(9, 7, ColorModel::Rgba, 8, data).unwrap();
+        let options = PngOptions {
+            compression_level: Some(9),
+            palette: true,
+            interlace: true,
+            ..PngOptions::default()
+        };
+        let png = encode(&image, &options).unwrap();
+        let header = png.image_header().unwrap();
+        assert_eq!(header.color_type(), ColorType::Indexed);
+        assert_eq!(header.bit_depth(), 4);
+        assert!(header.interlaced());
+        assert_eq!(decode(&png).unwrap().data(), image.data());
+
+        let too_shallow = PngOptions {
+            bit_depth: Some(2),
+            ..options
+        };
+        assert!(matches!(
+            encode(&image, &too_shallow),
+            Err(Error::LossyConversion(_))
+        ));
+    }
+
+    #[test]
+    fn test_encode_low_bit_gray() {
+        let image = Image::new(3, 2, ColorModel::Gray, 8, vec![0, 85, 170, 255, 255, 0]).unwrap();
+        let options = PngOptions {
+            bit_depth: Some(2),
+            ..PngOptions::default()
+        };
+        let png = encode(&image, &options).unwrap();
+        assert_eq!(png.image_header().unwrap().bit_depth(), 2);
+        assert_eq!(decode(&png).unwrap().data(), image.data());
+
+        let rgb = Image::new(1, 1, ColorModel::Rgb, 8, vec![0, 0, 0]).unwrap();
+        assert!(encode(&rgb, &options).is_err());
+    }
+
+    #[test]
+    fn test_decode_palette_with_transparency() {
+        let header = ImageHeader::new(3, 1, 2, ColorType::Indexed, false).unwrap();
+        let palette =
+            Chunk::new(PngChunkType::PLTE, vec![255, 0, 0, 0, 255, 0, 0, 0, 255]).unwrap();
+        let transparency =
+            Chunk::new(PngChunkType::try_from(*b"tRNS").unwrap(), vec![255, 0]).unwrap();
+        let png = png_from_raw(header, vec![palette, transparency], &[0, 0b00_01_10_00]);
+
+        let image = decode(&png).unwrap();
+        assert_eq!(image.color_model(), ColorModel::Rgba);
+        assert_eq!(
+            image.data(),
+            &[255, 0, 0, 255, 0, 255, 0, 0, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_decode_low_bit_gray() {
+        let header = ImageHeader::new(4, 1, 1, ColorType::Grayscale, false).unwrap();
+        let png = png_from_raw(header, vec![], &[0, 0b1010_0000]);
+        let image = decode(&png).unwrap();
+        assert_eq!(image.color_model(), ColorModel::Gray);
+        assert_eq!(image.data(), &[255, 0, 255, 0]);
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        // a 3x3 gray image: pass 1 holds (0,0), pass 4 holds (2,0), pass 5 holds (0,2) and (2,2),
+        // pass 6 holds (1,0) and (1,2), and pass 7 holds row 1
+        let header = ImageHeader::new(3, 3, 8, ColorType::Grayscale, true).unwrap();
+        let raw = [0, 1, 0, 3, 0, 7, 9, 0, 2, 0, 8, 0, 4, 5, 6];
+        let image = decode(&png_from_raw(header, vec![], &raw)).unwrap();
+        assert_eq!(image.data(), &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_filters_round_trip() {
+        let previous = [10, 20, 30, 40, 50, 60];
+        let row = [15, 5, 200, 45, 55, 0];
+        for filter_type in 0..5 {
+            let mut filtered = filter(filter_type, 3, &row, &previous);
+            unfilter(filter_type, 3, &mut filtered, &previous).unwrap();
+            assert_eq!(filtered, row);
+        }
+        assert!(unfilter(5, 3, &mut row.clone(), &previous).is_err());
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let header = ImageHeader::new(4, 4, 8, ColorType::Grayscale, false).unwrap();
+        let png = png_from_raw(header, vec![], &[0, 1, 2, 3, 4]);
+        assert!(decode(&png).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{Error, Result};
+
+/// The color types a PNG can declare in its IHDR chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Truecolor = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    TruecolorAlpha = 6,
+}
+
+impl ColorType {
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Truecolor => 3,
+            ColorType::TruecolorAlpha => 4,
+        }
+    }
+
+    fn allows_bit_depth(&self, bit_depth: u8) -> bool {
+        match self {
+            ColorType::Grayscale => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            ColorType::Indexed => matches!(bit_depth, 1 | 2 | 4 | 8),
+            _ => matches!(bit_depth, 8 | 16),
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Truecolor),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::TruecolorAlpha),
+            _ => Err(Error::invalid_argument(format!(
+                "{value} is not a valid PNG color type"
+            ))),
+        }
+    }
+}
+
+/// The contents of an IHDR chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageHeader {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    interlaced: bool,
+}
+
+impl ImageHeader {
+    pub fn new(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: ColorType,
+        interlaced: bool,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+            return Err(Error::invalid_argument(format!(
+                "{width}x{height} is not a valid PNG size"
+            )));
+        }
+        if !color_type.allows_bit_depth(bit_depth) {
+            return Err(Error::invalid_argument(format!(
+                "{color_type:?} PNGs can't have a bit depth of {bit_depth}"
+            )));
+        }
+        Ok(ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    /// Whether the image data is stored in Adam7 interlaced order
+    pub fn interlaced(&self) -> bool {
+        self.interlaced
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.width
+            .to_be_bytes()
+            .iter()
+            .chain(self.height.to_be_bytes().iter())
+            .chain(
+                [
+                    self.bit_depth,
+                    self.color_type as u8,
+                    0,
+                    0,
+                    self.interlaced as u8,
+                ]
+                .iter(),
+            )
+            .copied()
+            .collect()
+    }
+}
+
+impl TryFrom<&[u8]> for ImageHeader {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != 13 {
+            return Err(Error::malformed(
+                "PNG",
+                format!("IHDR chunks hold 13 bytes, not {}", value.len()),
+            ));
+        }
+        let mut buf: [u8; 4] = [0; 4];
+        buf.copy_from_slice(&value[0..4]);
+        let width = u32::from_be_bytes(buf);
+        buf.copy_from_slice(&value[4..8]);
+        let height = u32::from_be_bytes(buf);
+        if value[10] != 0 || value[11] != 0 {
+            return Err(Error::unsupported(
+                "PNG",
+                "compression or filter methods other than 0",
+            ));
+        }
+        let interlaced = match value[12] {
+            0 => false,
+            1 => true,
+            method => {
+                return Err(Error::unsupported(
+                    "PNG",
+                    format!("interlace method {method}"),
+                ))
+            }
+        };
+        ImageHeader::new(
+            width,
+            height,
+            value[8],
+            ColorType::try_from(value[9]).map_err(|e| e.into_malformed("PNG"))?,
+            interlaced,
+        )
+        .map_err(|e| e.into_malformed("PNG"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let header = ImageHeader::new(640, 480, 16, ColorType::TruecolorAlpha, true).unwrap();
+        let bytes = header.as_bytes();
+        assert_eq!(bytes.len(), 13);
+        assert_eq!(ImageHeader::try_from(bytes.as_ref()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(ImageHeader::new(0, 1, 8, ColorType::Grayscale, false).is_err());
+        assert!(ImageHeader::new(1, 1, 16, ColorType::Indexed, false).is_err());
+        assert!(ImageHeader::new(1, 1, 4, ColorType::Truecolor, false).is_err());
+
+        let mut bytes = ImageHeader::new(1, 1, 8, ColorType::Grayscale, false)
+            .unwrap()
+            .as_bytes();
+        bytes[12] = 2;
+        assert!(ImageHeader::try_from(bytes.as_ref()).is_err());
+        assert!(ImageHeader::try_from(&bytes[..12]).is_err());
+    }
+}
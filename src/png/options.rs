@@ -0,0 +1,46 @@
+use crate::{Error, Result};
+
+/// How PNG output is encoded. With every field left unset an existing PNG is written back
+/// out with its image data untouched; setting any field other than `max_idat_size` has
+/// the image re-encoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PngOptions {
+    /// zlib compression level, from 0 (store only) to 9 (smallest output)
+    pub compression_level: Option<u8>,
+    /// Bits per sample. Grayscale allows 1, 2, 4, 8 and 16, palettes 1 to 8, and every other
+    /// color type 8 or 16. Palettes default to the smallest depth that holds their colors.
+    pub bit_depth: Option<u8>,
+    /// Store pixels as indices into a PLTE palette, which fails for images with over 256 colors
+    pub palette: bool,
+    /// Write rows in Adam7 interlaced order
+    pub interlace: bool,
+    /// Largest IDAT chunk to write; existing chunks are kept if unset
+    pub max_idat_size: Option<u32>,
+}
+
+impl PngOptions {
+    /// Whether these options ask for the image data to be re-encoded
+    pub fn reencodes(&self) -> bool {
+        self.compression_level.is_some()
+            || self.bit_depth.is_some()
+            || self.palette
+            || self.interlace
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(level) = self.compression_level.filter(|&level| level > 9) {
+            return Err(Error::invalid_argument(format!(
+                "PNG compression levels run from 0 to 9, not {level}"
+            )));
+        }
+        match self.bit_depth {
+            Some(16) if self.palette => Err(Error::invalid_argument(
+                "PNG palettes can't have a bit depth of 16",
+            )),
+            Some(1 | 2 | 4 | 8 | 16) | None => Ok(()),
+            Some(bit_depth) => Err(Error::invalid_argument(format!(
+                "PNG bit depths are 1, 2, 4, 8 or 16, not {bit_depth}"
+            ))),
+        }
+    }
+}
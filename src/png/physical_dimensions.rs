@@ -0,0 +1,97 @@
+use crate::{
+    metadata::{Resolution, ResolutionUnit},
+    Error, Result,
+};
+
+/// The contents of a pHYs chunk: pixels per unit along each axis
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhysicalDimensions {
+    pub x: u32,
+    pub y: u32,
+    /// 1 when the unit is the meter, 0 when only the aspect ratio is known
+    pub unit: u8,
+}
+
+impl PhysicalDimensions {
+    pub const METER: u8 = 1;
+
+    pub fn to_resolution(&self) -> Resolution {
+        Resolution {
+            x: self.x as f64,
+            y: self.y as f64,
+            unit: match self.unit {
+                PhysicalDimensions::METER => ResolutionUnit::Meter,
+                _ => ResolutionUnit::Unknown,
+            },
+        }
+    }
+
+    /// Rounds `resolution` to whole pixels per meter, or `None` if it's too large to store
+    pub fn from_resolution(resolution: Resolution) -> Option<Self> {
+        let (resolution, unit) = match resolution.in_unit(ResolutionUnit::Meter) {
+            Some(per_meter) => (per_meter, PhysicalDimensions::METER),
+            None => (resolution, 0),
+        };
+        let round = |density: f64| {
+            let density = density.round();
+            (density >= 1.0 && density <= u32::MAX as f64).then_some(density as u32)
+        };
+        Some(PhysicalDimensions {
+            x: round(resolution.x)?,
+            y: round(resolution.y)?,
+            unit,
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.x.to_be_bytes().to_vec();
+        bytes.extend(self.y.to_be_bytes());
+        bytes.push(self.unit);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for PhysicalDimensions {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != 9 {
+            return Err(Error::malformed(
+                "PNG",
+                format!("pHYs chunks hold 9 bytes, not {}", value.len()),
+            ));
+        }
+        let density =
+            |at: usize| u32::from_be_bytes(value[at..at + 4].try_into().expect("4 bytes"));
+        Ok(PhysicalDimensions {
+            x: density(0),
+            y: density(4),
+            unit: value[8],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_round_trip() {
+        let dpi = Resolution {
+            x: 300.0,
+            y: 150.0,
+            unit: ResolutionUnit::Inch,
+        };
+        let phys = PhysicalDimensions::from_resolution(dpi).unwrap();
+        assert_eq!((phys.x, phys.y, phys.unit), (11811, 5906, 1));
+        let read = PhysicalDimensions::try_from(phys.as_bytes().as_slice()).unwrap();
+        assert_eq!(read, phys);
+        let back = read.to_resolution().in_unit(ResolutionUnit::Inch).unwrap();
+        assert_eq!((back.x.round(), back.y.round()), (300.0, 150.0));
+
+        let aspect = PhysicalDimensions::try_from([0, 0, 0, 2, 0, 0, 0, 1, 0].as_slice()).unwrap();
+        assert_eq!(aspect.to_resolution().unit, ResolutionUnit::Unknown);
+        assert_eq!(aspect.to_resolution().in_unit(ResolutionUnit::Inch), None);
+        assert!(PhysicalDimensions::try_from([0; 8].as_slice()).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{Error, Result};
+
+/// The contents of an sBIT chunk: how many bits of each channel were significant in the
+/// original data, listed in the order the image's color type stores its channels
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignificantBits {
+    bits: Vec<u8>,
+}
+
+impl SignificantBits {
+    pub fn new(bits: Vec<u8>) -> Result<Self> {
+        if bits.is_empty() || bits.len() > 4 {
+            return Err(Error::invalid_argument(format!(
+                "sBIT chunks describe 1 to 4 channels, not {}",
+                bits.len()
+            )));
+        }
+        if let Some(&invalid) = bits.iter().find(|&&bits| bits == 0 || bits > 16) {
+            return Err(Error::invalid_argument(format!(
+                "{invalid} is not a valid number of significant bits"
+            )));
+        }
+        Ok(SignificantBits { bits })
+    }
+
+    pub fn bits(&self) -> &[u8] {
+        &self.bits
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.bits.clone()
+    }
+}
+
+impl TryFrom<&[u8]> for SignificantBits {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        SignificantBits::new(value.to_vec()).map_err(|e| e.into_malformed("PNG"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let bits = SignificantBits::new(vec![5, 6, 5]).unwrap();
+        assert_eq!(
+            SignificantBits::try_from(bits.as_bytes().as_ref()).unwrap(),
+            bits
+        );
+    }
+
+    #[test]
+    fn test_invalid_bits() {
+        assert!(SignificantBits::new(vec![]).is_err());
+        assert!(SignificantBits::new(vec![8; 5]).is_err());
+        assert!(SignificantBits::new(vec![8, 0]).is_err());
+        assert!(SignificantBits::new(vec![17]).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    pub frequency: u16,
+}
+
+/// The contents of an sPLT chunk: a named palette suggested for displays that can't show
+/// the image's full range of colors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    name: String,
+    sample_depth: u8,
+    entries: Vec<PaletteEntry>,
+}
+
+impl SuggestedPalette {
+    pub fn new(name: String, sample_depth: u8, entries: Vec<PaletteEntry>) -> Result<Self> {
+        if name.is_empty() || name.len() > 79 || name.contains('\0') {
+            return Err(Error::invalid_argument(format!(
+                "\"{name}\" is not a valid sPLT palette name"
+            )));
+        }
+        if sample_depth != 8 && sample_depth != 16 {
+            return Err(Error::invalid_argument(format!(
+                "sPLT samples are 8 or 16 bits, not {sample_depth}"
+            )));
+        }
+        if sample_depth == 8 {
+            let too_deep = |entry: &PaletteEntry| {
+                [entry.red, entry.green, entry.blue, entry.alpha]
+                    .iter()
+                    .any(|&sample| sample > 255)
+            };
+            if entries.iter().any(too_deep) {
+                return Err(Error::invalid_argument(
+                    "8-bit sPLT entries can't hold samples above 255",
+                ));
+            }
+        }
+        Ok(SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn sample_depth(&self) -> u8 {
+        self.sample_depth
+    }
+
+    pub fn entries(&self) -> &[PaletteEntry] {
+        &self.entries
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.name.bytes().collect();
+        bytes.push(0);
+        bytes.push(self.sample_depth);
+        for entry in &self.entries {
+            for sample in [entry.red, entry.green, entry.blue, entry.alpha] {
+                match self.sample_depth {
+                    8 => bytes.push(sample as u8),
+                    _ => bytes.extend_from_slice(&sample.to_be_bytes()),
+                }
+            }
+            bytes.extend_from_slice(&entry.frequency.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for SuggestedPalette {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let name_end = value
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(|| Error::malformed("PNG", "sPLT palette name isn't null-terminated"))?;
+        // palette names are Latin-1, which maps directly onto the first 256 code points
+        let name: String = value[..name_end].iter().map(|&byte| byte as char).collect();
+        let sample_depth = *value
+            .get(name_end + 1)
+            .ok_or_else(|| Error::malformed("PNG", "sPLT chunk ends before its sample depth"))?;
+        let entry_len = match sample_depth {
+            8 => 6,
+            16 => 10,
+            _ => {
+                return Err(Error::malformed(
+                    "PNG",
+                    format!("sPLT samples are 8 or 16 bits, not {sample_depth}"),
+                ))
+            }
+        };
+        let entry_bytes = &value[name_end + 2..];
+        if !entry_bytes.len().is_multiple_of(entry_len) {
+            return Err(Error::malformed(
+                "PNG",
+                "sPLT chunk doesn't hold a whole number of entries",
+            ));
+        }
+
+        let entries = entry_bytes
+            .chunks_exact(entry_len)
+            .map(|entry| {
+                let sample = |idx: usize| match sample_depth {
+                    8 => entry[idx] as u16,
+                    _ => u16::from_be_bytes([entry[idx * 2], entry[idx * 2 + 1]]),
+                };
+                PaletteEntry {
+                    red: sample(0),
+                    green: sample(1),
+                    blue: sample(2),
+                    alpha: sample(3),
+                    frequency: u16::from_be_bytes([entry[entry_len - 2], entry[entry_len - 1]]),
+                }
+            })
+            .collect();
+        SuggestedPalette::new(name, sample_depth, entries).map_err(|e| e.into_malformed("PNG"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_entries() -> Vec<PaletteEntry> {
+        vec![
+            PaletteEntry {
+                red: 255,
+                green: 0,
+                blue: 0,
+                alpha: 255,
+                frequency: 10,
+            },
+            PaletteEntry {
+                red: 0,
+                green: 128,
+                blue: 64,
+                alpha: 0,
+                frequency: 2,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for sample_depth in [8, 16] {
+            let palette =
+                SuggestedPalette::new("web safe".to_string(), sample_depth, testing_entries())
+                    .unwrap();
+            let bytes = palette.as_bytes();
+            assert_eq!(SuggestedPalette::try_from(bytes.as_ref()).unwrap(), palette);
+        }
+    }
+
+    #[test]
+    fn test_invalid_palette() {
+        assert!(SuggestedPalette::new(String::new(), 8, vec![]).is_err());
+        assert!(SuggestedPalette::new("x".to_string(), 4, vec![]).is_err());
+        assert!(SuggestedPalette::try_from(&b"no terminator"[..]).is_err());
+        assert!(SuggestedPalette::try_from(&b"name\0\x08\0\0\0"[..]).is_err());
+
+        let mut deep = testing_entries();
+        deep[0].red = 256;
+        assert!(SuggestedPalette::new("x".to_string(), 8, deep).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Result};
+
+/// The contents of a tIME chunk: when the image was last modified, in UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Time {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+impl Time {
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Result<Self> {
+        // a second of 60 is allowed for leap seconds
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(Error::invalid_argument(format!(
+                "{year}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02} is not a valid tIME"
+            )));
+        }
+        Ok(Time {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    /// The current time, for stamping an image that has just been modified
+    pub fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Time::from_unix_seconds(secs)
+    }
+
+    fn from_unix_seconds(secs: u64) -> Self {
+        // converts days since the epoch to a civil date, from Howard Hinnant's date algorithms
+        let days = (secs / 86400) as i64 + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        } as u8;
+        let year = (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }) as u16;
+
+        let secs_of_day = secs % 86400;
+        Time {
+            year,
+            month,
+            day,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+        }
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.year
+            .to_be_bytes()
+            .iter()
+            .chain([self.month, self.day, self.hour, self.minute, self.second].iter())
+            .copied()
+            .collect()
+    }
+}
+
+impl TryFrom<&[u8]> for Time {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != 7 {
+            return Err(Error::malformed(
+                "PNG",
+                format!("tIME chunks hold 7 bytes, not {}", value.len()),
+            ));
+        }
+        Time::new(
+            u16::from_be_bytes([value[0], value[1]]),
+            value[2],
+            value[3],
+            value[4],
+            value[5],
+            value[6],
+        )
+        .map_err(|e| e.into_malformed("PNG"))
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let time = Time::new(2023, 6, 1, 12, 30, 5).unwrap();
+        assert_eq!(Time::try_from(time.as_bytes().as_ref()).unwrap(), time);
+        assert_eq!(time.to_string(), "2023-06-01T12:30:05Z");
+    }
+
+    #[test]
+    fn test_invalid_time() {
+        assert!(Time::new(2023, 13, 1, 0, 0, 0).is_err());
+        assert!(Time::new(2023, 1, 0, 0, 0, 0).is_err());
+        assert!(Time::new(2023, 1, 1, 24, 0, 0).is_err());
+        assert!(Time::try_from(&[7, 231, 1, 1][..]).is_err());
+    }
+
+    #[test]
+    fn test_from_unix_seconds() {
+        assert_eq!(
+            Time::from_unix_seconds(0),
+            Time::new(1970, 1, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            Time::from_unix_seconds(1709210096),
+            Time::new(2024, 2, 29, 12, 34, 56).unwrap()
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::io::{BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    convert::ConvertOptions,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{FormatDetails, ImageInfo, MetadataInfo, QoiDetails},
+    metadata::Metadata,
+    png::Png,
+    scanline::RowSource,
+    ConvertibleImage, Error, Result,
+};
+
+use self::{header::QoiHeader, options::QoiOptions};
+
+pub mod header;
+pub mod image_data;
+pub mod options;
+
+/// Registry entry for QOI files
+pub const CODEC: Codec = Codec {
+    name: "QOI",
+    extensions: &["qoi"],
+    magic: &[QoiHeader::MAGIC],
+    check_signature: None,
+    decoder: Some(decode_with::<Qoi>),
+    encoder: Some(write_qoi),
+    row_decoder: Some(read_qoi_rows),
+    row_encoder: Some(write_qoi_rows),
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a QOI file from its header for the registry
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let header = QoiHeader::read(&mut StreamReader::new("QOI", BufReader::new(reader)))?;
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: header.width,
+        height: header.height,
+        color_model: if header.channels == 4 { "rgba" } else { "rgb" },
+        bit_depth: 8,
+        compression: "QOI",
+        interlaced: false,
+        palette_size: None,
+        metadata: MetadataInfo::default(),
+        details: FormatDetails::Qoi(QoiDetails {
+            channels: header.channels,
+            colorspace: header.colorspace.name(),
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// Reads a QOI file a row at a time for the registry
+fn read_qoi_rows<'a>(
+    reader: &'a mut dyn Read,
+    _options: &ConvertOptions,
+) -> Result<Box<dyn RowSource + 'a>> {
+    image_data::decode_rows(reader)
+}
+
+/// Encodes `png` as a QOI file for the registry
+fn write_qoi(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    Qoi::from_image_with(&png.to_image()?, &options.qoi)?.to_writer(writer)
+}
+
+/// Writes rows out as a QOI file for the registry; QOI has nowhere to put metadata
+fn write_qoi_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    image_data::encode_rows(source, &options.qoi, writer)
+}
+
+pub struct Qoi {
+    header: QoiHeader,
+    /// The chunks and end marker, as stored
+    data: Vec<u8>,
+}
+
+impl Qoi {
+    pub fn header(&self) -> &QoiHeader {
+        &self.header
+    }
+
+    pub fn image_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decodes the chunks into an `Image`
+    pub fn to_image(&self) -> Result<Image> {
+        image_data::decode(self)
+    }
+
+    /// Encodes `image` as a new `Qoi`
+    pub fn from_image(image: &Image) -> Result<Qoi> {
+        image_data::encode(image, &QoiOptions::default())
+    }
+
+    /// Encodes `image` as a new `Qoi` with the header `options` asks for
+    pub fn from_image_with(image: &Image, options: &QoiOptions) -> Result<Qoi> {
+        image_data::encode(image, options)
+    }
+}
+
+impl TryFrom<&[u8]> for Qoi {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Qoi::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Qoi {
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("QOI", BufReader::new(reader));
+        let header = QoiHeader::read(&mut reader)?;
+        Ok(Qoi {
+            header,
+            data: reader.read_rest()?,
+        })
+    }
+
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.header.as_bytes())?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Qoi::from_image(&png.to_image()?)
+    }
+
+    fn to_png(&self) -> Result<Png> {
+        Png::from_image(&self.to_image()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{image::ColorModel, png::options::PngOptions, qoi::header::Colorspace};
+
+    /// An image with runs, gentle gradients, sharp edges, repeated colors and changing alpha,
+    /// so every op gets used
+    fn test_image(color_model: ColorModel) -> Image {
+        let (width, height) = (67, 13);
+        let mut data: Vec<u8> = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let rgba: [u8; 4] = match (x / 16, y % 4) {
+                    (0, _) => [10, 20, 30, 255],
+                    (1, _) => [x as u8 * 2, x as u8 * 3, y as u8, 255],
+                    (2, 0) => [(x * 37 % 256) as u8, (y * 91 % 256) as u8, 200, 255],
+                    (2, _) => [[255, 0, 0, 255], [0, 0, 255, 255]][x as usize % 2],
+                    _ => [x as u8, 100, 50, (x * y % 256) as u8],
+                };
+                data.extend(&rgba[..color_model.channels()]);
+            }
+        }
+        Image::new(width, height, color_model, 8, data).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_through_png() {
+        for color_model in [ColorModel::Rgb, ColorModel::Rgba] {
+            let png =
+                Png::from_image_with(&test_image(color_model), &PngOptions::default()).unwrap();
+            let pixels = Png::try_from(png.to_bytes().as_slice())
+                .unwrap()
+                .to_image()
+                .unwrap();
+
+            let bytes = Qoi::from_image(&pixels).unwrap().to_bytes();
+            let qoi = Qoi::try_from(bytes.as_slice()).unwrap();
+            assert_eq!(qoi.header().channels as usize, color_model.channels());
+            let decoded = qoi.to_image().unwrap();
+            assert_eq!(decoded.color_model(), color_model);
+            assert_eq!(decoded.data(), pixels.data());
+
+            let back = Png::try_from(qoi.to_png().unwrap().to_bytes().as_slice())
+                .unwrap()
+                .to_image()
+                .unwrap();
+            assert_eq!(back.data(), pixels.data());
+        }
+    }
+
+    #[test]
+    fn test_header_and_errors() {
+        let mut gray = Image::new(
+            2,
+            1,
+            ColorModel::GrayAlpha,
+            16,
+            vec![0x12, 0x34, 0xff, 0xff, 0, 0, 0, 0],
+        )
+        .unwrap();
+        let options = QoiOptions {
+            colorspace: Colorspace::Linear,
+        };
+        assert!(matches!(
+            Qoi::from_image_with(&gray, &options),
+            Err(Error::LossyConversion(_))
+        ));
+        gray.reduce_depth();
+        let bytes = Qoi::from_image_with(&gray, &options).unwrap().to_bytes();
+        assert_eq!(&bytes[..4], b"qoif");
+        assert_eq!(bytes[12..14], [4, 1]);
+        let qoi = Qoi::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(qoi.header().colorspace, Colorspace::Linear);
+        assert_eq!(
+            qoi.to_image().unwrap().data(),
+            [0x12, 0x12, 0x12, 0xff, 0, 0, 0, 0]
+        );
+
+        let truncated = Qoi::try_from(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(truncated.to_image(), Err(Error::Malformed { .. })));
+        let mut bad = bytes.clone();
+        bad[12] = 2;
+        assert!(Qoi::try_from(bad.as_slice()).is_err());
+        let mut huge = bytes.clone();
+        huge[4..12].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]);
+        // a single row within the pixel limit can still be too long to set aside
+        let mut wide = bytes.clone();
+        wide[4..12].copy_from_slice(&[0x17, 0xd7, 0x84, 0x00, 0, 0, 0, 1]);
+        for forged in [huge, wide] {
+            assert!(matches!(
+                Qoi::try_from(forged.as_slice()),
+                Err(Error::Malformed {
+                    offset: Some(4),
+                    ..
+                })
+            ));
+            assert!(image_data::decode_rows(&mut forged.as_slice()).is_err());
+        }
+    }
+}
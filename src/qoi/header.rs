@@ -0,0 +1,131 @@
+use std::io::BufRead;
+
+use crate::{
+    byte_reader::StreamReader,
+    image::ColorModel,
+    scanline::{RowLayout, MAX_ROW_LEN},
+    Error, Result,
+};
+
+/// How the color channels of a QOI file are meant to be read. Decoders don't change the
+/// samples either way; the flag is only informative.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Colorspace {
+    /// sRGB color channels with linear alpha
+    #[default]
+    Srgb,
+    /// Every channel linear
+    Linear,
+}
+
+impl Colorspace {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Colorspace::Srgb => "sRGB",
+            Colorspace::Linear => "linear",
+        }
+    }
+}
+
+/// The 14 bytes a QOI file starts with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QoiHeader {
+    pub width: u32,
+    pub height: u32,
+    /// 3 for RGB, 4 for RGBA
+    pub channels: u8,
+    pub colorspace: Colorspace,
+}
+
+impl QoiHeader {
+    pub const LENGTH: usize = 14;
+    pub const MAGIC: &'static [u8; 4] = b"qoif";
+    /// The reference decoder's limit on the image size. Rows are bounded separately, since
+    /// one this many pixels wide would still take gigabytes.
+    pub const MAX_PIXELS: u64 = 400_000_000;
+
+    /// Reads the header, leaving `reader` at the first chunk
+    pub fn read<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Self> {
+        if &reader.read_array::<4>()? != QoiHeader::MAGIC {
+            return Err(Error::malformed_at("QOI", 0, "invalid signature"));
+        }
+        let width = reader.read_u32_be()?;
+        let height = reader.read_u32_be()?;
+        let channels = reader.read_u8()?;
+        if !matches!(channels, 3 | 4) {
+            return Err(Error::malformed_at(
+                "QOI",
+                12,
+                format!("{channels} channels, where only 3 and 4 are allowed"),
+            ));
+        }
+        let colorspace = match reader.read_u8()? {
+            0 => Colorspace::Srgb,
+            1 => Colorspace::Linear,
+            other => {
+                return Err(Error::malformed_at(
+                    "QOI",
+                    13,
+                    format!("unknown colorspace {other}"),
+                ))
+            }
+        };
+        if width == 0 || height == 0 {
+            return Err(Error::malformed_at(
+                "QOI",
+                4,
+                format!("{width}x{height} is not a valid image size"),
+            ));
+        }
+        if width as u64 * height as u64 > QoiHeader::MAX_PIXELS {
+            return Err(Error::malformed_at(
+                "QOI",
+                4,
+                format!(
+                    "{width}x{height} is more than the {} pixels QOI allows",
+                    QoiHeader::MAX_PIXELS
+                ),
+            ));
+        }
+        let header = QoiHeader {
+            width,
+            height,
+            channels,
+            colorspace,
+        };
+        if header.layout().row_len() > MAX_ROW_LEN {
+            return Err(Error::malformed_at(
+                "QOI",
+                4,
+                format!("rows {width} pixels wide are too long to read"),
+            ));
+        }
+        Ok(header)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(QoiHeader::LENGTH);
+        bytes.extend(QoiHeader::MAGIC);
+        bytes.extend(self.width.to_be_bytes());
+        bytes.extend(self.height.to_be_bytes());
+        bytes.push(self.channels);
+        bytes.push(match self.colorspace {
+            Colorspace::Srgb => 0,
+            Colorspace::Linear => 1,
+        });
+        bytes
+    }
+
+    /// How the pixels are laid out once decoded
+    pub fn layout(&self) -> RowLayout {
+        RowLayout {
+            width: self.width,
+            height: self.height,
+            color_model: match self.channels {
+                3 => ColorModel::Rgb,
+                _ => ColorModel::Rgba,
+            },
+            bit_depth: 8,
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    image::Image,
+    metadata::Metadata,
+    scanline::{read_image, ImageRows, RowLayout, RowSource},
+    Error, Result,
+};
+
+use super::{header::QoiHeader, options::QoiOptions, Qoi};
+
+/// A full 8-bit RGB pixel follows
+const OP_RGB: u8 = 0xfe;
+/// A full 8-bit RGBA pixel follows
+const OP_RGBA: u8 = 0xff;
+/// The low 6 bits index the array of recently seen pixels
+const OP_INDEX: u8 = 0x00;
+/// Red, green and blue each differ from the last pixel by -2 to 1
+const OP_DIFF: u8 = 0x40;
+/// Green differs by -32 to 31, and red and blue by -8 to 7 more than green did
+const OP_LUMA: u8 = 0x80;
+/// The last pixel repeats 1 to 62 times
+const OP_RUN: u8 = 0xc0;
+/// Masks the two-bit tag of the ops that aren't OP_RGB or OP_RGBA
+const TAG_MASK: u8 = 0xc0;
+
+/// The longest run one OP_RUN can hold; 63 and 64 would clash with OP_RGB and OP_RGBA
+const MAX_RUN: u8 = 62;
+
+/// Seven zero bytes and a one mark the end of the chunks
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// What encoder and decoder both keep track of between pixels
+struct State {
+    /// Pixels seen so far, stored by their hash
+    index: [[u8; 4]; 64],
+    previous: [u8; 4],
+    /// Repeats of `previous` still to hand out or write
+    run: u8,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            index: [[0; 4]; 64],
+            previous: [0, 0, 0, 255],
+            run: 0,
+        }
+    }
+}
+
+/// Position of `pixel` in the index array
+fn hash([red, green, blue, alpha]: [u8; 4]) -> usize {
+    (red as usize * 3 + green as usize * 5 + blue as usize * 7 + alpha as usize * 11) % 64
+}
+
+/// Decodes the chunks of `qoi` into an `Image`
+pub fn decode(qoi: &Qoi) -> Result<Image> {
+    read_image(&mut QoiRows::new(
+        *qoi.header(),
+        StreamReader::new("QOI", qoi.image_data()),
+    ))
+}
+
+/// Reads a QOI file from `reader` a row at a time; the format is decoded front to back
+/// anyway, so only the decoder's state is kept between rows
+pub fn decode_rows(reader: &mut dyn Read) -> Result<Box<dyn RowSource + '_>> {
+    let mut reader = StreamReader::new("QOI", BufReader::new(reader));
+    let header = QoiHeader::read(&mut reader)?;
+    Ok(Box::new(QoiRows::new(header, reader)))
+}
+
+/// Reads the size of a QOI file from its header
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    Ok(QoiHeader::read(&mut StreamReader::new("QOI", BufReader::new(reader)))?.layout())
+}
+
+/// Hands out the rows of a QOI file as its chunks are decoded
+struct QoiRows<R> {
+    layout: RowLayout,
+    reader: StreamReader<R>,
+    state: State,
+    rows_left: u32,
+    metadata: Metadata,
+}
+
+impl<R: BufRead> QoiRows<R> {
+    fn new(header: QoiHeader, reader: StreamReader<R>) -> Self {
+        QoiRows {
+            layout: header.layout(),
+            reader,
+            state: State::new(),
+            rows_left: header.height,
+            metadata: Metadata::default(),
+        }
+    }
+
+    /// Decodes the next pixel, reading a chunk unless a run is still going
+    fn next_pixel(&mut self) -> Result<[u8; 4]> {
+        let state = &mut self.state;
+        if state.run > 0 {
+            state.run -= 1;
+            return Ok(state.previous);
+        }
+        let [red, green, blue, alpha] = state.previous;
+        let op = self.reader.read_u8()?;
+        let pixel = match op {
+            OP_RGB => {
+                let [red, green, blue] = self.reader.read_array()?;
+                [red, green, blue, alpha]
+            }
+            OP_RGBA => self.reader.read_array()?,
+            _ => match op & TAG_MASK {
+                OP_INDEX => state.index[op as usize],
+                OP_DIFF => {
+                    let diff = |shift: u8| (op >> shift & 0x03).wrapping_sub(2);
+                    [
+                        red.wrapping_add(diff(4)),
+                        green.wrapping_add(diff(2)),
+                        blue.wrapping_add(diff(0)),
+                        alpha,
+                    ]
+                }
+                OP_LUMA => {
+                    let second = self.reader.read_u8()?;
+                    let green_diff = (op & 0x3f).wrapping_sub(32);
+                    let red_diff = green_diff.wrapping_add(second >> 4).wrapping_sub(8);
+                    let blue_diff = green_diff.wrapping_add(second & 0x0f).wrapping_sub(8);
+                    [
+                        red.wrapping_add(red_diff),
+                        green.wrapping_add(green_diff),
+                        blue.wrapping_add(blue_diff),
+                        alpha,
+                    ]
+                }
+                // OP_RUN, which covers this pixel and up to 61 more
+                _ => {
+                    state.run = op & 0x3f;
+                    state.previous
+                }
+            },
+        };
+        state.index[hash(pixel)] = pixel;
+        state.previous = pixel;
+        Ok(pixel)
+    }
+}
+
+impl<R: BufRead> RowSource for QoiRows<R> {
+    fn layout(&self) -> RowLayout {
+        self.layout
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        let channels = self.layout.color_model.channels();
+        for pixel in row.chunks_exact_mut(channels) {
+            let decoded = self.next_pixel()?;
+            pixel.copy_from_slice(&decoded[..channels]);
+        }
+        self.rows_left -= 1;
+        if self.rows_left == 0 {
+            if self.state.run > 0 {
+                return Err(self.reader.error("a run goes on past the last pixel"));
+            }
+            if self.reader.read_array::<8>()? != END_MARKER {
+                return Err(self.reader.error("the end marker is missing"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes `image` as a new `Qoi`
+pub fn encode(image: &Image, options: &QoiOptions) -> Result<Qoi> {
+    let header = header_for(image.layout(), options)?;
+    let mut data: Vec<u8> = Vec::new();
+    encode_chunks(&mut ImageRows::new(image.clone()), &mut data)?;
+    Ok(Qoi { header, data })
+}
+
+/// Writes the rows of `source` out as a QOI file, header first
+pub fn encode_rows(
+    source: &mut dyn RowSource,
+    options: &QoiOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    writer.write_all(&header_for(source.layout(), options)?.as_bytes())?;
+    encode_chunks(source, writer)
+}
+
+/// The header for an image laid out as `layout`. QOI only holds 8-bit RGB and RGBA, so
+/// grayscale is widened and 16-bit samples are refused.
+fn header_for(layout: RowLayout, options: &QoiOptions) -> Result<QoiHeader> {
+    layout.require_8_bit("QOI")?;
+    Ok(QoiHeader {
+        width: layout.width,
+        height: layout.height,
+        channels: if layout.color_model.has_alpha() { 4 } else { 3 },
+        colorspace: options.colorspace,
+    })
+}
+
+/// Writes the chunks for every pixel of `source`, then the end marker
+fn encode_chunks(source: &mut dyn RowSource, writer: &mut dyn Write) -> Result<()> {
+    let layout = source.layout();
+    if layout.width == 0 || layout.height == 0 {
+        return Err(Error::invalid_argument("QOI images can't be empty"));
+    }
+    let mut state = State::new();
+    let mut row: Vec<u8> = vec![0; layout.row_len()];
+    let mut chunks: Vec<u8> = Vec::new();
+    for _ in 0..layout.height {
+        source.read_row(&mut row)?;
+        chunks.clear();
+        for x in 0..layout.width as usize {
+            encode_pixel(&mut state, layout.rgba8(&row, x), &mut chunks);
+        }
+        writer.write_all(&chunks)?;
+    }
+    if state.run > 0 {
+        writer.write_all(&[OP_RUN | (state.run - 1)])?;
+    }
+    writer.write_all(&END_MARKER)?;
+    Ok(())
+}
+
+/// Appends the chunk for `pixel`, or adds it to the current run
+fn encode_pixel(state: &mut State, pixel: [u8; 4], chunks: &mut Vec<u8>) {
+    if pixel == state.previous {
+        state.run += 1;
+        if state.run == MAX_RUN {
+            chunks.push(OP_RUN | (state.run - 1));
+            state.run = 0;
+        }
+        return;
+    }
+    if state.run > 0 {
+        chunks.push(OP_RUN | (state.run - 1));
+        state.run = 0;
+    }
+
+    let index = hash(pixel);
+    let [red, green, blue, alpha] = pixel;
+    let [previous_red, previous_green, previous_blue, previous_alpha] = state.previous;
+    state.previous = pixel;
+    if state.index[index] == pixel {
+        chunks.push(OP_INDEX | index as u8);
+        return;
+    }
+    state.index[index] = pixel;
+    if alpha != previous_alpha {
+        chunks.extend([OP_RGBA, red, green, blue, alpha]);
+        return;
+    }
+
+    let red_diff = red.wrapping_sub(previous_red) as i8;
+    let green_diff = green.wrapping_sub(previous_green) as i8;
+    let blue_diff = blue.wrapping_sub(previous_blue) as i8;
+    let red_green = red_diff.wrapping_sub(green_diff);
+    let blue_green = blue_diff.wrapping_sub(green_diff);
+    let small = |diff: i8| (-2..=1).contains(&diff);
+    if small(red_diff) && small(green_diff) && small(blue_diff) {
+        chunks.push(
+            OP_DIFF
+                | ((red_diff + 2) as u8) << 4
+                | ((green_diff + 2) as u8) << 2
+                | (blue_diff + 2) as u8,
+        );
+    } else if (-32..=31).contains(&green_diff)
+        && (-8..=7).contains(&red_green)
+        && (-8..=7).contains(&blue_green)
+    {
+        chunks.push(OP_LUMA | (green_diff + 32) as u8);
+        chunks.push(((red_green + 8) as u8) << 4 | (blue_green + 8) as u8);
+    } else {
+        chunks.extend([OP_RGB, red, green, blue]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_op() {
+        let pixels: [[u8; 4]; 8] = [
+            // a run of the starting pixel
+            [0, 0, 0, 255],
+            [1, 0, 255, 255],  // OP_DIFF
+            [15, 10, 3, 255],  // OP_LUMA
+            [200, 10, 3, 255], // OP_RGB
+            [200, 10, 3, 7],   // OP_RGBA
+            [1, 0, 255, 255],  // OP_INDEX
+            [1, 0, 255, 255],  // run
+            [1, 0, 255, 255],
+        ];
+        let mut state = State::new();
+        let mut chunks: Vec<u8> = Vec::new();
+        for pixel in pixels {
+            encode_pixel(&mut state, pixel, &mut chunks);
+        }
+        assert_eq!(state.run, 2);
+        chunks.push(OP_RUN | (state.run - 1));
+        assert_eq!(
+            chunks,
+            [
+                OP_RUN,
+                OP_DIFF | 3 << 4 | 2 << 2 | 1,
+                OP_LUMA | 42,
+                12 << 4 | 2,
+                OP_RGB,
+                200,
+                10,
+                3,
+                OP_RGBA,
+                200,
+                10,
+                3,
+                7,
+                OP_INDEX | hash([1, 0, 255, 255]) as u8,
+                OP_RUN | 1,
+            ]
+        );
+
+        chunks.extend(END_MARKER);
+        let mut rows = QoiRows::new(
+            QoiHeader {
+                width: 4,
+                height: 2,
+                channels: 4,
+                colorspace: Default::default(),
+            },
+            StreamReader::new("QOI", &chunks[..]),
+        );
+        let image = read_image(&mut rows).unwrap();
+        assert_eq!(image.data(), pixels.as_flattened());
+    }
+}
@@ -0,0 +1,8 @@
+use super::header::Colorspace;
+
+/// How QOI output is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QoiOptions {
+    /// Colorspace recorded in the header; the samples are written as they are either way
+    pub colorspace: Colorspace,
+}
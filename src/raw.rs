@@ -0,0 +1,59 @@
+use std::io::{Read, Write};
+
+use crate::{
+    convert::ConvertOptions,
+    format::Codec,
+    metadata::Metadata,
+    png::Png,
+    scanline::{ImageRows, RowSource},
+    ConvertibleImage, Result,
+};
+
+pub mod image_data;
+pub mod options;
+
+/// Registry entry for raw pixel dumps. With no header to go by, reading needs the layout
+/// in `ConvertOptions::raw`, and there's no signature to recognise the format from.
+pub const CODEC: Codec = Codec {
+    name: "raw",
+    extensions: &["raw"],
+    magic: &[],
+    check_signature: None,
+    decoder: Some(read_raw),
+    encoder: Some(write_raw),
+    row_decoder: Some(read_raw_rows),
+    row_encoder: Some(write_raw_rows),
+    probe: None,
+    inspector: None,
+};
+
+/// Decodes a dump laid out as `options.raw` describes for the registry
+fn read_raw(reader: &mut dyn Read, options: &ConvertOptions) -> Result<Box<dyn ConvertibleImage>> {
+    Ok(Box::new(Png::from_image(&image_data::decode(
+        reader,
+        &options.raw,
+    )?)?))
+}
+
+/// Reads a dump laid out as `options.raw` describes a row at a time for the registry
+fn read_raw_rows<'a>(
+    reader: &'a mut dyn Read,
+    options: &ConvertOptions,
+) -> Result<Box<dyn RowSource + 'a>> {
+    image_data::decode_rows(reader, &options.raw)
+}
+
+/// Writes `png`'s pixels out as `options.raw` describes for the registry
+fn write_raw(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    image_data::encode_rows(&mut ImageRows::new(png.to_image()?), &options.raw, writer)
+}
+
+/// Writes rows out as a dump for the registry; dumps have nowhere to put metadata
+fn write_raw_rows(
+    source: &mut dyn RowSource,
+    _metadata: &Metadata,
+    options: &ConvertOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    image_data::encode_rows(source, &options.raw, writer)
+}
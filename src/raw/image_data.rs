@@ -0,0 +1,230 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::{
+    byte_reader::StreamReader,
+    image::{ColorModel, Image},
+    metadata::Metadata,
+    scanline::{read_image, RowLayout, RowSource, MAX_ROW_LEN},
+    Error, Result,
+};
+
+use super::options::{ChannelOrder, RawOptions, SampleType};
+
+/// Decodes the dump in `reader`, laid out as `options` describes, into an `Image`
+pub fn decode(reader: &mut dyn Read, options: &RawOptions) -> Result<Image> {
+    read_image(decode_rows(reader, options)?.as_mut())
+}
+
+/// Reads a dump laid out as `options` describes from `reader` a row at a time
+pub fn decode_rows<'a>(
+    reader: &'a mut dyn Read,
+    options: &RawOptions,
+) -> Result<Box<dyn RowSource + 'a>> {
+    options.validate()?;
+    let (width, height) = options.size()?;
+    let stored_len = width as usize * options.channels.channels() * options.sample.byte_len();
+    if stored_len > MAX_ROW_LEN {
+        return Err(Error::invalid_argument(format!(
+            "raw rows {width} pixels wide are too long to read"
+        )));
+    }
+    Ok(Box::new(RawRows {
+        layout: RowLayout {
+            width,
+            height,
+            color_model: options.channels.color_model(),
+            bit_depth: options.sample.bit_depth(),
+        },
+        channels: options.channels,
+        sample: options.sample,
+        reader: StreamReader::new("raw", BufReader::new(reader)),
+        stored: vec![0; stored_len],
+        metadata: Metadata::default(),
+    }))
+}
+
+/// Hands out the rows of a dump, putting the channels in order and the samples into the
+/// form `Image` keeps them in
+struct RawRows<R> {
+    layout: RowLayout,
+    channels: ChannelOrder,
+    sample: SampleType,
+    reader: StreamReader<R>,
+    /// One row as stored
+    stored: Vec<u8>,
+    metadata: Metadata,
+}
+
+impl<R: BufRead> RowSource for RawRows<R> {
+    fn layout(&self) -> RowLayout {
+        self.layout
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        self.reader.fill(&mut self.stored)?;
+        let channels = self.channels.channels();
+        let indices = self.channels.rgba_indices();
+        let sample_len = self.sample.byte_len();
+        let out_len = self.layout.bit_depth as usize / 8;
+        for (pixel, stored) in self.stored.chunks_exact(channels * sample_len).enumerate() {
+            for (&channel, bytes) in indices.iter().zip(stored.chunks_exact(sample_len)) {
+                let at = (pixel * channels + channel) * out_len;
+                match self.sample {
+                    SampleType::U8 => row[at] = bytes[0],
+                    _ => row[at..at + 2].copy_from_slice(&read_sample(self.sample, bytes)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads one stored sample wider than a byte as a 16-bit big-endian one
+fn read_sample(sample: SampleType, bytes: &[u8]) -> [u8; 2] {
+    let float = |value: f32| (value.clamp(0.0, 1.0) * 65535.0).round() as u16;
+    let value = match sample {
+        SampleType::U8 => bytes[0] as u16 * 257,
+        SampleType::U16Le => u16::from_le_bytes([bytes[0], bytes[1]]),
+        SampleType::U16Be => u16::from_be_bytes([bytes[0], bytes[1]]),
+        SampleType::F32Le => float(f32::from_le_bytes(bytes.try_into().unwrap())),
+        SampleType::F32Be => float(f32::from_be_bytes(bytes.try_into().unwrap())),
+    };
+    value.to_be_bytes()
+}
+
+/// Appends a 16-bit sample in the stored form
+fn write_sample(sample: SampleType, value: u16, out: &mut Vec<u8>) {
+    let float = value as f32 / 65535.0;
+    match sample {
+        SampleType::U8 => out.push((value >> 8) as u8),
+        SampleType::U16Le => out.extend(value.to_le_bytes()),
+        SampleType::U16Be => out.extend(value.to_be_bytes()),
+        SampleType::F32Le => out.extend(float.to_le_bytes()),
+        SampleType::F32Be => out.extend(float.to_be_bytes()),
+    }
+}
+
+/// Writes the rows of `source` out as a dump laid out as `options` describes, scaling the
+/// samples to the stored type. The size in `options` is ignored.
+pub fn encode_rows(
+    source: &mut dyn RowSource,
+    options: &RawOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    options.validate()?;
+    let layout = source.layout();
+    if layout.color_model.has_alpha() && !options.channels.color_model().has_alpha() {
+        return Err(Error::lossy(format!(
+            "raw {} has no alpha channel; flatten the image or write RGBA instead",
+            options.channels.name()
+        )));
+    }
+    let gray = matches!(layout.color_model, ColorModel::Gray | ColorModel::GrayAlpha);
+    let mut row: Vec<u8> = vec![0; layout.row_len()];
+    let mut out: Vec<u8> = Vec::new();
+    for _ in 0..layout.height {
+        source.read_row(&mut row)?;
+        out.clear();
+        for x in 0..layout.width as usize {
+            let rgba = layout.rgba16(&row, x);
+            if options.channels == ChannelOrder::Gray
+                && !gray
+                && (rgba[0] != rgba[1] || rgba[1] != rgba[2])
+            {
+                return Err(Error::lossy(
+                    "raw gray only holds shades of gray, and the image has other colors",
+                ));
+            }
+            for &channel in options.channels.rgba_indices() {
+                write_sample(options.sample, rgba[channel], &mut out);
+            }
+        }
+        writer.write_all(&out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanline::ImageRows;
+
+    #[test]
+    fn test_layouts() {
+        let image = Image::new(
+            2,
+            1,
+            ColorModel::Rgba,
+            8,
+            vec![255, 128, 0, 255, 10, 20, 30, 0],
+        )
+        .unwrap();
+        let encode = |channels, sample| {
+            let options = RawOptions {
+                channels,
+                sample,
+                ..RawOptions::default()
+            };
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_rows(&mut ImageRows::new(image.clone()), &options, &mut bytes).map(|_| bytes)
+        };
+        let bgra = encode(ChannelOrder::Bgra, SampleType::U8).unwrap();
+        assert_eq!(bgra, [0, 128, 255, 255, 30, 20, 10, 0]);
+        let u16 = encode(ChannelOrder::Rgba, SampleType::U16Le).unwrap();
+        assert_eq!(u16[..4], [255, 255, 128, 128]);
+        let float = encode(ChannelOrder::Bgra, SampleType::F32Be).unwrap();
+        assert_eq!(float[8..12], 1.0f32.to_be_bytes());
+        assert!(matches!(
+            encode(ChannelOrder::Rgb, SampleType::U8),
+            Err(Error::LossyConversion(_))
+        ));
+
+        for (bytes, sample) in [
+            (&bgra, SampleType::U8),
+            (&u16, SampleType::U16Le),
+            (&float, SampleType::F32Be),
+        ] {
+            let options = RawOptions {
+                width: Some(2),
+                height: Some(1),
+                channels: match sample {
+                    SampleType::U16Le => ChannelOrder::Rgba,
+                    _ => ChannelOrder::Bgra,
+                },
+                sample,
+            };
+            let decoded = decode(&mut bytes.as_slice(), &options).unwrap();
+            let pixels: Vec<[u8; 4]> = (0..2).map(|pixel| decoded.rgba8(pixel)).collect();
+            assert_eq!(pixels, [[255, 128, 0, 255], [10, 20, 30, 0]]);
+        }
+
+        let gray = RawOptions {
+            width: Some(2),
+            height: Some(2),
+            channels: ChannelOrder::Gray,
+            sample: SampleType::U8,
+        };
+        assert!(matches!(
+            decode(&mut &[1, 2, 3][..], &gray),
+            Err(Error::Malformed { .. })
+        ));
+        let no_size = RawOptions {
+            height: None,
+            ..gray
+        };
+        assert!(decode(&mut &[1, 2, 3, 4][..], &no_size).is_err());
+        let too_wide = RawOptions {
+            width: Some(u32::MAX),
+            sample: SampleType::F32Le,
+            ..gray
+        };
+        assert!(matches!(
+            decode(&mut &[1, 2, 3, 4][..], &too_wide),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{image::ColorModel, Error, Result};
+
+/// Which channels each pixel of a raw dump holds, in the order they're stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelOrder {
+    #[default]
+    Rgba,
+    Bgra,
+    Rgb,
+    Gray,
+}
+
+impl ChannelOrder {
+    pub fn channels(&self) -> usize {
+        match self {
+            ChannelOrder::Rgba | ChannelOrder::Bgra => 4,
+            ChannelOrder::Rgb => 3,
+            ChannelOrder::Gray => 1,
+        }
+    }
+
+    /// The color model the pixels decode to
+    pub fn color_model(&self) -> ColorModel {
+        match self {
+            ChannelOrder::Rgba | ChannelOrder::Bgra => ColorModel::Rgba,
+            ChannelOrder::Rgb => ColorModel::Rgb,
+            ChannelOrder::Gray => ColorModel::Gray,
+        }
+    }
+
+    /// Where each stored channel comes from in an RGBA pixel
+    pub fn rgba_indices(&self) -> &'static [usize] {
+        match self {
+            ChannelOrder::Rgba => &[0, 1, 2, 3],
+            ChannelOrder::Bgra => &[2, 1, 0, 3],
+            ChannelOrder::Rgb => &[0, 1, 2],
+            ChannelOrder::Gray => &[0],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelOrder::Rgba => "RGBA",
+            ChannelOrder::Bgra => "BGRA",
+            ChannelOrder::Rgb => "RGB",
+            ChannelOrder::Gray => "gray",
+        }
+    }
+}
+
+/// How each sample of a raw dump is stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleType {
+    #[default]
+    U8,
+    U16Le,
+    U16Be,
+    /// Floats from 0.0 to 1.0; values outside that range are clamped when read
+    F32Le,
+    F32Be,
+}
+
+impl SampleType {
+    /// Bytes taken up by one sample
+    pub fn byte_len(&self) -> usize {
+        match self {
+            SampleType::U8 => 1,
+            SampleType::U16Le | SampleType::U16Be => 2,
+            SampleType::F32Le | SampleType::F32Be => 4,
+        }
+    }
+
+    /// Bits per sample of the decoded image; floats keep 16
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            SampleType::U8 => 8,
+            _ => 16,
+        }
+    }
+}
+
+/// The layout of a raw pixel dump, which has no header to describe it. Rows are stored top
+/// first with no padding between them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawOptions {
+    /// Needed for reading; output takes the image's own size
+    pub width: Option<u32>,
+    /// Needed for reading; output takes the image's own size
+    pub height: Option<u32>,
+    pub channels: ChannelOrder,
+    pub sample: SampleType,
+}
+
+impl RawOptions {
+    /// Checks for settings that are out of range
+    pub fn validate(&self) -> Result<()> {
+        if self.width == Some(0) || self.height == Some(0) {
+            return Err(Error::invalid_argument(
+                "raw images have to be at least 1 pixel wide and high",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The size to read a dump as, which has to be given
+    pub fn size(&self) -> Result<(u32, u32)> {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => Ok((width, height)),
+            _ => Err(Error::invalid_argument(
+                "reading raw pixels needs the image's width and height",
+            )),
+        }
+    }
+}
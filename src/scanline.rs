@@ -0,0 +1,394 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::{
+    image::{AlphaMatte, Color, ColorModel, Image},
+    metadata::Metadata,
+    Error, Result,
+};
+
+/// Longest row, in bytes, that decoders accept from a header. Rows are set aside whole
+/// before their data is read, so this bounds what a header lying about the width can cost.
+pub const MAX_ROW_LEN: usize = 1 << 28;
+
+/// Rows held in memory before a `Spill` moves them out to a temporary file
+pub const SPILL_THRESHOLD: usize = 64 << 20;
+
+/// The shape of the rows a `RowSource` produces. Rows are laid out as in `Image`: no
+/// padding, and 16-bit samples are big-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RowLayout {
+    pub width: u32,
+    pub height: u32,
+    pub color_model: ColorModel,
+    pub bit_depth: u8,
+}
+
+impl RowLayout {
+    /// Bytes taken up by one row of pixels
+    pub fn row_len(&self) -> usize {
+        self.width as usize * self.color_model.channels() * (self.bit_depth as usize / 8)
+    }
+
+    /// Reads the sample at `idx` (counted in samples, not bytes) of `row`
+    pub fn sample(&self, row: &[u8], idx: usize) -> u16 {
+        match self.bit_depth {
+            16 => u16::from_be_bytes([row[idx * 2], row[idx * 2 + 1]]),
+            _ => row[idx] as u16,
+        }
+    }
+
+    /// Reads pixel `x` of `row` as 8-bit RGBA
+    pub fn rgba8(&self, row: &[u8], x: usize) -> [u8; 4] {
+        let channels = self.color_model.channels();
+        let shift = self.bit_depth - 8;
+        let sample = |channel: usize| (self.sample(row, x * channels + channel) >> shift) as u8;
+        match self.color_model {
+            ColorModel::Gray => [sample(0), sample(0), sample(0), 255],
+            ColorModel::GrayAlpha => [sample(0), sample(0), sample(0), sample(1)],
+            ColorModel::Rgb => [sample(0), sample(1), sample(2), 255],
+            ColorModel::Rgba => [sample(0), sample(1), sample(2), sample(3)],
+        }
+    }
+
+    /// Reads pixel `x` of `row` as 16-bit RGBA, scaling 8-bit samples up to the full range
+    pub fn rgba16(&self, row: &[u8], x: usize) -> [u16; 4] {
+        let channels = self.color_model.channels();
+        let scale = if self.bit_depth == 8 { 257 } else { 1 };
+        let sample = |channel: usize| self.sample(row, x * channels + channel) * scale;
+        match self.color_model {
+            ColorModel::Gray => [sample(0), sample(0), sample(0), u16::MAX],
+            ColorModel::GrayAlpha => [sample(0), sample(0), sample(0), sample(1)],
+            ColorModel::Rgb => [sample(0), sample(1), sample(2), u16::MAX],
+            ColorModel::Rgba => [sample(0), sample(1), sample(2), sample(3)],
+        }
+    }
+
+    /// Fails for 16-bit rows, for encoders of formats that only hold 8-bit samples. Those
+    /// are reduced only when asked to, as alpha is only flattened when asked to.
+    pub fn require_8_bit(&self, format: &str) -> Result<()> {
+        match self.bit_depth {
+            16 => Err(Error::lossy(format!(
+                "{format} only holds 8-bit samples, and the image has 16-bit ones; reduce the depth first"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An image that is handed over one row at a time, from the top, so that neither side of a
+/// conversion has to hold all of its pixels at once
+pub trait RowSource {
+    fn layout(&self) -> RowLayout;
+    /// Metadata found ahead of the pixel data
+    fn metadata(&self) -> &Metadata;
+    /// Fills `row` with the next row of pixels. Called once for each row of the image.
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()>;
+}
+
+/// Hands out the rows of an image that has already been decoded, for formats that can't be
+/// read a row at a time
+pub struct ImageRows {
+    image: Image,
+    y: u32,
+}
+
+impl ImageRows {
+    pub fn new(image: Image) -> Self {
+        ImageRows { image, y: 0 }
+    }
+}
+
+impl RowSource for ImageRows {
+    fn layout(&self) -> RowLayout {
+        self.image.layout()
+    }
+
+    fn metadata(&self) -> &Metadata {
+        self.image.metadata()
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        if self.y >= self.image.height() {
+            return Err(Error::invalid_argument(
+                "read past the last row of the image",
+            ));
+        }
+        row.copy_from_slice(self.image.row(self.y));
+        self.y += 1;
+        Ok(())
+    }
+}
+
+/// Composites each row of `source` onto an opaque matte as it goes by, as
+/// `Image::flatten_alpha` does for a whole image
+pub struct FlattenRows<'a> {
+    source: Box<dyn RowSource + 'a>,
+    matte: Option<AlphaMatte>,
+    buffer: Vec<u8>,
+}
+
+impl<'a> FlattenRows<'a> {
+    /// Without an explicit `matte` the source's background color is used, falling back to white
+    pub fn new(source: Box<dyn RowSource + 'a>, matte: Option<Color>) -> Self {
+        let layout = source.layout();
+        let matte = matte
+            .or(source.metadata().background())
+            .unwrap_or(Color::WHITE);
+        FlattenRows {
+            matte: AlphaMatte::new(layout.color_model, layout.bit_depth, matte),
+            buffer: vec![0; layout.row_len()],
+            source,
+        }
+    }
+}
+
+impl RowSource for FlattenRows<'_> {
+    fn layout(&self) -> RowLayout {
+        let layout = self.source.layout();
+        match &self.matte {
+            Some(matte) => RowLayout {
+                color_model: matte.color_model(),
+                ..layout
+            },
+            None => layout,
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        self.source.metadata()
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        match &self.matte {
+            Some(matte) => {
+                self.source.read_row(&mut self.buffer)?;
+                matte.flatten(&self.buffer, row);
+                Ok(())
+            }
+            None => self.source.read_row(row),
+        }
+    }
+}
+
+/// Reduces each row of `source` to 8 bits per sample as it goes by, as
+/// `Image::reduce_depth` does for a whole image
+pub struct ReduceDepthRows<'a> {
+    source: Box<dyn RowSource + 'a>,
+    buffer: Vec<u8>,
+}
+
+impl<'a> ReduceDepthRows<'a> {
+    pub fn new(source: Box<dyn RowSource + 'a>) -> Self {
+        let layout = source.layout();
+        ReduceDepthRows {
+            buffer: vec![0; layout.row_len()],
+            source,
+        }
+    }
+}
+
+impl RowSource for ReduceDepthRows<'_> {
+    fn layout(&self) -> RowLayout {
+        RowLayout {
+            bit_depth: 8,
+            ..self.source.layout()
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        self.source.metadata()
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<()> {
+        match self.source.layout().bit_depth {
+            16 => {
+                self.source.read_row(&mut self.buffer)?;
+                for (out, sample) in row.iter_mut().zip(self.buffer.chunks_exact(2)) {
+                    *out = sample[0];
+                }
+                Ok(())
+            }
+            _ => self.source.read_row(row),
+        }
+    }
+}
+
+/// Reads every remaining row of `source` into an `Image`, for encoders that need to see the
+/// whole image at once. The image grows as rows arrive, so a header claiming more rows than
+/// the data holds fails before memory is set aside for all of them.
+pub fn read_image(source: &mut dyn RowSource) -> Result<Image> {
+    let layout = source.layout();
+    let row_len = layout.row_len();
+    let mut row: Vec<u8> = vec![0; row_len];
+    let mut data: Vec<u8> = Vec::new();
+    for _ in 0..layout.height {
+        source.read_row(&mut row)?;
+        data.extend_from_slice(&row);
+    }
+    let mut image = Image::new(
+        layout.width,
+        layout.height,
+        layout.color_model,
+        layout.bit_depth,
+        data,
+    )?;
+    image.set_metadata(source.metadata().clone());
+    Ok(image)
+}
+
+/// Somewhere to put data that has to be read back in a different order than it arrived,
+/// such as the rows of a bottom-up BMP. Data stays in memory until it grows past
+/// `threshold` bytes, then moves to a temporary file that is deleted when the `Spill` is.
+pub struct Spill {
+    memory: Vec<u8>,
+    file: Option<File>,
+    threshold: usize,
+    len: u64,
+}
+
+impl Spill {
+    pub fn new(threshold: usize) -> Self {
+        Spill {
+            memory: Vec::new(),
+            file: None,
+            threshold,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the data has outgrown memory and moved to a file
+    pub fn is_on_disk(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Adds `bytes` to the end of the data
+    pub fn append(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.file.is_none() && self.memory.len() + bytes.len() > self.threshold {
+            let mut file = tempfile::tempfile()?;
+            file.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some(file);
+        }
+        match &mut self.file {
+            Some(file) => {
+                file.seek(SeekFrom::End(0))?;
+                file.write_all(bytes)?;
+            }
+            None => self.memory.extend_from_slice(bytes),
+        }
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Copies everything `reader` has left onto the end of the data
+    pub fn append_from(&mut self, reader: &mut dyn Read) -> Result<()> {
+        let mut buffer: Vec<u8> = vec![0; 64 * 1024];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.append(&buffer[..read])?;
+        }
+    }
+
+    /// Fills `buf` with the data starting at `offset`
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= self.len);
+        if end.is_none() {
+            return Err(Error::invalid_argument(format!(
+                "read of {} bytes at {offset} is past the {} bytes held",
+                buf.len(),
+                self.len
+            )));
+        }
+        match &mut self.file {
+            Some(file) => {
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(buf)?;
+            }
+            None => buf.copy_from_slice(&self.memory[offset as usize..offset as usize + buf.len()]),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_moves_to_disk() {
+        let mut spill = Spill::new(4);
+        spill.append(&[1, 2, 3]).unwrap();
+        assert!(!spill.is_on_disk());
+        spill.append(&[4, 5, 6]).unwrap();
+        assert!(spill.is_on_disk());
+        spill.append_from(&mut &[7, 8][..]).unwrap();
+        assert_eq!(spill.len(), 8);
+
+        let mut buf = [0; 3];
+        spill.read_at(2, &mut buf).unwrap();
+        assert_eq!(buf, [3, 4, 5]);
+        assert!(spill.read_at(6, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_flatten_rows() {
+        let image = Image::new(
+            2,
+            2,
+            ColorModel::Rgba,
+            8,
+            vec![255, 0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 128, 9, 9, 9, 255],
+        )
+        .unwrap();
+        let mut flattened = image.clone();
+        flattened.flatten_alpha(None);
+
+        let mut rows = FlattenRows::new(Box::new(ImageRows::new(image)), None);
+        assert_eq!(rows.layout().color_model, ColorModel::Rgb);
+        let streamed = read_image(&mut rows).unwrap();
+        assert_eq!(streamed.data(), flattened.data());
+        assert!(rows.read_row(&mut [0; 6]).is_err());
+    }
+
+    #[test]
+    fn test_reduce_depth_rows() {
+        let image = Image::new(
+            2,
+            1,
+            ColorModel::GrayAlpha,
+            16,
+            vec![0x12, 0x34, 0xff, 0xff, 0xab, 0xcd, 0, 0x80],
+        )
+        .unwrap();
+        assert!(matches!(
+            image.layout().require_8_bit("BMP"),
+            Err(Error::LossyConversion(_))
+        ));
+        let mut reduced = image.clone();
+        reduced.reduce_depth();
+        assert_eq!(reduced.data(), [0x12, 0xff, 0xab, 0]);
+        assert!(reduced.layout().require_8_bit("BMP").is_ok());
+
+        let mut rows = ReduceDepthRows::new(Box::new(ImageRows::new(image)));
+        assert_eq!(rows.layout().bit_depth, 8);
+        assert_eq!(read_image(&mut rows).unwrap().data(), reduced.data());
+    }
+}
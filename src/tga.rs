@@ -0,0 +1,412 @@
+use std::io::{BufReader, Read, Write};
+
+use crate::{
+    byte_reader::{ByteReader, StreamReader},
+    convert::ConvertOptions,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{FormatDetails, ImageInfo, MetadataInfo, TgaDetails},
+    metadata::Metadata,
+    png::Png,
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    extension::{TgaExtension, TgaFooter},
+    header::{ImageKind, TgaHeader},
+    options::TgaOptions,
+};
+
+pub mod extension;
+pub mod header;
+pub mod image_data;
+pub mod options;
+
+/// Registry entry for TGA files, which have no signature and are only known by extension
+pub const CODEC: Codec = Codec {
+    name: "TGA",
+    extensions: &["tga", "icb", "vda", "vst"],
+    magic: &[],
+    check_signature: None,
+    decoder: Some(decode_with::<Tga>),
+    encoder: Some(write_tga),
+    row_decoder: None,
+    row_encoder: None,
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a TGA from its headers and extension area for the registry
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let tga = Tga::from_reader(reader)?;
+    let header = tga.header();
+    let alpha = image_data::uses_alpha(header, tga.extension());
+    let (color_model, bit_depth) = match header.kind()? {
+        ImageKind::ColorMapped => ("indexed", header.pixel_depth),
+        ImageKind::TrueColor if alpha => ("rgba", 8),
+        ImageKind::TrueColor if header.pixel_depth < 24 => ("rgb", 5),
+        ImageKind::TrueColor => ("rgb", 8),
+        ImageKind::Grayscale if alpha => ("gray+alpha", 8),
+        ImageKind::Grayscale => ("gray", 8),
+    };
+    let extension = tga.extension();
+    let text = |text: &str| Some(text.to_string()).filter(|text| !text.is_empty());
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: header.width as u32,
+        height: header.height as u32,
+        color_model,
+        bit_depth,
+        compression: if header.is_run_length_encoded() {
+            "RLE"
+        } else {
+            "none"
+        },
+        interlaced: false,
+        palette_size: (header.color_map_type == 1).then_some(header.color_map_length as usize),
+        metadata: MetadataInfo::from(&tga.metadata()),
+        details: FormatDetails::Tga(TgaDetails {
+            version: if tga.footer.is_some() { "2.0" } else { "1.0" },
+            image_type: header.image_type,
+            image_id: text(&String::from_utf8_lossy(tga.image_id())),
+            origin: match (header.top_down(), header.right_to_left()) {
+                (false, false) => "bottom-left",
+                (false, true) => "bottom-right",
+                (true, false) => "top-left",
+                (true, true) => "top-right",
+            },
+            alpha_bits: header.alpha_bits(),
+            color_map_entry_size: (header.color_map_type == 1)
+                .then_some(header.color_map_entry_size),
+            author: extension.and_then(|extension| text(&extension.author_name)),
+            comments: extension.and_then(|extension| text(&extension.author_comments)),
+            job: extension.and_then(|extension| text(&extension.job_name)),
+            software: extension.and_then(|extension| text(&extension.software_id)),
+            saved: extension
+                .and_then(|extension| extension.date_time)
+                .map(|time| time.to_string()),
+            attributes_type: extension.map(|extension| extension.attributes_type),
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// Encodes `png` as a TGA for the registry
+fn write_tga(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    Tga::from_image_with(&png.to_image()?, &options.tga)?.to_writer(writer)
+}
+
+pub struct Tga {
+    header: TgaHeader,
+    image_id: Vec<u8>,
+    /// The color map as stored, empty if there is none
+    color_map: Vec<u8>,
+    data: Vec<u8>,
+    extension: Option<TgaExtension>,
+    /// The TGA 2.0 footer, if the file was read with one
+    footer: Option<TgaFooter>,
+}
+
+impl Tga {
+    pub fn header(&self) -> &TgaHeader {
+        &self.header
+    }
+
+    pub fn image_id(&self) -> &[u8] {
+        &self.image_id
+    }
+
+    pub fn color_map(&self) -> &[u8] {
+        &self.color_map
+    }
+
+    pub fn image_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn extension(&self) -> Option<&TgaExtension> {
+        self.extension.as_ref()
+    }
+
+    /// The background color, which TGA keeps as the extension area's key color
+    pub fn metadata(&self) -> Metadata {
+        image_data::metadata(self.extension())
+    }
+
+    /// Decodes the pixels into an `Image`
+    pub fn to_image(&self) -> Result<Image> {
+        image_data::decode(self)
+    }
+
+    /// Encodes `image` as a new `Tga`
+    pub fn from_image(image: &Image) -> Result<Tga> {
+        image_data::encode(image, &TgaOptions::default())
+    }
+
+    /// Encodes `image` as a new `Tga` laid out as `options` asks
+    pub fn from_image_with(image: &Image, options: &TgaOptions) -> Result<Tga> {
+        image_data::encode(image, options)
+    }
+
+    /// Bytes ahead of the image data: the header, image ID and color map
+    fn prefix_len(&self) -> usize {
+        TgaHeader::LENGTH + self.image_id.len() + self.color_map.len()
+    }
+}
+
+impl TryFrom<&[u8]> for Tga {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Tga::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Tga {
+    /// Reads the whole file, since the footer saying where the extension area is comes last
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("TGA", BufReader::new(reader));
+        let header_bytes = reader.read_bytes(TgaHeader::LENGTH)?;
+        let header = TgaHeader::read(&mut ByteReader::new("TGA", &header_bytes))?;
+        let image_id = reader.read_bytes(header.id_length as usize)?;
+        let color_map = reader.read_bytes(header.color_map_len())?;
+        let prefix_len = reader.position();
+        let mut data = reader.read_rest()?;
+
+        let (end, footer) = image_data::split_footer(prefix_len, &data);
+        let extension = match footer {
+            Some(footer) if footer.extension_offset != 0 => {
+                let offset = (footer.extension_offset as usize)
+                    .checked_sub(prefix_len)
+                    .filter(|&offset| offset < data.len())
+                    .ok_or_else(|| {
+                        Error::malformed(
+                            "TGA",
+                            format!(
+                                "the extension area offset {} is outside the file",
+                                footer.extension_offset
+                            ),
+                        )
+                    })?;
+                Some(TgaExtension::read(
+                    &mut ByteReader::new("TGA", &data).at(offset)?,
+                )?)
+            }
+            _ => None,
+        };
+        data.truncate(end);
+        Ok(Tga {
+            header,
+            image_id,
+            color_map,
+            data,
+            extension,
+            footer,
+        })
+    }
+
+    /// Writes the file, with a TGA 2.0 footer pointing at the extension area if there is one
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.header.as_bytes())?;
+        writer.write_all(&self.image_id)?;
+        writer.write_all(&self.color_map)?;
+        writer.write_all(&self.data)?;
+        if let Some(extension) = &self.extension {
+            writer.write_all(&extension.as_bytes())?;
+            let footer = TgaFooter {
+                extension_offset: (self.prefix_len() + self.data.len()) as u32,
+                developer_offset: 0,
+            };
+            writer.write_all(&footer.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn to_png(&self) -> Result<Png> {
+        Png::from_image(&self.to_image()?)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Tga::metadata(self))
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Tga::from_image(&png.to_image()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    use crate::{
+        format::Registry,
+        image::{Color, ColorModel},
+    };
+
+    fn gradient(color_model: ColorModel) -> Image {
+        let len = 7 * 3 * color_model.channels();
+        let data: Vec<u8> = (0..len).map(|idx| (idx * 29 % 256) as u8).collect();
+        Image::new(7, 3, color_model, 8, data).unwrap()
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for color_model in [
+            ColorModel::Gray,
+            ColorModel::GrayAlpha,
+            ColorModel::Rgb,
+            ColorModel::Rgba,
+        ] {
+            let image = gradient(color_model);
+            for (rle, top_down, color_map) in [
+                (false, false, false),
+                (true, false, false),
+                (false, true, false),
+                (true, true, true),
+                (false, false, true),
+            ] {
+                let options = TgaOptions {
+                    rle,
+                    top_down,
+                    color_map,
+                    image_id: Some("test".to_string()),
+                };
+                let bytes = Tga::from_image_with(&image, &options).unwrap().to_bytes();
+                let tga = Tga::try_from(bytes.as_slice()).unwrap();
+                assert_eq!(tga.image_id(), b"test");
+                let decoded = tga.to_image().unwrap();
+                // color maps with alpha come back as RGBA, even for gray images
+                let expected = match color_map && color_model.has_alpha() {
+                    true => ColorModel::Rgba,
+                    false => color_model,
+                };
+                assert_eq!(decoded.color_model(), expected, "{options:?}");
+                for pixel in 0..21 {
+                    assert_eq!(decoded.rgba8(pixel), image.rgba8(pixel), "{options:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_extension_area() {
+        let mut image = gradient(ColorModel::Rgba);
+        image
+            .metadata_mut()
+            .set_background(Some(Color::from_rgb8(10, 20, 30)));
+        let bytes = Tga::from_image(&image).unwrap().to_bytes();
+        assert!(bytes.ends_with(TgaFooter::SIGNATURE));
+        let tga = Tga::try_from(bytes.as_slice()).unwrap();
+        let extension = tga.extension().unwrap();
+        assert_eq!(extension.software_id, "modular-image-converter");
+        assert_eq!(extension.key_color, [30, 20, 10, 255]);
+        assert_eq!(tga.image_data().len(), 7 * 3 * 4);
+        assert_eq!(
+            tga.metadata().background(),
+            Some(Color::from_rgb8(10, 20, 30))
+        );
+
+        // the extension area can say the alpha bits are junk
+        let mut tga = tga;
+        tga.extension.as_mut().unwrap().attributes_type = 2;
+        let decoded = Tga::try_from(tga.to_bytes().as_slice())
+            .unwrap()
+            .to_image()
+            .unwrap();
+        assert_eq!(decoded.color_model(), ColorModel::Rgb);
+    }
+
+    #[test]
+    fn test_read_original_format() {
+        // a 2x2 bottom-up 16-bit image with no footer; the attribute bit marks opaque pixels
+        let mut bytes = TgaHeader {
+            id_length: 0,
+            color_map_type: 0,
+            image_type: 2,
+            color_map_first: 0,
+            color_map_length: 0,
+            color_map_entry_size: 0,
+            x_origin: 0,
+            y_origin: 0,
+            width: 2,
+            height: 2,
+            pixel_depth: 16,
+            descriptor: 1,
+        }
+        .as_bytes();
+        for pixel in [0x801fu16, 0x0000, 0xfc00, 0x83e0] {
+            bytes.extend(pixel.to_le_bytes());
+        }
+        let tga = Tga::try_from(bytes.as_slice()).unwrap();
+        assert!(tga.extension().is_none());
+        let image = tga.to_image().unwrap();
+        assert_eq!(image.color_model(), ColorModel::Rgba);
+        assert_eq!(image.rgba8(0), [255, 0, 0, 255]);
+        assert_eq!(image.rgba8(1), [0, 255, 0, 255]);
+        assert_eq!(image.rgba8(2), [0, 0, 255, 255]);
+        assert_eq!(image.rgba8(3), [0, 0, 0, 0]);
+
+        assert!(Tga::try_from(&bytes[..bytes.len() - 1])
+            .unwrap()
+            .to_image()
+            .is_err());
+    }
+
+    #[test]
+    fn test_too_many_colors() {
+        let data: Vec<u8> = (0..=255).flat_map(|value| [value, 0, 1]).collect();
+        let image = Image::new(256, 1, ColorModel::Rgb, 8, data).unwrap();
+        let options = TgaOptions {
+            color_map: true,
+            ..TgaOptions::default()
+        };
+        assert!(Tga::from_image_with(&image, &options).is_ok());
+        let mut bigger = image.data().to_vec();
+        bigger.extend([0, 0, 0]);
+        let image = Image::new(257, 1, ColorModel::Rgb, 8, bigger).unwrap();
+        assert!(matches!(
+            Tga::from_image_with(&image, &options),
+            Err(Error::LossyConversion(_))
+        ));
+    }
+
+    #[test]
+    fn test_detected_by_registry() {
+        let registry = Registry::default();
+        for color_model in [ColorModel::Gray, ColorModel::Rgb, ColorModel::Rgba] {
+            for (rle, color_map) in [(false, false), (true, false), (false, true)] {
+                let options = TgaOptions {
+                    rle,
+                    color_map,
+                    ..TgaOptions::default()
+                };
+                let bytes = Tga::from_image_with(&gradient(color_model), &options)
+                    .unwrap()
+                    .to_bytes();
+                let signature = &bytes[..registry.signature_len()];
+                // TGA has no signature, so only the extension can tell it apart
+                assert!(registry.sniff(signature).is_none(), "{options:?}");
+                let detection = registry.detect(Path::new("image.tga"), signature).unwrap();
+                assert_eq!(detection.codec.name, "TGA");
+                assert!(detection.extension_mismatch.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn test_forged_size() {
+        let options = TgaOptions {
+            rle: true,
+            ..TgaOptions::default()
+        };
+        let mut bytes = Tga::from_image_with(&gradient(ColorModel::Rgb), &options)
+            .unwrap()
+            .to_bytes();
+        // 65535x65535 pixels, which the few bytes of data can't hold
+        bytes[12..16].fill(0xff);
+        let tga = Tga::try_from(bytes.as_slice()).unwrap();
+        assert!(matches!(tga.to_image(), Err(Error::Malformed { .. })));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{byte_reader::ByteReader, png::time::Time, Result};
+
+/// The last bytes of a TGA 2.0 file: where the extension area and developer directory
+/// are, then the signature
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TgaFooter {
+    /// File offset of the extension area, or 0 if there is none
+    pub extension_offset: u32,
+    /// File offset of the developer directory, or 0 if there is none
+    pub developer_offset: u32,
+}
+
+impl TgaFooter {
+    pub const LENGTH: usize = 26;
+    pub const SIGNATURE: &'static [u8; 18] = b"TRUEVISION-XFILE.\0";
+
+    /// Reads the footer from the end of `bytes`, if `bytes` ends in one. Files without it
+    /// are in the original TGA format.
+    pub fn find(bytes: &[u8]) -> Option<Self> {
+        let footer = bytes.len().checked_sub(TgaFooter::LENGTH)?;
+        if !bytes.ends_with(TgaFooter::SIGNATURE) {
+            return None;
+        }
+        let offset = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Some(TgaFooter {
+            extension_offset: offset(footer),
+            developer_offset: offset(footer + 4),
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(TgaFooter::LENGTH);
+        bytes.extend(self.extension_offset.to_le_bytes());
+        bytes.extend(self.developer_offset.to_le_bytes());
+        bytes.extend(TgaFooter::SIGNATURE);
+        bytes
+    }
+}
+
+/// The TGA 2.0 extension area, which describes where the image came from and how its
+/// alpha channel is meant to be used
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TgaExtension {
+    pub author_name: String,
+    /// Up to four lines of comments, joined with newlines
+    pub author_comments: String,
+    /// When the image was saved
+    pub date_time: Option<Time>,
+    pub job_name: String,
+    /// Hours, minutes and seconds spent on the job
+    pub job_time: [u16; 3],
+    /// Program that saved the file
+    pub software_id: String,
+    /// Version times 100, then a letter such as b'b', or a space
+    pub software_version: (u16, u8),
+    /// The background or transparent color, as blue, green, red and alpha
+    pub key_color: [u8; 4],
+    /// Pixel width and height as a ratio, or 0 if not given
+    pub pixel_aspect: [u16; 2],
+    /// Gamma as a ratio, or 0 if not given
+    pub gamma: [u16; 2],
+    pub color_correction_offset: u32,
+    pub postage_stamp_offset: u32,
+    pub scan_line_offset: u32,
+    /// What the alpha channel means: 0 none, 1 and 2 undefined, 3 alpha, 4 premultiplied alpha
+    pub attributes_type: u8,
+}
+
+/// Lengths of the text fields, including the terminating NUL
+const NAME_LENGTH: usize = 41;
+const COMMENT_LINE_LENGTH: usize = 81;
+const COMMENT_LINES: usize = 4;
+
+impl TgaExtension {
+    /// Length of the extension area as TGA 2.0 defines it; later versions may add to it
+    pub const LENGTH: u16 = 495;
+
+    /// Alpha is there to be used
+    pub const ALPHA: u8 = 3;
+    /// Alpha is there to be used, and the color channels have been multiplied by it
+    pub const PREMULTIPLIED_ALPHA: u8 = 4;
+
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        let length = reader.read_u16_le()?;
+        if length < TgaExtension::LENGTH {
+            return Err(reader.error(format!(
+                "the extension area is {length} bytes, less than the {} it has to be",
+                TgaExtension::LENGTH
+            )));
+        }
+        let author_name = read_text(reader, NAME_LENGTH)?;
+        let author_comments = (0..COMMENT_LINES)
+            .map(|_| read_text(reader, COMMENT_LINE_LENGTH))
+            .collect::<Result<Vec<String>>>()?
+            .into_iter()
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<String>>()
+            .join("\n");
+        let mut date_time = [0u16; 6];
+        for value in &mut date_time {
+            *value = reader.read_u16_le()?;
+        }
+        let [month, day, year, hour, minute, second] = date_time;
+        // a date of all zeros means none was recorded
+        let date_time = match date_time {
+            [0, 0, 0, ..] => None,
+            _ => Time::new(
+                year,
+                month as u8,
+                day as u8,
+                hour as u8,
+                minute as u8,
+                second as u8,
+            )
+            .ok(),
+        };
+        let job_name = read_text(reader, NAME_LENGTH)?;
+        let job_time = [
+            reader.read_u16_le()?,
+            reader.read_u16_le()?,
+            reader.read_u16_le()?,
+        ];
+        let software_id = read_text(reader, NAME_LENGTH)?;
+        let software_version = (reader.read_u16_le()?, reader.read_u8()?);
+        let key_color = reader.read_array()?;
+        let pixel_aspect = [reader.read_u16_le()?, reader.read_u16_le()?];
+        let gamma = [reader.read_u16_le()?, reader.read_u16_le()?];
+        Ok(TgaExtension {
+            author_name,
+            author_comments,
+            date_time,
+            job_name,
+            job_time,
+            software_id,
+            software_version,
+            key_color,
+            pixel_aspect,
+            gamma,
+            color_correction_offset: reader.read_u32_le()?,
+            postage_stamp_offset: reader.read_u32_le()?,
+            scan_line_offset: reader.read_u32_le()?,
+            attributes_type: reader.read_u8()?,
+        })
+    }
+
+    /// The extension area as written to a file. Text longer than its field is cut short.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(TgaExtension::LENGTH as usize);
+        bytes.extend(TgaExtension::LENGTH.to_le_bytes());
+        write_text(&mut bytes, &self.author_name, NAME_LENGTH);
+        let mut lines = self.author_comments.lines();
+        for _ in 0..COMMENT_LINES {
+            write_text(&mut bytes, lines.next().unwrap_or(""), COMMENT_LINE_LENGTH);
+        }
+        let date_time = match self.date_time {
+            Some(time) => [
+                time.month() as u16,
+                time.day() as u16,
+                time.year(),
+                time.hour() as u16,
+                time.minute() as u16,
+                time.second() as u16,
+            ],
+            None => [0; 6],
+        };
+        for value in date_time {
+            bytes.extend(value.to_le_bytes());
+        }
+        write_text(&mut bytes, &self.job_name, NAME_LENGTH);
+        for value in self.job_time {
+            bytes.extend(value.to_le_bytes());
+        }
+        write_text(&mut bytes, &self.software_id, NAME_LENGTH);
+        bytes.extend(self.software_version.0.to_le_bytes());
+        bytes.push(self.software_version.1);
+        bytes.extend(self.key_color);
+        for value in self.pixel_aspect.into_iter().chain(self.gamma) {
+            bytes.extend(value.to_le_bytes());
+        }
+        for offset in [
+            self.color_correction_offset,
+            self.postage_stamp_offset,
+            self.scan_line_offset,
+        ] {
+            bytes.extend(offset.to_le_bytes());
+        }
+        bytes.push(self.attributes_type);
+        bytes
+    }
+}
+
+/// Reads a NUL-padded text field of `length` bytes
+fn read_text(reader: &mut ByteReader, length: usize) -> Result<String> {
+    let field = reader.read_bytes(length)?;
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(length);
+    Ok(String::from_utf8_lossy(&field[..end])
+        .trim_end()
+        .to_string())
+}
+
+/// Appends `text` as a NUL-padded field of `length` bytes, always leaving room for the NUL
+fn write_text(bytes: &mut Vec<u8>, text: &str, length: usize) {
+    let mut end = text.len().min(length - 1);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    bytes.extend(&text.as_bytes()[..end]);
+    bytes.resize(bytes.len() + length - end, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_round_trip() {
+        let extension = TgaExtension {
+            author_name: "Somebody".to_string(),
+            author_comments: "first line\nsecond line".to_string(),
+            date_time: Some(Time::new(2023, 7, 14, 9, 30, 5).unwrap()),
+            job_name: "level 3 textures".to_string(),
+            job_time: [1, 2, 3],
+            software_id: "x".repeat(60),
+            software_version: (210, b'b'),
+            key_color: [1, 2, 3, 4],
+            pixel_aspect: [1, 1],
+            gamma: [22, 10],
+            attributes_type: TgaExtension::ALPHA,
+            ..TgaExtension::default()
+        };
+        let bytes = extension.as_bytes();
+        assert_eq!(bytes.len(), TgaExtension::LENGTH as usize);
+        let read = TgaExtension::read(&mut ByteReader::new("TGA", &bytes)).unwrap();
+        assert_eq!(read.software_id.len(), NAME_LENGTH - 1);
+        assert_eq!(
+            read,
+            TgaExtension {
+                software_id: "x".repeat(NAME_LENGTH - 1),
+                ..extension
+            }
+        );
+
+        let footer = TgaFooter {
+            extension_offset: 100,
+            developer_offset: 0,
+        };
+        let file = [vec![0; 100], bytes, footer.as_bytes()].concat();
+        assert_eq!(TgaFooter::find(&file), Some(footer));
+        assert_eq!(TgaFooter::find(&file[..file.len() - 1]), None);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{byte_reader::ByteReader, Error, Result};
+
+/// What a TGA's pixels hold, from its image type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageKind {
+    /// Indices into the color map; image types 1 and 9
+    ColorMapped,
+    /// BGR or BGRA pixels; image types 2 and 10
+    TrueColor,
+    /// Gray, or gray and alpha, pixels; image types 3 and 11
+    Grayscale,
+}
+
+impl ImageKind {
+    /// The image type byte for this kind of image, run-length encoded or not
+    pub fn image_type(&self, rle: bool) -> u8 {
+        let image_type = match self {
+            ImageKind::ColorMapped => 1,
+            ImageKind::TrueColor => 2,
+            ImageKind::Grayscale => 3,
+        };
+        if rle {
+            image_type + 8
+        } else {
+            image_type
+        }
+    }
+}
+
+/// The 18 bytes every TGA file starts with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TgaHeader {
+    /// Length of the image ID field that follows the header
+    pub id_length: u8,
+    /// 1 if a color map follows the image ID
+    pub color_map_type: u8,
+    pub image_type: u8,
+    /// Index the first color map entry stands for
+    pub color_map_first: u16,
+    pub color_map_length: u16,
+    /// Bits per color map entry: 15, 16, 24 or 32
+    pub color_map_entry_size: u8,
+    pub x_origin: u16,
+    pub y_origin: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Bits per pixel, or per color map index
+    pub pixel_depth: u8,
+    /// Alpha bits per pixel in bits 0 to 3, then the origin in bits 4 and 5
+    pub descriptor: u8,
+}
+
+impl TgaHeader {
+    pub const LENGTH: usize = 18;
+
+    /// Set in the descriptor when rows are stored right to left
+    const RIGHT_TO_LEFT: u8 = 0x10;
+    /// Set in the descriptor when the top row is stored first
+    const TOP_DOWN: u8 = 0x20;
+
+    /// Reads the header from the start of a TGA file
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        let header = TgaHeader {
+            id_length: reader.read_u8()?,
+            color_map_type: reader.read_u8()?,
+            image_type: reader.read_u8()?,
+            color_map_first: reader.read_u16_le()?,
+            color_map_length: reader.read_u16_le()?,
+            color_map_entry_size: reader.read_u8()?,
+            x_origin: reader.read_u16_le()?,
+            y_origin: reader.read_u16_le()?,
+            width: reader.read_u16_le()?,
+            height: reader.read_u16_le()?,
+            pixel_depth: reader.read_u8()?,
+            descriptor: reader.read_u8()?,
+        };
+        header.validate()?;
+        Ok(header)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.color_map_type > 1 {
+            return Err(Error::malformed_at(
+                "TGA",
+                1,
+                format!("color map type {} is not 0 or 1", self.color_map_type),
+            ));
+        }
+        if self.color_map_type == 1 && !matches!(self.color_map_entry_size, 15 | 16 | 24 | 32) {
+            return Err(Error::unsupported(
+                "TGA",
+                format!("{}-bit color map entries", self.color_map_entry_size),
+            ));
+        }
+        let kind = self.kind()?;
+        if kind == ImageKind::ColorMapped && self.color_map_type == 0 {
+            return Err(Error::malformed_at(
+                "TGA",
+                1,
+                "color-mapped image without a color map",
+            ));
+        }
+        let depth_allowed = match kind {
+            ImageKind::ColorMapped => matches!(self.pixel_depth, 8 | 16),
+            ImageKind::TrueColor => matches!(self.pixel_depth, 15 | 16 | 24 | 32),
+            ImageKind::Grayscale => matches!(self.pixel_depth, 8 | 16),
+        };
+        if !depth_allowed {
+            return Err(Error::unsupported(
+                "TGA",
+                format!(
+                    "{}-bit pixels in image type {}",
+                    self.pixel_depth, self.image_type
+                ),
+            ));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::malformed_at(
+                "TGA",
+                12,
+                format!("{}x{} is not a valid image size", self.width, self.height),
+            ));
+        }
+        Ok(())
+    }
+
+    /// What the pixels hold; image type 0, which has no pixels, isn't supported
+    pub fn kind(&self) -> Result<ImageKind> {
+        match self.image_type & !8 {
+            1 => Ok(ImageKind::ColorMapped),
+            2 => Ok(ImageKind::TrueColor),
+            3 => Ok(ImageKind::Grayscale),
+            0 if self.image_type == 0 => Err(Error::unsupported("TGA", "files without an image")),
+            _ => Err(Error::malformed_at(
+                "TGA",
+                2,
+                format!("unknown image type {}", self.image_type),
+            )),
+        }
+    }
+
+    pub fn is_run_length_encoded(&self) -> bool {
+        self.image_type & 8 != 0
+    }
+
+    pub fn alpha_bits(&self) -> u8 {
+        self.descriptor & 0x0f
+    }
+
+    pub fn top_down(&self) -> bool {
+        self.descriptor & TgaHeader::TOP_DOWN != 0
+    }
+
+    pub fn right_to_left(&self) -> bool {
+        self.descriptor & TgaHeader::RIGHT_TO_LEFT != 0
+    }
+
+    /// The descriptor byte for `alpha_bits` of alpha and rows stored from the top or bottom
+    pub fn descriptor_for(alpha_bits: u8, top_down: bool) -> u8 {
+        alpha_bits | if top_down { TgaHeader::TOP_DOWN } else { 0 }
+    }
+
+    /// Bytes one pixel takes up in the image data
+    pub fn pixel_len(&self) -> usize {
+        (self.pixel_depth as usize).div_ceil(8)
+    }
+
+    /// Bytes the color map takes up
+    pub fn color_map_len(&self) -> usize {
+        match self.color_map_type {
+            0 => 0,
+            _ => self.color_map_length as usize * (self.color_map_entry_size as usize).div_ceil(8),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![self.id_length, self.color_map_type, self.image_type];
+        bytes.extend(self.color_map_first.to_le_bytes());
+        bytes.extend(self.color_map_length.to_le_bytes());
+        bytes.push(self.color_map_entry_size);
+        for value in [self.x_origin, self.y_origin, self.width, self.height] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.push(self.pixel_depth);
+        bytes.push(self.descriptor);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = TgaHeader {
+            id_length: 3,
+            color_map_type: 1,
+            image_type: ImageKind::ColorMapped.image_type(true),
+            color_map_first: 0,
+            color_map_length: 16,
+            color_map_entry_size: 24,
+            x_origin: 0,
+            y_origin: 0,
+            width: 300,
+            height: 2,
+            pixel_depth: 8,
+            descriptor: TgaHeader::descriptor_for(0, true),
+        };
+        let bytes = header.as_bytes();
+        assert_eq!(bytes.len(), TgaHeader::LENGTH);
+        let read = TgaHeader::read(&mut ByteReader::new("TGA", &bytes)).unwrap();
+        assert_eq!(read, header);
+        assert!(read.is_run_length_encoded() && read.top_down());
+        assert_eq!(read.kind().unwrap(), ImageKind::ColorMapped);
+        assert_eq!(read.color_map_len(), 48);
+
+        let mut bad = bytes.clone();
+        bad[2] = 0;
+        assert!(matches!(
+            TgaHeader::read(&mut ByteReader::new("TGA", &bad)),
+            Err(Error::Unsupported { .. })
+        ));
+        bad[2] = 10;
+        bad[16] = 12;
+        assert!(TgaHeader::read(&mut ByteReader::new("TGA", &bad)).is_err());
+    }
+}
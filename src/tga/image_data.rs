@@ -0,0 +1,457 @@
+use std::{
+    borrow::Cow,
+    io::{BufReader, Read},
+};
+
+use crate::{
+    byte_reader::{ByteReader, StreamReader},
+    image::{Color, ColorModel, Image},
+    metadata::Metadata,
+    scanline::RowLayout,
+    Error, Result,
+};
+
+use super::{
+    extension::{TgaExtension, TgaFooter},
+    header::{ImageKind, TgaHeader},
+    options::TgaOptions,
+    Tga,
+};
+
+/// Program name recorded in the extension area of new files
+const SOFTWARE_ID: &str = env!("CARGO_PKG_NAME");
+
+/// Decodes the pixels of `tga` into an `Image`. Color-mapped images with an opaque, all-gray
+/// color map come out as grayscale, and alpha is only read where the header or extension
+/// area says it's there to be used.
+pub fn decode(tga: &Tga) -> Result<Image> {
+    let header = tga.header();
+    let kind = header.kind()?;
+    let (width, height) = (header.width as usize, header.height as usize);
+    let pixel_len = header.pixel_len();
+    let len = width * height * pixel_len;
+    let pixels: Cow<[u8]> = match header.is_run_length_encoded() {
+        true => Cow::Owned(decode_rle(tga.image_data(), pixel_len, len)?),
+        false => match tga.image_data().get(..len) {
+            Some(pixels) => Cow::Borrowed(pixels),
+            None => {
+                return Err(Error::malformed(
+                    "TGA",
+                    format!(
+                    "{width}x{height} pixels need {len} bytes of image data, but there are only {}",
+                    tga.image_data().len()
+                ),
+                ))
+            }
+        },
+    };
+    let alpha = uses_alpha(header, tga.extension());
+    let premultiplied = tga
+        .extension()
+        .is_some_and(|extension| extension.attributes_type == TgaExtension::PREMULTIPLIED_ALPHA);
+    let color_map = match kind {
+        ImageKind::ColorMapped => read_color_map(header, tga.color_map(), alpha),
+        _ => Vec::new(),
+    };
+    let color_model = match kind {
+        ImageKind::ColorMapped if alpha => ColorModel::Rgba,
+        ImageKind::ColorMapped if color_map.iter().all(|&[r, g, b, _]| r == g && g == b) => {
+            ColorModel::Gray
+        }
+        ImageKind::ColorMapped => ColorModel::Rgb,
+        ImageKind::TrueColor if alpha => ColorModel::Rgba,
+        ImageKind::TrueColor => ColorModel::Rgb,
+        ImageKind::Grayscale if alpha => ColorModel::GrayAlpha,
+        ImageKind::Grayscale => ColorModel::Gray,
+    };
+
+    // `pixels` holds every pixel by now, so this is no larger than the file allows
+    let mut data: Vec<u8> = Vec::with_capacity(width * height * color_model.channels());
+    for y in 0..height {
+        let file_y = if header.top_down() { y } else { height - 1 - y };
+        for x in 0..width {
+            let file_x = if header.right_to_left() {
+                width - 1 - x
+            } else {
+                x
+            };
+            let offset = (file_y * width + file_x) * pixel_len;
+            let pixel = &pixels[offset..offset + pixel_len];
+            let mut rgba = match kind {
+                ImageKind::ColorMapped => {
+                    let index = match pixel_len {
+                        2 => u16::from_le_bytes([pixel[0], pixel[1]]),
+                        _ => pixel[0] as u16,
+                    };
+                    index
+                        .checked_sub(header.color_map_first)
+                        .and_then(|index| color_map.get(index as usize))
+                        .copied()
+                        .ok_or_else(|| {
+                            Error::malformed(
+                                "TGA",
+                                format!("color map index {index} is outside the color map"),
+                            )
+                        })?
+                }
+                ImageKind::TrueColor => unpack_color(pixel, header.pixel_depth, alpha),
+                ImageKind::Grayscale => {
+                    let gray = pixel[0];
+                    [gray, gray, gray, if alpha { pixel[1] } else { 255 }]
+                }
+            };
+            if premultiplied {
+                unpremultiply(&mut rgba);
+            }
+            let [red, green, blue, alpha] = rgba;
+            match color_model {
+                ColorModel::Gray => data.push(red),
+                ColorModel::GrayAlpha => data.extend([red, alpha]),
+                ColorModel::Rgb => data.extend([red, green, blue]),
+                ColorModel::Rgba => data.extend(rgba),
+            }
+        }
+    }
+    let mut image = Image::new(width as u32, height as u32, color_model, 8, data)?;
+    image.set_metadata(tga.metadata());
+    Ok(image)
+}
+
+/// Reads the size of a TGA from its header. The extension area at the end of the file can
+/// rule alpha out, so the layout assumes alpha wherever the pixels have room for it.
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let bytes = StreamReader::new("TGA", BufReader::new(reader)).read_bytes(TgaHeader::LENGTH)?;
+    let header = TgaHeader::read(&mut ByteReader::new("TGA", &bytes))?;
+    let color_model = match header.kind()? {
+        ImageKind::ColorMapped if matches!(header.color_map_entry_size, 16 | 32) => {
+            ColorModel::Rgba
+        }
+        ImageKind::ColorMapped => ColorModel::Rgb,
+        ImageKind::TrueColor if matches!(header.pixel_depth, 16 | 32) => ColorModel::Rgba,
+        ImageKind::TrueColor => ColorModel::Rgb,
+        ImageKind::Grayscale if header.pixel_depth == 16 => ColorModel::GrayAlpha,
+        ImageKind::Grayscale => ColorModel::Gray,
+    };
+    Ok(RowLayout {
+        width: header.width as u32,
+        height: header.height as u32,
+        color_model,
+        bit_depth: 8,
+    })
+}
+
+/// Whether the alpha channel holds anything. The extension area has the final say; without
+/// one, the alpha bits in the header do. Pixels without room for alpha never have it.
+pub fn uses_alpha(header: &TgaHeader, extension: Option<&TgaExtension>) -> bool {
+    let has_room = match header.kind() {
+        Ok(ImageKind::ColorMapped) => matches!(header.color_map_entry_size, 16 | 32),
+        Ok(_) => matches!(header.pixel_depth, 16 | 32),
+        Err(_) => false,
+    };
+    let wanted = match extension.map(|extension| extension.attributes_type) {
+        Some(TgaExtension::ALPHA | TgaExtension::PREMULTIPLIED_ALPHA) => true,
+        // types 1 and 2 mark the alpha bits as junk
+        Some(1 | 2) => false,
+        _ => header.alpha_bits() > 0,
+    };
+    has_room && wanted
+}
+
+/// Encodes `image` as a new `Tga` laid out as `options` asks. Grayscale images are stored as
+/// 8-bit gray, with alpha if they have it, and color images as 24 or 32-bit pixels. TGA has
+/// no room for 16-bit samples, so those images are refused.
+pub fn encode(image: &Image, options: &TgaOptions) -> Result<Tga> {
+    options.validate()?;
+    image.layout().require_8_bit("TGA")?;
+    let (width, height) = match (u16::try_from(image.width()), u16::try_from(image.height())) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(Error::unsupported(
+                "TGA",
+                format!(
+                    "{}x{} images; TGA allows at most 65535 pixels each way",
+                    image.width(),
+                    image.height()
+                ),
+            ))
+        }
+    };
+    let color_model = image.color_model();
+    let alpha = color_model.has_alpha();
+    let pixel_count = width as usize * height as usize;
+
+    let (kind, pixel_depth, color_map, color_map_entry_size, pixels) = match options.color_map {
+        true => {
+            let indexed = image.to_indexed(256).ok_or_else(|| {
+                Error::lossy("the image has more than the 256 colors a TGA color map can hold")
+            })?;
+            let color_map: Vec<u8> = indexed
+                .palette
+                .iter()
+                .flat_map(|&rgba| pack_color(rgba, alpha))
+                .collect();
+            let entry_size = if alpha { 32 } else { 24 };
+            (
+                ImageKind::ColorMapped,
+                8,
+                color_map,
+                entry_size,
+                indexed.indices,
+            )
+        }
+        false => {
+            let (kind, pixel_depth) = match color_model {
+                ColorModel::Gray => (ImageKind::Grayscale, 8),
+                ColorModel::GrayAlpha => (ImageKind::Grayscale, 16),
+                ColorModel::Rgb => (ImageKind::TrueColor, 24),
+                ColorModel::Rgba => (ImageKind::TrueColor, 32),
+            };
+            let mut pixels: Vec<u8> = Vec::with_capacity(pixel_count * pixel_depth / 8);
+            for pixel in 0..pixel_count {
+                let rgba = image.rgba8(pixel);
+                match kind {
+                    ImageKind::Grayscale => pixels.extend(&[rgba[0], rgba[3]][..pixel_depth / 8]),
+                    _ => pixels.extend(pack_color(rgba, alpha)),
+                }
+            }
+            (kind, pixel_depth as u8, Vec::new(), 0, pixels)
+        }
+    };
+
+    let header = TgaHeader {
+        id_length: options
+            .image_id
+            .as_ref()
+            .map_or(0, |image_id| image_id.len() as u8),
+        color_map_type: if color_map.is_empty() { 0 } else { 1 },
+        image_type: kind.image_type(options.rle),
+        color_map_first: 0,
+        color_map_length: (color_map.len() / (color_map_entry_size as usize / 8).max(1)) as u16,
+        color_map_entry_size,
+        x_origin: 0,
+        y_origin: 0,
+        width,
+        height,
+        pixel_depth,
+        descriptor: TgaHeader::descriptor_for(if alpha { 8 } else { 0 }, options.top_down),
+    };
+
+    let row_len = width as usize * header.pixel_len();
+    let mut data: Vec<u8> = Vec::with_capacity(pixels.len());
+    let mut rows: Vec<&[u8]> = pixels.chunks_exact(row_len).collect();
+    if !options.top_down {
+        rows.reverse();
+    }
+    for row in rows {
+        match options.rle {
+            true => encode_rle_row(row, header.pixel_len(), &mut data),
+            false => data.extend(row),
+        }
+    }
+
+    let extension = TgaExtension {
+        software_id: SOFTWARE_ID.to_string(),
+        software_version: (software_version(), b' '),
+        key_color: image.metadata().background().map_or([0; 4], |color| {
+            let [red, green, blue, alpha] = rgba8(color);
+            [blue, green, red, alpha]
+        }),
+        attributes_type: if alpha { TgaExtension::ALPHA } else { 0 },
+        ..TgaExtension::default()
+    };
+    Ok(Tga {
+        header,
+        image_id: options.image_id.clone().unwrap_or_default().into_bytes(),
+        color_map,
+        data,
+        extension: Some(extension),
+        footer: None,
+    })
+}
+
+/// The background color the key color in `extension` stands for, if one is set
+pub fn key_color(extension: &TgaExtension) -> Option<Color> {
+    match extension.key_color {
+        [0, 0, 0, 0] => None,
+        key_color => {
+            let [red, green, blue, _] = unpack_color(&key_color, 32, true);
+            Some(Color::from_rgb8(red, green, blue))
+        }
+    }
+}
+
+/// The metadata the extension area holds
+pub fn metadata(extension: Option<&TgaExtension>) -> Metadata {
+    let mut metadata = Metadata::default();
+    metadata.set_background(extension.and_then(key_color));
+    metadata
+}
+
+/// This crate's version as the extension area records it, major version times 100 plus
+/// the minor version
+fn software_version() -> u16 {
+    let major: u16 = env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0);
+    let minor: u16 = env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0);
+    major.saturating_mul(100).saturating_add(minor.min(99))
+}
+
+fn rgba8(color: Color) -> [u8; 4] {
+    [
+        (color.red >> 8) as u8,
+        (color.green >> 8) as u8,
+        (color.blue >> 8) as u8,
+        255,
+    ]
+}
+
+/// Reads every entry of the color map as RGBA
+fn read_color_map(header: &TgaHeader, color_map: &[u8], alpha: bool) -> Vec<[u8; 4]> {
+    let entry_len = (header.color_map_entry_size as usize).div_ceil(8);
+    color_map
+        .chunks_exact(entry_len)
+        .map(|entry| unpack_color(entry, header.color_map_entry_size, alpha))
+        .collect()
+}
+
+/// Turns a stored 15, 16, 24 or 32-bit color into RGBA, keeping its alpha only if `alpha`
+fn unpack_color(bytes: &[u8], bits: u8, alpha: bool) -> [u8; 4] {
+    let [red, green, blue, stored_alpha] = match bits {
+        15 | 16 => {
+            let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let channel = |shift: u16| {
+                let value = (value >> shift & 0x1f) as u8;
+                value << 3 | value >> 2
+            };
+            let attribute = if value & 0x8000 != 0 { 255 } else { 0 };
+            [channel(10), channel(5), channel(0), attribute]
+        }
+        24 => [bytes[2], bytes[1], bytes[0], 255],
+        _ => [bytes[2], bytes[1], bytes[0], bytes[3]],
+    };
+    [red, green, blue, if alpha { stored_alpha } else { 255 }]
+}
+
+/// Stores an RGBA color as BGR, or BGRA if `alpha`
+fn pack_color([red, green, blue, alpha_value]: [u8; 4], alpha: bool) -> Vec<u8> {
+    match alpha {
+        true => vec![blue, green, red, alpha_value],
+        false => vec![blue, green, red],
+    }
+}
+
+/// Undoes premultiplied alpha, so the color channels are independent of alpha again
+fn unpremultiply(rgba: &mut [u8; 4]) {
+    let alpha = rgba[3] as u32;
+    if alpha == 0 {
+        return;
+    }
+    for channel in &mut rgba[..3] {
+        *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+    }
+}
+
+/// Expands run-length encoded pixels until `len` bytes of them have been produced. Packets
+/// can run on from one row into the next, as older writers let them.
+fn decode_rle(data: &[u8], pixel_len: usize, len: usize) -> Result<Vec<u8>> {
+    // a packet stands for at most 128 pixels, so data too short for the size the header
+    // gives is turned away before the pixels are allocated
+    let max_len = data.len() / (1 + pixel_len) * 128 * pixel_len;
+    if len > max_len {
+        return Err(Error::malformed(
+            "TGA",
+            format!(
+                "{} bytes of run-length encoded data can't hold the {len} bytes of pixels the header calls for",
+                data.len()
+            ),
+        ));
+    }
+    let mut reader = ByteReader::new("TGA", data);
+    let mut pixels: Vec<u8> = Vec::with_capacity(len);
+    while pixels.len() < len {
+        let packet = reader.read_u8()?;
+        let count = (packet & 0x7f) as usize + 1;
+        if packet & 0x80 != 0 {
+            let pixel = reader.read_bytes(pixel_len)?;
+            for _ in 0..count {
+                pixels.extend(pixel);
+            }
+        } else {
+            pixels.extend(reader.read_bytes(count * pixel_len)?);
+        }
+    }
+    pixels.truncate(len);
+    Ok(pixels)
+}
+
+/// Appends one row of pixels as run-length packets. Two or more repeats make a run packet
+/// and everything between runs goes into raw packets, each at most 128 pixels long.
+fn encode_rle_row(row: &[u8], pixel_len: usize, data: &mut Vec<u8>) {
+    let count = row.len() / pixel_len;
+    let pixel = |idx: usize| &row[idx * pixel_len..(idx + 1) * pixel_len];
+    let mut idx = 0;
+    while idx < count {
+        let mut run = 1;
+        while idx + run < count && run < 128 && pixel(idx + run) == pixel(idx) {
+            run += 1;
+        }
+        if run > 1 {
+            data.push(0x80 | (run - 1) as u8);
+            data.extend(pixel(idx));
+            idx += run;
+            continue;
+        }
+        let start = idx;
+        while idx < count && idx - start < 128 && !(idx + 1 < count && pixel(idx) == pixel(idx + 1))
+        {
+            idx += 1;
+        }
+        data.push((idx - start - 1) as u8);
+        data.extend(&row[start * pixel_len..idx * pixel_len]);
+    }
+}
+
+/// Where the image data of a file read as `prefix_len` bytes of header, image ID and color
+/// map followed by `rest` ends, and the footer if the file has one
+pub fn split_footer(prefix_len: usize, rest: &[u8]) -> (usize, Option<TgaFooter>) {
+    let Some(footer) = TgaFooter::find(rest) else {
+        return (rest.len(), None);
+    };
+    // the image data runs up to whichever of the other areas comes first
+    let end = [footer.extension_offset, footer.developer_offset]
+        .into_iter()
+        .filter_map(|offset| (offset as usize).checked_sub(prefix_len))
+        .filter(|&offset| offset > 0)
+        .fold(rest.len() - TgaFooter::LENGTH, usize::min);
+    (end, Some(footer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rle_round_trip() {
+        let row: Vec<u8> = [1, 1, 1, 2, 3, 4, 4, 5]
+            .iter()
+            .chain([9; 200].iter())
+            .flat_map(|&value| [value, value + 1])
+            .collect();
+        let mut data: Vec<u8> = Vec::new();
+        encode_rle_row(&row, 2, &mut data);
+        assert_eq!(data[..3], [0x82, 1, 2]);
+        assert_eq!(data[3], 1);
+        assert_eq!(decode_rle(&data, 2, row.len()).unwrap(), row);
+        assert!(decode_rle(&data[..data.len() - 1], 2, row.len()).is_err());
+    }
+
+    #[test]
+    fn test_unpack_color() {
+        // 16-bit colors are 5 bits each of red, green and blue, then an attribute bit
+        let pixel = (0x8000u16 | 0x1f << 10 | 0x10).to_le_bytes();
+        assert_eq!(unpack_color(&pixel, 16, true), [255, 0, 132, 255]);
+        assert_eq!(unpack_color(&[1, 2, 3, 4], 32, false), [3, 2, 1, 255]);
+        let mut rgba = [64, 128, 0, 128];
+        unpremultiply(&mut rgba);
+        assert_eq!(rgba, [128, 255, 0, 128]);
+    }
+}
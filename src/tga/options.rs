@@ -0,0 +1,30 @@
+use crate::{Error, Result};
+
+/// How TGA output is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TgaOptions {
+    /// Run-length encode the pixels, one row at a time
+    pub rle: bool,
+    /// Store the top row first rather than the usual bottom row
+    pub top_down: bool,
+    /// Store pixels as indices into a color map, which fails for images with more than
+    /// 256 colors
+    pub color_map: bool,
+    /// Text for the image ID field, at most 255 bytes
+    pub image_id: Option<String>,
+}
+
+impl TgaOptions {
+    /// Checks for settings that are out of range
+    pub fn validate(&self) -> Result<()> {
+        match &self.image_id {
+            Some(image_id) if image_id.len() > u8::MAX as usize => {
+                Err(Error::invalid_argument(format!(
+                    "a TGA image ID can be at most 255 bytes long, not {}",
+                    image_id.len()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::io::{Read, Write};
+
+use crate::{
+    byte_reader::ByteReader,
+    convert::ConvertOptions,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{FormatDetails, ImageInfo, MetadataInfo, TiffDetails, TiffPageInfo},
+    metadata::Metadata,
+    png::Png,
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    ifd::{ByteOrder, Field, Ifd},
+    image_data::PageLayout,
+    options::TiffOptions,
+};
+
+pub mod compression;
+pub mod ifd;
+pub mod image_data;
+pub mod lzw;
+pub mod options;
+pub mod tag;
+
+/// Registry entry for TIFF
+pub const CODEC: Codec = Codec {
+    name: "TIFF",
+    extensions: &["tif", "tiff"],
+    magic: &[b"II*\0", b"MM\0*"],
+    check_signature: None,
+    decoder: Some(decode_with::<Tiff>),
+    encoder: Some(write_tiff),
+    row_decoder: None,
+    row_encoder: None,
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes every page of a TIFF from its IFD, without decoding any pixels
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let tiff = Tiff::from_reader(reader)?;
+    let order = tiff.byte_order();
+    let layouts = tiff
+        .pages()
+        .iter()
+        .map(|page| PageLayout::read(&page.ifd, order))
+        .collect::<Result<Vec<PageLayout>>>()?;
+    let first = &layouts[0];
+    let text = |tag: u16| tiff.pages()[0].ifd.get(tag).and_then(Field::text);
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width: first.width,
+        height: first.height,
+        color_model: first.color_model_name(),
+        bit_depth: first.bits_per_sample as u8,
+        compression: compression::name(first.compression),
+        interlaced: false,
+        palette_size: (first.photometric == image_data::PALETTE)
+            .then(|| 1 << first.bits_per_sample),
+        metadata: MetadataInfo::from(&tiff.metadata()?),
+        details: FormatDetails::Tiff(TiffDetails {
+            byte_order: match order {
+                ByteOrder::LittleEndian => "little-endian",
+                ByteOrder::BigEndian => "big-endian",
+            },
+            pages: layouts
+                .iter()
+                .map(|layout| TiffPageInfo {
+                    width: layout.width,
+                    height: layout.height,
+                    color_model: layout.color_model_name(),
+                    bits_per_sample: layout.bits_per_sample,
+                    samples_per_pixel: layout.samples_per_pixel,
+                    compression: compression::name(layout.compression),
+                    predictor: layout.predictor == 2,
+                    tiles: layout.tiles.map(|(width, height)| [width, height]),
+                    segments: layout.segment_count(),
+                })
+                .collect(),
+            software: text(tag::SOFTWARE),
+            date_time: text(tag::DATE_TIME),
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// Encodes `png` as a TIFF for the registry, with one page for each frame of an APNG
+fn write_tiff(png: Png, options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    let images = match png.to_animation()? {
+        Some(animation) => {
+            let metadata = png.metadata()?;
+            animation
+                .frames()
+                .iter()
+                .map(|frame| {
+                    let mut image = frame.image.clone();
+                    image.set_metadata(metadata.clone());
+                    image
+                })
+                .collect()
+        }
+        None => vec![png.to_image()?],
+    };
+    Tiff::from_images_with(&images, &options.tiff)?.to_writer(writer)
+}
+
+/// One page of a TIFF: its fields and its strips or tiles, still compressed
+#[derive(Clone, Debug)]
+pub struct Page {
+    /// Fields describing the page. The offsets of its strips or tiles are rewritten
+    /// whenever the file is written.
+    pub ifd: Ifd,
+    pub segments: Vec<Vec<u8>>,
+}
+
+impl Page {
+    /// The tags giving the offsets and byte counts of the page's strips or tiles
+    fn segment_tags(&self) -> (u16, u16) {
+        match self.ifd.get(tag::TILE_OFFSETS) {
+            Some(_) => (tag::TILE_OFFSETS, tag::TILE_BYTE_COUNTS),
+            None => (tag::STRIP_OFFSETS, tag::STRIP_BYTE_COUNTS),
+        }
+    }
+
+    /// Copies each strip or tile of the IFD at `index` out of `file`
+    fn read(file: &ByteReader, ifd: Ifd, index: usize, order: ByteOrder) -> Result<Page> {
+        let (offsets_tag, counts_tag) = Page {
+            ifd: ifd.clone(),
+            segments: Vec::new(),
+        }
+        .segment_tags();
+        let missing = || {
+            Error::malformed(
+                "TIFF",
+                format!("page {index} doesn't say where its pixel data is"),
+            )
+        };
+        let offsets = ifd.numbers(offsets_tag, order).ok_or_else(missing)?;
+        let counts = ifd.numbers(counts_tag, order).ok_or_else(missing)?;
+        if offsets.len() != counts.len() {
+            return Err(Error::malformed(
+                "TIFF",
+                format!(
+                    "page {index} lists {} offsets but {} byte counts",
+                    offsets.len(),
+                    counts.len()
+                ),
+            ));
+        }
+        let segments = offsets
+            .iter()
+            .zip(&counts)
+            .enumerate()
+            .map(|(segment, (&offset, &count))| {
+                file.at(offset as usize)
+                    .and_then(|mut data| data.read_bytes(count as usize))
+                    .map(<[u8]>::to_vec)
+                    .map_err(|_| {
+                        Error::malformed_at(
+                            "TIFF",
+                            offset as usize,
+                            format!(
+                                "segment {segment} of page {index} runs past the end of the file"
+                            ),
+                        )
+                    })
+            })
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+        Ok(Page { ifd, segments })
+    }
+}
+
+/// A TIFF file: a chain of pages, each described by an IFD
+#[derive(Clone, Debug)]
+pub struct Tiff {
+    byte_order: ByteOrder,
+    pages: Vec<Page>,
+}
+
+impl Tiff {
+    /// Bytes before the first IFD can start: the byte order, 42 and the first IFD's offset
+    const HEADER_LENGTH: usize = 8;
+
+    /// Bundles `pages` into a file. Fails if there are none.
+    pub fn new(byte_order: ByteOrder, pages: Vec<Page>) -> Result<Self> {
+        if pages.is_empty() {
+            return Err(Error::invalid_argument("a TIFF needs at least one page"));
+        }
+        Ok(Tiff { byte_order, pages })
+    }
+
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    pub fn pages(&self) -> &[Page] {
+        &self.pages
+    }
+
+    /// Decodes page `index` into an `Image`, carrying its resolution
+    pub fn to_image(&self, index: usize) -> Result<Image> {
+        match self.pages.get(index) {
+            Some(page) => image_data::decode(page, self.byte_order),
+            None => Err(Error::no_such_image(index, self.pages.len())),
+        }
+    }
+
+    /// Encodes `image` as a single-page `Tiff`
+    pub fn from_image(image: &Image) -> Result<Tiff> {
+        Tiff::from_images_with(std::slice::from_ref(image), &TiffOptions::default())
+    }
+
+    /// Encodes `images` as the pages of a `Tiff`, laid out as `options` asks
+    pub fn from_images_with(images: &[Image], options: &TiffOptions) -> Result<Tiff> {
+        let byte_order = match options.big_endian {
+            true => ByteOrder::BigEndian,
+            false => ByteOrder::LittleEndian,
+        };
+        let mut pages: Vec<Page> = Vec::with_capacity(images.len());
+        for (index, image) in images.iter().enumerate() {
+            let mut page = image_data::encode(image, options, byte_order)?;
+            if images.len() > 1 {
+                page.ifd.set(Field::shorts(
+                    tag::PAGE_NUMBER,
+                    &[index as u16, images.len() as u16],
+                    byte_order,
+                ));
+            }
+            pages.push(page);
+        }
+        Tiff::new(byte_order, pages)
+    }
+}
+
+impl TryFrom<&[u8]> for Tiff {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Tiff::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Tiff {
+    fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        // IFDs and pixel data can sit anywhere in the file, so all of it is read first
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let file = ByteReader::new("TIFF", &bytes);
+        let mut header = file.at(0)?;
+        let byte_order = match header.read_array::<2>()? {
+            [b'I', b'I'] => ByteOrder::LittleEndian,
+            [b'M', b'M'] => ByteOrder::BigEndian,
+            _ => return Err(Error::malformed_at("TIFF", 0, "no byte order mark")),
+        };
+        match byte_order.read_u16(&mut header)? {
+            42 => {}
+            43 => return Err(Error::unsupported("TIFF", "BigTIFF files")),
+            version => {
+                return Err(Error::malformed_at(
+                    "TIFF",
+                    2,
+                    format!("version {version} isn't 42"),
+                ))
+            }
+        }
+        let first = byte_order.read_u32(&mut header)?;
+        let ifds = Ifd::read_chain(&file, first, byte_order)?;
+        if ifds.is_empty() {
+            return Err(Error::malformed_at("TIFF", 4, "the file has no pages"));
+        }
+        let pages = ifds
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut ifd)| {
+                // pointers into the file would be left dangling once it's rewritten
+                for pointer in tag::POINTERS {
+                    ifd.remove(pointer);
+                }
+                Page::read(&file, ifd, index, byte_order)
+            })
+            .collect::<Result<Vec<Page>>>()?;
+        Ok(Tiff { byte_order, pages })
+    }
+
+    /// Writes each page's strips or tiles, then its IFD, so every IFD points forward
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        let order = self.byte_order;
+        let mut position = Tiff::HEADER_LENGTH;
+        let mut layout: Vec<(Vec<usize>, Ifd, usize)> = Vec::with_capacity(self.pages.len());
+        for page in &self.pages {
+            let mut offsets: Vec<usize> = Vec::with_capacity(page.segments.len());
+            for segment in &page.segments {
+                offsets.push(position);
+                position += segment.len().next_multiple_of(2);
+            }
+            let (offsets_tag, counts_tag) = page.segment_tags();
+            let mut ifd = page.ifd.clone();
+            let as_longs = |values: &mut dyn Iterator<Item = usize>| {
+                values.map(|value| value as u32).collect::<Vec<u32>>()
+            };
+            ifd.set(Field::longs(
+                offsets_tag,
+                &as_longs(&mut offsets.iter().copied()),
+                order,
+            ));
+            ifd.set(Field::longs(
+                counts_tag,
+                &as_longs(&mut page.segments.iter().map(Vec::len)),
+                order,
+            ));
+            layout.push((offsets, ifd, position));
+            position += layout.last().map_or(0, |(_, ifd, _)| ifd.encoded_len());
+        }
+
+        writer.write_all(&order.signature())?;
+        writer.write_all(&order.u32_bytes(layout[0].2 as u32))?;
+        for (index, (page, (_, ifd, ifd_at))) in self.pages.iter().zip(&layout).enumerate() {
+            for segment in &page.segments {
+                writer.write_all(segment)?;
+                if segment.len() % 2 == 1 {
+                    writer.write_all(&[0])?;
+                }
+            }
+            let next = layout.get(index + 1).map_or(0, |(_, _, next)| *next);
+            writer.write_all(&ifd.encode(*ifd_at as u32, next as u32, order))?;
+        }
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Tiff::from_image(&png.to_image()?)
+    }
+
+    /// Converts the first page
+    fn to_png(&self) -> Result<Png> {
+        self.image_to_png(0)
+    }
+
+    /// The first page's resolution
+    fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::default();
+        metadata.set_resolution(image_data::resolution(&self.pages[0].ifd, self.byte_order));
+        Ok(metadata)
+    }
+
+    fn image_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn image_to_png(&self, index: usize) -> Result<Png> {
+        Png::from_image(&self.to_image(index)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image::ColorModel,
+        metadata::{Resolution, ResolutionUnit},
+    };
+
+    use self::options::TiffCompression;
+
+    /// A 20x18 RGBA gradient, big enough to need several strips or tiles
+    fn test_image() -> Image {
+        let data = (0..18_u32)
+            .flat_map(|y| (0..20_u32).map(move |x| [x as u8 * 12, y as u8 * 14, 90, 255 - x as u8]))
+            .flatten()
+            .collect();
+        Image::new(20, 18, ColorModel::Rgba, 8, data).unwrap()
+    }
+
+    #[test]
+    fn test_layouts_round_trip() {
+        let mut image = test_image();
+        image.metadata_mut().set_resolution(Some(Resolution {
+            x: 300.0,
+            y: 150.0,
+            unit: ResolutionUnit::Inch,
+        }));
+        for compression in [
+            TiffCompression::None,
+            TiffCompression::PackBits,
+            TiffCompression::Lzw,
+            TiffCompression::Deflate,
+        ] {
+            for (big_endian, tile_size, rows_per_strip) in [
+                (false, None, None),
+                (true, Some(16), None),
+                (false, None, Some(5)),
+            ] {
+                let options = TiffOptions {
+                    compression,
+                    predictor: matches!(
+                        compression,
+                        TiffCompression::Lzw | TiffCompression::Deflate
+                    ),
+                    tile_size,
+                    rows_per_strip,
+                    big_endian,
+                    palette: false,
+                };
+                let bytes = Tiff::from_images_with(std::slice::from_ref(&image), &options)
+                    .unwrap()
+                    .to_bytes();
+                assert_eq!(bytes[..2], if big_endian { *b"MM" } else { *b"II" });
+                let tiff = Tiff::try_from(bytes.as_slice()).unwrap();
+                let decoded = tiff.to_image(0).unwrap();
+                assert_eq!(decoded.data(), image.data(), "{options:?}");
+                assert_eq!(
+                    decoded.metadata().resolution(),
+                    image.metadata().resolution()
+                );
+                let segments = match (tile_size, rows_per_strip) {
+                    (Some(_), _) => 4,
+                    (_, Some(_)) => 4,
+                    _ => 1,
+                };
+                assert_eq!(tiff.pages()[0].segments.len(), segments);
+            }
+        }
+        let bytes = Tiff::from_image(&image).unwrap().to_bytes();
+        assert!(Tiff::try_from(&bytes[..bytes.len() - 10]).is_err());
+        assert!(Tiff::try_from(&bytes[..6]).is_err());
+    }
+
+    #[test]
+    fn test_pages_palette_and_bilevel() {
+        let bilevel = Image::new(
+            10,
+            2,
+            ColorModel::Gray,
+            8,
+            (0..20)
+                .map(|idx| if idx % 3 == 0 { 255 } else { 0 })
+                .collect(),
+        )
+        .unwrap();
+        let gray16 = Image::new(
+            3,
+            1,
+            ColorModel::Gray,
+            16,
+            vec![0x12, 0x34, 0, 0, 0xff, 0xff],
+        )
+        .unwrap();
+        let colors = Image::new(
+            3,
+            1,
+            ColorModel::Rgb,
+            8,
+            vec![255, 0, 0, 0, 255, 0, 255, 0, 0],
+        )
+        .unwrap();
+        let bytes = Tiff::from_images_with(
+            &[bilevel.clone(), gray16.clone(), colors.clone()],
+            &TiffOptions::default(),
+        )
+        .unwrap()
+        .to_bytes();
+        let tiff = Tiff::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(tiff.image_count(), 3);
+        let order = tiff.byte_order();
+        assert_eq!(
+            tiff.pages()[0].ifd.number(tag::BITS_PER_SAMPLE, order),
+            Some(1)
+        );
+        assert_eq!(
+            tiff.pages()[2].ifd.numbers(tag::PAGE_NUMBER, order),
+            Some(vec![2, 3])
+        );
+        assert_eq!(tiff.to_image(0).unwrap().data(), bilevel.data());
+        assert_eq!(tiff.to_image(1).unwrap().data(), gray16.data());
+        assert_eq!(tiff.to_image(2).unwrap().data(), colors.data());
+        assert!(matches!(
+            tiff.image_to_png(3),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        let palette = TiffOptions {
+            palette: true,
+            compression: TiffCompression::PackBits,
+            ..TiffOptions::default()
+        };
+        let tiff = Tiff::try_from(
+            Tiff::from_images_with(std::slice::from_ref(&colors), &palette)
+                .unwrap()
+                .to_bytes()
+                .as_slice(),
+        )
+        .unwrap();
+        let ifd = &tiff.pages()[0].ifd;
+        assert_eq!(
+            ifd.number(tag::PHOTOMETRIC_INTERPRETATION, tiff.byte_order()),
+            Some(image_data::PALETTE as u32)
+        );
+        assert_eq!(ifd.number(tag::BITS_PER_SAMPLE, tiff.byte_order()), Some(4));
+        assert_eq!(tiff.to_image(0).unwrap().data(), colors.data());
+        assert!(matches!(
+            Tiff::from_images_with(&[test_image()], &palette),
+            Err(Error::LossyConversion(_))
+        ));
+    }
+}
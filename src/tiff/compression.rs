@@ -0,0 +1,192 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{Error, Result};
+
+use super::{lzw, options::TiffCompression};
+
+/// Values of the Compression tag
+pub const NONE: u16 = 1;
+pub const LZW: u16 = 5;
+pub const DEFLATE: u16 = 8;
+pub const PACKBITS: u16 = 32773;
+/// The code Deflate was given before it was registered
+pub const OLD_DEFLATE: u16 = 32946;
+
+/// A readable name for a Compression tag value
+pub fn name(compression: u16) -> &'static str {
+    match compression {
+        NONE => "none",
+        2 => "CCITT modified Huffman",
+        3 => "CCITT T.4",
+        4 => "CCITT T.6",
+        LZW => "LZW",
+        6 => "old-style JPEG",
+        7 => "JPEG",
+        DEFLATE | OLD_DEFLATE => "deflate",
+        PACKBITS => "PackBits",
+        _ => "unknown",
+    }
+}
+
+impl TiffCompression {
+    /// The Compression tag value for this method
+    pub fn code(&self) -> u16 {
+        match self {
+            TiffCompression::None => NONE,
+            TiffCompression::PackBits => PACKBITS,
+            TiffCompression::Lzw => LZW,
+            TiffCompression::Deflate => DEFLATE,
+        }
+    }
+}
+
+/// Decompresses one strip or tile, which has to come out at least `len` bytes long
+pub fn decompress(compression: u16, data: &[u8], len: usize, segment: usize) -> Result<Vec<u8>> {
+    let mut out = match compression {
+        NONE => data.to_vec(),
+        PACKBITS => unpack_bits(data, len),
+        LZW => lzw::decode(data, len)?,
+        DEFLATE | OLD_DEFLATE => {
+            let mut out: Vec<u8> = Vec::new();
+            ZlibDecoder::new(data)
+                .take(len as u64)
+                .read_to_end(&mut out)
+                .map_err(|e| Error::malformed("TIFF", format!("segment {segment}: {e}")))?;
+            out
+        }
+        other => {
+            return Err(Error::unsupported(
+                "TIFF",
+                format!("{} compression ({other})", name(other)),
+            ))
+        }
+    };
+    if out.len() < len {
+        return Err(Error::malformed(
+            "TIFF",
+            format!(
+                "segment {segment} holds {} bytes of pixel data, not the {len} its size calls for",
+                out.len()
+            ),
+        ));
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+/// Compresses one strip or tile made of rows `row_len` bytes long
+pub fn compress(compression: TiffCompression, data: &[u8], row_len: usize) -> Vec<u8> {
+    match compression {
+        TiffCompression::None => data.to_vec(),
+        // rows are packed separately, as TIFF asks
+        TiffCompression::PackBits => data.chunks(row_len.max(1)).flat_map(pack_bits).collect(),
+        TiffCompression::Lzw => lzw::encode(data),
+        TiffCompression::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(data)
+                .and_then(|_| encoder.finish())
+                .expect("writing to a Vec can't fail")
+        }
+    }
+}
+
+/// Expands PackBits data: a signed count byte, then either that many plus one literal
+/// bytes or, for negative counts, one byte repeated one minus the count times
+pub fn unpack_bits(data: &[u8], max_len: usize) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(max_len.min(data.len().saturating_mul(64)));
+    let mut bytes = data.iter();
+    while out.len() < max_len {
+        let Some(&count) = bytes.next() else {
+            break;
+        };
+        match count as i8 {
+            -128 => {}
+            count @ 0.. => out.extend(bytes.by_ref().take(count as usize + 1)),
+            count => {
+                if let Some(&byte) = bytes.next() {
+                    out.extend(std::iter::repeat_n(byte, 1 + count.unsigned_abs() as usize));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Packs one row with PackBits, repeating runs of three or more bytes and copying the rest
+pub fn pack_bits(row: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut literal_start = 0;
+    let mut idx = 0;
+    let flush = |out: &mut Vec<u8>, literal: &[u8]| {
+        for chunk in literal.chunks(128) {
+            out.push(chunk.len() as u8 - 1);
+            out.extend(chunk);
+        }
+    };
+    while idx < row.len() {
+        let run = row[idx..]
+            .iter()
+            .take(128)
+            .take_while(|&&byte| byte == row[idx])
+            .count();
+        if run >= 3 {
+            flush(&mut out, &row[literal_start..idx]);
+            out.extend([(1 - run as i16) as u8, row[idx]]);
+            idx += run;
+            literal_start = idx;
+        } else {
+            idx += run;
+        }
+    }
+    flush(&mut out, &row[literal_start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_bits() {
+        // the example from the TIFF 6.0 specification
+        let packed = [
+            0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0xfd, 0xaa, 0x03, 0x80, 0x00, 0x2a, 0x22, 0xf7,
+            0xaa,
+        ];
+        let unpacked = [
+            0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0xaa, 0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0x22,
+            0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+        ];
+        assert_eq!(unpack_bits(&packed, 100), unpacked);
+        assert_eq!(unpack_bits(&pack_bits(&unpacked), 100), unpacked);
+
+        let long: Vec<u8> = (0..300)
+            .map(|idx| if idx < 200 { 7 } else { idx as u8 })
+            .collect();
+        let packed = pack_bits(&long);
+        assert!(packed.len() < 120);
+        assert_eq!(unpack_bits(&packed, long.len()), long);
+
+        let data: Vec<u8> = (0..1000).map(|idx| (idx / 10) as u8).collect();
+        for method in [
+            TiffCompression::None,
+            TiffCompression::PackBits,
+            TiffCompression::Lzw,
+            TiffCompression::Deflate,
+        ] {
+            let compressed = compress(method, &data, 100);
+            assert_eq!(
+                decompress(method.code(), &compressed, 1000, 0).unwrap(),
+                data
+            );
+            assert!(decompress(method.code(), &compressed, 1001, 0).is_err());
+        }
+        assert!(matches!(
+            decompress(7, &data, 10, 0),
+            Err(Error::Unsupported { .. })
+        ));
+    }
+}
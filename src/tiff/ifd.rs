@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+
+use crate::{byte_reader::ByteReader, Error, Result};
+
+/// Field types, which say how a field's values are stored
+pub const BYTE: u16 = 1;
+pub const ASCII: u16 = 2;
+pub const SHORT: u16 = 3;
+pub const LONG: u16 = 4;
+pub const RATIONAL: u16 = 5;
+/// Pointer to another IFD, from the TIFF/EP and EXIF additions
+pub const IFD: u16 = 13;
+
+/// Bytes taken up by one value of `field_type`, or `None` for types this crate doesn't know
+fn type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// Byte order of every number in a TIFF, which the first two bytes of the file give
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    /// The first four bytes of a TIFF in this byte order, including the version number 42
+    pub fn signature(&self) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => *b"II*\0",
+            ByteOrder::BigEndian => *b"MM\0*",
+        }
+    }
+
+    pub fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    pub fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    pub fn read_u16(&self, reader: &mut ByteReader) -> Result<u16> {
+        match self {
+            ByteOrder::LittleEndian => reader.read_u16_le(),
+            ByteOrder::BigEndian => reader.read_u16_be(),
+        }
+    }
+
+    pub fn read_u32(&self, reader: &mut ByteReader) -> Result<u32> {
+        match self {
+            ByteOrder::LittleEndian => reader.read_u32_le(),
+            ByteOrder::BigEndian => reader.read_u32_be(),
+        }
+    }
+}
+
+/// One entry of an IFD
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub tag: u16,
+    pub field_type: u16,
+    /// Number of values, not bytes
+    pub count: u32,
+    /// The values as stored, in the file's byte order
+    pub data: Vec<u8>,
+}
+
+impl Field {
+    pub fn shorts(tag: u16, values: &[u16], order: ByteOrder) -> Self {
+        Field {
+            tag,
+            field_type: SHORT,
+            count: values.len() as u32,
+            data: values
+                .iter()
+                .flat_map(|&value| order.u16_bytes(value))
+                .collect(),
+        }
+    }
+
+    pub fn longs(tag: u16, values: &[u32], order: ByteOrder) -> Self {
+        Field {
+            tag,
+            field_type: LONG,
+            count: values.len() as u32,
+            data: values
+                .iter()
+                .flat_map(|&value| order.u32_bytes(value))
+                .collect(),
+        }
+    }
+
+    /// A field of numerator and denominator pairs
+    pub fn rationals(tag: u16, values: &[(u32, u32)], order: ByteOrder) -> Self {
+        Field {
+            tag,
+            field_type: RATIONAL,
+            count: values.len() as u32,
+            data: values
+                .iter()
+                .flat_map(|&(numerator, denominator)| {
+                    [order.u32_bytes(numerator), order.u32_bytes(denominator)].concat()
+                })
+                .collect(),
+        }
+    }
+
+    /// A field of ASCII text, which TIFF ends with a NUL
+    pub fn ascii(tag: u16, text: &str) -> Self {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        Field {
+            tag,
+            field_type: ASCII,
+            count: data.len() as u32,
+            data,
+        }
+    }
+
+    /// The values of a BYTE, SHORT or LONG field, which TIFF lets writers choose between
+    /// for most numeric tags
+    pub fn numbers(&self, order: ByteOrder) -> Option<Vec<u32>> {
+        match self.field_type {
+            BYTE => Some(self.data.iter().map(|&value| value as u32).collect()),
+            SHORT => Some(
+                self.data
+                    .chunks_exact(2)
+                    .map(|value| order.u16(value) as u32)
+                    .collect(),
+            ),
+            LONG | IFD => Some(
+                self.data
+                    .chunks_exact(4)
+                    .map(|value| order.u32(value))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// The numerator and denominator pairs of a RATIONAL field
+    pub fn rational_values(&self, order: ByteOrder) -> Option<Vec<(u32, u32)>> {
+        (self.field_type == RATIONAL).then(|| {
+            self.data
+                .chunks_exact(8)
+                .map(|value| (order.u32(&value[..4]), order.u32(&value[4..])))
+                .collect()
+        })
+    }
+
+    /// The text of an ASCII field, up to its first NUL
+    pub fn text(&self) -> Option<String> {
+        (self.field_type == ASCII).then(|| {
+            let end = self.data.iter().position(|&byte| byte == 0);
+            String::from_utf8_lossy(&self.data[..end.unwrap_or(self.data.len())]).into_owned()
+        })
+    }
+}
+
+/// An image file directory: the fields describing one image, kept sorted by tag as TIFF
+/// requires
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ifd {
+    fields: Vec<Field>,
+}
+
+impl Ifd {
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn get(&self, tag: u16) -> Option<&Field> {
+        self.fields
+            .binary_search_by_key(&tag, |field| field.tag)
+            .ok()
+            .map(|idx| &self.fields[idx])
+    }
+
+    /// Adds `field`, replacing any field with the same tag
+    pub fn set(&mut self, field: Field) {
+        match self
+            .fields
+            .binary_search_by_key(&field.tag, |field| field.tag)
+        {
+            Ok(idx) => self.fields[idx] = field,
+            Err(idx) => self.fields.insert(idx, field),
+        }
+    }
+
+    pub fn remove(&mut self, tag: u16) {
+        self.fields.retain(|field| field.tag != tag);
+    }
+
+    /// The values of a numeric field, or `None` if it's missing or not numeric
+    pub fn numbers(&self, tag: u16, order: ByteOrder) -> Option<Vec<u32>> {
+        self.get(tag)?.numbers(order)
+    }
+
+    /// The first value of a numeric field
+    pub fn number(&self, tag: u16, order: ByteOrder) -> Option<u32> {
+        self.numbers(tag, order)?.first().copied()
+    }
+
+    /// Reads the IFD at `offset` in `file`, returning it and the offset of the next one,
+    /// which is 0 after the last. Fields of types this crate doesn't know are skipped, as
+    /// TIFF asks readers to.
+    pub fn read(file: &ByteReader, offset: u32, order: ByteOrder) -> Result<(Ifd, u32)> {
+        let mut reader = file.at(offset as usize)?;
+        let count = order.read_u16(&mut reader)?;
+        let mut ifd = Ifd::default();
+        for _ in 0..count {
+            let at = reader.position();
+            let tag = order.read_u16(&mut reader)?;
+            let field_type = order.read_u16(&mut reader)?;
+            let value_count = order.read_u32(&mut reader)?;
+            let value = reader.read_bytes(4)?;
+            let Some(size) = type_size(field_type) else {
+                continue;
+            };
+            let len = (value_count as usize).checked_mul(size).ok_or_else(|| {
+                Error::malformed_at("TIFF", at, format!("tag {tag} has too many values"))
+            })?;
+            let data = match len <= 4 {
+                true => value[..len].to_vec(),
+                false => file
+                    .at(order.u32(value) as usize)
+                    .and_then(|mut values| values.read_bytes(len))
+                    .map_err(|_| {
+                        Error::malformed_at(
+                            "TIFF",
+                            at + 8,
+                            format!("the values of tag {tag} run past the end of the file"),
+                        )
+                    })?
+                    .to_vec(),
+            };
+            ifd.set(Field {
+                tag,
+                field_type,
+                count: value_count,
+                data,
+            });
+        }
+        let next = order.read_u32(&mut reader)?;
+        Ok((ifd, next))
+    }
+
+    /// Reads the chain of IFDs starting at `offset`, stopping if it loops back on itself
+    pub fn read_chain(file: &ByteReader, mut offset: u32, order: ByteOrder) -> Result<Vec<Ifd>> {
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut ifds: Vec<Ifd> = Vec::new();
+        while offset != 0 && seen.insert(offset) {
+            let (ifd, next) = Ifd::read(file, offset, order)?;
+            ifds.push(ifd);
+            offset = next;
+        }
+        Ok(ifds)
+    }
+
+    /// Bytes `encode` produces: the entries, then whatever values don't fit in them
+    pub fn encoded_len(&self) -> usize {
+        let values: usize = self
+            .fields
+            .iter()
+            .filter(|field| field.data.len() > 4)
+            .map(|field| field.data.len().next_multiple_of(2))
+            .sum();
+        2 + self.fields.len() * 12 + 4 + values
+    }
+
+    /// Encodes the IFD as it sits at `offset` in the file, followed by the values too large
+    /// to fit in their entries. `next` is where the next IFD starts, or 0.
+    pub fn encode(&self, offset: u32, next: u32, order: ByteOrder) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.encoded_len());
+        let mut values: Vec<u8> = Vec::new();
+        let values_at = offset as usize + 2 + self.fields.len() * 12 + 4;
+        bytes.extend(order.u16_bytes(self.fields.len() as u16));
+        for field in &self.fields {
+            bytes.extend(order.u16_bytes(field.tag));
+            bytes.extend(order.u16_bytes(field.field_type));
+            bytes.extend(order.u32_bytes(field.count));
+            match field.data.len() <= 4 {
+                true => {
+                    bytes.extend(&field.data);
+                    bytes.extend(vec![0; 4 - field.data.len()]);
+                }
+                false => {
+                    bytes.extend(order.u32_bytes((values_at + values.len()) as u32));
+                    values.extend(&field.data);
+                    // values start on a word boundary
+                    if values.len() % 2 == 1 {
+                        values.push(0);
+                    }
+                }
+            }
+        }
+        bytes.extend(order.u32_bytes(next));
+        bytes.extend(values);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ifd_round_trip() {
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut ifd = Ifd::default();
+            ifd.set(Field::ascii(305, "converter"));
+            ifd.set(Field::shorts(258, &[8, 8, 8], order));
+            ifd.set(Field::longs(256, &[70000], order));
+            ifd.set(Field::rationals(282, &[(300, 1)], order));
+            ifd.set(Field::shorts(256, &[640], order));
+            assert_eq!(
+                ifd.fields()
+                    .iter()
+                    .map(|field| field.tag)
+                    .collect::<Vec<u16>>(),
+                [256, 258, 282, 305]
+            );
+
+            let mut file = order.signature().to_vec();
+            file.extend(order.u32_bytes(8));
+            file.extend(ifd.encode(8, 0, order));
+            assert_eq!(file.len(), 8 + ifd.encoded_len());
+            let reader = ByteReader::new("TIFF", &file);
+            let (read, next) = Ifd::read(&reader, 8, order).unwrap();
+            assert_eq!(next, 0);
+            assert_eq!(read, ifd);
+            assert_eq!(read.number(256, order), Some(640));
+            assert_eq!(read.numbers(258, order), Some(vec![8, 8, 8]));
+            assert_eq!(
+                read.get(282).unwrap().rational_values(order),
+                Some(vec![(300, 1)])
+            );
+            assert_eq!(read.get(305).unwrap().text().as_deref(), Some("converter"));
+
+            // an IFD that names itself as the next one is only read once
+            let mut looped = file.clone();
+            let next_at = 8 + 2 + 4 * 12;
+            looped[next_at..next_at + 4].copy_from_slice(&order.u32_bytes(8));
+            let chain = Ifd::read_chain(&ByteReader::new("TIFF", &looped), 8, order).unwrap();
+            assert_eq!(chain.len(), 1);
+            assert!(Ifd::read(&ByteReader::new("TIFF", &file[..40]), 8, order).is_err());
+        }
+    }
+}
@@ -0,0 +1,757 @@
+use std::io::Read;
+
+use crate::{
+    image::{ColorModel, Image},
+    metadata::{Resolution, ResolutionUnit},
+    scanline::RowLayout,
+    ConvertibleImage, Error, Result,
+};
+
+use super::{
+    compression,
+    ifd::{ByteOrder, Field, Ifd},
+    options::TiffOptions,
+    tag, Page, Tiff,
+};
+
+/// Values of the PhotometricInterpretation tag
+pub const WHITE_IS_ZERO: u16 = 0;
+pub const BLACK_IS_ZERO: u16 = 1;
+pub const RGB: u16 = 2;
+pub const PALETTE: u16 = 3;
+
+/// ExtraSamples values for alpha premultiplied into the colors, and for plain alpha
+const ASSOCIATED_ALPHA: u32 = 1;
+const UNASSOCIATED_ALPHA: u32 = 2;
+
+/// Strips are cut to about this many bytes unless the options say otherwise
+const STRIP_SIZE: usize = 8192;
+
+/// What a page's fields say about how its pixels are stored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageLayout {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u16,
+    pub bits_per_sample: u16,
+    pub photometric: u16,
+    pub compression: u16,
+    /// 1 for none, 2 for horizontal differencing
+    pub predictor: u16,
+    /// Whether each sample is stored in segments of its own rather than interleaved
+    pub planar: bool,
+    /// Width and height of each tile, for tiled pages
+    pub tiles: Option<(u32, u32)>,
+    pub rows_per_strip: u32,
+    /// What each sample after the color ones holds
+    pub extra_samples: Vec<u32>,
+    /// 1 for unsigned integers, the only kind this crate decodes
+    pub sample_format: u32,
+    pub fill_order: u32,
+    /// Red, then green, then blue values for every index, for palette images
+    pub color_map: Option<Vec<u16>>,
+}
+
+impl PageLayout {
+    pub fn read(ifd: &Ifd, order: ByteOrder) -> Result<Self> {
+        let required = |tag: u16, name: &str| {
+            ifd.number(tag, order)
+                .ok_or_else(|| Error::malformed("TIFF", format!("the {name} tag is missing")))
+        };
+        let width = required(tag::IMAGE_WIDTH, "ImageWidth")?;
+        let height = required(tag::IMAGE_LENGTH, "ImageLength")?;
+        if width == 0 || height == 0 {
+            return Err(Error::malformed(
+                "TIFF",
+                format!("a {width}x{height} image has no pixels"),
+            ));
+        }
+        let samples_per_pixel = ifd.number(tag::SAMPLES_PER_PIXEL, order).unwrap_or(1) as u16;
+        let bits =
+            ifd.numbers(tag::BITS_PER_SAMPLE, order)
+                .unwrap_or(vec![1; samples_per_pixel as usize]);
+        if bits.is_empty() {
+            return Err(Error::malformed("TIFF", "a page has no samples"));
+        }
+        if bits.len() != samples_per_pixel as usize {
+            return Err(Error::malformed(
+                "TIFF",
+                format!(
+                    "BitsPerSample gives {} sizes for {samples_per_pixel} samples per pixel",
+                    bits.len()
+                ),
+            ));
+        }
+        if bits.iter().any(|&sample_bits| sample_bits != bits[0]) {
+            return Err(Error::unsupported(
+                "TIFF",
+                format!("samples of different sizes ({bits:?} bits)"),
+            ));
+        }
+        // checked here so describing a page can rely on it as much as decoding one
+        let bits_per_sample = bits[0];
+        if !(1..=64).contains(&bits_per_sample) {
+            return Err(Error::malformed(
+                "TIFF",
+                format!("{bits_per_sample}-bit samples"),
+            ));
+        }
+        let photometric =
+            required(tag::PHOTOMETRIC_INTERPRETATION, "PhotometricInterpretation")? as u16;
+        if photometric == PALETTE && bits_per_sample > 16 {
+            return Err(Error::malformed(
+                "TIFF",
+                format!("{bits_per_sample}-bit palette indices"),
+            ));
+        }
+        let tiles = match (
+            ifd.number(tag::TILE_WIDTH, order),
+            ifd.number(tag::TILE_LENGTH, order),
+        ) {
+            (Some(0), _) | (_, Some(0)) => {
+                return Err(Error::malformed("TIFF", "tiles have no pixels"))
+            }
+            (Some(tile_width), Some(tile_length)) => Some((tile_width, tile_length)),
+            _ => None,
+        };
+        Ok(PageLayout {
+            width,
+            height,
+            samples_per_pixel,
+            bits_per_sample: bits_per_sample as u16,
+            photometric,
+            compression: ifd
+                .number(tag::COMPRESSION, order)
+                .unwrap_or(compression::NONE as u32) as u16,
+            predictor: ifd.number(tag::PREDICTOR, order).unwrap_or(1) as u16,
+            planar: ifd.number(tag::PLANAR_CONFIGURATION, order) == Some(2),
+            tiles,
+            rows_per_strip: ifd
+                .number(tag::ROWS_PER_STRIP, order)
+                .unwrap_or(u32::MAX)
+                .clamp(1, height),
+            extra_samples: ifd.numbers(tag::EXTRA_SAMPLES, order).unwrap_or_default(),
+            sample_format: ifd.number(tag::SAMPLE_FORMAT, order).unwrap_or(1),
+            fill_order: ifd.number(tag::FILL_ORDER, order).unwrap_or(1),
+            color_map: ifd
+                .numbers(tag::COLOR_MAP, order)
+                .map(|values| values.into_iter().map(|value| value as u16).collect()),
+        })
+    }
+
+    /// Samples that make up the color, before any extra ones
+    fn color_samples(&self) -> usize {
+        match self.photometric {
+            RGB | 6 | 8 => 3,
+            5 => 4,
+            _ => 1,
+        }
+    }
+
+    /// Index of the alpha sample, and whether the colors have been multiplied by it
+    fn alpha(&self) -> Option<(usize, bool)> {
+        let first_extra = self.color_samples();
+        self.extra_samples
+            .iter()
+            .take((self.samples_per_pixel as usize).saturating_sub(first_extra))
+            .position(|&extra| extra == ASSOCIATED_ALPHA || extra == UNASSOCIATED_ALPHA)
+            .map(|idx| {
+                (
+                    first_extra + idx,
+                    self.extra_samples[idx] == ASSOCIATED_ALPHA,
+                )
+            })
+    }
+
+    /// Width and height of each strip or tile
+    pub fn segment_size(&self) -> (u32, u32) {
+        self.tiles.unwrap_or((self.width, self.rows_per_strip))
+    }
+
+    /// Strips or tiles the pixels are divided into
+    pub fn segment_count(&self) -> usize {
+        let (segment_width, segment_height) = self.segment_size();
+        let planes = if self.planar {
+            self.samples_per_pixel as usize
+        } else {
+            1
+        };
+        self.width.div_ceil(segment_width) as usize
+            * self.height.div_ceil(segment_height) as usize
+            * planes
+    }
+
+    /// How the color is stored, named as `ImageInfo` names color models
+    pub fn color_model_name(&self) -> &'static str {
+        let alpha = self.alpha().is_some();
+        match self.photometric {
+            WHITE_IS_ZERO | BLACK_IS_ZERO if alpha => "gray+alpha",
+            WHITE_IS_ZERO | BLACK_IS_ZERO => "gray",
+            RGB if alpha => "rgba",
+            RGB => "rgb",
+            PALETTE => "indexed",
+            4 => "transparency mask",
+            5 => "cmyk",
+            6 => "ycbcr",
+            8 => "cielab",
+            _ => "unknown",
+        }
+    }
+
+    /// Fails for layouts `decode` can't turn into an `Image`
+    fn check_supported(&self) -> Result<()> {
+        let unsupported = |feature: String| Err(Error::unsupported("TIFF", feature));
+        if !matches!(
+            self.photometric,
+            WHITE_IS_ZERO | BLACK_IS_ZERO | RGB | PALETTE
+        ) {
+            return unsupported(format!("{} images", self.color_model_name()));
+        }
+        if !matches!(self.bits_per_sample, 1 | 2 | 4 | 8 | 16) {
+            return unsupported(format!("{}-bit samples", self.bits_per_sample));
+        }
+        if self.sample_format != 1 {
+            return unsupported("samples that aren't unsigned integers".to_string());
+        }
+        if self.predictor > 2 {
+            return unsupported(format!("predictor {}", self.predictor));
+        }
+        if self.fill_order != 1 && self.bits_per_sample < 8 {
+            return unsupported("bits filled from the least significant end".to_string());
+        }
+        if (self.samples_per_pixel as usize) < self.color_samples() {
+            return Err(Error::malformed(
+                "TIFF",
+                format!(
+                    "{} images need {} samples per pixel, not {}",
+                    self.color_model_name(),
+                    self.color_samples(),
+                    self.samples_per_pixel
+                ),
+            ));
+        }
+        if self.photometric == PALETTE {
+            let entries = 1_usize << self.bits_per_sample;
+            if self.color_map.as_ref().map(Vec::len) != Some(entries * 3) {
+                return Err(Error::malformed(
+                    "TIFF",
+                    format!(
+                        "a {}-bit palette image needs a ColorMap of {} values",
+                        self.bits_per_sample,
+                        entries * 3
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reports the first page's size, without decoding it
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let tiff = Tiff::from_reader(reader)?;
+    let layout = PageLayout::read(&tiff.pages()[0].ifd, tiff.byte_order())?;
+    Ok(RowLayout {
+        width: layout.width,
+        height: layout.height,
+        color_model: ColorModel::Rgba,
+        bit_depth: 16,
+    })
+}
+
+/// The resolution a page's tags give, if they give both densities
+pub fn resolution(ifd: &Ifd, order: ByteOrder) -> Option<Resolution> {
+    let density = |tag: u16| {
+        let (numerator, denominator) = *ifd.get(tag)?.rational_values(order)?.first()?;
+        (denominator != 0).then(|| numerator as f64 / denominator as f64)
+    };
+    Some(Resolution {
+        x: density(tag::X_RESOLUTION)?,
+        y: density(tag::Y_RESOLUTION)?,
+        unit: match ifd.number(tag::RESOLUTION_UNIT, order).unwrap_or(2) {
+            2 => ResolutionUnit::Inch,
+            3 => ResolutionUnit::Centimeter,
+            _ => ResolutionUnit::Unknown,
+        },
+    })
+}
+
+/// Decodes one page into an `Image`, along with its resolution
+pub fn decode(page: &Page, order: ByteOrder) -> Result<Image> {
+    let layout = PageLayout::read(&page.ifd, order)?;
+    layout.check_supported()?;
+    let samples = read_samples(page, &layout, order)?;
+    let mut image = to_image(&layout, &samples)?;
+    if let Some(resolution) = resolution(&page.ifd, order) {
+        image.metadata_mut().set_resolution(Some(resolution));
+    }
+    Ok(image)
+}
+
+/// Decompresses every strip or tile and gathers their samples, unpacked to one per `u16`,
+/// into pixel order
+fn read_samples(page: &Page, layout: &PageLayout, order: ByteOrder) -> Result<Vec<u16>> {
+    let (width, height) = (layout.width as usize, layout.height as usize);
+    let samples_per_pixel = layout.samples_per_pixel as usize;
+    let bits = layout.bits_per_sample as usize;
+    if page.segments.len() < layout.segment_count() {
+        return Err(Error::malformed(
+            "TIFF",
+            format!(
+                "the image is stored in {} segments, but {} are listed",
+                layout.segment_count(),
+                page.segments.len()
+            ),
+        ));
+    }
+    // checked before allocating, so forged dimensions can't exhaust memory; no method
+    // here expands data more than about 2000 times
+    let stored: usize = page.segments.iter().map(Vec::len).sum();
+    let sample_count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(samples_per_pixel))
+        .filter(|&count| count / 8 * bits <= stored.saturating_mul(2048))
+        .ok_or_else(|| {
+            Error::malformed(
+                "TIFF",
+                format!("{stored} bytes of pixel data can't hold a {width}x{height} image"),
+            )
+        })?;
+
+    let (segment_width, segment_height) = layout.segment_size();
+    let (segment_width, segment_height) = (segment_width as usize, segment_height as usize);
+    let (across, down) = (
+        width.div_ceil(segment_width),
+        height.div_ceil(segment_height),
+    );
+    let (planes, per_segment) = match layout.planar {
+        true => (samples_per_pixel, 1),
+        false => (1, samples_per_pixel),
+    };
+    let row_len = (segment_width * per_segment * bits).div_ceil(8);
+    let mask = ((1_u32 << bits) - 1) as u16;
+
+    let mut samples: Vec<u16> = vec![0; sample_count];
+    for plane in 0..planes {
+        for segment_y in 0..down {
+            for segment_x in 0..across {
+                let index = (plane * down + segment_y) * across + segment_x;
+                let (x0, y0) = (segment_x * segment_width, segment_y * segment_height);
+                // the last strip stops at the bottom of the image; tiles are always whole
+                let rows = match layout.tiles {
+                    Some(_) => segment_height,
+                    None => segment_height.min(height - y0),
+                };
+                let data = compression::decompress(
+                    layout.compression,
+                    &page.segments[index],
+                    row_len * rows,
+                    index,
+                )?;
+                let columns = segment_width.min(width - x0);
+                for (y, stored_row) in (y0..height).zip(data.chunks_exact(row_len)) {
+                    let mut row = unpack(stored_row, bits, segment_width * per_segment, order);
+                    if layout.predictor == 2 {
+                        for idx in per_segment..row.len() {
+                            row[idx] = row[idx].wrapping_add(row[idx - per_segment]) & mask;
+                        }
+                    }
+                    for x in 0..columns {
+                        let at = (y * width + x0 + x) * samples_per_pixel + plane;
+                        samples[at..at + per_segment]
+                            .copy_from_slice(&row[x * per_segment..(x + 1) * per_segment]);
+                    }
+                }
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// Splits a stored row into `count` samples of `bits` each
+fn unpack(row: &[u8], bits: usize, count: usize, order: ByteOrder) -> Vec<u16> {
+    match bits {
+        8 => row[..count].iter().map(|&sample| sample as u16).collect(),
+        16 => row
+            .chunks_exact(2)
+            .take(count)
+            .map(|sample| order.u16(sample))
+            .collect(),
+        _ => (0..count)
+            .map(|idx| {
+                let bit = idx * bits;
+                (row[bit / 8] >> (8 - bits - bit % 8) & ((1 << bits) - 1) as u8) as u16
+            })
+            .collect(),
+    }
+}
+
+/// Packs samples of `bits` each into a stored row, the reverse of `unpack`
+fn pack(samples: &[u16], bits: usize, order: ByteOrder) -> Vec<u8> {
+    match bits {
+        8 => samples.iter().map(|&sample| sample as u8).collect(),
+        16 => samples
+            .iter()
+            .flat_map(|&sample| order.u16_bytes(sample))
+            .collect(),
+        _ => {
+            let mut row: Vec<u8> = vec![0; (samples.len() * bits).div_ceil(8)];
+            for (idx, &sample) in samples.iter().enumerate() {
+                let bit = idx * bits;
+                row[bit / 8] |= (sample as u8) << (8 - bits - bit % 8);
+            }
+            row
+        }
+    }
+}
+
+/// Turns unpacked samples into an `Image`, resolving the photometric interpretation, the
+/// color map and premultiplied alpha
+fn to_image(layout: &PageLayout, samples: &[u16]) -> Result<Image> {
+    let bits = layout.bits_per_sample;
+    let max = (1_u32 << bits) - 1;
+    let samples_per_pixel = layout.samples_per_pixel as usize;
+    let alpha = layout.alpha();
+    let gray = matches!(layout.photometric, WHITE_IS_ZERO | BLACK_IS_ZERO);
+    let color_map = layout.color_map.as_deref().unwrap_or_default();
+    // color maps hold 16-bit values, which are often just 8-bit ones scaled up
+    let depth: u8 = match layout.photometric {
+        PALETTE if color_map.iter().any(|value| value % 257 != 0) => 16,
+        PALETTE => 8,
+        _ if bits == 16 => 16,
+        _ => 8,
+    };
+    let out_max = (1_u32 << depth) - 1;
+    let scale = |sample: u16| (sample as u32 * out_max / max) as u16;
+    let color_model = match (gray, alpha.is_some()) {
+        (true, false) => ColorModel::Gray,
+        (true, true) => ColorModel::GrayAlpha,
+        (false, false) => ColorModel::Rgb,
+        (false, true) => ColorModel::Rgba,
+    };
+
+    let mut data: Vec<u8> =
+        Vec::with_capacity(samples.len() / samples_per_pixel * color_model.channels() * 2);
+    let mut channels: Vec<u16> = Vec::with_capacity(4);
+    for pixel in samples.chunks_exact(samples_per_pixel) {
+        channels.clear();
+        match layout.photometric {
+            WHITE_IS_ZERO => channels.push(scale(max as u16 - pixel[0])),
+            BLACK_IS_ZERO => channels.push(scale(pixel[0])),
+            PALETTE => {
+                let entries = color_map.len() / 3;
+                let index = pixel[0] as usize;
+                for channel in 0..3 {
+                    let value = color_map[channel * entries + index];
+                    channels.push(if depth == 8 { value >> 8 } else { value });
+                }
+            }
+            _ => channels.extend(pixel[..3].iter().map(|&sample| scale(sample))),
+        }
+        if let Some((alpha_at, premultiplied)) = alpha {
+            let alpha = scale(pixel[alpha_at]);
+            if premultiplied && alpha > 0 {
+                for channel in channels.iter_mut() {
+                    *channel = (*channel as u32 * out_max / alpha as u32).min(out_max) as u16;
+                }
+            }
+            channels.push(alpha);
+        }
+        for &channel in &channels {
+            match depth {
+                8 => data.push(channel as u8),
+                _ => data.extend(channel.to_be_bytes()),
+            }
+        }
+    }
+    Image::new(layout.width, layout.height, color_model, depth, data)
+}
+
+/// How an image's pixels are going to be stored
+struct Stored {
+    photometric: u16,
+    bits: usize,
+    samples_per_pixel: usize,
+    alpha: bool,
+    color_map: Option<Vec<u16>>,
+    /// Every sample of every pixel, in pixel order
+    samples: Vec<u16>,
+}
+
+/// Works out how to store `image`: as a color map when asked, as 1-bit samples when it's
+/// black and white, and at its own depth otherwise
+fn stored(image: &Image, options: &TiffOptions) -> Result<Stored> {
+    let pixel_count = image.width() as usize * image.height() as usize;
+    if options.palette {
+        let indexed = image.to_indexed(256).ok_or_else(|| {
+            Error::lossy("the image has more than the 256 colors a TIFF color map can hold")
+        })?;
+        if indexed.palette.iter().any(|color| color[3] != 255) {
+            return Err(Error::lossy(
+                "the image has translucent pixels, which a TIFF color map can't hold",
+            ));
+        }
+        let bits = if indexed.palette.len() <= 16 { 4 } else { 8 };
+        let entries = 1 << bits;
+        let mut color_map: Vec<u16> = vec![0; entries * 3];
+        for (index, color) in indexed.palette.iter().enumerate() {
+            for channel in 0..3 {
+                color_map[channel * entries + index] = color[channel] as u16 * 257;
+            }
+        }
+        return Ok(Stored {
+            photometric: PALETTE,
+            bits,
+            samples_per_pixel: 1,
+            alpha: false,
+            color_map: Some(color_map),
+            samples: indexed.indices.iter().map(|&index| index as u16).collect(),
+        });
+    }
+
+    let channels = image.color_model().channels();
+    let samples: Vec<u16> = (0..pixel_count * channels)
+        .map(|idx| image.sample(idx))
+        .collect();
+    let bilevel = image.color_model() == ColorModel::Gray
+        && image.bit_depth() == 8
+        && samples.iter().all(|&sample| sample == 0 || sample == 255);
+    Ok(Stored {
+        photometric: match image.color_model() {
+            ColorModel::Gray | ColorModel::GrayAlpha => BLACK_IS_ZERO,
+            ColorModel::Rgb | ColorModel::Rgba => RGB,
+        },
+        bits: if bilevel {
+            1
+        } else {
+            image.bit_depth() as usize
+        },
+        samples_per_pixel: channels,
+        alpha: image.color_model().has_alpha(),
+        color_map: None,
+        samples: match bilevel {
+            true => samples.iter().map(|&sample| sample & 1).collect(),
+            false => samples,
+        },
+    })
+}
+
+/// Encodes `image` as one page of a TIFF in `order`, laid out as `options` asks
+pub fn encode(image: &Image, options: &TiffOptions, order: ByteOrder) -> Result<Page> {
+    options.validate()?;
+    let stored = stored(image, options)?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let samples_per_pixel = stored.samples_per_pixel;
+    let bits = stored.bits;
+    let predictor = options.predictor && bits >= 8;
+    let mask = ((1_u32 << bits) - 1) as u16;
+
+    let pixel_row_len = |pixels: usize| (pixels * samples_per_pixel * bits).div_ceil(8);
+    let (segment_width, segment_height) = match options.tile_size {
+        Some(tile_size) => (tile_size as usize, tile_size as usize),
+        None => {
+            let rows = match options.rows_per_strip {
+                Some(rows) => rows as usize,
+                None => (STRIP_SIZE / pixel_row_len(width)).max(1),
+            };
+            (width, rows.min(height))
+        }
+    };
+    let row_len = pixel_row_len(segment_width);
+
+    let mut segments: Vec<Vec<u8>> = Vec::new();
+    for y0 in (0..height).step_by(segment_height) {
+        for x0 in (0..width).step_by(segment_width) {
+            // tiles are always whole, padded out past the edges of the image
+            let rows = match options.tile_size {
+                Some(_) => segment_height,
+                None => segment_height.min(height - y0),
+            };
+            let columns = segment_width.min(width - x0);
+            let mut data: Vec<u8> = Vec::with_capacity(row_len * rows);
+            let mut row: Vec<u16> = vec![0; segment_width * samples_per_pixel];
+            for y in y0..y0 + rows {
+                row.fill(0);
+                if y < height {
+                    let at = (y * width + x0) * samples_per_pixel;
+                    row[..columns * samples_per_pixel]
+                        .copy_from_slice(&stored.samples[at..at + columns * samples_per_pixel]);
+                }
+                if predictor {
+                    for idx in (samples_per_pixel..row.len()).rev() {
+                        row[idx] = row[idx].wrapping_sub(row[idx - samples_per_pixel]) & mask;
+                    }
+                }
+                data.extend(pack(&row, bits, order));
+            }
+            segments.push(compression::compress(options.compression, &data, row_len));
+        }
+    }
+
+    let mut ifd = Ifd::default();
+    ifd.set(Field::longs(tag::IMAGE_WIDTH, &[width as u32], order));
+    ifd.set(Field::longs(tag::IMAGE_LENGTH, &[height as u32], order));
+    ifd.set(Field::shorts(
+        tag::BITS_PER_SAMPLE,
+        &vec![bits as u16; samples_per_pixel],
+        order,
+    ));
+    ifd.set(Field::shorts(
+        tag::COMPRESSION,
+        &[options.compression.code()],
+        order,
+    ));
+    ifd.set(Field::shorts(
+        tag::PHOTOMETRIC_INTERPRETATION,
+        &[stored.photometric],
+        order,
+    ));
+    ifd.set(Field::shorts(
+        tag::SAMPLES_PER_PIXEL,
+        &[samples_per_pixel as u16],
+        order,
+    ));
+    ifd.set(Field::shorts(tag::PLANAR_CONFIGURATION, &[1], order));
+    // the offsets are filled in once the file is laid out
+    let placeholders = vec![0; segments.len()];
+    match options.tile_size {
+        Some(tile_size) => {
+            ifd.set(Field::longs(tag::TILE_WIDTH, &[tile_size], order));
+            ifd.set(Field::longs(tag::TILE_LENGTH, &[tile_size], order));
+            ifd.set(Field::longs(tag::TILE_OFFSETS, &placeholders, order));
+            ifd.set(Field::longs(tag::TILE_BYTE_COUNTS, &placeholders, order));
+        }
+        None => {
+            ifd.set(Field::longs(tag::STRIP_OFFSETS, &placeholders, order));
+            ifd.set(Field::longs(
+                tag::ROWS_PER_STRIP,
+                &[segment_height as u32],
+                order,
+            ));
+            ifd.set(Field::longs(tag::STRIP_BYTE_COUNTS, &placeholders, order));
+        }
+    }
+    if let Some(resolution) = image.metadata().resolution() {
+        set_resolution(&mut ifd, resolution, order);
+    }
+    if predictor {
+        ifd.set(Field::shorts(tag::PREDICTOR, &[2], order));
+    }
+    if let Some(color_map) = &stored.color_map {
+        ifd.set(Field::shorts(tag::COLOR_MAP, color_map, order));
+    }
+    if stored.alpha {
+        ifd.set(Field::shorts(
+            tag::EXTRA_SAMPLES,
+            &[UNASSOCIATED_ALPHA as u16],
+            order,
+        ));
+    }
+    Ok(Page { ifd, segments })
+}
+
+/// Records `resolution` in the resolution tags, which count per inch or per centimeter
+fn set_resolution(ifd: &mut Ifd, resolution: Resolution, order: ByteOrder) {
+    let (resolution, unit) = match resolution.unit {
+        ResolutionUnit::Unknown => (resolution, 1),
+        ResolutionUnit::Inch => (resolution, 2),
+        ResolutionUnit::Centimeter | ResolutionUnit::Meter => (
+            resolution
+                .in_unit(ResolutionUnit::Centimeter)
+                .expect("known units convert"),
+            3,
+        ),
+    };
+    // whole densities are stored exactly, others to a ten-thousandth
+    let rational = |density: f64| match density.fract() == 0.0 {
+        true => (density.min(u32::MAX as f64) as u32, 1),
+        false => (
+            (density * 10_000.0).round().min(u32::MAX as f64) as u32,
+            10_000,
+        ),
+    };
+    ifd.set(Field::rationals(
+        tag::X_RESOLUTION,
+        &[rational(resolution.x)],
+        order,
+    ));
+    ifd.set(Field::rationals(
+        tag::Y_RESOLUTION,
+        &[rational(resolution.y)],
+        order,
+    ));
+    ifd.set(Field::shorts(tag::RESOLUTION_UNIT, &[unit], order));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpack_and_predictor() {
+        let samples = [1, 0, 3, 2, 1];
+        let packed = pack(&samples, 2, ByteOrder::BigEndian);
+        assert_eq!(packed, [0b0100_1110, 0b0100_0000]);
+        assert_eq!(unpack(&packed, 2, 5, ByteOrder::BigEndian), samples);
+        let wide = pack(&[0x1234], 16, ByteOrder::LittleEndian);
+        assert_eq!(wide, [0x34, 0x12]);
+        assert_eq!(unpack(&wide, 16, 1, ByteOrder::LittleEndian), [0x1234]);
+
+        // a 16-bit gray gradient, stored with the predictor in big-endian order
+        let data: Vec<u8> = (0..64_u16)
+            .flat_map(|value| (value * 1000).to_be_bytes())
+            .collect();
+        let image = Image::new(8, 8, ColorModel::Gray, 16, data).unwrap();
+        let options = TiffOptions {
+            compression: super::super::options::TiffCompression::Lzw,
+            predictor: true,
+            rows_per_strip: Some(3),
+            ..TiffOptions::default()
+        };
+        let page = encode(&image, &options, ByteOrder::BigEndian).unwrap();
+        assert_eq!(page.segments.len(), 3);
+        assert_eq!(
+            page.ifd.number(tag::PREDICTOR, ByteOrder::BigEndian),
+            Some(2)
+        );
+        let decoded = decode(&page, ByteOrder::BigEndian).unwrap();
+        assert_eq!(decoded.data(), image.data());
+    }
+
+    #[test]
+    fn test_bits_per_sample_checked() {
+        let order = ByteOrder::LittleEndian;
+        let mut ifd = Ifd::default();
+        ifd.set(Field::longs(tag::IMAGE_WIDTH, &[4], order));
+        ifd.set(Field::longs(tag::IMAGE_LENGTH, &[4], order));
+        ifd.set(Field::shorts(
+            tag::PHOTOMETRIC_INTERPRETATION,
+            &[RGB],
+            order,
+        ));
+        ifd.set(Field::shorts(tag::SAMPLES_PER_PIXEL, &[3], order));
+        ifd.set(Field::shorts(tag::BITS_PER_SAMPLE, &[8, 8, 8], order));
+        assert_eq!(PageLayout::read(&ifd, order).unwrap().bits_per_sample, 8);
+
+        for bits in [&[][..], &[8], &[8, 8, 8, 8], &[0, 0, 0], &[65535; 3]] {
+            ifd.set(Field::shorts(tag::BITS_PER_SAMPLE, bits, order));
+            assert!(matches!(
+                PageLayout::read(&ifd, order),
+                Err(Error::Malformed { .. })
+            ));
+        }
+
+        // describing a forged palette page doesn't get as far as sizing its palette
+        ifd.set(Field::shorts(
+            tag::PHOTOMETRIC_INTERPRETATION,
+            &[PALETTE],
+            order,
+        ));
+        ifd.set(Field::shorts(tag::SAMPLES_PER_PIXEL, &[1], order));
+        ifd.set(Field::shorts(tag::BITS_PER_SAMPLE, &[32], order));
+        assert!(matches!(
+            PageLayout::read(&ifd, order),
+            Err(Error::Malformed { .. })
+        ));
+    }
+}
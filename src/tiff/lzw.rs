@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use crate::{Error, Result};
+
+const CLEAR: u16 = 256;
+const END: u16 = 257;
+const FIRST_FREE: u16 = 258;
+const MIN_CODE_SIZE: u8 = 9;
+const MAX_CODE_SIZE: u8 = 12;
+/// The encoder starts over before the decoder's table could need a 13th bit
+const TABLE_LIMIT: u16 = 4094;
+
+/// One string in the decoder's table, stored as the code of the string it extends
+#[derive(Clone, Copy)]
+struct Entry {
+    prefix: u16,
+    last: u8,
+    first: u8,
+    len: u16,
+}
+
+/// Decompresses TIFF LZW data: codes of 9 to 12 bits packed most significant bit first,
+/// which widen one code earlier than GIF's. Decoding stops at the end code, or once
+/// `max_len` bytes are out.
+pub fn decode(data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let mut table: Vec<Entry> = (0..1 << MAX_CODE_SIZE)
+        .map(|code| Entry {
+            prefix: 0,
+            last: code as u8,
+            first: code as u8,
+            len: 1,
+        })
+        .collect();
+    let mut next = FIRST_FREE;
+    let mut code_size = MIN_CODE_SIZE;
+    let mut previous: Option<u16> = None;
+
+    // sized from the input too, since `max_len` comes from headers that may be forged
+    let mut out: Vec<u8> = Vec::with_capacity(max_len.min(data.len().saturating_mul(8)));
+    let mut bits: u32 = 0;
+    let mut bit_count: u8 = 0;
+    let mut bytes = data.iter();
+    while out.len() < max_len {
+        while bit_count < code_size {
+            match bytes.next() {
+                Some(&byte) => {
+                    bits = bits << 8 | byte as u32;
+                    bit_count += 8;
+                }
+                None => return Ok(out),
+            }
+        }
+        let code = (bits >> (bit_count - code_size) & ((1 << code_size) - 1)) as u16;
+        bit_count -= code_size;
+
+        if code == CLEAR {
+            next = FIRST_FREE;
+            code_size = MIN_CODE_SIZE;
+            previous = None;
+            continue;
+        }
+        if code == END {
+            break;
+        }
+        let first = match previous {
+            _ if code < CLEAR => code as u8,
+            Some(_) if code < next => table[code as usize].first,
+            Some(previous) if code == next => table[previous as usize].first,
+            _ => {
+                return Err(Error::malformed(
+                    "TIFF",
+                    format!("LZW code {code} isn't in the table yet"),
+                ))
+            }
+        };
+        if let Some(previous) = previous.filter(|_| (next as usize) < table.len()) {
+            let prefix = table[previous as usize];
+            table[next as usize] = Entry {
+                prefix: previous,
+                last: first,
+                first: prefix.first,
+                len: prefix.len + 1,
+            };
+            next += 1;
+            if next == (1 << code_size) - 1 && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+        }
+
+        // strings are stored back to front, so they're written from their last byte
+        let entry = table[code as usize];
+        let start = out.len();
+        out.resize(start + entry.len as usize, 0);
+        let mut current = entry;
+        for idx in (start..out.len()).rev() {
+            out[idx] = current.last;
+            current = table[current.prefix as usize];
+        }
+        previous = Some(code);
+    }
+    out.truncate(max_len);
+    Ok(out)
+}
+
+/// Packs codes into bytes most significant bit first
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, code_size: u8) {
+        self.bits = self.bits << code_size | code as u32;
+        self.bit_count += code_size;
+        while self.bit_count >= 8 {
+            self.out.push((self.bits >> (self.bit_count - 8)) as u8);
+            self.bit_count -= 8;
+        }
+        self.bits &= (1 << self.bit_count) - 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push((self.bits << (8 - self.bit_count)) as u8);
+        }
+        self.out
+    }
+}
+
+/// Compresses `data` the way `decode` reads it back, starting the table over whenever it
+/// fills up
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = FIRST_FREE;
+    let mut code_size = MIN_CODE_SIZE;
+    let mut writer = BitWriter {
+        out: Vec::new(),
+        bits: 0,
+        bit_count: 0,
+    };
+    writer.write(CLEAR, code_size);
+
+    // the decoder adds each entry one code later than this, and widens its codes a code
+    // early, so both switch width for the same code
+    let add_entry = |next: &mut u16, code_size: &mut u8| {
+        *next += 1;
+        if *next == 1 << *code_size && *code_size < MAX_CODE_SIZE {
+            *code_size += 1;
+        }
+    };
+    let mut current: Option<u16> = None;
+    for &byte in data {
+        let Some(prefix) = current else {
+            current = Some(byte as u16);
+            continue;
+        };
+        if let Some(&code) = table.get(&(prefix, byte)) {
+            current = Some(code);
+            continue;
+        }
+        writer.write(prefix, code_size);
+        if next < TABLE_LIMIT {
+            table.insert((prefix, byte), next);
+            add_entry(&mut next, &mut code_size);
+        } else {
+            writer.write(CLEAR, code_size);
+            table.clear();
+            next = FIRST_FREE;
+            code_size = MIN_CODE_SIZE;
+        }
+        current = Some(byte as u16);
+    }
+    if let Some(code) = current {
+        writer.write(code, code_size);
+        // the decoder adds an entry for this last code before it reads the end code
+        add_entry(&mut next, &mut code_size);
+    }
+    writer.write(END, code_size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // long enough to fill the table and force clears, with both repetitive and noisy runs
+        let mut data: Vec<u8> = (0..20_000_u32).map(|idx| (idx / 7 % 16) as u8).collect();
+        data.extend((0..30_000_u32).map(|idx| (idx.wrapping_mul(2_654_435_761) >> 24) as u8));
+        let encoded = encode(&data);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+        // the end code is read at the width the decoder expects, whatever the length
+        for len in 250..270 {
+            let data: Vec<u8> = (0..len).map(|idx| (idx % 251) as u8).collect();
+            assert_eq!(decode(&encode(&data), 1000).unwrap(), data);
+        }
+        assert!(decode(&encode(&[]), 10).unwrap().is_empty());
+
+        // clear, "A", "B", the new entry "AB" and the end code, packed by hand
+        assert_eq!(
+            decode(&[0x80, 0x10, 0x48, 0x50, 0x28, 0x08], 4).unwrap(),
+            b"ABAB"
+        );
+        assert!(decode(&[0x80, 0x4b, 0x00], 10).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{Error, Result};
+
+/// How TIFF output compresses its strips or tiles
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TiffCompression {
+    #[default]
+    None,
+    PackBits,
+    Lzw,
+    Deflate,
+}
+
+/// How TIFF output is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TiffOptions {
+    pub compression: TiffCompression,
+    /// Store each sample as the difference from the one to its left, which helps LZW and
+    /// Deflate with photographs. Images under 8 bits per sample are stored without it.
+    pub predictor: bool,
+    /// Store square tiles with this side, a multiple of 16, instead of strips
+    pub tile_size: Option<u32>,
+    /// Rows in each strip; strips of about 8 KiB are written otherwise
+    pub rows_per_strip: Option<u32>,
+    /// Write big-endian (Motorola) numbers instead of little-endian (Intel) ones
+    pub big_endian: bool,
+    /// Store pixels as indices into a color map, which fails for images with more than 256
+    /// colors or with translucent pixels
+    pub palette: bool,
+}
+
+impl TiffOptions {
+    /// Checks for settings that are out of range or contradict each other
+    pub fn validate(&self) -> Result<()> {
+        if let Some(tile_size) = self.tile_size {
+            if tile_size == 0 || !tile_size.is_multiple_of(16) {
+                return Err(Error::invalid_argument(format!(
+                    "TIFF tiles are a multiple of 16 pixels wide, not {tile_size}"
+                )));
+            }
+        }
+        if self.rows_per_strip == Some(0) {
+            return Err(Error::invalid_argument("TIFF strips need at least one row"));
+        }
+        if self.tile_size.is_some() && self.rows_per_strip.is_some() {
+            return Err(Error::invalid_argument(
+                "a TIFF is stored in either tiles or strips, not both",
+            ));
+        }
+        if self.predictor
+            && matches!(
+                self.compression,
+                TiffCompression::None | TiffCompression::PackBits
+            )
+        {
+            return Err(Error::invalid_argument(
+                "the TIFF predictor only goes with LZW or Deflate compression",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    io::{BufReader, Read, Write},
+    time::Duration,
+};
+
+use crate::{
+    animation::{Animation, Frame},
+    byte_reader::StreamReader,
+    convert::ConvertOptions,
+    exif::Exif,
+    format::{decode_with, Codec},
+    image::Image,
+    info::{FormatDetails, ImageInfo, MetadataInfo, WebpDetails},
+    metadata::Metadata,
+    png::{options::PngOptions, Png},
+    ConvertibleImage, Error, Result,
+};
+
+use self::{
+    chunk::{Chunk, ALPH, ANIM, ANMF, EXIF, ICCP, VP8, VP8L, VP8X},
+    header::{AnimationParameters, ExtendedHeader, LosslessHeader},
+};
+
+pub mod chunk;
+pub mod header;
+pub mod huffman;
+pub mod image_data;
+pub mod lossless;
+pub mod transform;
+
+/// Registry entry for WebP. Other RIFF files share the magic, and are turned away once
+/// their form type has been read.
+pub const CODEC: Codec = Codec {
+    name: "WebP",
+    extensions: &["webp"],
+    magic: &[Webp::RIFF],
+    check_signature: None,
+    decoder: Some(decode_with::<Webp>),
+    encoder: Some(write_webp),
+    row_decoder: None,
+    row_encoder: None,
+    probe: Some(image_data::probe),
+    inspector: Some(inspect),
+};
+
+/// Describes a WebP from its chunks and headers, without decoding any image data
+fn inspect(reader: &mut dyn Read) -> Result<ImageInfo> {
+    let webp = Webp::from_reader(reader)?;
+    let (width, height, alpha) = webp.canvas()?;
+    let lossy = webp.find_image_chunk(VP8).is_some();
+    let animation = webp.animation_parameters()?;
+    Ok(ImageInfo {
+        format: CODEC.name,
+        width,
+        height,
+        color_model: match alpha {
+            true => "rgba",
+            false => "rgb",
+        },
+        bit_depth: 8,
+        compression: match lossy {
+            true => "VP8",
+            false => "VP8L",
+        },
+        interlaced: false,
+        palette_size: None,
+        metadata: MetadataInfo::from(&webp.metadata()?),
+        details: FormatDetails::Webp(WebpDetails {
+            extended: webp.extended_header()?.is_some(),
+            chunks: webp
+                .chunks()
+                .iter()
+                .map(|chunk| String::from_utf8_lossy(&chunk.fourcc).into_owned())
+                .collect(),
+            frames: match animation {
+                Some(_) => webp.frame_count(),
+                None => 1,
+            },
+            loop_count: animation.map(|parameters| parameters.loop_count),
+        }),
+        warnings: Vec::new(),
+    })
+}
+
+/// Encodes `png`, animated or not, as a lossless WebP for the registry
+fn write_webp(png: Png, _options: &ConvertOptions, writer: &mut dyn Write) -> Result<()> {
+    Webp::from_animation(&animation_of(&png)?)?.to_writer(writer)
+}
+
+/// The frames of `png` and its metadata, which is a single frame unless it's an APNG
+fn animation_of(png: &Png) -> Result<Animation> {
+    let mut animation = match png.to_animation()? {
+        Some(animation) => animation,
+        None => Animation::new(
+            vec![Frame {
+                image: png.to_image()?,
+                delay: Duration::ZERO,
+            }],
+            1,
+        )?,
+    };
+    animation.set_metadata(png.metadata()?);
+    Ok(animation)
+}
+
+/// A WebP file, kept as its RIFF chunks
+#[derive(Clone, Debug)]
+pub struct Webp {
+    chunks: Vec<Chunk>,
+}
+
+impl Webp {
+    pub const RIFF: &'static [u8; 4] = b"RIFF";
+    /// Form type that follows the RIFF header's size
+    pub const FORM_TYPE: &'static [u8; 4] = b"WEBP";
+
+    /// Bundles `chunks`, which go after the RIFF header
+    pub fn new(chunks: Vec<Chunk>) -> Self {
+        Webp { chunks }
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    fn chunk(&self, fourcc: [u8; 4]) -> Option<&Chunk> {
+        self.chunks.iter().find(|chunk| chunk.fourcc == fourcc)
+    }
+
+    /// The first chunk of image data of kind `fourcc`, at the top level or in a frame
+    fn find_image_chunk(&self, fourcc: [u8; 4]) -> Option<Chunk> {
+        self.chunks.iter().find_map(|chunk| match chunk.fourcc {
+            found if found == fourcc => Some(chunk.clone()),
+            ANMF => chunk
+                .sub_chunks(header::FrameHeader::LEN)
+                .ok()?
+                .into_iter()
+                .find(|sub_chunk| sub_chunk.fourcc == fourcc),
+            _ => None,
+        })
+    }
+
+    /// The VP8X chunk of an extended file
+    pub fn extended_header(&self) -> Result<Option<ExtendedHeader>> {
+        self.chunk(VP8X)
+            .map(|chunk| ExtendedHeader::read(&chunk.data))
+            .transpose()
+    }
+
+    /// The ANIM chunk of an animated file
+    pub fn animation_parameters(&self) -> Result<Option<AnimationParameters>> {
+        self.chunk(ANIM)
+            .map(|chunk| AnimationParameters::read(&chunk.data))
+            .transpose()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.fourcc == ANMF)
+            .count()
+    }
+
+    /// Width and height of the canvas, and whether the file says some pixel isn't opaque
+    pub fn canvas(&self) -> Result<(u32, u32, bool)> {
+        if let Some(header) = self.extended_header()? {
+            return Ok((
+                header.canvas_width,
+                header.canvas_height,
+                header.has(ExtendedHeader::ALPHA),
+            ));
+        }
+        if let Some(chunk) = self.chunk(VP8L) {
+            let header = LosslessHeader::read(&chunk.data)?;
+            return Ok((header.width, header.height, header.alpha));
+        }
+        if let Some(chunk) = self.chunk(VP8) {
+            let (width, height) = header::lossy_size(&chunk.data)?;
+            return Ok((width, height, self.chunk(ALPH).is_some()));
+        }
+        Err(Error::malformed("WebP", "there is no image data"))
+    }
+
+    /// Collects the ICC profile and EXIF block
+    pub fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::default();
+        if let Some(chunk) = self.chunk(ICCP) {
+            metadata.set_icc_profile(Some(chunk.data.clone()));
+        }
+        if let Some(chunk) = self.chunk(EXIF) {
+            // some writers keep the prefix JPEG's APP1 segment has
+            let exif = chunk.data.strip_prefix(b"Exif\0\0").unwrap_or(&chunk.data);
+            metadata.set_exif(Some(Exif::try_from(exif)?));
+        }
+        Ok(metadata)
+    }
+
+    /// Decodes every frame, each composited onto the ones before it
+    pub fn to_animation(&self) -> Result<Animation> {
+        let mut animation = image_data::decode(self)?;
+        animation.set_metadata(self.metadata()?);
+        Ok(animation)
+    }
+
+    /// Decodes the first frame into an 8-bit RGB or RGBA `Image`
+    pub fn to_image(&self) -> Result<Image> {
+        let animation = self.to_animation()?;
+        let mut image = animation.frames()[0].image.clone();
+        image.set_metadata(animation.metadata().clone());
+        Ok(image)
+    }
+
+    /// Encodes `image` as a new lossless `Webp`, including its metadata
+    pub fn from_image(image: &Image) -> Result<Webp> {
+        let frame = Frame {
+            image: image.clone(),
+            delay: Duration::ZERO,
+        };
+        let mut animation = Animation::new(vec![frame], 1)?;
+        animation.set_metadata(image.metadata().clone());
+        image_data::encode(&animation)
+    }
+
+    /// Encodes `animation` as a new lossless `Webp`, animated if it has more than one frame
+    pub fn from_animation(animation: &Animation) -> Result<Webp> {
+        image_data::encode(animation)
+    }
+}
+
+impl TryFrom<&[u8]> for Webp {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Webp::from_reader(value)
+    }
+}
+
+impl ConvertibleImage for Webp {
+    fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = StreamReader::new("WebP", BufReader::new(reader));
+        if &reader.read_array::<4>()? != Webp::RIFF {
+            return Err(Error::malformed_at("WebP", 0, "invalid RIFF signature"));
+        }
+        let size = reader.read_u32_le()?;
+        if &reader.read_array::<4>()? != Webp::FORM_TYPE {
+            return Err(Error::malformed_at("WebP", 8, "the RIFF file isn't a WebP"));
+        }
+        // the size counts the form type, and anything past it isn't part of the file
+        let body_len = size.checked_sub(4).ok_or_else(|| {
+            Error::malformed_at(
+                "WebP",
+                4,
+                format!("a RIFF size of {size} can't hold the form type"),
+            )
+        })?;
+        let mut body = StreamReader::new("WebP", BufReader::new(reader.take(body_len as u64)));
+        let mut chunks: Vec<Chunk> = Vec::new();
+        while let Some(chunk) = Chunk::read(&mut body)? {
+            chunks.push(chunk);
+        }
+        if chunks.is_empty() {
+            return Err(Error::malformed("WebP", "the file has no chunks"));
+        }
+        Ok(Webp { chunks })
+    }
+
+    fn to_writer(&self, writer: &mut dyn Write) -> Result<()> {
+        let size = 4 + self.chunks.iter().map(Chunk::stored_len).sum::<usize>();
+        let size = u32::try_from(size)
+            .map_err(|_| Error::invalid_argument("a WebP file can't be over 4 GiB"))?;
+        writer.write_all(Webp::RIFF)?;
+        writer.write_all(&size.to_le_bytes())?;
+        writer.write_all(Webp::FORM_TYPE)?;
+        for chunk in &self.chunks {
+            chunk.write(writer)?;
+        }
+        Ok(())
+    }
+
+    fn from_png(png: Png) -> Result<Self> {
+        Webp::from_animation(&animation_of(&png)?)
+    }
+
+    /// Single images become a plain PNG and animations an APNG
+    fn to_png(&self) -> Result<Png> {
+        let animation = self.to_animation()?;
+        match animation.frames() {
+            [frame] => {
+                let mut image = frame.image.clone();
+                image.set_metadata(animation.metadata().clone());
+                Png::from_image(&image)
+            }
+            _ => Png::from_animation(&animation, &PngOptions::default()),
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Webp::metadata(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ColorModel;
+
+    #[test]
+    fn test_still_round_trip() {
+        let data: Vec<u8> = (0..40 * 30 * 3).map(|idx| (idx * 7 % 251) as u8).collect();
+        let image = Image::new(40, 30, ColorModel::Rgb, 8, data).unwrap();
+        let bytes = Webp::from_image(&image).unwrap().to_bytes();
+        assert_eq!(&bytes[12..16], b"VP8L");
+        let webp = Webp::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(webp.canvas().unwrap(), (40, 30, false));
+        assert_eq!(webp.to_image().unwrap().data(), image.data());
+
+        let mut metadata = Metadata::default();
+        metadata.set_icc_profile(Some(vec![1, 2, 3]));
+        let mut image = Image::new(1, 2, ColorModel::GrayAlpha, 8, vec![9, 0, 200, 255]).unwrap();
+        image.set_metadata(metadata);
+        let bytes = Webp::from_image(&image).unwrap().to_bytes();
+        let webp = Webp::try_from(bytes.as_slice()).unwrap();
+        let header = webp.extended_header().unwrap().unwrap();
+        assert_eq!(
+            header.flags,
+            ExtendedHeader::ICC_PROFILE | ExtendedHeader::ALPHA
+        );
+        assert_eq!(webp.metadata().unwrap().icc_profile(), Some(&[1, 2, 3][..]));
+        let decoded = webp.to_image().unwrap();
+        assert_eq!(decoded.color_model(), ColorModel::Rgba);
+        assert_eq!(decoded.data(), [9, 9, 9, 0, 200, 200, 200, 255]);
+
+        let mut wave = bytes.clone();
+        wave[8..12].copy_from_slice(b"WAVE");
+        assert!(Webp::try_from(wave.as_slice()).is_err());
+        assert!(Webp::try_from(&bytes[..bytes.len() - 2]).is_err());
+        let mut too_small = bytes.clone();
+        too_small[4..8].copy_from_slice(&3_u32.to_le_bytes());
+        assert!(matches!(
+            Webp::try_from(too_small.as_slice()),
+            Err(Error::Malformed {
+                offset: Some(4),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_animation_round_trip() {
+        let delay = Duration::from_millis(70);
+        let canvases = (0..3)
+            .map(|frame| {
+                let mut canvas: Vec<u8> = vec![0; 5 * 4 * 4];
+                canvas[frame * 4..frame * 4 + 4].copy_from_slice(&[255, 0, 0, 255]);
+                (canvas, delay)
+            })
+            .collect();
+        let animation = Animation::from_rgba8(5, 4, canvases, 0).unwrap();
+        let bytes = Webp::from_animation(&animation).unwrap().to_bytes();
+        let webp = Webp::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(webp.frame_count(), 3);
+        assert_eq!(webp.animation_parameters().unwrap().unwrap().loop_count, 0);
+
+        let apng = webp.to_png().unwrap();
+        assert!(apng.is_animated());
+        let back = Webp::from_png(apng).unwrap().to_animation().unwrap();
+        assert_eq!(back.plays(), 0);
+        for (back, frame) in back.frames().iter().zip(animation.frames()) {
+            assert_eq!(back.image.data(), frame.image.data());
+            assert_eq!(back.delay, delay);
+        }
+    }
+
+    #[test]
+    fn test_libwebp_files() {
+        // a gradient with some translucent pixels and a checkered corner, which libwebp
+        // codes with predictor and cross-color transforms and a color cache
+        let webp = Webp::try_from(&include_bytes!("webp/testdata/lossless.webp")[..]).unwrap();
+        let expected: Vec<u8> = (0..16_u8)
+            .flat_map(|y| (0..24_u8).map(move |x| (x, y)))
+            .flat_map(|(x, y)| match (x, y) {
+                (16.., 8..) => {
+                    let red = if (x ^ y) & 1 == 1 { 250 } else { 10 };
+                    [red, 30, red, 255]
+                }
+                _ => [
+                    x * 10,
+                    y * 15,
+                    if x < 12 { 40 } else { 200 - y * 3 },
+                    if (x + y) % 5 == 0 { 128 } else { 255 },
+                ],
+            })
+            .collect();
+        let image = webp.to_image().unwrap();
+        assert_eq!((image.width(), image.height()), (24, 16));
+        assert_eq!(image.data(), expected);
+
+        // five colors, which libwebp codes as a palette with two pixels to a byte. It
+        // also clears the color of the transparent one.
+        let webp = Webp::try_from(&include_bytes!("webp/testdata/palette.webp")[..]).unwrap();
+        let palette = [
+            [255, 0, 0, 255],
+            [0, 128, 0, 255],
+            [0, 0, 255, 255],
+            [0, 0, 0, 0],
+            [30, 40, 50, 200],
+        ];
+        let expected: Vec<u8> = (0..10_usize)
+            .flat_map(|y| (0..20_usize).map(move |x| palette[(x / 3 + y * 2 + x * y % 3) % 5]))
+            .flatten()
+            .collect();
+        assert_eq!(webp.to_image().unwrap().data(), expected);
+    }
+}
@@ -0,0 +1,89 @@
+//! The RIFF chunks a WebP file is made of
+
+use std::io::{BufRead, Write};
+
+use crate::{byte_reader::StreamReader, Error, Result};
+
+/// Lossy image data
+pub const VP8: [u8; 4] = *b"VP8 ";
+/// Lossless image data
+pub const VP8L: [u8; 4] = *b"VP8L";
+/// Extended header, which says what other chunks there are
+pub const VP8X: [u8; 4] = *b"VP8X";
+/// Alpha channel for lossy image data
+pub const ALPH: [u8; 4] = *b"ALPH";
+/// Animation parameters, and then one frame per ANMF
+pub const ANIM: [u8; 4] = *b"ANIM";
+pub const ANMF: [u8; 4] = *b"ANMF";
+pub const ICCP: [u8; 4] = *b"ICCP";
+pub const EXIF: [u8; 4] = *b"EXIF";
+pub const XMP: [u8; 4] = *b"XMP ";
+
+/// Most data a chunk can hold and still have the file's size fit the RIFF header
+pub const MAX_CHUNK_LEN: usize = u32::MAX as usize - 10;
+
+/// One chunk: a FourCC, a little-endian length, and data padded to an even length
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub fourcc: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn new(fourcc: [u8; 4], data: Vec<u8>) -> Self {
+        Chunk { fourcc, data }
+    }
+
+    /// Reads a chunk, or returns `None` at the end of the file
+    pub fn read<R: BufRead>(reader: &mut StreamReader<R>) -> Result<Option<Self>> {
+        if reader.is_empty()? {
+            return Ok(None);
+        }
+        let fourcc = reader.read_array()?;
+        let len = reader.read_u32_le()? as usize;
+        let data = reader.read_bytes(len)?;
+        // the last chunk's padding byte is sometimes left off
+        if len % 2 == 1 && !reader.is_empty()? {
+            reader.skip(1)?;
+        }
+        Ok(Some(Chunk { fourcc, data }))
+    }
+
+    /// Chunks in the data of this one, which only ANMF has
+    pub fn sub_chunks(&self, offset: usize) -> Result<Vec<Chunk>> {
+        let mut reader = StreamReader::new("WebP", self.data.get(offset..).unwrap_or(&[]));
+        let mut chunks: Vec<Chunk> = Vec::new();
+        while let Some(chunk) = Chunk::read(&mut reader)? {
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+
+    /// Bytes the chunk takes up in a file
+    pub fn stored_len(&self) -> usize {
+        8 + self.data.len() + self.data.len() % 2
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        if self.data.len() > MAX_CHUNK_LEN {
+            return Err(Error::invalid_argument(format!(
+                "a WebP {} chunk can't hold {} bytes",
+                String::from_utf8_lossy(&self.fourcc),
+                self.data.len()
+            )));
+        }
+        writer.write_all(&self.fourcc)?;
+        writer.write_all(&(self.data.len() as u32).to_le_bytes())?;
+        writer.write_all(&self.data)?;
+        if self.data.len() % 2 == 1 {
+            writer.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.stored_len());
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{byte_reader::ByteReader, Error, Result};
+
+/// Largest canvas side the 24-bit fields of VP8X and ANMF can give
+pub const MAX_CANVAS_SIDE: u32 = 1 << 24;
+
+/// Largest image side a VP8L header's 14-bit fields can give
+pub const MAX_VP8L_SIDE: u32 = 1 << 14;
+
+/// First byte of every VP8L bitstream
+pub const VP8L_SIGNATURE: u8 = 0x2f;
+
+fn read_u24(reader: &mut ByteReader) -> Result<u32> {
+    let [a, b, c] = reader.read_array()?;
+    Ok(u32::from_le_bytes([a, b, c, 0]))
+}
+
+fn u24_bytes(value: u32) -> [u8; 3] {
+    let [a, b, c, _] = value.to_le_bytes();
+    [a, b, c]
+}
+
+/// The VP8X chunk of an extended file: which features it uses, and the canvas size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtendedHeader {
+    pub flags: u8,
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+}
+
+impl ExtendedHeader {
+    pub const ICC_PROFILE: u8 = 0x20;
+    pub const ALPHA: u8 = 0x10;
+    pub const EXIF: u8 = 0x08;
+    pub const XMP: u8 = 0x04;
+    pub const ANIMATION: u8 = 0x02;
+
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new("WebP", data);
+        let flags = reader.read_u8()?;
+        reader.skip(3)?;
+        Ok(ExtendedHeader {
+            flags,
+            canvas_width: read_u24(&mut reader)? + 1,
+            canvas_height: read_u24(&mut reader)? + 1,
+        })
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.flags, 0, 0, 0];
+        bytes.extend(u24_bytes(self.canvas_width - 1));
+        bytes.extend(u24_bytes(self.canvas_height - 1));
+        bytes
+    }
+}
+
+/// The ANIM chunk: what to clear the canvas to, and how often to play the frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimationParameters {
+    /// A hint stored as blue, green, red and alpha bytes; viewers are free to ignore it
+    pub background: [u8; 4],
+    /// Times to play the animation, or 0 for forever
+    pub loop_count: u16,
+}
+
+impl AnimationParameters {
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new("WebP", data);
+        Ok(AnimationParameters {
+            background: reader.read_array()?,
+            loop_count: reader.read_u16_le()?,
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.background.to_vec();
+        bytes.extend(self.loop_count.to_le_bytes());
+        bytes
+    }
+}
+
+/// The header at the start of an ANMF chunk, ahead of the frame's own chunks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// Offset on the canvas, which is always even
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Milliseconds the frame is shown for
+    pub duration: u32,
+    /// Whether the frame replaces what's under it instead of being alpha blended onto it
+    pub overwrite: bool,
+    /// Whether the frame's area is cleared once it has been shown
+    pub dispose: bool,
+}
+
+impl FrameHeader {
+    /// Bytes the header takes up
+    pub const LEN: usize = 16;
+
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new("WebP", data);
+        let x = read_u24(&mut reader)? * 2;
+        let y = read_u24(&mut reader)? * 2;
+        let width = read_u24(&mut reader)? + 1;
+        let height = read_u24(&mut reader)? + 1;
+        let duration = read_u24(&mut reader)?;
+        let flags = reader.read_u8()?;
+        Ok(FrameHeader {
+            x,
+            y,
+            width,
+            height,
+            duration,
+            overwrite: flags & 0x02 != 0,
+            dispose: flags & 0x01 != 0,
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(FrameHeader::LEN);
+        for value in [
+            self.x / 2,
+            self.y / 2,
+            self.width - 1,
+            self.height - 1,
+            self.duration,
+        ] {
+            bytes.extend(u24_bytes(value));
+        }
+        bytes.push((self.overwrite as u8) << 1 | self.dispose as u8);
+        bytes
+    }
+}
+
+/// The first five bytes of a VP8L bitstream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LosslessHeader {
+    pub width: u32,
+    pub height: u32,
+    /// A hint that some pixel isn't opaque
+    pub alpha: bool,
+}
+
+impl LosslessHeader {
+    pub const LEN: usize = 5;
+
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new("WebP", data);
+        if reader.read_u8()? != VP8L_SIGNATURE {
+            return Err(Error::malformed("WebP", "invalid VP8L signature"));
+        }
+        let bits = reader.read_u32_le()?;
+        if bits >> 29 != 0 {
+            return Err(Error::unsupported(
+                "WebP",
+                format!("VP8L version {}", bits >> 29),
+            ));
+        }
+        Ok(LosslessHeader {
+            width: (bits & 0x3fff) + 1,
+            height: (bits >> 14 & 0x3fff) + 1,
+            alpha: bits >> 28 & 1 == 1,
+        })
+    }
+
+    pub fn as_bytes(&self) -> [u8; LosslessHeader::LEN] {
+        let bits = (self.width - 1) | (self.height - 1) << 14 | (self.alpha as u32) << 28;
+        let [a, b, c, d] = bits.to_le_bytes();
+        [VP8L_SIGNATURE, a, b, c, d]
+    }
+}
+
+/// Reads the size of a lossy VP8 key frame from its frame header
+pub fn lossy_size(data: &[u8]) -> Result<(u32, u32)> {
+    let mut reader = ByteReader::new("WebP", data);
+    let tag = read_u24(&mut reader)?;
+    if tag & 1 != 0 || reader.read_array::<3>()? != [0x9d, 0x01, 0x2a] {
+        return Err(Error::malformed("WebP", "the VP8 data isn't a key frame"));
+    }
+    let width = reader.read_u16_le()? & 0x3fff;
+    let height = reader.read_u16_le()? & 0x3fff;
+    Ok((width as u32, height as u32))
+}
@@ -0,0 +1,495 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::{Error, Result};
+
+/// Longest code a VP8L prefix code can have
+pub const MAX_CODE_LENGTH: u8 = 15;
+
+/// Bits looked up at once when decoding; longer codes are found a bit at a time
+const LOOKUP_BITS: u32 = 8;
+
+/// Order the code lengths of the code length code are stored in
+pub const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// Reads bits least significant first, the way VP8L packs them
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            position: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.count <= 56 {
+            let Some(&byte) = self.data.get(self.position) else {
+                break;
+            };
+            self.bits |= (byte as u64) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+    }
+
+    /// The next `len` bits without consuming them, padded with zeros past the end
+    fn peek(&mut self, len: u32) -> u32 {
+        if self.count < len {
+            self.fill();
+        }
+        (self.bits & ((1 << len) - 1)) as u32
+    }
+
+    fn consume(&mut self, len: u32) -> Result<()> {
+        if self.count < len {
+            return Err(Error::malformed("WebP", "the image data ends early"));
+        }
+        self.bits >>= len;
+        self.count -= len;
+        Ok(())
+    }
+
+    /// Reads `len` bits, up to 32, as an unsigned number
+    pub fn read_bits(&mut self, len: u32) -> Result<u32> {
+        let value = self.peek(len);
+        self.consume(len)?;
+        Ok(value)
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+}
+
+/// Writes bits least significant first
+#[derive(Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter::default()
+    }
+
+    /// Writes the low `len` bits of `value`, up to 32 of them
+    pub fn write_bits(&mut self, value: u32, len: u32) {
+        self.bits |= ((value as u64) & ((1 << len) - 1)) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.data.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Pads the last byte with zeros and returns the data
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.data.push(self.bits as u8);
+        }
+        self.data
+    }
+}
+
+/// Canonical codes for `lengths`, shortest first and then by symbol, as numbers whose most
+/// significant bit comes first. Fails if the lengths call for more codes than fit.
+fn canonical_codes(lengths: &[u8]) -> Result<Vec<u16>> {
+    let mut counts = [0_u32; MAX_CODE_LENGTH as usize + 1];
+    for &len in lengths.iter().filter(|&&len| len > 0) {
+        counts[len as usize] += 1;
+    }
+    let mut next = [0_u32; MAX_CODE_LENGTH as usize + 1];
+    let mut code = 0;
+    for len in 1..=MAX_CODE_LENGTH as usize {
+        code = (code + counts[len - 1]) << 1;
+        next[len] = code;
+        if code + counts[len] > 1 << len {
+            return Err(Error::malformed(
+                "WebP",
+                "a prefix code has more codes than fit their lengths",
+            ));
+        }
+    }
+    Ok(lengths
+        .iter()
+        .map(|&len| match len {
+            0 => 0,
+            len => {
+                let code = next[len as usize];
+                next[len as usize] += 1;
+                code as u16
+            }
+        })
+        .collect())
+}
+
+/// `code`'s `len` bits in the opposite order, which is how they're stored
+fn reverse(code: u16, len: u8) -> u16 {
+    code.reverse_bits() >> (16 - len as u32)
+}
+
+/// A prefix code arranged for decoding
+pub struct PrefixCode {
+    /// Symbol and code length for every `LOOKUP_BITS`-bit pattern starting with a short
+    /// code, as read; a length of 0 means the code is longer
+    lookup: Vec<(u16, u8)>,
+    /// How many codes there are of each length, and the symbols in code order
+    counts: [u16; MAX_CODE_LENGTH as usize + 1],
+    symbols: Vec<u16>,
+    /// The symbol of a code with just one, which takes no bits at all
+    single: Option<u16>,
+}
+
+impl PrefixCode {
+    /// Arranges the code that gives each symbol the length at its index, 0 for unused ones
+    pub fn new(lengths: &[u8]) -> Result<Self> {
+        let mut used = lengths
+            .iter()
+            .enumerate()
+            .filter(|&(_, &len)| len > 0)
+            .map(|(symbol, _)| symbol as u16);
+        let (first, second) = (used.next(), used.next());
+        match (first, second) {
+            (None, _) => {
+                return Err(Error::malformed("WebP", "a prefix code has no symbols"));
+            }
+            (Some(symbol), None) => {
+                return Ok(PrefixCode {
+                    lookup: Vec::new(),
+                    counts: [0; MAX_CODE_LENGTH as usize + 1],
+                    symbols: Vec::new(),
+                    single: Some(symbol),
+                });
+            }
+            _ => {}
+        }
+        let codes = canonical_codes(lengths)?;
+        let mut counts = [0; MAX_CODE_LENGTH as usize + 1];
+        let mut lookup: Vec<(u16, u8)> = vec![(0, 0); 1 << LOOKUP_BITS];
+        for (symbol, (&len, &code)) in lengths.iter().zip(&codes).enumerate() {
+            if len == 0 {
+                continue;
+            }
+            counts[len as usize] += 1;
+            if len as u32 <= LOOKUP_BITS {
+                let start = reverse(code, len) as usize;
+                for entry in lookup[start..].iter_mut().step_by(1 << len) {
+                    *entry = (symbol as u16, len);
+                }
+            }
+        }
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] > 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| (lengths[symbol as usize], codes[symbol as usize]));
+        Ok(PrefixCode {
+            lookup,
+            counts,
+            symbols,
+            single: None,
+        })
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+        if let Some(symbol) = self.single {
+            return Ok(symbol);
+        }
+        let (symbol, len) = self.lookup[reader.peek(LOOKUP_BITS) as usize];
+        if len > 0 {
+            reader.consume(len as u32)?;
+            return Ok(symbol);
+        }
+        // walks the canonical code a bit at a time
+        let (mut code, mut first, mut index) = (0_u32, 0_u32, 0_u32);
+        for len in 1..=MAX_CODE_LENGTH as usize {
+            code |= reader.read_bits(1)?;
+            let count = self.counts[len] as u32;
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::malformed(
+            "WebP",
+            "the image data holds an invalid prefix code",
+        ))
+    }
+}
+
+/// Code lengths, none over `max_len`, that code symbols occurring `frequencies` times in
+/// close to the fewest bits. A lone symbol gets a length of 1, and unused ones 0.
+pub fn code_lengths(frequencies: &[u32], max_len: u8) -> Vec<u8> {
+    let mut lengths: Vec<u8> = vec![0; frequencies.len()];
+    let used: Vec<usize> = (0..frequencies.len())
+        .filter(|&symbol| frequencies[symbol] > 0)
+        .collect();
+    if let [symbol] = used.as_slice() {
+        lengths[*symbol] = 1;
+    }
+    if used.len() < 2 {
+        return lengths;
+    }
+    // rare symbols are made less rare until the tree is shallow enough, as libwebp does
+    let mut floor = 1_u64;
+    loop {
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+        // nodes past the leaves are merged pairs; each remembers its parent
+        let mut parents: Vec<usize> = vec![usize::MAX; used.len()];
+        for (leaf, &symbol) in used.iter().enumerate() {
+            heap.push(Reverse(((frequencies[symbol] as u64).max(floor), leaf)));
+        }
+        while let (Some(Reverse((a, left))), Some(Reverse((b, right)))) = (heap.pop(), heap.pop()) {
+            let node = parents.len();
+            parents.push(usize::MAX);
+            parents[left] = node;
+            parents[right] = node;
+            heap.push(Reverse((a + b, node)));
+        }
+        let mut depths: Vec<u8> = vec![0; parents.len()];
+        for node in (0..parents.len()).rev() {
+            if parents[node] != usize::MAX {
+                depths[node] = depths[parents[node]] + 1;
+            }
+        }
+        if depths[..used.len()].iter().all(|&depth| depth <= max_len) {
+            for (leaf, &symbol) in used.iter().enumerate() {
+                lengths[symbol] = depths[leaf];
+            }
+            return lengths;
+        }
+        floor *= 2;
+    }
+}
+
+/// A prefix code arranged for encoding
+pub struct PrefixEncoder {
+    /// Each symbol's code as stored, and its length
+    codes: Vec<(u16, u8)>,
+}
+
+impl PrefixEncoder {
+    /// Arranges the code that `lengths` describe; a code with one symbol takes no bits
+    pub fn new(lengths: &[u8]) -> Result<Self> {
+        if lengths.iter().filter(|&&len| len > 0).count() < 2 {
+            return Ok(PrefixEncoder {
+                codes: vec![(0, 0); lengths.len()],
+            });
+        }
+        let codes = canonical_codes(lengths)?;
+        Ok(PrefixEncoder {
+            codes: codes
+                .iter()
+                .zip(lengths)
+                .map(|(&code, &len)| (reverse(code, len.max(1)), len))
+                .collect(),
+        })
+    }
+
+    pub fn write(&self, writer: &mut BitWriter, symbol: u16) {
+        let (code, len) = self.codes[symbol as usize];
+        writer.write_bits(code as u32, len as u32);
+    }
+}
+
+/// Reads a prefix code for an alphabet of `alphabet_size` symbols
+pub fn read_prefix_code(reader: &mut BitReader, alphabet_size: usize) -> Result<PrefixCode> {
+    let mut lengths: Vec<u8> = vec![0; alphabet_size];
+    let out_of_range = || Error::malformed("WebP", "a prefix code has a symbol out of range");
+    if reader.read_bit()? {
+        // a simple code, of one or two symbols under 256
+        let two = reader.read_bit()?;
+        let first_bits = match reader.read_bit()? {
+            true => 8,
+            false => 1,
+        };
+        let first = reader.read_bits(first_bits)? as usize;
+        *lengths.get_mut(first).ok_or_else(out_of_range)? = 1;
+        if two {
+            let second = reader.read_bits(8)? as usize;
+            *lengths.get_mut(second).ok_or_else(out_of_range)? = 1;
+        }
+        return PrefixCode::new(&lengths);
+    }
+
+    let mut length_lengths = [0_u8; 19];
+    let count = reader.read_bits(4)? as usize + 4;
+    for &symbol in &CODE_LENGTH_ORDER[..count] {
+        length_lengths[symbol] = reader.read_bits(3)? as u8;
+    }
+    let length_code = PrefixCode::new(&length_lengths)?;
+    let mut max_symbol = match reader.read_bit()? {
+        true => {
+            let bits = 2 + 2 * reader.read_bits(3)?;
+            2 + reader.read_bits(bits)? as usize
+        }
+        false => alphabet_size,
+    };
+    if max_symbol > alphabet_size {
+        return Err(out_of_range());
+    }
+    let mut symbol = 0;
+    let mut previous = 8;
+    while symbol < alphabet_size && max_symbol > 0 {
+        max_symbol -= 1;
+        let (value, repeat) = match length_code.decode(reader)? {
+            len @ 0..=15 => {
+                if len != 0 {
+                    previous = len as u8;
+                }
+                (len as u8, 1)
+            }
+            16 => (previous, 3 + reader.read_bits(2)? as usize),
+            17 => (0, 3 + reader.read_bits(3)? as usize),
+            _ => (0, 11 + reader.read_bits(7)? as usize),
+        };
+        lengths
+            .get_mut(symbol..symbol + repeat)
+            .ok_or_else(out_of_range)?
+            .fill(value);
+        symbol += repeat;
+    }
+    PrefixCode::new(&lengths)
+}
+
+/// Writes `lengths` as a prefix code, in the simple form when it fits
+pub fn write_prefix_code(writer: &mut BitWriter, lengths: &[u8]) -> Result<()> {
+    let used: Vec<usize> = (0..lengths.len()).filter(|&idx| lengths[idx] > 0).collect();
+    if used.len() <= 2 && used.iter().all(|&symbol| symbol < 256) {
+        writer.write_bits(1, 1);
+        writer.write_bits(used.len().saturating_sub(1) as u32, 1);
+        let first = used.first().copied().unwrap_or(0);
+        match first {
+            0 | 1 => {
+                writer.write_bits(0, 1);
+                writer.write_bits(first as u32, 1);
+            }
+            _ => {
+                writer.write_bits(1, 1);
+                writer.write_bits(first as u32, 8);
+            }
+        }
+        if let Some(&second) = used.get(1) {
+            writer.write_bits(second as u32, 8);
+        }
+        return Ok(());
+    }
+
+    // runs of zeros and repeated lengths are shortened with symbols 16 to 18
+    let mut tokens: Vec<(u8, u32)> = Vec::new();
+    let mut idx = 0;
+    while idx < lengths.len() {
+        let len = lengths[idx];
+        let run = lengths[idx..]
+            .iter()
+            .take_while(|&&other| other == len)
+            .count();
+        match len {
+            0 if run >= 11 => {
+                let run = run.min(138);
+                tokens.push((18, run as u32 - 11));
+                idx += run;
+            }
+            0 if run >= 3 => {
+                tokens.push((17, run as u32 - 3));
+                idx += run;
+            }
+            _ => {
+                tokens.push((len, 0));
+                idx += 1;
+                // repeats of a length just written, which is the one 16 copies
+                let mut rest = run - 1;
+                while len != 0 && rest >= 3 {
+                    let repeat = rest.min(6);
+                    tokens.push((16, repeat as u32 - 3));
+                    idx += repeat;
+                    rest -= repeat;
+                }
+            }
+        }
+    }
+    let mut frequencies = [0_u32; 19];
+    for &(symbol, _) in &tokens {
+        frequencies[symbol as usize] += 1;
+    }
+    let length_lengths = code_lengths(&frequencies, 7);
+    let count = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&symbol| length_lengths[symbol] > 0)
+        .map_or(4, |last| (last + 1).max(4));
+    writer.write_bits(0, 1);
+    writer.write_bits(count as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..count] {
+        writer.write_bits(length_lengths[symbol] as u32, 3);
+    }
+    // every length is written, so there's no maximum symbol to give
+    writer.write_bits(0, 1);
+    let encoder = PrefixEncoder::new(&length_lengths)?;
+    for (symbol, extra) in tokens {
+        encoder.write(writer, symbol as u16);
+        match symbol {
+            16 => writer.write_bits(extra, 2),
+            17 => writer.write_bits(extra, 3),
+            18 => writer.write_bits(extra, 7),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_code_round_trip() {
+        let mut frequencies: Vec<u32> = vec![0; 300];
+        for (symbol, frequency) in frequencies.iter_mut().enumerate().step_by(3) {
+            *frequency = (symbol as u32 % 17 + 1) * 1000 / (symbol as u32 + 1);
+        }
+        // a long tail of rare symbols would take codes over 15 bits if nothing limited them
+        frequencies[1] = 1 << 30;
+        let lengths = code_lengths(&frequencies, MAX_CODE_LENGTH);
+        assert!(lengths.iter().all(|&len| len <= MAX_CODE_LENGTH));
+        assert_eq!(lengths[1], 1);
+
+        let simple = code_lengths(&[3, 0, 7], MAX_CODE_LENGTH);
+        let single = code_lengths(&[0, 0, 0, 9], MAX_CODE_LENGTH);
+        assert_eq!(single, [0, 0, 0, 1]);
+        let mut writer = BitWriter::new();
+        for lengths in [&lengths, &simple, &single] {
+            write_prefix_code(&mut writer, lengths).unwrap();
+            let encoder = PrefixEncoder::new(lengths).unwrap();
+            for symbol in (0..lengths.len()).filter(|&symbol| lengths[symbol] > 0) {
+                encoder.write(&mut writer, symbol as u16);
+            }
+        }
+        writer.write_bits(0x2a, 6);
+        let data = writer.finish();
+
+        let mut reader = BitReader::new(&data);
+        for lengths in [&lengths, &simple, &single] {
+            let code = read_prefix_code(&mut reader, lengths.len()).unwrap();
+            for symbol in (0..lengths.len()).filter(|&symbol| lengths[symbol] > 0) {
+                assert_eq!(code.decode(&mut reader).unwrap(), symbol as u16);
+            }
+        }
+        assert_eq!(reader.read_bits(6).unwrap(), 0x2a);
+        assert!(reader.read_bits(8).is_err());
+        assert!(PrefixCode::new(&[1, 1, 1]).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use std::{io::Read, time::Duration};
+
+use crate::{
+    animation::Animation,
+    image::{ColorModel, Image},
+    scanline::RowLayout,
+    ConvertibleImage, Error, Result,
+};
+
+use super::{
+    chunk::{Chunk, ANIM, ANMF, EXIF, ICCP, VP8, VP8L, VP8X},
+    header::{AnimationParameters, ExtendedHeader, FrameHeader},
+    lossless, Webp,
+};
+
+/// Longest frame duration an ANMF chunk can hold, in milliseconds
+const MAX_DURATION: u32 = (1 << 24) - 1;
+
+/// Reports the canvas size, without decoding any image data
+pub fn probe(reader: &mut dyn Read) -> Result<RowLayout> {
+    let (width, height, alpha) = Webp::from_reader(reader)?.canvas()?;
+    Ok(RowLayout {
+        width,
+        height,
+        color_model: match alpha {
+            true => ColorModel::Rgba,
+            false => ColorModel::Rgb,
+        },
+        bit_depth: 8,
+    })
+}
+
+/// Decodes the image in `chunks`, a file's top-level chunks or a frame's, into its size
+/// and ARGB pixels
+fn decode_bitstream(chunks: &[Chunk]) -> Result<(u32, u32, Vec<u32>)> {
+    let chunk = chunks
+        .iter()
+        .find(|chunk| chunk.fourcc == VP8L || chunk.fourcc == VP8)
+        .ok_or_else(|| Error::malformed("WebP", "there is no image data"))?;
+    if chunk.fourcc == VP8 {
+        return Err(Error::unsupported("WebP", "lossy (VP8) image data"));
+    }
+    let (header, pixels) = lossless::decode(&chunk.data)?;
+    Ok((header.width, header.height, pixels))
+}
+
+fn argb_to_rgba(pixel: u32) -> [u8; 4] {
+    let [alpha, red, green, blue] = pixel.to_be_bytes();
+    [red, green, blue, alpha]
+}
+
+/// Draws `source` over `destination`, both unpremultiplied
+fn blend(source: [u8; 4], destination: [u8; 4]) -> [u8; 4] {
+    let source_alpha = source[3] as u32;
+    match source_alpha {
+        255 => return source,
+        0 => return destination,
+        _ => {}
+    }
+    let destination_alpha = destination[3] as u32 * (255 - source_alpha) / 255;
+    let alpha = source_alpha + destination_alpha;
+    let channel = |idx: usize| {
+        ((source[idx] as u32 * source_alpha
+            + destination[idx] as u32 * destination_alpha
+            + alpha / 2)
+            / alpha) as u8
+    };
+    [channel(0), channel(1), channel(2), alpha as u8]
+}
+
+/// Decodes `webp` into its frames, each composited onto the canvas as it stood after the
+/// one before. The canvas starts out transparent, whatever background the ANIM chunk
+/// suggests, as browsers draw it.
+pub fn decode(webp: &Webp) -> Result<Animation> {
+    let header = match webp.extended_header()? {
+        Some(header) if header.has(ExtendedHeader::ANIMATION) => header,
+        _ => {
+            let (width, height, pixels) = decode_bitstream(webp.chunks())?;
+            if let Some(header) = webp.extended_header()? {
+                if (header.canvas_width, header.canvas_height) != (width, height) {
+                    return Err(Error::malformed(
+                        "WebP",
+                        "the image isn't the size of the canvas",
+                    ));
+                }
+            }
+            let rgba = pixels.into_iter().flat_map(argb_to_rgba).collect();
+            return Animation::from_rgba8(width, height, vec![(rgba, Duration::ZERO)], 1);
+        }
+    };
+    let parameters = webp
+        .animation_parameters()?
+        .ok_or_else(|| Error::malformed("WebP", "an animated file has no ANIM chunk"))?;
+    let (width, height) = (header.canvas_width as usize, header.canvas_height as usize);
+    let mut canvas: Vec<u8> = vec![0; width * height * 4];
+    let mut canvases: Vec<(Vec<u8>, Duration)> = Vec::new();
+    for chunk in webp.chunks().iter().filter(|chunk| chunk.fourcc == ANMF) {
+        let frame = FrameHeader::read(&chunk.data)?;
+        let (frame_width, frame_height, pixels) =
+            decode_bitstream(&chunk.sub_chunks(FrameHeader::LEN)?)?;
+        if (frame_width, frame_height) != (frame.width, frame.height) {
+            return Err(Error::malformed(
+                "WebP",
+                "a frame's image isn't the size its ANMF chunk gives",
+            ));
+        }
+        let (left, top) = (frame.x as usize, frame.y as usize);
+        let (frame_width, frame_height) = (frame_width as usize, frame_height as usize);
+        if left + frame_width > width || top + frame_height > height {
+            return Err(Error::malformed(
+                "WebP",
+                "a frame reaches past the edge of the canvas",
+            ));
+        }
+        for (idx, &pixel) in pixels.iter().enumerate() {
+            let (x, y) = (left + idx % frame_width, top + idx / frame_width);
+            let at = (y * width + x) * 4;
+            let source = argb_to_rgba(pixel);
+            let drawn = match frame.overwrite {
+                true => source,
+                false => blend(source, canvas[at..at + 4].try_into().unwrap()),
+            };
+            canvas[at..at + 4].copy_from_slice(&drawn);
+        }
+        canvases.push((canvas.clone(), Duration::from_millis(frame.duration as u64)));
+        if frame.dispose {
+            for y in top..top + frame_height {
+                canvas[(y * width + left) * 4..(y * width + left + frame_width) * 4].fill(0);
+            }
+        }
+    }
+    if canvases.is_empty() {
+        return Err(Error::malformed("WebP", "an animated file has no frames"));
+    }
+    Animation::from_rgba8(
+        width as u32,
+        height as u32,
+        canvases,
+        parameters.loop_count as u32,
+    )
+}
+
+fn to_argb(image: &Image) -> Vec<u32> {
+    (0..image.width() as usize * image.height() as usize)
+        .map(|pixel| {
+            let [red, green, blue, alpha] = image.rgba8(pixel);
+            u32::from_be_bytes([alpha, red, green, blue])
+        })
+        .collect()
+}
+
+/// Encodes `animation` losslessly, refusing 16-bit samples, which WebP has no room for. A
+/// lone frame without metadata becomes a simple file holding just the VP8L chunk; the rest
+/// get a VP8X header, and animations store every frame whole.
+pub fn encode(animation: &Animation) -> Result<Webp> {
+    let (width, height) = (animation.width(), animation.height());
+    let metadata = animation.metadata();
+    let frames = animation
+        .frames()
+        .iter()
+        .map(|frame| {
+            frame.image.layout().require_8_bit("WebP")?;
+            let pixels = to_argb(&frame.image);
+            let alpha = pixels.iter().any(|&pixel| pixel >> 24 != 0xff);
+            Ok((
+                Chunk::new(VP8L, lossless::encode(width, height, &pixels)?),
+                alpha,
+            ))
+        })
+        .collect::<Result<Vec<(Chunk, bool)>>>()?;
+    let animated = frames.len() > 1;
+    if !animated && metadata.icc_profile().is_none() && metadata.exif().is_none() {
+        let (chunk, _) = frames.into_iter().next().expect("animations have a frame");
+        return Ok(Webp::new(vec![chunk]));
+    }
+
+    let flags = [
+        (
+            metadata.icc_profile().is_some(),
+            ExtendedHeader::ICC_PROFILE,
+        ),
+        (
+            frames.iter().any(|&(_, alpha)| alpha),
+            ExtendedHeader::ALPHA,
+        ),
+        (metadata.exif().is_some(), ExtendedHeader::EXIF),
+        (animated, ExtendedHeader::ANIMATION),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .fold(0, |flags, (_, flag)| flags | flag);
+    let header = ExtendedHeader {
+        flags,
+        canvas_width: width,
+        canvas_height: height,
+    };
+    let mut chunks = vec![Chunk::new(VP8X, header.as_bytes())];
+    if let Some(profile) = metadata.icc_profile() {
+        chunks.push(Chunk::new(ICCP, profile.to_vec()));
+    }
+    match animated {
+        true => {
+            let parameters = AnimationParameters {
+                background: [0; 4],
+                loop_count: animation.plays().min(u16::MAX as u32) as u16,
+            };
+            chunks.push(Chunk::new(ANIM, parameters.as_bytes()));
+            for ((chunk, _), frame) in frames.into_iter().zip(animation.frames()) {
+                let header = FrameHeader {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                    duration: (frame.delay.as_millis() as u64).min(MAX_DURATION as u64) as u32,
+                    overwrite: true,
+                    dispose: false,
+                };
+                let mut data = header.as_bytes();
+                data.extend(chunk.as_bytes()?);
+                chunks.push(Chunk::new(ANMF, data));
+            }
+        }
+        false => chunks.extend(frames.into_iter().map(|(chunk, _)| chunk)),
+    }
+    if let Some(exif) = metadata.exif() {
+        chunks.push(Chunk::new(EXIF, exif.as_bytes().to_vec()));
+    }
+    Ok(Webp::new(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ANMF chunk holding a `width` by `height` frame of one ARGB color
+    fn frame_chunk(header: FrameHeader, color: u32) -> Chunk {
+        let pixels = vec![color; (header.width * header.height) as usize];
+        let image = lossless::encode(header.width, header.height, &pixels).unwrap();
+        let mut data = header.as_bytes();
+        data.extend(Chunk::new(VP8L, image).as_bytes().unwrap());
+        Chunk::new(ANMF, data)
+    }
+
+    #[test]
+    fn test_decode_frames() {
+        assert_eq!(blend([10, 20, 30, 0], [1, 2, 3, 4]), [1, 2, 3, 4]);
+        assert_eq!(blend([255, 0, 0, 128], [0, 0, 0, 0]), [255, 0, 0, 128]);
+
+        let header = ExtendedHeader {
+            flags: ExtendedHeader::ANIMATION | ExtendedHeader::ALPHA,
+            canvas_width: 4,
+            canvas_height: 3,
+        };
+        let parameters = AnimationParameters {
+            background: [255; 4],
+            loop_count: 2,
+        };
+        let full = FrameHeader {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 3,
+            duration: 50,
+            overwrite: true,
+            dispose: true,
+        };
+        // the first frame is cleared away, the second stays, and a half-transparent red
+        // square is blended onto its top right
+        let square = FrameHeader {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 2,
+            duration: 80,
+            overwrite: false,
+            dispose: false,
+        };
+        let webp = Webp::new(vec![
+            Chunk::new(VP8X, header.as_bytes()),
+            Chunk::new(ANIM, parameters.as_bytes()),
+            frame_chunk(full, 0xff00_00ff),
+            frame_chunk(
+                FrameHeader {
+                    dispose: false,
+                    ..full
+                },
+                0xff00_00ff,
+            ),
+            frame_chunk(square, 0x80ff_0000),
+        ]);
+        let animation = decode(&webp).unwrap();
+        assert_eq!(animation.plays(), 2);
+        let frames = animation.frames();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].delay, Duration::from_millis(80));
+        let last = &frames[2].image;
+        assert_eq!(last.rgba8(0), [0, 0, 255, 255]);
+        assert_eq!(last.rgba8(3), [128, 0, 127, 255]);
+        assert_eq!(last.rgba8(11), [0, 0, 255, 255]);
+
+        let outside = Webp::new(vec![
+            Chunk::new(VP8X, header.as_bytes()),
+            Chunk::new(ANIM, parameters.as_bytes()),
+            frame_chunk(FrameHeader { x: 4, ..square }, 0xff00_0000),
+        ]);
+        assert!(decode(&outside).is_err());
+    }
+}
@@ -0,0 +1,783 @@
+//! The VP8L lossless bitstream: transforms, then ARGB pixels coded with a color cache,
+//! LZ77 backward references and groups of prefix codes
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{Error, Result};
+
+use super::{
+    header::{LosslessHeader, MAX_VP8L_SIDE},
+    huffman::{
+        self, read_prefix_code, write_prefix_code, BitReader, BitWriter, PrefixCode, PrefixEncoder,
+        MAX_CODE_LENGTH,
+    },
+    transform::{self, Transform, BLACK},
+};
+
+/// The green code's alphabet starts with literal greens, then has the length prefixes of
+/// backward references, then the color cache's indices
+const LITERALS: usize = 256;
+const LENGTH_PREFIXES: usize = 24;
+const DISTANCE_PREFIXES: usize = 40;
+
+/// The (x, y) offsets back from the current pixel that the first 120 distance codes
+/// stand for, nearest first
+#[rustfmt::skip]
+const PLANE_OFFSETS: [(i8, i8); 120] = [
+    (0, 1), (1, 0), (1, 1), (-1, 1), (0, 2), (2, 0), (1, 2), (-1, 2),
+    (2, 1), (-2, 1), (2, 2), (-2, 2), (0, 3), (3, 0), (1, 3), (-1, 3),
+    (3, 1), (-3, 1), (2, 3), (-2, 3), (3, 2), (-3, 2), (0, 4), (4, 0),
+    (1, 4), (-1, 4), (4, 1), (-4, 1), (3, 3), (-3, 3), (2, 4), (-2, 4),
+    (4, 2), (-4, 2), (0, 5), (3, 4), (-3, 4), (4, 3), (-4, 3), (5, 0),
+    (1, 5), (-1, 5), (5, 1), (-5, 1), (2, 5), (-2, 5), (5, 2), (-5, 2),
+    (4, 4), (-4, 4), (3, 5), (-3, 5), (5, 3), (-5, 3), (0, 6), (6, 0),
+    (1, 6), (-1, 6), (6, 1), (-6, 1), (2, 6), (-2, 6), (6, 2), (-6, 2),
+    (4, 5), (-4, 5), (5, 4), (-5, 4), (3, 6), (-3, 6), (6, 3), (-6, 3),
+    (0, 7), (7, 0), (1, 7), (-1, 7), (5, 5), (-5, 5), (7, 1), (-7, 1),
+    (4, 6), (-4, 6), (6, 4), (-6, 4), (2, 7), (-2, 7), (7, 2), (-7, 2),
+    (3, 7), (-3, 7), (7, 3), (-7, 3), (5, 6), (-5, 6), (6, 5), (-6, 5),
+    (8, 0), (4, 7), (-4, 7), (7, 4), (-7, 4), (8, 1), (8, 2), (6, 6),
+    (-6, 6), (8, 3), (5, 7), (-5, 7), (7, 5), (-7, 5), (8, 4), (6, 7),
+    (-6, 7), (7, 6), (-7, 6), (8, 5), (7, 7), (-7, 7), (8, 6), (8, 7),
+];
+
+const MAX_CACHE_BITS: u32 = 11;
+
+/// Multiplier that hashes colors into the color cache
+const CACHE_MULTIPLIER: u32 = 0x1e35_a7bd;
+
+/// Longest backward reference, and the farthest back one can reach
+const MAX_LENGTH: usize = 4096;
+const MAX_DISTANCE: usize = (1 << 20) - PLANE_OFFSETS.len();
+
+/// Shortest backward reference the encoder bothers with
+const MIN_LENGTH: usize = 3;
+
+/// Earlier positions with the same hash the encoder tries before settling on a match
+const MAX_CHAIN: usize = 32;
+
+/// Side of the predictor transform's blocks the encoder uses, as a power of two
+const PREDICTOR_BITS: u32 = 4;
+
+/// Bounds on the blocks the encoder picks a prefix code group for: at least 16 pixels on
+/// a side, and few enough that clustering them stays quick
+const MIN_GROUP_BITS: u32 = 4;
+const MAX_GROUP_BLOCKS: usize = 1024;
+const MAX_GROUPS: usize = 32;
+
+/// Rough bits the encoder expects to spend describing one code length, and a group's
+/// five codes apart from their lengths
+const SYMBOL_COST: f64 = 4.0;
+const GROUP_COST: f64 = 200.0;
+
+fn cache_index(pixel: u32, bits: u32) -> usize {
+    (pixel.wrapping_mul(CACHE_MULTIPLIER) >> (32 - bits)) as usize
+}
+
+fn alphabet_sizes(cache_bits: u32) -> [usize; 5] {
+    let cache_size = match cache_bits {
+        0 => 0,
+        bits => 1 << bits,
+    };
+    [
+        LITERALS + LENGTH_PREFIXES + cache_size,
+        256,
+        256,
+        256,
+        DISTANCE_PREFIXES,
+    ]
+}
+
+/// Reads the length or distance code that `prefix` and the extra bits after it give
+fn read_prefixed(reader: &mut BitReader, prefix: u16) -> Result<usize> {
+    let prefix = prefix as u32;
+    if prefix < 4 {
+        return Ok(prefix as usize + 1);
+    }
+    let extra_bits = (prefix - 2) >> 1;
+    let offset = (2 + (prefix & 1)) << extra_bits;
+    Ok((offset + reader.read_bits(extra_bits)?) as usize + 1)
+}
+
+/// Splits a length or distance code into its prefix, the number of extra bits and the
+/// extra bits themselves
+fn prefix_of(value: u32) -> (u16, u32, u32) {
+    if value <= 4 {
+        return (value as u16 - 1, 0, 0);
+    }
+    let value = value - 1;
+    let highest = 31 - value.leading_zeros();
+    let second = value >> (highest - 1) & 1;
+    let extra_bits = highest - 1;
+    (
+        (2 * highest + second) as u16,
+        extra_bits,
+        value & ((1 << extra_bits) - 1),
+    )
+}
+
+/// Distance in pixels that a distance code stands for in an image `width` pixels wide
+fn distance_of(code: usize, width: usize) -> usize {
+    match code.checked_sub(PLANE_OFFSETS.len() + 1) {
+        Some(distance) => distance + 1,
+        None => {
+            let (x, y) = PLANE_OFFSETS[code - 1];
+            (x as isize + y as isize * width as isize).max(1) as usize
+        }
+    }
+}
+
+/// Decodes a VP8L bitstream, header included, into ARGB pixels
+pub fn decode(data: &[u8]) -> Result<(LosslessHeader, Vec<u32>)> {
+    let header = LosslessHeader::read(data)?;
+    let mut reader = BitReader::new(&data[LosslessHeader::LEN..]);
+    let (width, height) = (header.width as usize, header.height as usize);
+    // each transform along with the width of the image it applies to
+    let mut transforms: Vec<(Transform, usize)> = Vec::new();
+    let mut coded_width = width;
+    while reader.read_bit()? {
+        let transform = read_transform(&mut reader, coded_width, height)?;
+        if transforms
+            .iter()
+            .any(|(other, _)| std::mem::discriminant(other) == std::mem::discriminant(&transform))
+        {
+            return Err(Error::malformed("WebP", "a VP8L transform is used twice"));
+        }
+        let next_width = transform.transformed_width(coded_width);
+        transforms.push((transform, coded_width));
+        coded_width = next_width;
+    }
+    let mut pixels = read_image(&mut reader, coded_width, height, true)?;
+    for (transform, width) in transforms.iter().rev() {
+        pixels = transform.invert(pixels, *width, height);
+    }
+    Ok((header, pixels))
+}
+
+fn read_transform(reader: &mut BitReader, width: usize, height: usize) -> Result<Transform> {
+    Ok(match reader.read_bits(2)? {
+        kind @ (0 | 1) => {
+            let size_bits = reader.read_bits(3)? + 2;
+            let block = 1 << size_bits;
+            let entries = read_image(reader, width.div_ceil(block), height.div_ceil(block), false)?;
+            match kind {
+                0 => Transform::Predictor {
+                    size_bits,
+                    modes: entries,
+                },
+                _ => Transform::CrossColor {
+                    size_bits,
+                    elements: entries,
+                },
+            }
+        }
+        2 => Transform::SubtractGreen,
+        _ => {
+            let len = reader.read_bits(8)? as usize + 1;
+            let mut palette = read_image(reader, len, 1, false)?;
+            // each color is stored as its difference from the one before
+            for idx in 1..len {
+                palette[idx] = transform::add_pixels(palette[idx], palette[idx - 1]);
+            }
+            Transform::ColorIndexing {
+                width_bits: Transform::width_bits(len),
+                palette,
+            }
+        }
+    })
+}
+
+/// Reads a `width` by `height` image of ARGB pixels; only the main image, as opposed to
+/// those that transforms and prefix code groups store, can use more than one group
+fn read_image(reader: &mut BitReader, width: usize, height: usize, main: bool) -> Result<Vec<u32>> {
+    let cache_bits = match reader.read_bit()? {
+        true => match reader.read_bits(4)? {
+            bits @ 1..=MAX_CACHE_BITS => bits,
+            bits => {
+                return Err(Error::malformed(
+                    "WebP",
+                    format!("invalid color cache size of {bits} bits"),
+                ))
+            }
+        },
+        false => 0,
+    };
+    let (group_bits, group_image) = match main && reader.read_bit()? {
+        true => {
+            let bits = reader.read_bits(3)? + 2;
+            let block = 1 << bits;
+            let image = read_image(reader, width.div_ceil(block), height.div_ceil(block), false)?;
+            let groups: Vec<usize> = image
+                .iter()
+                .map(|&pixel| (pixel >> 8 & 0xffff) as usize)
+                .collect();
+            (bits, groups)
+        }
+        false => (0, Vec::new()),
+    };
+    let group_count = group_image.iter().max().map_or(1, |&max| max + 1);
+    let sizes = alphabet_sizes(cache_bits);
+    let groups = (0..group_count)
+        .map(|_| {
+            sizes
+                .iter()
+                .map(|&size| read_prefix_code(reader, size))
+                .collect::<Result<Vec<PrefixCode>>>()
+        })
+        .collect::<Result<Vec<Vec<PrefixCode>>>>()?;
+
+    let total = width * height;
+    let groups_wide = width.div_ceil(1 << group_bits);
+    let mut pixels: Vec<u32> = Vec::with_capacity(total);
+    let mut cache: Vec<u32> = vec![0; sizes[0] - LITERALS - LENGTH_PREFIXES];
+    let mut cached = 0;
+    while pixels.len() < total {
+        let idx = pixels.len();
+        let (x, y) = (idx % width, idx / width);
+        let group = match group_bits {
+            0 => &groups[0],
+            bits => &groups[group_image[(y >> bits) * groups_wide + (x >> bits)]],
+        };
+        let green = group[0].decode(reader)? as usize;
+        if green < LITERALS {
+            let red = group[1].decode(reader)? as u32;
+            let blue = group[2].decode(reader)? as u32;
+            let alpha = group[3].decode(reader)? as u32;
+            pixels.push(alpha << 24 | red << 16 | (green as u32) << 8 | blue);
+        } else if green < LITERALS + LENGTH_PREFIXES {
+            let length = read_prefixed(reader, (green - LITERALS) as u16)?;
+            let distance_prefix = group[4].decode(reader)?;
+            let distance = distance_of(read_prefixed(reader, distance_prefix)?, width);
+            if distance > idx || length > total - idx {
+                return Err(Error::malformed(
+                    "WebP",
+                    format!("a backward reference at pixel {idx} reaches outside the image"),
+                ));
+            }
+            for _ in 0..length {
+                pixels.push(pixels[pixels.len() - distance]);
+            }
+        } else {
+            pixels.push(cache[green - LITERALS - LENGTH_PREFIXES]);
+        }
+        if cache_bits > 0 {
+            for &pixel in &pixels[cached..] {
+                cache[cache_index(pixel, cache_bits)] = pixel;
+            }
+            cached = pixels.len();
+        }
+    }
+    Ok(pixels)
+}
+
+/// What the encoder writes for one pixel or run of pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Symbol {
+    Literal(u32),
+    CacheIndex(u32),
+    Copy { length: u32, distance_code: u32 },
+}
+
+impl Symbol {
+    fn pixels(&self) -> usize {
+        match self {
+            Symbol::Copy { length, .. } => *length as usize,
+            _ => 1,
+        }
+    }
+}
+
+fn xlogx(x: u32) -> f64 {
+    match x {
+        0 => 0.0,
+        x => x as f64 * (x as f64).log2(),
+    }
+}
+
+/// How often each symbol of a group's five codes is used
+#[derive(Clone, Debug)]
+struct Histogram {
+    counts: [Vec<u32>; 5],
+    totals: [u32; 5],
+}
+
+impl Histogram {
+    fn new(cache_bits: u32) -> Self {
+        Histogram {
+            counts: alphabet_sizes(cache_bits).map(|size| vec![0; size]),
+            totals: [0; 5],
+        }
+    }
+
+    fn count(&mut self, code: usize, symbol: usize) {
+        self.counts[code][symbol] += 1;
+        self.totals[code] += 1;
+    }
+
+    fn add(&mut self, symbol: Symbol) {
+        match symbol {
+            Symbol::Literal(pixel) => {
+                let [alpha, red, green, blue] = pixel.to_be_bytes();
+                for (code, sample) in [green, red, blue, alpha].into_iter().enumerate() {
+                    self.count(code, sample as usize);
+                }
+            }
+            Symbol::CacheIndex(index) => {
+                self.count(0, LITERALS + LENGTH_PREFIXES + index as usize);
+            }
+            Symbol::Copy {
+                length,
+                distance_code,
+            } => {
+                self.count(0, LITERALS + prefix_of(length).0 as usize);
+                self.count(4, prefix_of(distance_code).0 as usize);
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for code in 0..5 {
+            for (count, other) in self.counts[code].iter_mut().zip(&other.counts[code]) {
+                *count += other;
+            }
+            self.totals[code] += other.totals[code];
+        }
+    }
+
+    /// The code and symbol of every count that isn't zero
+    fn used(&self) -> Vec<(usize, usize)> {
+        (0..5)
+            .flat_map(|code| {
+                (0..self.counts[code].len())
+                    .filter(move |&symbol| self.counts[code][symbol] > 0)
+                    .map(move |symbol| (code, symbol))
+            })
+            .collect()
+    }
+
+    /// Roughly how many bits the symbols and code lengths take
+    fn cost(&self) -> f64 {
+        (0..5)
+            .map(|code| {
+                let counts = &self.counts[code];
+                xlogx(self.totals[code]) - counts.iter().map(|&count| xlogx(count)).sum::<f64>()
+                    + counts.iter().filter(|&&count| count > 0).count() as f64 * SYMBOL_COST
+            })
+            .sum()
+    }
+
+    /// How much `cost` grows by merging in `other`, whose used symbols are `used`
+    fn added_cost(&self, other: &Histogram, used: &[(usize, usize)]) -> f64 {
+        let mut cost = 0.0;
+        for code in 0..5 {
+            cost += xlogx(self.totals[code] + other.totals[code]) - xlogx(self.totals[code]);
+        }
+        for &(code, symbol) in used {
+            let (count, added) = (self.counts[code][symbol], other.counts[code][symbol]);
+            cost -= xlogx(count + added) - xlogx(count);
+            if count == 0 {
+                cost += SYMBOL_COST;
+            }
+        }
+        cost
+    }
+}
+
+/// Encodes ARGB `pixels` as a VP8L bitstream, header included. Images of up to 256
+/// colors are stored through a palette, and the rest with the predictor transform.
+pub fn encode(width: u32, height: u32, pixels: &[u32]) -> Result<Vec<u8>> {
+    if width > MAX_VP8L_SIDE || height > MAX_VP8L_SIDE {
+        return Err(Error::invalid_argument(format!(
+            "lossless WebP images are at most {MAX_VP8L_SIDE} pixels on a side, not {width}x{height}"
+        )));
+    }
+    let header = LosslessHeader {
+        width,
+        height,
+        alpha: pixels.iter().any(|&pixel| pixel >> 24 != 0xff),
+    };
+    let (width, height) = (width as usize, height as usize);
+    let mut writer = BitWriter::new();
+    let (coded, coded_width) = match palette_of(pixels) {
+        Some((palette, indices)) => {
+            writer.write_bits(1, 1);
+            writer.write_bits(3, 2);
+            writer.write_bits(palette.len() as u32 - 1, 8);
+            let deltas: Vec<u32> = palette
+                .iter()
+                .enumerate()
+                .map(|(idx, &color)| match idx {
+                    0 => color,
+                    _ => transform::subtract_pixels(color, palette[idx - 1]),
+                })
+                .collect();
+            write_image(&mut writer, &deltas, deltas.len(), false)?;
+            let width_bits = Transform::width_bits(palette.len());
+            (
+                transform::bundle(&indices, width, width_bits),
+                width.div_ceil(1 << width_bits),
+            )
+        }
+        None => {
+            writer.write_bits(1, 1);
+            writer.write_bits(2, 2);
+            let subtracted: Vec<u32> = pixels
+                .iter()
+                .map(|&pixel| transform::subtract_green(pixel))
+                .collect();
+            writer.write_bits(1, 1);
+            writer.write_bits(0, 2);
+            writer.write_bits(PREDICTOR_BITS - 2, 3);
+            let (modes, residuals) =
+                transform::apply_predictor(&subtracted, width, height, PREDICTOR_BITS);
+            write_image(
+                &mut writer,
+                &modes,
+                width.div_ceil(1 << PREDICTOR_BITS),
+                false,
+            )?;
+            (residuals, width)
+        }
+    };
+    writer.write_bits(0, 1);
+    write_image(&mut writer, &coded, coded_width, true)?;
+    let mut data = header.as_bytes().to_vec();
+    data.extend(writer.finish());
+    Ok(data)
+}
+
+/// The colors of `pixels`, sorted, and each pixel's index among them, if there are at
+/// most 256
+fn palette_of(pixels: &[u32]) -> Option<(Vec<u32>, Vec<u8>)> {
+    let mut colors: HashSet<u32> = HashSet::new();
+    for &pixel in pixels {
+        if colors.insert(pixel) && colors.len() > 256 {
+            return None;
+        }
+    }
+    let mut palette: Vec<u32> = colors.into_iter().collect();
+    palette.sort_unstable();
+    let indices: HashMap<u32, u8> = palette
+        .iter()
+        .enumerate()
+        .map(|(idx, &color)| (color, idx as u8))
+        .collect();
+    Some((palette, pixels.iter().map(|pixel| indices[pixel]).collect()))
+}
+
+/// Writes `pixels`, an image `width` wide, the way `read_image` reads it
+fn write_image(writer: &mut BitWriter, pixels: &[u32], width: usize, main: bool) -> Result<()> {
+    let symbols = backward_references(pixels, width);
+    let cache_bits = match main {
+        true => (0..=MAX_CACHE_BITS)
+            .map(|bits| {
+                let mut histogram = Histogram::new(bits);
+                for symbol in apply_cache(&symbols, pixels, bits) {
+                    histogram.add(symbol);
+                }
+                (histogram.cost(), bits)
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or(0, |(_, bits)| bits),
+        false => 0,
+    };
+    let symbols = apply_cache(&symbols, pixels, cache_bits);
+    match cache_bits {
+        0 => writer.write_bits(0, 1),
+        bits => {
+            writer.write_bits(1, 1);
+            writer.write_bits(bits, 4);
+        }
+    }
+
+    let height = pixels.len().div_ceil(width);
+    let (group_bits, group_image, histograms) = match main {
+        true => group_symbols(&symbols, width, height, cache_bits),
+        false => (0, Vec::new(), Vec::new()),
+    };
+    let histograms = match histograms.len() {
+        0 | 1 => {
+            if main {
+                writer.write_bits(0, 1);
+            }
+            let mut histogram = Histogram::new(cache_bits);
+            for &symbol in &symbols {
+                histogram.add(symbol);
+            }
+            vec![histogram]
+        }
+        _ => {
+            writer.write_bits(1, 1);
+            writer.write_bits(group_bits - 2, 3);
+            let entries: Vec<u32> = group_image
+                .iter()
+                .map(|&group| BLACK | (group as u32) << 8)
+                .collect();
+            write_image(writer, &entries, width.div_ceil(1 << group_bits), false)?;
+            histograms
+        }
+    };
+    let mut groups: Vec<Vec<PrefixEncoder>> = Vec::with_capacity(histograms.len());
+    for histogram in &histograms {
+        let mut codes: Vec<PrefixEncoder> = Vec::with_capacity(5);
+        for counts in &histogram.counts {
+            let lengths = huffman::code_lengths(counts, MAX_CODE_LENGTH);
+            write_prefix_code(writer, &lengths)?;
+            codes.push(PrefixEncoder::new(&lengths)?);
+        }
+        groups.push(codes);
+    }
+
+    let groups_wide = width.div_ceil(1 << group_bits);
+    let mut position = 0;
+    for symbol in symbols {
+        let (x, y) = (position % width, position / width);
+        let codes = match group_image.is_empty() {
+            true => &groups[0],
+            false => &groups[group_image[(y >> group_bits) * groups_wide + (x >> group_bits)]],
+        };
+        match symbol {
+            Symbol::Literal(pixel) => {
+                let [alpha, red, green, blue] = pixel.to_be_bytes();
+                for (code, sample) in [green, red, blue, alpha].into_iter().enumerate() {
+                    codes[code].write(writer, sample as u16);
+                }
+            }
+            Symbol::CacheIndex(index) => {
+                codes[0].write(writer, (LITERALS + LENGTH_PREFIXES) as u16 + index as u16);
+            }
+            Symbol::Copy {
+                length,
+                distance_code,
+            } => {
+                let (prefix, extra_bits, extra) = prefix_of(length);
+                codes[0].write(writer, LITERALS as u16 + prefix);
+                writer.write_bits(extra, extra_bits);
+                let (prefix, extra_bits, extra) = prefix_of(distance_code);
+                codes[4].write(writer, prefix);
+                writer.write_bits(extra, extra_bits);
+            }
+        }
+        position += symbol.pixels();
+    }
+    Ok(())
+}
+
+/// Finds runs of `pixels` that repeat earlier ones, as literals and backward references
+fn backward_references(pixels: &[u32], width: usize) -> Vec<Symbol> {
+    const HASH_BITS: u32 = 16;
+    let mut plane_codes: HashMap<usize, u32> = HashMap::new();
+    for (code, &(x, y)) in PLANE_OFFSETS.iter().enumerate().rev() {
+        let distance = x as isize + y as isize * width as isize;
+        if distance >= 1 {
+            plane_codes.insert(distance as usize, code as u32 + 1);
+        }
+    }
+    let len = pixels.len();
+    let hash = |idx: usize| {
+        let mixed =
+            pixels[idx].wrapping_mul(0x9e37_79b1) ^ pixels[idx + 1].wrapping_mul(0x85eb_ca6b);
+        (mixed >> (32 - HASH_BITS)) as usize
+    };
+    // the most recent position with each hash, and the one before each position
+    let mut head: Vec<usize> = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous: Vec<usize> = vec![usize::MAX; len];
+    let insert = |idx: usize, head: &mut [usize], previous: &mut [usize]| {
+        if idx + 1 < len {
+            let hash = hash(idx);
+            previous[idx] = head[hash];
+            head[hash] = idx;
+        }
+    };
+    let match_length = |idx: usize, distance: usize| {
+        (0..(len - idx).min(MAX_LENGTH))
+            .take_while(|&offset| pixels[idx + offset] == pixels[idx + offset - distance])
+            .count()
+    };
+
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut idx = 0;
+    while idx < len {
+        let longest = (len - idx).min(MAX_LENGTH);
+        let mut best = (0, 0);
+        // the pixels to the left and above are worth trying whatever the hash finds
+        for distance in [1, width] {
+            if distance <= idx {
+                let length = match_length(idx, distance);
+                if length > best.0 {
+                    best = (length, distance);
+                }
+            }
+        }
+        if idx + 1 < len {
+            let mut candidate = head[hash(idx)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || idx - candidate > MAX_DISTANCE || best.0 == longest {
+                    break;
+                }
+                let length = match_length(idx, idx - candidate);
+                if length > best.0 {
+                    best = (length, idx - candidate);
+                }
+                candidate = previous[candidate];
+            }
+        }
+        let (length, distance) = best;
+        if length >= MIN_LENGTH {
+            symbols.push(Symbol::Copy {
+                length: length as u32,
+                distance_code: plane_codes
+                    .get(&distance)
+                    .copied()
+                    .unwrap_or((distance + PLANE_OFFSETS.len()) as u32),
+            });
+            for covered in idx..idx + length {
+                insert(covered, &mut head, &mut previous);
+            }
+            idx += length;
+        } else {
+            symbols.push(Symbol::Literal(pixels[idx]));
+            insert(idx, &mut head, &mut previous);
+            idx += 1;
+        }
+    }
+    symbols
+}
+
+/// Swaps literals for color cache indices wherever a cache of `bits` would hold the color
+fn apply_cache(symbols: &[Symbol], pixels: &[u32], bits: u32) -> Vec<Symbol> {
+    if bits == 0 {
+        return symbols.to_vec();
+    }
+    let mut cache: Vec<u32> = vec![0; 1 << bits];
+    let mut position = 0;
+    symbols
+        .iter()
+        .map(|&symbol| {
+            let mut symbol = symbol;
+            if let Symbol::Literal(pixel) = symbol {
+                let index = cache_index(pixel, bits);
+                if cache[index] == pixel {
+                    symbol = Symbol::CacheIndex(index as u32);
+                }
+            }
+            for &pixel in &pixels[position..position + symbol.pixels()] {
+                cache[cache_index(pixel, bits)] = pixel;
+            }
+            position += symbol.pixels();
+            symbol
+        })
+        .collect()
+}
+
+/// Sorts square blocks of the image into groups whose symbols are alike, each to get its
+/// own prefix codes. Returns the block size in bits, the group of each block, and the
+/// symbol counts of each group.
+fn group_symbols(
+    symbols: &[Symbol],
+    width: usize,
+    height: usize,
+    cache_bits: u32,
+) -> (u32, Vec<usize>, Vec<Histogram>) {
+    let mut bits = MIN_GROUP_BITS;
+    while bits < 9 && width.div_ceil(1 << bits) * height.div_ceil(1 << bits) > MAX_GROUP_BLOCKS {
+        bits += 1;
+    }
+    let blocks_wide = width.div_ceil(1 << bits);
+    let block_count = blocks_wide * height.div_ceil(1 << bits);
+    if block_count < 2 {
+        return (bits, Vec::new(), Vec::new());
+    }
+    let mut blocks: Vec<Histogram> = vec![Histogram::new(cache_bits); block_count];
+    let mut position = 0;
+    for &symbol in symbols {
+        let (x, y) = (position % width, position / width);
+        blocks[(y >> bits) * blocks_wide + (x >> bits)].add(symbol);
+        position += symbol.pixels();
+    }
+
+    // each block joins whichever group it adds least to, or starts a new one if that's
+    // cheaper still
+    let mut groups: Vec<Histogram> = Vec::new();
+    let mut assignments: Vec<usize> = Vec::with_capacity(block_count);
+    for block in &blocks {
+        let used = block.used();
+        let best = groups
+            .iter()
+            .enumerate()
+            .map(|(group, histogram)| (histogram.added_cost(block, &used), group))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        let group = match best {
+            Some((cost, group))
+                if groups.len() == MAX_GROUPS || cost <= block.cost() + GROUP_COST =>
+            {
+                groups[group].merge(block);
+                group
+            }
+            _ => {
+                groups.push(block.clone());
+                groups.len() - 1
+            }
+        };
+        assignments.push(group);
+    }
+    (bits, assignments, groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefixed_values() {
+        for value in (1..=4096).chain([PLANE_OFFSETS.len() as u32 + MAX_DISTANCE as u32]) {
+            let (prefix, extra_bits, extra) = prefix_of(value);
+            let mut writer = BitWriter::new();
+            writer.write_bits(extra, extra_bits);
+            let data = writer.finish();
+            let mut reader = BitReader::new(&data);
+            assert_eq!(read_prefixed(&mut reader, prefix).unwrap(), value as usize);
+            if value <= 4096 {
+                assert!((prefix as usize) < LENGTH_PREFIXES);
+            }
+            assert!((prefix as usize) < DISTANCE_PREFIXES);
+        }
+        assert_eq!(distance_of(1, 100), 100);
+        assert_eq!(distance_of(2, 100), 1);
+        assert_eq!(distance_of(4, 100), 99);
+        assert_eq!(distance_of(121, 100), 1);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (width, height) = (67_u32, 41_u32);
+        // a gradient with noise needs the predictor, and a few flat patches make repeats
+        let photo: Vec<u32> = (0..width * height)
+            .map(|idx| {
+                let (x, y) = (idx % width, idx / width);
+                let noise = idx.wrapping_mul(0x9e37_79b9) >> 29;
+                let value = match (x / 16 + y / 16) % 3 {
+                    0 => 0x40,
+                    _ => (x * 3 + y * 2 + noise) & 0xff,
+                };
+                (0xff - (y & 1) * 0x80) << 24 | value << 16 | (value ^ 0x55) << 8 | (x & 0xff)
+            })
+            .collect();
+        let indexed: Vec<u32> = (0..width * height)
+            .map(|idx| [0xff00_0000, 0xffff_ffff, 0x0000_0000, 0xff12_3456][(idx % 7 % 4) as usize])
+            .collect();
+        for pixels in [photo, indexed, vec![0x8040_2010; 3]] {
+            let (width, height) = match pixels.len() {
+                3 => (3, 1),
+                _ => (width, height),
+            };
+            let data = encode(width, height, &pixels).unwrap();
+            let (header, decoded) = decode(&data).unwrap();
+            assert_eq!((header.width, header.height), (width, height));
+            assert!(header.alpha);
+            assert_eq!(decoded, pixels);
+            assert!(decode(&data[..data.len() / 2]).is_err());
+        }
+        assert!(encode(MAX_VP8L_SIDE + 1, 1, &[0; MAX_VP8L_SIDE as usize + 1]).is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! The transforms VP8L applies to ARGB pixels before entropy coding them, and their
+//! inverses
+
+/// Opaque black, which the predictors fall back to at the top-left corner
+pub const BLACK: u32 = 0xff00_0000;
+
+/// One transform, as read from the bitstream in the order the encoder applied them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Each pixel is stored as its difference from a prediction, using the mode given for
+    /// its `1 << size_bits` square block in the green channel of `modes`
+    Predictor { size_bits: u32, modes: Vec<u32> },
+    /// Red and blue are stored less multiples of green and red, per block, with each
+    /// block's multipliers packed into a pixel of `elements`
+    CrossColor { size_bits: u32, elements: Vec<u32> },
+    /// Green is subtracted from red and blue
+    SubtractGreen,
+    /// Pixels are stored as indices into `palette`, `1 << width_bits` to a pixel
+    ColorIndexing { width_bits: u32, palette: Vec<u32> },
+}
+
+impl Transform {
+    /// Bits of index per pixel in a palette of `len` colors, as a power of two packed in
+    pub fn width_bits(len: usize) -> u32 {
+        match len {
+            0..=2 => 3,
+            3..=4 => 2,
+            5..=16 => 1,
+            _ => 0,
+        }
+    }
+
+    /// Width of the image this transform leaves behind, given the width it starts from
+    pub fn transformed_width(&self, width: usize) -> usize {
+        match self {
+            Transform::ColorIndexing { width_bits, .. } => width.div_ceil(1 << width_bits),
+            _ => width,
+        }
+    }
+
+    /// Undoes the transform on `pixels`, where `width` is that of the image before it was
+    /// applied
+    pub fn invert(&self, pixels: Vec<u32>, width: usize, height: usize) -> Vec<u32> {
+        match self {
+            Transform::Predictor { size_bits, modes } => {
+                invert_predictor(pixels, width, height, *size_bits, modes)
+            }
+            Transform::CrossColor {
+                size_bits,
+                elements,
+            } => invert_cross_color(pixels, width, *size_bits, elements),
+            Transform::SubtractGreen => pixels.into_iter().map(add_green).collect(),
+            Transform::ColorIndexing {
+                width_bits,
+                palette,
+            } => invert_color_indexing(&pixels, width, height, *width_bits, palette),
+        }
+    }
+}
+
+/// Adds the channels of two pixels, each modulo 256
+pub fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xff00_ff00).wrapping_add(b & 0xff00_ff00);
+    let red_blue = (a & 0x00ff_00ff).wrapping_add(b & 0x00ff_00ff);
+    (alpha_green & 0xff00_ff00) | (red_blue & 0x00ff_00ff)
+}
+
+/// Subtracts the channels of `b` from `a`, each modulo 256
+pub fn subtract_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a | 0x00ff_00ff).wrapping_sub(b & 0xff00_ff00);
+    let red_blue = (a | 0xff00_ff00).wrapping_sub(b & 0x00ff_00ff);
+    (alpha_green & 0xff00_ff00) | (red_blue & 0x00ff_00ff)
+}
+
+fn channels(pixel: u32) -> [i32; 4] {
+    pixel.to_be_bytes().map(|channel| channel as i32)
+}
+
+fn from_channels(channels: [i32; 4]) -> u32 {
+    u32::from_be_bytes(channels.map(|channel| channel.clamp(0, 255) as u8))
+}
+
+/// The per-channel average of two pixels, rounded down
+fn average(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xfefe_fefe) >> 1) + (a & b)
+}
+
+/// Whichever of `left` and `top` is closer to the gradient `top + left - top_left`
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let distance = |a: u32, b: u32| {
+        channels(a)
+            .iter()
+            .zip(channels(b))
+            .map(|(a, b)| (a - b).abs())
+            .sum::<i32>()
+    };
+    // the spec names these the other way round; what matters is which one wins
+    match distance(top, top_left) < distance(left, top_left) {
+        true => left,
+        false => top,
+    }
+}
+
+fn clamp_add_subtract_full(a: u32, b: u32, c: u32) -> u32 {
+    let (a, b, c) = (channels(a), channels(b), channels(c));
+    from_channels([0, 1, 2, 3].map(|idx| a[idx] + b[idx] - c[idx]))
+}
+
+fn clamp_add_subtract_half(a: u32, b: u32) -> u32 {
+    let (a, b) = (channels(a), channels(b));
+    from_channels([0, 1, 2, 3].map(|idx| a[idx] + (a[idx] - b[idx]) / 2))
+}
+
+/// What predictor `mode` guesses for a pixel away from the top row and left column
+pub fn predict(mode: u32, left: u32, top: u32, top_left: u32, top_right: u32) -> u32 {
+    match mode {
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average(average(left, top_right), top),
+        6 => average(left, top_left),
+        7 => average(left, top),
+        8 => average(top_left, top),
+        9 => average(top, top_right),
+        10 => average(average(left, top_left), average(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => clamp_add_subtract_full(left, top, top_left),
+        13 => clamp_add_subtract_half(average(left, top), top_left),
+        // 0, and the two unused modes, which decoders treat the same way
+        _ => BLACK,
+    }
+}
+
+/// The prediction for pixel `idx` at (`x`, `y`) from the pixels before it
+fn prediction(pixels: &[u32], width: usize, idx: usize, x: usize, y: usize, mode: u32) -> u32 {
+    match (x, y) {
+        (0, 0) => BLACK,
+        (_, 0) => pixels[idx - 1],
+        (0, _) => pixels[idx - width],
+        // at the right edge the pixel to the top right wraps round to the current row
+        _ => predict(
+            mode,
+            pixels[idx - 1],
+            pixels[idx - width],
+            pixels[idx - width - 1],
+            pixels[idx - width + 1],
+        ),
+    }
+}
+
+/// The entry of a per-block sub-image that covers pixel (`x`, `y`)
+fn block_entry(entries: &[u32], width: usize, size_bits: u32, x: usize, y: usize) -> u32 {
+    let blocks_wide = width.div_ceil(1 << size_bits);
+    entries
+        .get((y >> size_bits) * blocks_wide + (x >> size_bits))
+        .copied()
+        .unwrap_or(0)
+}
+
+fn invert_predictor(
+    mut pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    size_bits: u32,
+    modes: &[u32],
+) -> Vec<u32> {
+    for y in 0..height {
+        for x in 0..width {
+            let idx = y * width + x;
+            let mode = block_entry(modes, width, size_bits, x, y) >> 8 & 0xf;
+            let prediction = prediction(&pixels, width, idx, x, y, mode);
+            pixels[idx] = add_pixels(pixels[idx], prediction);
+        }
+    }
+    pixels
+}
+
+/// Picks a predictor for each `1 << size_bits` block and returns the modes, packed into
+/// green as the transform stores them, and the residuals
+pub fn apply_predictor(
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+    size_bits: u32,
+) -> (Vec<u32>, Vec<u32>) {
+    let block = 1 << size_bits;
+    let (blocks_wide, blocks_high) = (width.div_ceil(block), height.div_ceil(block));
+    let mut modes: Vec<u32> = Vec::with_capacity(blocks_wide * blocks_high);
+    let mut residuals: Vec<u32> = vec![0; pixels.len()];
+    for block_y in 0..blocks_high {
+        for block_x in 0..blocks_wide {
+            let ys = block_y * block..((block_y + 1) * block).min(height);
+            let xs = block_x * block..((block_x + 1) * block).min(width);
+            // small residuals cost the fewest bits once entropy coded
+            let cost = |mode: u32| -> u32 {
+                let mut cost = 0;
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        let idx = y * width + x;
+                        let prediction = prediction(pixels, width, idx, x, y, mode);
+                        let residual = subtract_pixels(pixels[idx], prediction);
+                        cost += residual
+                            .to_be_bytes()
+                            .iter()
+                            .map(|&channel| (channel as i8).unsigned_abs() as u32)
+                            .sum::<u32>();
+                    }
+                }
+                cost
+            };
+            let mode = (0..14).min_by_key(|&mode| cost(mode)).unwrap_or(0);
+            modes.push(BLACK | mode << 8);
+            for y in ys {
+                for x in xs.clone() {
+                    let idx = y * width + x;
+                    let prediction = prediction(pixels, width, idx, x, y, mode);
+                    residuals[idx] = subtract_pixels(pixels[idx], prediction);
+                }
+            }
+        }
+    }
+    (modes, residuals)
+}
+
+/// The color transform's correction: a multiplier and a sample as 3.5 fixed point numbers
+fn color_delta(multiplier: u8, sample: u8) -> u8 {
+    ((multiplier as i8 as i32 * sample as i8 as i32) >> 5) as u8
+}
+
+fn invert_cross_color(
+    mut pixels: Vec<u32>,
+    width: usize,
+    size_bits: u32,
+    elements: &[u32],
+) -> Vec<u32> {
+    for (idx, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = (idx % width, idx / width);
+        let [_, red_to_blue, green_to_blue, green_to_red] =
+            block_entry(elements, width, size_bits, x, y).to_be_bytes();
+        let [alpha, red, green, blue] = pixel.to_be_bytes();
+        let red = red.wrapping_add(color_delta(green_to_red, green));
+        let blue = blue
+            .wrapping_add(color_delta(green_to_blue, green))
+            .wrapping_add(color_delta(red_to_blue, red));
+        *pixel = u32::from_be_bytes([alpha, red, green, blue]);
+    }
+    pixels
+}
+
+fn add_green(pixel: u32) -> u32 {
+    let green = pixel >> 8 & 0xff;
+    add_pixels(pixel, green << 16 | green)
+}
+
+/// Subtracts green from red and blue, the inverse of what the decoder does
+pub fn subtract_green(pixel: u32) -> u32 {
+    let green = pixel >> 8 & 0xff;
+    subtract_pixels(pixel, green << 16 | green)
+}
+
+fn invert_color_indexing(
+    packed: &[u32],
+    width: usize,
+    height: usize,
+    width_bits: u32,
+    palette: &[u32],
+) -> Vec<u32> {
+    let packed_width = width.div_ceil(1 << width_bits);
+    let bits = 8 >> width_bits;
+    let mask = (1 << bits) - 1;
+    let mut pixels: Vec<u32> = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = packed[y * packed_width + (x >> width_bits)];
+            let shift = (x & ((1 << width_bits) - 1)) as u32 * bits;
+            let index = (pixel >> 8 >> shift) & mask;
+            // indices past the end of the palette are transparent black
+            pixels.push(palette.get(index as usize).copied().unwrap_or(0));
+        }
+    }
+    pixels
+}
+
+/// Packs palette `indices` into green, `1 << width_bits` to a pixel, with the first one
+/// in the lowest bits
+pub fn bundle(indices: &[u8], width: usize, width_bits: u32) -> Vec<u32> {
+    let bits = 8 >> width_bits;
+    indices
+        .chunks(width)
+        .flat_map(|row| {
+            row.chunks(1 << width_bits).map(|group| {
+                let packed = group.iter().enumerate().fold(0, |packed, (idx, &index)| {
+                    packed | (index as u32) << (idx as u32 * bits)
+                });
+                BLACK | packed << 8
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inverses() {
+        let (width, height) = (7, 5);
+        let pixels: Vec<u32> = (0..(width * height) as u32)
+            .map(|idx| idx.wrapping_mul(0x9e37_79b9) | (idx % 3 * 0x7f) << 24)
+            .collect();
+        let green_subtracted: Vec<u32> =
+            pixels.iter().map(|&pixel| subtract_green(pixel)).collect();
+        let (modes, residuals) = apply_predictor(&green_subtracted, width, height, 2);
+        assert_eq!(modes.len(), 4);
+        let predictor = Transform::Predictor {
+            size_bits: 2,
+            modes,
+        };
+        let restored = predictor.invert(residuals, width, height);
+        assert_eq!(restored, green_subtracted);
+        assert_eq!(
+            Transform::SubtractGreen.invert(restored, width, height),
+            pixels
+        );
+
+        // the gradient points at the left pixel, and clamping stops at each channel's limits
+        let (left, top) = (0xff00_0000, 0xff10_1010);
+        assert_eq!(predict(11, left, top, top, 0), left);
+        assert_eq!(predict(11, left, top, left, 0), top);
+        assert_eq!(
+            predict(12, 0xff80_0000, 0xff90_0000, 0x0000_0010, 0),
+            0xffff_0000
+        );
+
+        // a red-to-blue multiplier of 1.0 adds the corrected red to blue
+        let cross_color = Transform::CrossColor {
+            size_bits: 2,
+            elements: vec![0xff20_0000],
+        };
+        assert_eq!(
+            cross_color.invert(vec![0xff10_0005], 1, 1),
+            vec![0xff10_0015]
+        );
+
+        let palette = vec![0xff00_0000, 0xffff_ffff, 0x8000_ff00];
+        let indices = [0, 1, 2, 1, 0, 2, 2, 1, 0, 3];
+        let width_bits = Transform::width_bits(palette.len());
+        let packed = bundle(&indices, 5, width_bits);
+        assert_eq!(packed.len(), 4);
+        let indexing = Transform::ColorIndexing {
+            width_bits,
+            palette: palette.clone(),
+        };
+        assert_eq!(indexing.transformed_width(5), 2);
+        let unpacked = indexing.invert(packed, 5, 2);
+        let expected: Vec<u32> = indices
+            .iter()
+            .map(|&index| palette.get(index as usize).copied().unwrap_or(0))
+            .collect();
+        assert_eq!(unpacked, expected);
+    }
+}